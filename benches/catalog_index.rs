@@ -0,0 +1,37 @@
+use std::hint::black_box;
+
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use unichain::catalog::IndexedCatalog;
+use unichain::model::{File, FileType};
+
+const RECORDS: i64 = 1_000_000;
+const OWNERS: i64 = 1_000;
+
+fn get_file(id: i64) -> File {
+    let owner = (id % OWNERS, format!("user-{}", id % OWNERS), format!("user-{}@unichain.local", id % OWNERS));
+    File {
+        id, name: format!("file-{}", id), file_type: FileType::Pdf, size: 1024,
+        created: Utc::now().naive_utc(), modified: None, accessed: None, owner: owner.clone(),
+        people_with_access: vec![owner], ipfs_hash: String::new(), onchain_txn_id: String::new(),
+        download_permission: false, description: None, content_hash: Some(format!("{:064x}", id))
+    }
+}
+
+fn bench_lookups(c: &mut Criterion) {
+    let mut catalog = IndexedCatalog::from_files((0..RECORDS).map(get_file).collect());
+    let mut group = c.benchmark_group("catalog_1m");
+    group.bench_function("get_by_id", |b| b.iter(|| black_box(catalog.get(black_box(RECORDS / 2)))));
+    group.bench_function("by_owner", |b| b.iter(|| black_box(catalog.by_owner(black_box(OWNERS / 2)).len())));
+    group.bench_function("by_name", |b| b.iter(|| black_box(catalog.by_name(black_box("file-500000")).len())));
+    group.bench_function("by_content_hash", |b| {
+        let hash = format!("{:064x}", RECORDS / 2);
+        b.iter(|| black_box(catalog.by_content_hash(black_box(&hash)).len()))
+    });
+    group.bench_function("upsert_existing", |b| b.iter(|| black_box(catalog.upsert(get_file(black_box(RECORDS / 3))))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_lookups);
+criterion_main!(benches);
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use base64::Engine;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::{content_hash, CatalogLock};
+use crate::identity::{IdentityRegistry, LocalIdentity, PublicIdentity};
+use crate::ledger::RecordPool;
+use crate::model::FileError;
+
+/// How far the timestamp of a signed request may be from the server clock, in seconds.
+pub const SIGNATURE_WINDOW_SECONDS: i64 = 300;
+
+pub const IDENTITY_HEADER: &str = "X-UniChain-Identity";
+pub const TIMESTAMP_HEADER: &str = "X-UniChain-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-UniChain-Signature";
+
+/// A bearer token issued to an identity. Only the hash of the token is kept.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApiToken {
+    pub token_hash: String,
+    pub identity_id: i64,
+    pub expires: Option<NaiveDateTime>,
+}
+
+/// An access key for the S3 endpoint. SigV4 needs the secret itself to check a signature,
+/// so unlike a bearer token it is kept, sealed like the rest of the repository.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub identity_id: i64,
+}
+
+/// The identity a request was authenticated as, and the bearer token it presented if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caller {
+    pub identity: PublicIdentity,
+    pub token: Option<String>,
+}
+
+/// Tokens issued to API callers.
+pub fn api_tokens(catalog_path: &Path) -> RecordPool<ApiToken> {
+    RecordPool::open(catalog_path, ".tokens")
+}
+
+/// Issues a bearer token for a registered identity, valid for `ttl` or until revoked.
+pub fn issue_token(catalog_path: &Path, identity_id: i64, ttl: Option<Duration>) -> Result<(String, ApiToken), FileError> {
+    IdentityRegistry::open(catalog_path)?.get(identity_id)?
+        .ok_or_else(|| FileError::InputError(format!("Identity {} is not registered", identity_id)))?;
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let token = hex::encode(secret);
+    let record = ApiToken { token_hash: content_hash(token.as_bytes()), identity_id, expires: ttl.map(|ttl| Utc::now().naive_utc() + ttl) };
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    api_tokens(catalog_path).add(std::slice::from_ref(&record))?;
+    Ok((token, record))
+}
+
+/// Access keys issued for the S3 endpoint.
+pub fn s3_credentials(catalog_path: &Path) -> RecordPool<S3Credentials> {
+    RecordPool::open(catalog_path, ".s3-keys")
+}
+
+/// Issues an S3 access key for a registered identity.
+pub fn issue_s3_credentials(catalog_path: &Path, identity_id: i64) -> Result<S3Credentials, FileError> {
+    IdentityRegistry::open(catalog_path)?.get(identity_id)?
+        .ok_or_else(|| FileError::InputError(format!("Identity {} is not registered", identity_id)))?;
+    let mut key_id = [0u8; 10];
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut key_id);
+    rand::thread_rng().fill_bytes(&mut secret);
+    let credentials = S3Credentials { access_key_id: format!("UC{}", hex::encode_upper(key_id)), secret_access_key: hex::encode(secret), identity_id };
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    s3_credentials(catalog_path).add(std::slice::from_ref(&credentials))?;
+    Ok(credentials)
+}
+
+/// Revokes a token, along with every token that has expired. Returns whether the token existed.
+pub fn revoke_token(catalog_path: &Path, token: &str) -> Result<bool, FileError> {
+    let token_hash = content_hash(token.as_bytes());
+    let now = Utc::now().naive_utc();
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    let mut found = false;
+    api_tokens(catalog_path).retain(|record| {
+        found |= record.token_hash == token_hash;
+        record.token_hash != token_hash && record.expires.is_none_or(|expires| expires > now)
+    })?;
+    Ok(found)
+}
+
+/// What a caller signs: the method, the request target, the timestamp and the hash of the body.
+pub fn signing_message(method: &str, target: &str, timestamp: i64, body: &[u8]) -> Vec<u8> {
+    signing_message_for_hash(method, target, timestamp, &content_hash(body))
+}
+
+fn signing_message_for_hash(method: &str, target: &str, timestamp: i64, body_hash: &str) -> Vec<u8> {
+    format!("{}\n{}\n{}\n{}", method.to_uppercase(), target, timestamp, body_hash).into_bytes()
+}
+
+/// The headers that authenticate a request as `identity`.
+pub fn sign_request(identity: &LocalIdentity, method: &str, target: &str, body: &[u8]) -> Vec<(&'static str, String)> {
+    let timestamp = Utc::now().timestamp();
+    let signature = identity.sign(&signing_message(method, target, timestamp, body));
+    vec![(IDENTITY_HEADER, identity.id().to_string()), (TIMESTAMP_HEADER, timestamp.to_string()), (SIGNATURE_HEADER, hex::encode(signature))]
+}
+
+/// What the headers of a request prove about its caller. A signed request is only
+/// authenticated once its body is read, since the signature covers the body.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credentials {
+    Caller(Caller),
+    Signed { identity: PublicIdentity, timestamp: i64, signature: Vec<u8> },
+}
+
+/// Checks who is calling, by a token in `Authorization` (as a bearer token or as the
+/// password of Basic credentials) or by a request signed with the caller's identity key.
+/// A signature is accepted only once.
+#[derive(Default)]
+pub struct Authenticator {
+    seen_signatures: Mutex<HashMap<Vec<u8>, i64>>,
+}
+
+impl Authenticator {
+    pub fn authenticate(&self, catalog_path: &Path, method: &str, target: &str, header: impl Fn(&'static str) -> Option<String>, body: &[u8]) -> Result<Caller, FileError> {
+        let credentials = self.check_headers(catalog_path, header)?;
+        self.check_body(credentials, method, target, &content_hash(body))
+    }
+
+    /// Checks what can be checked before the body is read: a token entirely, and for a
+    /// signed request that it comes from a registered identity and is recent.
+    pub fn check_headers(&self, catalog_path: &Path, header: impl Fn(&'static str) -> Option<String>) -> Result<Credentials, FileError> {
+        if let Some(authorization) = header("Authorization") {
+            let token = match authorization.trim().split_once(' ') {
+                Some((scheme, token)) if scheme.eq_ignore_ascii_case("Bearer") => token.trim().to_string(),
+                Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("Basic") => basic_password(credentials)?,
+                _ => return Err(FileError::Unauthenticated("Only bearer tokens, or tokens as Basic passwords, are accepted in Authorization".to_string())),
+            };
+            return authenticate_token(catalog_path, &token).map(Credentials::Caller);
+        }
+        let (Some(identity_id), Some(timestamp), Some(signature)) = (header(IDENTITY_HEADER), header(TIMESTAMP_HEADER), header(SIGNATURE_HEADER)) else {
+            return Err(FileError::Unauthenticated("Send a bearer token or a signed request".to_string()));
+        };
+        let identity_id: i64 = identity_id.trim().parse().map_err(|_| FileError::Unauthenticated(format!("Invalid {}", IDENTITY_HEADER)))?;
+        let timestamp: i64 = timestamp.trim().parse().map_err(|_| FileError::Unauthenticated(format!("Invalid {}", TIMESTAMP_HEADER)))?;
+        let signature = hex::decode(signature.trim()).map_err(|_| FileError::Unauthenticated(format!("Invalid {}", SIGNATURE_HEADER)))?;
+        let now = Utc::now().timestamp();
+        if (now - timestamp).abs() > SIGNATURE_WINDOW_SECONDS {
+            return Err(FileError::Unauthenticated("The request timestamp is too far from the server clock".to_string()));
+        }
+        let identity = IdentityRegistry::open(catalog_path)?.get(identity_id)?
+            .ok_or_else(|| FileError::Unauthenticated(format!("Identity {} is not registered", identity_id)))?;
+        Ok(Credentials::Signed { identity, timestamp, signature })
+    }
+
+    /// Finishes authenticating a request once its body is read, given the hash
+    /// `content_hash` gives of the body.
+    pub fn check_body(&self, credentials: Credentials, method: &str, target: &str, body_hash: &str) -> Result<Caller, FileError> {
+        let (identity, timestamp, signature) = match credentials {
+            Credentials::Caller(caller) => return Ok(caller),
+            Credentials::Signed { identity, timestamp, signature } => (identity, timestamp, signature),
+        };
+        identity.verify(&signing_message_for_hash(method, target, timestamp, body_hash), &signature)
+            .map_err(|_| FileError::Unauthenticated("The signature does not match the request".to_string()))?;
+        let now = Utc::now().timestamp();
+        let mut seen = self.seen_signatures.lock().unwrap();
+        seen.retain(|_, seen_at| now - *seen_at <= SIGNATURE_WINDOW_SECONDS * 2);
+        if seen.insert(signature, timestamp).is_some() {
+            return Err(FileError::Unauthenticated("The signed request was already used".to_string()));
+        }
+        Ok(Caller { identity, token: None })
+    }
+}
+
+/// The password of Basic credentials, which is where WebDAV clients put a token.
+fn basic_password(credentials: &str) -> Result<String, FileError> {
+    let invalid = || FileError::Unauthenticated("Invalid Basic credentials".to_string());
+    let decoded = base64::engine::general_purpose::STANDARD.decode(credentials.trim()).map_err(|_| invalid())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+    decoded.split_once(':').map(|(_, password)| password.to_string()).ok_or_else(invalid)
+}
+
+fn authenticate_token(catalog_path: &Path, token: &str) -> Result<Caller, FileError> {
+    let token_hash = content_hash(token.as_bytes());
+    let now = Utc::now().naive_utc();
+    let record = api_tokens(catalog_path).list()?.into_iter()
+        .find(|record| record.token_hash == token_hash)
+        .ok_or_else(|| FileError::Unauthenticated("Unknown or revoked token".to_string()))?;
+    if record.expires.is_some_and(|expires| expires <= now) {
+        return Err(FileError::Unauthenticated("The token expired".to_string()));
+    }
+    let identity = IdentityRegistry::open(catalog_path)?.get(record.identity_id)?
+        .ok_or_else(|| FileError::Unauthenticated(format!("Identity {} is no longer registered", record.identity_id)))?;
+    Ok(Caller { identity, token: Some(token.to_string()) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_signed_requests_verify_once_and_only_unchanged() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("assets");
+        let caller = LocalIdentity::generate(5, "Guest", "guest@gmail.com");
+        IdentityRegistry::open(&path).unwrap().register(&caller.public).unwrap();
+        let authenticator = Authenticator::default();
+        let headers = sign_request(&caller, "GET", "/files", b"");
+        let header = |name: &'static str| headers.iter().find(|(header, _)| *header == name).map(|(_, value)| value.clone());
+
+        assert!(matches!(authenticator.authenticate(&path, "GET", "/files?owner=1", header, b""), Err(FileError::Unauthenticated(_))));
+        assert_eq!(authenticator.authenticate(&path, "GET", "/files", header, b"").unwrap().identity, caller.public);
+        assert!(matches!(authenticator.authenticate(&path, "GET", "/files", header, b""), Err(FileError::Unauthenticated(_))));
+        assert!(matches!(authenticator.authenticate(&path, "GET", "/files", |_| None, b""), Err(FileError::Unauthenticated(_))));
+    }
+
+    #[test]
+    fn test_tokens_expire_and_revoke() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("assets");
+        let caller = LocalIdentity::generate(5, "Guest", "guest@gmail.com");
+        IdentityRegistry::open(&path).unwrap().register(&caller.public).unwrap();
+        assert!(issue_token(&path, 6, None).is_err());
+        let (token, _) = issue_token(&path, 5, None).unwrap();
+        let (expired, _) = issue_token(&path, 5, Some(Duration::seconds(-1))).unwrap();
+        let bearer = |token: String| move |name: &'static str| (name == "Authorization").then(|| format!("Bearer {}", token));
+        let authenticator = Authenticator::default();
+
+        let authenticated = authenticator.authenticate(&path, "GET", "/files", bearer(token.clone()), b"").unwrap();
+        assert_eq!((authenticated.identity.id, authenticated.token.as_deref()), (5, Some(token.as_str())));
+        assert!(authenticator.authenticate(&path, "GET", "/files", bearer(expired), b"").is_err());
+        assert!(revoke_token(&path, &token).unwrap());
+        assert!(authenticator.authenticate(&path, "GET", "/files", bearer(token.clone()), b"").is_err());
+        assert!(api_tokens(&path).list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,677 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Cursor, Read};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use chrono::Utc;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use crate::catalog::content_hash;
+use crate::model::{File, FileError, FileType};
+use crate::net::percent_decode;
+use crate::permissions::access::AccessStatus;
+use crate::permissions::deletion::Removal;
+use crate::permissions::{authorize, is_allowed, Action};
+use crate::utils::{generate_fake_hash, generate_id};
+
+pub mod auth;
+pub mod multipart;
+pub mod s3;
+pub mod sigv4;
+pub mod webdav;
+
+use auth::{Authenticator, Caller};
+use multipart::Part;
+use webdav::Locks;
+
+/// Largest request body the API accepts, which bounds the size of an upload.
+pub const MAX_BODY_LEN: usize = 256 * 1024 * 1024;
+
+const DEFAULT_WORKERS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiConfig {
+    pub listen: String,
+    pub workers: usize,
+}
+
+impl ApiConfig {
+    /// Reads the API settings from `UNICHAIN_API_LISTEN` and `UNICHAIN_API_WORKERS`.
+    /// Returns `None` when no listen address is set, in which case no API is served.
+    pub fn from_env() -> Option<Self> {
+        let listen = env::var("UNICHAIN_API_LISTEN").ok().filter(|listen| !listen.trim().is_empty())?;
+        let workers = env::var("UNICHAIN_API_WORKERS").ok()
+            .and_then(|workers| workers.trim().parse().ok())
+            .filter(|workers| *workers > 0)
+            .unwrap_or(DEFAULT_WORKERS);
+        Some(ApiConfig { listen: listen.trim().to_string(), workers })
+    }
+}
+
+/// Metadata of a file created through the API. The name defaults to the name of the
+/// uploaded file and the type to its extension.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct NewFile {
+    pub name: Option<String>,
+    pub file_type: Option<String>,
+    pub description: Option<String>,
+    pub download_permission: bool,
+    pub people_with_access: Vec<(i64, String, String)>,
+}
+
+/// Changes to the metadata of a file; fields left out stay as they are.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct FileUpdate {
+    pub name: Option<String>,
+    pub file_type: Option<String>,
+    pub description: Option<String>,
+    pub download_permission: Option<bool>,
+    pub people_with_access: Option<Vec<(i64, String, String)>>,
+}
+
+/// Serves the catalog as a REST API on a pool of worker threads. The server stops when
+/// it is dropped.
+pub struct ApiServer {
+    server: Arc<Server>,
+    local_addr: SocketAddr,
+}
+
+impl ApiServer {
+    pub fn start(config: &ApiConfig) -> Result<Self, FileError> {
+        let catalog_path = crate::get_path();
+        if !catalog_path.exists() {
+            OpenOptions::new().create(true).truncate(false).write(true).open(&catalog_path)?;
+        }
+        let server = Server::http(&config.listen).map_err(|e| FileError::InputError(format!("Cannot serve the API on {}: {}", config.listen, e)))?;
+        let local_addr = server.server_addr().to_ip().ok_or_else(|| FileError::InputError(format!("{} is not an IP address", config.listen)))?;
+        let server = Arc::new(server);
+        let authenticator = Arc::new(Authenticator::default());
+        let locks = Arc::new(Locks::default());
+        for _ in 0..config.workers.max(1) {
+            let (server, authenticator, locks) = (server.clone(), authenticator.clone(), locks.clone());
+            thread::spawn(move || {
+                while let Ok(mut request) = server.recv() {
+                    let response = handle(&mut request, &authenticator, &locks);
+                    if let Err(e) = request.respond(response) {
+                        warn!("Failed to answer an API request: {}", e);
+                    }
+                }
+            });
+        }
+        info!("API listening on {}.", local_addr);
+        Ok(ApiServer { server, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for ApiServer {
+    fn drop(&mut self) {
+        for _ in 0..Arc::strong_count(&self.server) {
+            self.server.unblock();
+        }
+    }
+}
+
+type ApiResponse = Response<Cursor<Vec<u8>>>;
+
+/// Authenticates a request and routes it:
+///
+/// - `GET /files`, optionally filtered by `owner`, `name` or `content_hash`
+/// - `POST /files` with a `multipart/form-data` body of a `metadata` JSON part and a
+///   `content` file part, or with a JSON body of metadata alone
+/// - `GET /files/{id}`, `PATCH /files/{id}` with a JSON body, `DELETE /files/{id}`
+/// - `GET /files/{id}/content`
+/// - `PUT /files/{id}/deletion-policy` with a JSON `threshold` of approvals for deleting it,
+///   `GET /deletions` for the deletions waiting for approvals, and
+///   `POST /files/{id}/approvals` with a JSON `signature` to approve one
+/// - `POST /files/{id}/transfer` with the JSON `to` identity and the owner's `signature`,
+///   `POST /files/{id}/transfer/accept` with the recipient's `signature`,
+///   `DELETE /files/{id}/transfer` to withdraw or decline one, and `GET /transfers`
+/// - `POST /files/{id}/access-requests` with a JSON `reason`, `GET /access-requests`, and
+///   `POST /access-requests/{id}/approve` or `/deny` for the owner to answer one
+/// - `POST /tokens` to get a bearer token, `DELETE /tokens` to revoke the one presented
+/// - WebDAV methods under `/dav/`, served by [`webdav::handle`]
+fn handle(request: &mut Request, authenticator: &Authenticator, locks: &Locks) -> ApiResponse {
+    let target = request.url().to_string();
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let method = request.method().clone();
+    let credentials = authenticator.check_headers(&crate::get_path(), |name| header(request, name));
+    let result = credentials.and_then(|credentials| {
+        let content_type = header(request, "Content-Type").unwrap_or_default();
+        if let (Method::Post, ["files"], Some(boundary)) = (&method, segments.as_slice(), multipart::boundary(&content_type)) {
+            let (parts, body_hash) = read_multipart(request, &boundary)?;
+            return create_file_from_parts(&authenticator.check_body(credentials, method.as_str(), &target, &body_hash)?, parts);
+        }
+        let body = read_body(request)?;
+        let caller = authenticator.check_body(credentials, method.as_str(), &target, &content_hash(&body))?;
+        match (&method, segments.as_slice()) {
+            (_, ["dav", rest @ ..]) => webdav::handle(&caller, &method, rest, &|name| header(request, name), body, locks),
+            (Method::Get, ["files"]) => list_files(&caller, query),
+            (Method::Post, ["files"]) => create_file(&caller, parse_json(&body)?, None),
+            (Method::Get, ["files", id]) => parse_id(id).and_then(|id| checked_file(&caller, id, Action::View)).and_then(|file| json(200, &crate::get_file(file.id)?)),
+            (Method::Patch, ["files", id]) => parse_id(id).and_then(|id| update_file(&caller, id, &body)),
+            (Method::Delete, ["files", id]) => parse_id(id).and_then(|id| checked_file(&caller, id, Action::Remove)).and_then(|file| removal(crate::remove_file(file.id)?)),
+            (Method::Get, ["files", id, "content"]) => parse_id(id).and_then(|id| download_file(&caller, id)),
+            (Method::Put, ["files", id, "deletion-policy"]) => parse_id(id).and_then(|id| set_deletion_policy(&caller, id, &body)),
+            (Method::Post, ["files", id, "approvals"]) => parse_id(id).and_then(|id| approve_deletion(&caller, id, &body)),
+            (Method::Get, ["deletions"]) => list_deletions(&caller),
+            (Method::Post, ["files", id, "transfer"]) => parse_id(id).and_then(|id| propose_transfer(&caller, id, &body)),
+            (Method::Post, ["files", id, "transfer", "accept"]) => parse_id(id).and_then(|id| accept_transfer(&caller, id, &body)),
+            (Method::Delete, ["files", id, "transfer"]) => parse_id(id).and_then(|id| crate::withdraw_transfer(id, caller.identity.id)).map(|_| no_content()),
+            (Method::Get, ["transfers"]) => list_transfers(&caller),
+            (Method::Post, ["files", id, "access-requests"]) => parse_id(id).and_then(|id| request_access(&caller, id, &body)),
+            (Method::Get, ["access-requests"]) => list_access_requests(&caller),
+            (Method::Post, ["access-requests", id, answer @ ("approve" | "deny")]) => parse_id(id)
+                .and_then(|id| json(200, &crate::decide_access_request(id, caller.identity.id, *answer == "approve")?)),
+            (Method::Post, ["tokens"]) => issue_token(&caller, &body),
+            (Method::Delete, ["tokens"]) => revoke_token(&caller),
+            (_, ["files"] | ["files", _] | ["files", _, "content" | "deletion-policy" | "approvals" | "transfer" | "access-requests"] | ["files", _, "transfer", "accept"]
+                | ["deletions" | "transfers" | "access-requests"] | ["access-requests", _, "approve" | "deny"] | ["tokens"]) => Ok(error_response(405, "Method not allowed")),
+            _ => Ok(error_response(404, "No such resource")),
+        }
+    });
+    result.unwrap_or_else(|e| {
+        let response = error_response(status_code(&e), &e.to_string());
+        match e {
+            FileError::Unauthenticated(_) if segments.first() == Some(&"dav") => response.with_header(make_header("WWW-Authenticate", "Basic realm=\"UniChain\"")),
+            FileError::Unauthenticated(_) => response.with_header(make_header("WWW-Authenticate", "Bearer")),
+            _ => response,
+        }
+    })
+}
+
+/// The HTTP status that reports an error.
+pub fn status_code(error: &FileError) -> u16 {
+    match error {
+        FileError::InputError(_) | FileError::ParseError | FileError::InvalidFileType(_) | FileError::InvalidFileSize | FileError::DeserializationError(_) => 400,
+        FileError::Unauthenticated(_) => 401,
+        FileError::PermissionDenied => 403,
+        FileError::FileNotFound => 404,
+        FileError::FileAlreadyExists => 409,
+        FileError::PeerError(_) => 502,
+        FileError::RepositoryLocked(_) | FileError::PassphraseRequired | FileError::WrongPassphrase => 503,
+        FileError::IOError(_) | FileError::IdGenerationError(_) | FileError::DatabaseError(_) | FileError::IntegrityError(_) => 500,
+    }
+}
+
+fn list_files(caller: &Caller, query: &str) -> Result<ApiResponse, FileError> {
+    let params = parse_query(query);
+    let param = |key: &str| params.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str());
+    let mut files = match (param("owner"), param("name"), param("content_hash")) {
+        (Some(owner), _, _) => crate::get_files_by_owner(owner.parse().map_err(|_| FileError::ParseError)?)?,
+        (_, Some(name), _) => crate::get_files_by_name(name)?,
+        (_, _, Some(content_hash)) => crate::get_files_by_content_hash(content_hash)?,
+        _ => crate::get_all_files()?,
+    };
+    files.retain(|file| is_allowed(caller.identity.id, file, Action::View));
+    json(200, &files)
+}
+
+/// Looks a file up and checks that the caller may carry out `action` on it.
+fn checked_file(caller: &Caller, file_id: i64, action: Action) -> Result<File, FileError> {
+    let file = crate::find_file(file_id)?;
+    authorize(caller.identity.id, &file, action)?;
+    Ok(file)
+}
+
+fn create_file_from_parts(caller: &Caller, parts: Vec<Part>) -> Result<ApiResponse, FileError> {
+    let metadata = match parts.iter().find(|part| part.name == "metadata") {
+        Some(part) => parse_json(&part.data)?,
+        None => NewFile::default(),
+    };
+    create_file(caller, metadata, parts.into_iter().find(|part| part.name == "content"))
+}
+
+fn create_file(caller: &Caller, metadata: NewFile, upload: Option<Part>) -> Result<ApiResponse, FileError> {
+    let filename = upload.as_ref().and_then(|part| part.filename.clone());
+    let name = metadata.name.or(filename.clone()).ok_or_else(|| FileError::InputError("The file needs a name".to_string()))?;
+    let file_type = match metadata.file_type.as_deref() {
+        Some(file_type) => file_type.parse()?,
+        None => file_type_of(filename.as_deref().unwrap_or(&name)),
+    };
+    let content = upload.map(|part| part.data);
+    let owner = caller.identity.as_person();
+    let mut people_with_access = vec![owner.clone()];
+    people_with_access.extend(metadata.people_with_access.into_iter().filter(|person| person.0 != owner.0));
+    let file = File {
+        id: generate_id()?, name, file_type, size: content.as_ref().map_or(0, |content| content.len() as u64),
+        created: Utc::now().naive_utc(), modified: None, accessed: None, owner, people_with_access,
+        ipfs_hash: generate_fake_hash(46), onchain_txn_id: String::new(), download_permission: metadata.download_permission,
+        description: metadata.description, content_hash: None,
+    };
+    let file = crate::store_new_file(file, content)?;
+    Ok(json(201, &file)?.with_header(make_header("Location", &format!("/files/{}", file.id))))
+}
+
+fn update_file(caller: &Caller, file_id: i64, body: &[u8]) -> Result<ApiResponse, FileError> {
+    let update: FileUpdate = parse_json(body)?;
+    let mut file = checked_file(caller, file_id, Action::Modify)?;
+    if let Some(name) = update.name {
+        file.name = name;
+    }
+    if let Some(file_type) = update.file_type {
+        file.file_type = file_type.parse()?;
+    }
+    if update.description.is_some() {
+        file.description = update.description;
+    }
+    if let Some(download_permission) = update.download_permission {
+        file.download_permission = download_permission;
+    }
+    if let Some(people_with_access) = update.people_with_access {
+        file.people_with_access = vec![file.owner.clone()];
+        file.people_with_access.extend(people_with_access.into_iter().filter(|person| person.0 != file.owner.0));
+    }
+    let now = Utc::now().naive_utc();
+    file.modified = Some(now);
+    file.accessed = Some(now);
+    json(200, &crate::save_modified_file(file_id, file)?)
+}
+
+fn download_file(caller: &Caller, file_id: i64) -> Result<ApiResponse, FileError> {
+    checked_file(caller, file_id, Action::Download)?;
+    let file = crate::get_file(file_id)?;
+    let content = crate::read_file_content(file_id)?;
+    let disposition = format!("attachment; filename=\"{}\"", file.name.chars().map(|c| if c == ' ' || (c.is_ascii_graphic() && !matches!(c, '"' | '\\')) { c } else { '_' }).collect::<String>());
+    Ok(Response::from_data(content)
+        .with_header(make_header("Content-Type", "application/octet-stream"))
+        .with_header(make_header("Content-Disposition", &disposition)))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct DeletionPolicyRequest {
+    threshold: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct ApprovalRequest {
+    signature: String,
+}
+
+/// 204 when the file is gone, or 202 with the deletion still waiting for approvals.
+fn removal(removal: Removal) -> Result<ApiResponse, FileError> {
+    match removal {
+        Removal::Removed => Ok(no_content()),
+        Removal::Pending(pending) => json(202, &pending),
+    }
+}
+
+fn set_deletion_policy(caller: &Caller, file_id: i64, body: &[u8]) -> Result<ApiResponse, FileError> {
+    let request: DeletionPolicyRequest = parse_json(body)?;
+    let file = checked_file(caller, file_id, Action::Remove)?;
+    crate::set_deletion_threshold(file.id, request.threshold)?;
+    Ok(no_content())
+}
+
+fn approve_deletion(caller: &Caller, file_id: i64, body: &[u8]) -> Result<ApiResponse, FileError> {
+    let request: ApprovalRequest = parse_json(body)?;
+    let signature = hex::decode(request.signature.trim()).map_err(|_| FileError::InputError("The signature is not hex".to_string()))?;
+    checked_file(caller, file_id, Action::View)?;
+    match crate::submit_deletion_approval(file_id, caller.identity.id, &signature)? {
+        Removal::Pending(pending) => json(200, &pending),
+        removed => removal(removed),
+    }
+}
+
+fn list_deletions(caller: &Caller) -> Result<ApiResponse, FileError> {
+    let mut pending = crate::list_pending_deletions()?;
+    pending.retain(|deletion| crate::find_file(deletion.file_id).is_ok_and(|file| is_allowed(caller.identity.id, &file, Action::View)));
+    json(200, &pending)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct TransferRequest {
+    to: i64,
+    signature: String,
+}
+
+fn propose_transfer(caller: &Caller, file_id: i64, body: &[u8]) -> Result<ApiResponse, FileError> {
+    let request: TransferRequest = parse_json(body)?;
+    let signature = hex::decode(request.signature.trim()).map_err(|_| FileError::InputError("The signature is not hex".to_string()))?;
+    checked_file(caller, file_id, Action::View)?;
+    json(201, &crate::submit_transfer_proposal(file_id, caller.identity.id, request.to, &signature)?)
+}
+
+/// Lets the recipient take the file, which they may not be able to view until then.
+fn accept_transfer(caller: &Caller, file_id: i64, body: &[u8]) -> Result<ApiResponse, FileError> {
+    let request: ApprovalRequest = parse_json(body)?;
+    let signature = hex::decode(request.signature.trim()).map_err(|_| FileError::InputError("The signature is not hex".to_string()))?;
+    json(200, &crate::submit_transfer_acceptance(file_id, caller.identity.id, &signature)?)
+}
+
+/// The transfers the caller offered or was offered.
+fn list_transfers(caller: &Caller) -> Result<ApiResponse, FileError> {
+    let mut proposals = crate::list_transfer_proposals()?;
+    proposals.retain(|proposal| proposal.from == caller.identity.id || proposal.to == caller.identity.id);
+    json(200, &proposals)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct AccessRequestBody {
+    reason: String,
+}
+
+/// Asks for access to a file the caller cannot view yet.
+fn request_access(caller: &Caller, file_id: i64, body: &[u8]) -> Result<ApiResponse, FileError> {
+    let request: AccessRequestBody = parse_json(body)?;
+    json(201, &crate::request_access(file_id, caller.identity.as_person(), &request.reason)?)
+}
+
+/// The caller's own requests with their answers, and the pending requests for the files
+/// the caller owns.
+fn list_access_requests(caller: &Caller) -> Result<ApiResponse, FileError> {
+    let mut requests = crate::list_access_requests()?;
+    requests.retain(|request| request.requester.0 == caller.identity.id || (request.status == AccessStatus::Pending
+        && crate::find_file(request.file_id).is_ok_and(|file| file.owner.0 == caller.identity.id)));
+    json(200, &requests)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+struct TokenRequest {
+    ttl_seconds: Option<i64>,
+}
+
+fn issue_token(caller: &Caller, body: &[u8]) -> Result<ApiResponse, FileError> {
+    let request: TokenRequest = if body.is_empty() { TokenRequest::default() } else { parse_json(body)? };
+    let ttl = request.ttl_seconds.filter(|seconds| *seconds > 0).map(chrono::Duration::seconds);
+    let (token, record) = auth::issue_token(&crate::get_path(), caller.identity.id, ttl)?;
+    json(201, &serde_json::json!({ "token": token, "identity_id": record.identity_id, "expires": record.expires }))
+}
+
+fn revoke_token(caller: &Caller) -> Result<ApiResponse, FileError> {
+    let token = caller.token.as_deref().ok_or_else(|| FileError::InputError("Only a bearer token can be revoked this way".to_string()))?;
+    auth::revoke_token(&crate::get_path(), token)?;
+    Ok(no_content())
+}
+
+/// A new private file of the caller, typed by the extension of its name.
+fn new_file(caller: &Caller, name: String, size: usize) -> Result<File, FileError> {
+    let owner = caller.identity.as_person();
+    Ok(File {
+        id: generate_id()?, file_type: file_type_of(&name), name, size: size as u64, created: Utc::now().naive_utc(),
+        modified: None, accessed: None, owner: owner.clone(), people_with_access: vec![owner], ipfs_hash: generate_fake_hash(46),
+        onchain_txn_id: String::new(), download_permission: false, description: None, content_hash: None,
+    })
+}
+
+/// Names files by their name, or by `{id}~{name}` when another of `files` shares the name,
+/// for the interfaces that address files by path.
+fn unique_names(files: &[File]) -> Vec<(String, &File)> {
+    files.iter().map(|file| {
+        let shared = files.iter().filter(|other| other.name == file.name).count() > 1;
+        let name = if shared { format!("{}~{}", file.id, file.name) } else { file.name.clone() };
+        (name, file)
+    }).collect()
+}
+
+fn file_type_of(name: &str) -> FileType {
+    Path::new(name).extension().and_then(|extension| extension.to_str()).and_then(|extension| extension.parse().ok()).unwrap_or(FileType::Unknown)
+}
+
+fn parse_id(id: &str) -> Result<i64, FileError> {
+    id.parse().map_err(|_| FileError::ParseError)
+}
+
+fn parse_json<T: for<'de> Deserialize<'de>>(body: &[u8]) -> Result<T, FileError> {
+    serde_json::from_slice(body).map_err(|e| FileError::DeserializationError(format!("Invalid JSON body: {}", e)))
+}
+
+fn read_body(request: &mut Request) -> Result<Vec<u8>, FileError> {
+    if request.body_length().is_some_and(|len| len > MAX_BODY_LEN) {
+        return Err(FileError::InvalidFileSize);
+    }
+    let mut body = Vec::new();
+    request.as_reader().take(MAX_BODY_LEN as u64 + 1).read_to_end(&mut body)?;
+    if body.len() > MAX_BODY_LEN {
+        return Err(FileError::InvalidFileSize);
+    }
+    Ok(body)
+}
+
+/// Parses a `multipart/form-data` body as it arrives rather than reading it whole first.
+/// Returns its parts and the hash `content_hash` gives of the body, for checking a signature.
+fn read_multipart(request: &mut Request, boundary: &str) -> Result<(Vec<Part>, String), FileError> {
+    if request.body_length().is_some_and(|len| len > MAX_BODY_LEN) {
+        return Err(FileError::InvalidFileSize);
+    }
+    let mut body = HashingReader { inner: request.as_reader().take(MAX_BODY_LEN as u64 + 1), hasher: Sha256::new(), len: 0 };
+    let parts = multipart::parse(&mut body, boundary).and_then(|parts| {
+        io::copy(&mut body, &mut io::sink())?;
+        Ok(parts)
+    });
+    if body.len > MAX_BODY_LEN {
+        return Err(FileError::InvalidFileSize);
+    }
+    Ok((parts?, hex::encode(body.hasher.finalize())))
+}
+
+/// Hashes and counts what is read through it.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    len: usize,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.len += read;
+        Ok(read)
+    }
+}
+
+fn header(request: &Request, name: &'static str) -> Option<String> {
+    request.headers().iter().find(|header| header.field.equiv(name)).map(|header| header.value.as_str().to_string())
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query.split('&').filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .map(|(key, value)| (percent_decode(key, true), percent_decode(value, true)))
+        .collect()
+}
+
+/// Decodes `%XX` escapes, and `+` as a space when `plus_as_space` is set as in query strings.
+fn no_content() -> ApiResponse {
+    Response::from_data(Vec::new()).with_status_code(204)
+}
+
+fn json<T: Serialize>(status: u16, value: &T) -> Result<ApiResponse, FileError> {
+    let body = serde_json::to_vec(value).map_err(|e| FileError::DeserializationError(e.to_string()))?;
+    Ok(Response::from_data(body).with_status_code(StatusCode(status)).with_header(make_header("Content-Type", "application/json")))
+}
+
+fn error_response(status: u16, message: &str) -> ApiResponse {
+    let body = serde_json::json!({ "error": message }).to_string().into_bytes();
+    Response::from_data(body).with_status_code(StatusCode(status)).with_header(make_header("Content-Type", "application/json"))
+}
+
+fn make_header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("Header names and values are ASCII")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    use tempfile::tempdir;
+
+    use crate::identity::{IdentityRegistry, LocalIdentity};
+    use crate::testing::AssetsPath;
+
+    /// Sends one request and returns the status, the headers and the body of the response.
+    pub(super) fn send(addr: SocketAddr, method: &str, target: &str, headers: &[(&str, &str)], body: &[u8]) -> (u16, String, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n", method, target, body.len());
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&response[..split]).into_owned();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, head, response[split + 4..].to_vec())
+    }
+
+    pub(super) fn start_api() -> ApiServer {
+        ApiServer::start(&ApiConfig { listen: "127.0.0.1:0".to_string(), workers: 2 }).unwrap()
+    }
+
+    /// Registers an identity and gets a bearer token for it with a signed request.
+    pub(super) fn log_in(api: &ApiServer, id: i64, email: &str) -> String {
+        let identity = LocalIdentity::generate(id, "Caller", email);
+        IdentityRegistry::open(&crate::get_path()).unwrap().register(&identity.public).unwrap();
+        let signed = auth::sign_request(&identity, "POST", "/tokens", b"");
+        let headers: Vec<(&str, &str)> = signed.iter().map(|(name, value)| (*name, value.as_str())).collect();
+        let (status, _, body) = send(api.local_addr(), "POST", "/tokens", &headers, b"");
+        assert_eq!(status, 201, "{}", String::from_utf8_lossy(&body));
+        let issued: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        format!("Bearer {}", issued["token"].as_str().unwrap())
+    }
+
+    #[test]
+    fn test_upload_download_update_and_delete() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let api = start_api();
+        let bearer = log_in(&api, 5, "owner@gmail.com");
+        let auth = ("Authorization", bearer.as_str());
+
+        let body = b"--b0und\r\nContent-Disposition: form-data; name=\"metadata\"\r\n\r\n{\"description\":\"Q3\"}\r\n\
+--b0und\r\nContent-Disposition: form-data; name=\"content\"; filename=\"report.pdf\"\r\n\r\n%PDF-1.4 report\r\n--b0und--\r\n";
+        let (status, head, created) = send(api.local_addr(), "POST", "/files", &[auth, ("Content-Type", "multipart/form-data; boundary=b0und")], body);
+        assert_eq!(status, 201, "{}", String::from_utf8_lossy(&created));
+        let created: File = serde_json::from_slice(&created).unwrap();
+        assert_eq!((created.name.as_str(), &created.file_type, created.size, created.owner.0), ("report.pdf", &FileType::Pdf, 15, 5));
+        assert!(head.contains(&format!("/files/{}", created.id)));
+
+        let (status, _, content) = send(api.local_addr(), "GET", &format!("/files/{}/content", created.id), &[auth], b"");
+        assert_eq!((status, content), (200, b"%PDF-1.4 report".to_vec()));
+        let (status, _, listed) = send(api.local_addr(), "GET", "/files?name=report.pdf", &[auth], b"");
+        assert_eq!(status, 200);
+        assert_eq!(serde_json::from_slice::<Vec<File>>(&listed).unwrap().len(), 1);
+
+        let (status, _, updated) = send(api.local_addr(), "PATCH", &format!("/files/{}", created.id), &[auth, ("Content-Type", "application/json")], br#"{"name":"final.pdf","download_permission":true}"#);
+        assert_eq!(status, 200);
+        let updated: File = serde_json::from_slice(&updated).unwrap();
+        assert_eq!((updated.name.as_str(), updated.download_permission, updated.description.as_deref()), ("final.pdf", true, Some("Q3")));
+        let (status, _, updated) = send(api.local_addr(), "PATCH", &format!("/files/{}", created.id), &[auth], br#"{"people_with_access":[[6,"Guest","guest@gmail.com"]]}"#);
+        assert_eq!(status, 200);
+        let updated: File = serde_json::from_slice(&updated).unwrap();
+        assert_eq!(updated.people_with_access.iter().map(|person| person.0).collect::<Vec<_>>(), vec![5, 6]);
+
+        assert_eq!(send(api.local_addr(), "DELETE", &format!("/files/{}", created.id), &[auth], b"").0, 204);
+        assert_eq!(send(api.local_addr(), "GET", &format!("/files/{}", created.id), &[auth], b"").0, 404);
+    }
+
+    #[test]
+    fn test_callers_get_the_permission_checks_of_the_cli() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let api = start_api();
+        let owner = log_in(&api, 5, "owner@gmail.com");
+        let guest = log_in(&api, 6, "guest@gmail.com");
+        let stranger = log_in(&api, 7, "stranger@gmail.com");
+
+        assert_eq!(send(api.local_addr(), "GET", "/files", &[], b"").0, 401);
+        assert_eq!(send(api.local_addr(), "GET", "/files", &[("Authorization", "Bearer 00")], b"").0, 401);
+        let metadata = br#"{"name":"notes.txt","people_with_access":[[6,"Guest","guest@gmail.com"]]}"#;
+        let (status, _, created) = send(api.local_addr(), "POST", "/files", &[("Authorization", &owner), ("Content-Type", "application/json")], metadata);
+        assert_eq!(status, 201);
+        let file: File = serde_json::from_slice(&created).unwrap();
+        let target = format!("/files/{}", file.id);
+
+        assert_eq!(send(api.local_addr(), "GET", &target, &[("Authorization", &guest)], b"").0, 200);
+        assert_eq!(send(api.local_addr(), "GET", &format!("{}/content", target), &[("Authorization", &guest)], b"").0, 403);
+        assert_eq!(send(api.local_addr(), "PATCH", &target, &[("Authorization", &guest)], b"{}").0, 403);
+        assert_eq!(send(api.local_addr(), "DELETE", &target, &[("Authorization", &guest)], b"").0, 403);
+        assert_eq!(send(api.local_addr(), "GET", &target, &[("Authorization", &stranger)], b"").0, 403);
+        let (_, _, listed) = send(api.local_addr(), "GET", "/files", &[("Authorization", &stranger)], b"");
+        assert!(serde_json::from_slice::<Vec<File>>(&listed).unwrap().is_empty());
+
+        assert_eq!(send(api.local_addr(), "DELETE", "/tokens", &[("Authorization", &stranger)], b"").0, 204);
+        assert_eq!(send(api.local_addr(), "GET", "/files", &[("Authorization", &stranger)], b"").0, 401);
+    }
+
+    #[test]
+    fn test_errors_map_to_status_codes() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let api = start_api();
+        let bearer = log_in(&api, 5, "owner@gmail.com");
+        let auth = ("Authorization", bearer.as_str());
+        assert_eq!(send(api.local_addr(), "GET", "/files/not-a-number", &[auth], b"").0, 400);
+        assert_eq!(send(api.local_addr(), "DELETE", "/files/42", &[auth], b"").0, 404);
+        assert_eq!(send(api.local_addr(), "POST", "/files", &[auth, ("Content-Type", "application/json")], b"{").0, 400);
+        assert_eq!(send(api.local_addr(), "PUT", "/files", &[auth], b"").0, 405);
+        assert_eq!(send(api.local_addr(), "GET", "/nowhere", &[auth], b"").0, 404);
+        assert_eq!(status_code(&FileError::RepositoryLocked(String::new())), 503);
+    }
+
+    #[test]
+    fn test_headers_authenticate_before_the_body_is_read() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let api = start_api();
+
+        let mut stream = TcpStream::connect(api.local_addr()).unwrap();
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+        let head = format!("POST /files HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n--b\r\n", MAX_BODY_LEN);
+        stream.write_all(head.as_bytes()).unwrap();
+        let mut response = [0u8; 12];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"HTTP/1.1 401");
+
+        let identity = LocalIdentity::generate(5, "Caller", "owner@gmail.com");
+        IdentityRegistry::open(&crate::get_path()).unwrap().register(&identity.public).unwrap();
+        let body = b"--b\r\nContent-Disposition: form-data; name=\"content\"; filename=\"notes.txt\"\r\n\r\nsigned\r\n--b--\r\n";
+        let signed = auth::sign_request(&identity, "POST", "/files", body);
+        let mut headers: Vec<(&str, &str)> = signed.iter().map(|(name, value)| (*name, value.as_str())).collect();
+        headers.push(("Content-Type", "multipart/form-data; boundary=b"));
+        let (status, _, created) = send(api.local_addr(), "POST", "/files", &headers, body);
+        assert_eq!(status, 201, "{}", String::from_utf8_lossy(&created));
+        assert_eq!(serde_json::from_slice::<File>(&created).unwrap().owner.0, 5);
+        let tampered = b"--b\r\nContent-Disposition: form-data; name=\"content\"; filename=\"notes.txt\"\r\n\r\nforged\r\n--b--\r\n";
+        assert_eq!(send(api.local_addr(), "POST", "/files", &headers, tampered).0, 401);
+    }
+
+    #[test]
+    fn test_concurrent_writes_wait_for_each_other() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let api = ApiServer::start(&ApiConfig { listen: "127.0.0.1:0".to_string(), workers: 4 }).unwrap();
+        let bearer = log_in(&api, 5, "owner@gmail.com");
+
+        let writers: Vec<_> = (0..8).map(|n| {
+            let (addr, bearer) = (api.local_addr(), bearer.clone());
+            thread::spawn(move || {
+                let metadata = format!("{{\"name\":\"notes-{}.txt\"}}", n);
+                send(addr, "POST", "/files", &[("Authorization", &bearer), ("Content-Type", "application/json")], metadata.as_bytes()).0
+            })
+        }).collect();
+        let statuses: Vec<u16> = writers.into_iter().map(|writer| writer.join().unwrap()).collect();
+        assert_eq!(statuses, vec![201; 8]);
+        assert_eq!(crate::load_files_from_file(&crate::get_path()).unwrap().len(), 8);
+    }
+}
@@ -0,0 +1,163 @@
+use std::io::Read;
+
+use crate::model::FileError;
+
+/// One part of a `multipart/form-data` body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Returns the boundary of a `multipart/form-data` content type, or `None` for any other type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';').map(str::trim);
+    if !params.next()?.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Longest preamble or set of part headers accepted, which keeps a body without the
+/// expected line breaks from being buffered whole.
+const MAX_HEADERS_LEN: usize = 16 * 1024;
+
+/// Splits a `multipart/form-data` body into its parts as it is read, so that the body is
+/// never held in memory besides the parts taken from it.
+pub fn parse(body: impl Read, boundary: &str) -> Result<Vec<Part>, FileError> {
+    let malformed = |reason: &str| FileError::InputError(format!("Malformed multipart body: {}", reason));
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let closing = [b"\r\n".as_slice(), &delimiter].concat();
+    let mut body = Stream { reader: body, buffer: Vec::new() };
+    body.take_until(&delimiter, MAX_HEADERS_LEN)?.ok_or_else(|| malformed("missing boundary"))?;
+    let mut parts = Vec::new();
+    loop {
+        if body.starts_with(b"--")? {
+            return Ok(parts);
+        }
+        if !body.starts_with(b"\r\n")? {
+            return Err(malformed("boundary not followed by a line break"));
+        }
+        body.buffer.drain(..2);
+        let headers = body.take_until(b"\r\n\r\n", MAX_HEADERS_LEN)?.ok_or_else(|| malformed("unterminated part headers"))?;
+        let headers = String::from_utf8_lossy(&headers).into_owned();
+        let data = body.take_until(&closing, usize::MAX)?.ok_or_else(|| malformed("unterminated part"))?;
+        parts.push(parse_part(&headers, data).ok_or_else(|| malformed("part without a name"))?);
+    }
+}
+
+/// A body read in chunks, of which `buffer` holds what was read but not taken yet.
+struct Stream<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> Stream<R> {
+    /// Takes what comes before `needle` and drops the needle, or returns `None` when the
+    /// body ends first. Fails once more than `limit` bytes come before the needle.
+    fn take_until(&mut self, needle: &[u8], limit: usize) -> Result<Option<Vec<u8>>, FileError> {
+        let mut searched = 0;
+        loop {
+            if let Some(position) = find(&self.buffer, needle, searched) {
+                let rest = self.buffer.split_off(position + needle.len());
+                self.buffer.truncate(position);
+                return Ok(Some(std::mem::replace(&mut self.buffer, rest)));
+            }
+            searched = (self.buffer.len() + 1).saturating_sub(needle.len());
+            if searched > limit {
+                return Err(FileError::InputError("Malformed multipart body: headers too long".to_string()));
+            }
+            if !self.fill()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn starts_with(&mut self, prefix: &[u8]) -> Result<bool, FileError> {
+        while self.buffer.len() < prefix.len() {
+            if !self.fill()? {
+                break;
+            }
+        }
+        Ok(self.buffer.starts_with(prefix))
+    }
+
+    /// Reads the next chunk into the buffer. Returns false at the end of the body.
+    fn fill(&mut self) -> Result<bool, FileError> {
+        let mut chunk = [0u8; 8192];
+        let read = self.reader.read(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(read > 0)
+    }
+}
+
+fn parse_part(headers: &str, data: Vec<u8>) -> Option<Part> {
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+    for line in headers.split("\r\n") {
+        let Some((header, value)) = line.split_once(':') else { continue };
+        if header.trim().eq_ignore_ascii_case("content-disposition") {
+            for param in value.split(';').skip(1) {
+                match param.trim().split_once('=') {
+                    Some(("name", value)) => name = Some(value.trim_matches('"').to_string()),
+                    Some(("filename", value)) => filename = Some(value.trim_matches('"').to_string()),
+                    _ => {},
+                }
+            }
+        } else if header.trim().eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+    Some(Part { name: name?, filename, content_type, data })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack.get(from..)?.windows(needle.len()).position(|window| window == needle).map(|offset| from + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_form_with_file_and_field() {
+        let content_type = "multipart/form-data; boundary=\"XyZ\"";
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"metadata\"\r\n\r\n{\"name\":\"a\"}\r\n\
+--XyZ\r\nContent-Disposition: form-data; name=\"content\"; filename=\"report.pdf\"\r\nContent-Type: application/pdf\r\n\r\n%PDF\r\n--X\r\n--XyZ--\r\n";
+        let parts = parse(&body[..], &boundary(content_type).unwrap()).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0], Part { name: "metadata".to_string(), filename: None, content_type: None, data: b"{\"name\":\"a\"}".to_vec() });
+        assert_eq!(parts[1].filename.as_deref(), Some("report.pdf"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("application/pdf"));
+        assert_eq!(parts[1].data, b"%PDF\r\n--X".to_vec());
+    }
+
+    #[test]
+    fn test_rejects_other_content_types_and_truncated_bodies() {
+        assert_eq!(boundary("application/json"), None);
+        assert!(parse(&b"--b\r\nContent-Disposition: form-data; name=\"x\"\r\n\r\nno end"[..], "b").is_err());
+    }
+
+    #[test]
+    fn test_parts_split_across_reads() {
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let read = self.0.len().min(buf.len()).min(3);
+                buf[..read].copy_from_slice(&self.0[..read]);
+                self.0 = &self.0[read..];
+                Ok(read)
+            }
+        }
+        let body = b"--b\r\nContent-Disposition: form-data; name=\"content\"\r\n\r\n0123456789\r\n-\r\n--b--\r\n";
+        let parts = parse(Trickle(body), "b").unwrap();
+        assert_eq!((parts.len(), parts[0].data.as_slice()), (1, b"0123456789\r\n-".as_slice()));
+        assert!(parse(Trickle(&[b'x'; 2 * MAX_HEADERS_LEN]), "b").is_err());
+    }
+}
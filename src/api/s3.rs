@@ -0,0 +1,609 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use chrono::{NaiveDateTime, Utc};
+use log::{info, warn};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tiny_http::{Request, Response, Server, StatusCode};
+
+use super::auth::Caller;
+use super::sigv4;
+use super::{make_header, new_file, parse_query, read_body, status_code, unique_names, ApiResponse, DEFAULT_WORKERS, MAX_BODY_LEN};
+use crate::catalog::content_hash;
+use crate::identity::{IdentityRegistry, PublicIdentity};
+use crate::model::{File, FileError};
+use crate::net::percent_decode;
+use crate::net::sigv4::uri_encode;
+use crate::net::xml::{elements, escape_xml, unescape_xml};
+use crate::permissions::deletion::Removal;
+use crate::permissions::{authorize, is_allowed, Action};
+
+/// Most keys returned by one `ListObjectsV2`.
+pub const MAX_KEYS: usize = 1000;
+
+/// Buckets are named after the identity that owns their files, as `owner-{id}`.
+pub const BUCKET_PREFIX: &str = "owner-";
+
+const MAX_PART_NUMBER: u32 = 10000;
+const XMLNS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3Config {
+    pub listen: String,
+    pub workers: usize,
+}
+
+impl S3Config {
+    /// Reads the S3 endpoint settings from `UNICHAIN_S3_LISTEN` and `UNICHAIN_S3_WORKERS`.
+    /// Returns `None` when no listen address is set, in which case no S3 endpoint is served.
+    pub fn from_env() -> Option<Self> {
+        let listen = env::var("UNICHAIN_S3_LISTEN").ok().filter(|listen| !listen.trim().is_empty())?;
+        let workers = env::var("UNICHAIN_S3_WORKERS").ok()
+            .and_then(|workers| workers.trim().parse().ok())
+            .filter(|workers| *workers > 0)
+            .unwrap_or(DEFAULT_WORKERS);
+        Some(S3Config { listen: listen.trim().to_string(), workers })
+    }
+}
+
+/// Serves a subset of the S3 API over the catalog, authenticated with SigV4. Each identity
+/// has a bucket holding the files it owns, keyed by file name. The server stops when it is
+/// dropped.
+pub struct S3Server {
+    server: Arc<Server>,
+    local_addr: SocketAddr,
+}
+
+impl S3Server {
+    pub fn start(config: &S3Config) -> Result<Self, FileError> {
+        let catalog_path = crate::get_path();
+        if !catalog_path.exists() {
+            OpenOptions::new().create(true).truncate(false).write(true).open(&catalog_path)?;
+        }
+        let server = Server::http(&config.listen).map_err(|e| FileError::InputError(format!("Cannot serve S3 on {}: {}", config.listen, e)))?;
+        let local_addr = server.server_addr().to_ip().ok_or_else(|| FileError::InputError(format!("{} is not an IP address", config.listen)))?;
+        let server = Arc::new(server);
+        for _ in 0..config.workers.max(1) {
+            let server = server.clone();
+            thread::spawn(move || {
+                while let Ok(mut request) = server.recv() {
+                    let response = handle(&mut request);
+                    if let Err(e) = request.respond(response) {
+                        warn!("Failed to answer an S3 request: {}", e);
+                    }
+                }
+            });
+        }
+        info!("S3 endpoint listening on {}.", local_addr);
+        Ok(S3Server { server, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for S3Server {
+    fn drop(&mut self) {
+        for _ in 0..Arc::strong_count(&self.server) {
+            self.server.unblock();
+        }
+    }
+}
+
+/// An error in the form S3 clients expect.
+#[derive(Debug, Clone, PartialEq)]
+struct S3Error {
+    status: u16,
+    code: &'static str,
+    message: String,
+}
+
+impl S3Error {
+    fn new(status: u16, code: &'static str, message: impl Into<String>) -> Self {
+        S3Error { status, code, message: message.into() }
+    }
+
+    fn response(&self, resource: &str) -> ApiResponse {
+        xml(self.status, format!("<Error><Code>{}</Code><Message>{}</Message><Resource>{}</Resource></Error>",
+            self.code, escape_xml(&self.message), escape_xml(resource)))
+    }
+}
+
+impl From<FileError> for S3Error {
+    fn from(error: FileError) -> Self {
+        let status = status_code(&error);
+        let code = match (&error, status) {
+            (FileError::FileNotFound, _) => "NoSuchKey",
+            (FileError::InvalidFileSize, _) => "EntityTooLarge",
+            (_, 400) => "InvalidArgument",
+            (_, 401 | 403) => "AccessDenied",
+            (_, 503) => "ServiceUnavailable",
+            _ => "InternalError",
+        };
+        S3Error::new(if status == 401 { 403 } else { status }, code, error.to_string())
+    }
+}
+
+type S3Result = Result<ApiResponse, S3Error>;
+
+/// A multipart upload in progress. Its parts are kept next to it until it completes.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct Upload {
+    identity_id: i64,
+    bucket: String,
+    key: String,
+}
+
+fn handle(request: &mut Request) -> ApiResponse {
+    let target = request.url().to_string();
+    let path = target.split_once('?').map_or(target.as_str(), |(path, _)| path).to_string();
+    route(request, &target).unwrap_or_else(|e| e.response(&path))
+}
+
+/// Authenticates a request and routes it to the operation it names.
+fn route(request: &mut Request, target: &str) -> S3Result {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let method = request.method().as_str().to_uppercase();
+    let headers: Vec<(String, String)> = request.headers().iter()
+        .map(|header| (header.field.as_str().as_str().to_lowercase(), header.value.as_str().to_string()))
+        .collect();
+    let header = |name: &str| headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.trim().to_string());
+    let signed = sigv4::verify_headers(&crate::get_path(), &method, target, &headers)?;
+    let body = signed.verify_body(read_body(request)?)?;
+    let caller = Caller { identity: signed.identity().clone(), token: None };
+    let params = parse_query(query);
+    let param = |key: &str| params.iter().find(|(name, _)| name == key).map(|(_, value)| value.clone());
+    let (bucket, key) = locate(path, header("host").as_deref());
+    let Some(bucket) = bucket else {
+        return match method.as_str() {
+            "GET" => list_buckets(&caller),
+            _ => Err(not_implemented(&method)),
+        };
+    };
+    let owner = bucket_owner(&bucket)?;
+    let Some(key) = key else {
+        return match method.as_str() {
+            "HEAD" => Ok(empty(200)),
+            "PUT" if owner.id == caller.identity.id => Ok(empty(200).with_header(make_header("Location", &format!("/{}", bucket)))),
+            "PUT" => Err(S3Error::new(409, "BucketAlreadyExists", "Buckets belong to the identity they are named after")),
+            "GET" => list_objects(&caller, &bucket, &owner, param),
+            "POST" if param("delete").is_some() => delete_objects(&caller, &owner, &body),
+            _ => Err(not_implemented(&method)),
+        };
+    };
+    match (method.as_str(), param("uploadId"), param("partNumber")) {
+        ("POST", None, _) if param("uploads").is_some() => create_multipart_upload(&caller, &bucket, &owner, &key),
+        ("POST", Some(upload_id), _) => complete_multipart_upload(&caller, &bucket, &owner, &key, &upload_id, &body),
+        ("PUT", Some(upload_id), Some(part_number)) => upload_part(&caller, &bucket, &key, &upload_id, &part_number, &body),
+        ("DELETE", Some(upload_id), _) => abort_multipart_upload(&caller, &bucket, &key, &upload_id),
+        ("PUT", None, _) if header("x-amz-copy-source").is_some() => Err(not_implemented("CopyObject")),
+        ("PUT", None, _) => put_object(&caller, &owner, &key, body).map(|file| empty(200).with_header(make_header("ETag", &etag(&file)))),
+        ("GET", None, _) => get_object(&caller, &owner, &key, header("range").as_deref()),
+        ("HEAD", None, _) => head_object(&caller, &owner, &key),
+        ("DELETE", None, _) => delete_object(&caller, &owner, &key).map(|_| empty(204)),
+        _ => Err(not_implemented(&method)),
+    }
+}
+
+/// The bucket and key a request addresses, by path or by a virtual host named after the bucket.
+fn locate(path: &str, host: Option<&str>) -> (Option<String>, Option<String>) {
+    let path = path.trim_start_matches('/');
+    let host_bucket = host.and_then(|host| host.split_once('.')).map(|(label, _)| label).filter(|label| label.starts_with(BUCKET_PREFIX));
+    let (bucket, key) = match host_bucket {
+        Some(bucket) => (bucket, path),
+        None => path.split_once('/').unwrap_or((path, "")),
+    };
+    ((!bucket.is_empty()).then(|| bucket.to_string()), (!key.is_empty()).then(|| percent_decode(key, false)))
+}
+
+fn bucket_owner(bucket: &str) -> Result<PublicIdentity, S3Error> {
+    let no_such_bucket = || S3Error::new(404, "NoSuchBucket", format!("There is no bucket {}", bucket));
+    let owner_id: i64 = bucket.strip_prefix(BUCKET_PREFIX).and_then(|id| id.parse().ok()).ok_or_else(no_such_bucket)?;
+    IdentityRegistry::open(&crate::get_path())?.get(owner_id)?.ok_or_else(no_such_bucket)
+}
+
+/// The files of `owner` the caller may see, keyed and sorted by object key.
+fn objects(caller: &Caller, owner: &PublicIdentity) -> Result<Vec<(String, File)>, FileError> {
+    let mut files = crate::get_files_by_owner(owner.id)?;
+    files.retain(|file| is_allowed(caller.identity.id, file, Action::View));
+    let mut objects: Vec<(String, File)> = unique_names(&files).into_iter().map(|(key, file)| (key, file.clone())).collect();
+    objects.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(objects)
+}
+
+fn find_object(caller: &Caller, owner: &PublicIdentity, key: &str) -> Result<Option<File>, FileError> {
+    Ok(objects(caller, owner)?.into_iter().find(|(object, _)| object == key).map(|(_, file)| file))
+}
+
+fn list_buckets(caller: &Caller) -> S3Result {
+    let mut files = crate::get_all_files()?;
+    files.retain(|file| is_allowed(caller.identity.id, file, Action::View));
+    let mut buckets: BTreeMap<i64, NaiveDateTime> = BTreeMap::from([(caller.identity.id, Utc::now().naive_utc())]);
+    for file in &files {
+        let created = buckets.entry(file.owner.0).or_insert(file.created);
+        *created = (*created).min(file.created);
+    }
+    let buckets: String = buckets.iter().map(|(owner, created)| {
+        format!("<Bucket><Name>{}{}</Name><CreationDate>{}</CreationDate></Bucket>", BUCKET_PREFIX, owner, iso_date(*created))
+    }).collect();
+    Ok(xml(200, format!("<ListAllMyBucketsResult xmlns=\"{}\"><Owner><ID>{}</ID><DisplayName>{}</DisplayName></Owner><Buckets>{}</Buckets></ListAllMyBucketsResult>",
+        XMLNS, caller.identity.id, escape_xml(&caller.identity.name), buckets)))
+}
+
+/// Lists a bucket as `ListObjectsV2`, folding keys into common prefixes at `delimiter`.
+fn list_objects(caller: &Caller, bucket: &str, owner: &PublicIdentity, param: impl Fn(&str) -> Option<String>) -> S3Result {
+    let prefix = param("prefix").unwrap_or_default();
+    let delimiter = param("delimiter").filter(|delimiter| !delimiter.is_empty());
+    let max_keys = param("max-keys").and_then(|max_keys| max_keys.parse().ok()).unwrap_or(MAX_KEYS).min(MAX_KEYS);
+    let url_encoded = param("encoding-type").as_deref() == Some("url");
+    let after = match param("continuation-token") {
+        Some(token) => String::from_utf8(hex::decode(token).map_err(|_| S3Error::new(400, "InvalidArgument", "Invalid continuation token"))?)
+            .map_err(|_| S3Error::new(400, "InvalidArgument", "Invalid continuation token"))?,
+        None => param("start-after").unwrap_or_default(),
+    };
+    let mut entries: Vec<(String, Option<File>)> = Vec::new();
+    for (key, file) in objects(caller, owner)?.into_iter().filter(|(key, _)| key.starts_with(&prefix)) {
+        let folded = delimiter.as_ref().and_then(|delimiter| key[prefix.len()..].find(delimiter.as_str()).map(|at| key[..prefix.len() + at + delimiter.len()].to_string()));
+        match folded {
+            Some(common) if entries.last().is_some_and(|(last, file)| file.is_none() && *last == common) => {},
+            Some(common) => entries.push((common, None)),
+            None => entries.push((key, Some(file))),
+        }
+    }
+    entries.retain(|(key, _)| *key > after);
+    let truncated = entries.len() > max_keys;
+    entries.truncate(max_keys);
+    let encode = |key: &str| if url_encoded { key.split('/').map(uri_encode).collect::<Vec<_>>().join("/") } else { escape_xml(key) };
+    let mut body = format!("<ListBucketResult xmlns=\"{}\"><Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>",
+        XMLNS, bucket, encode(&prefix), entries.len(), max_keys, truncated);
+    if let Some(delimiter) = &delimiter {
+        body.push_str(&format!("<Delimiter>{}</Delimiter>", encode(delimiter)));
+    }
+    if url_encoded {
+        body.push_str("<EncodingType>url</EncodingType>");
+    }
+    if let (true, Some((last, _))) = (truncated, entries.last()) {
+        body.push_str(&format!("<NextContinuationToken>{}</NextContinuationToken>", hex::encode(last)));
+    }
+    for (key, file) in &entries {
+        body.push_str(&match file {
+            Some(file) => format!("<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+                encode(key), iso_date(last_modified(file)), escape_xml(&etag(file)), file.size),
+            None => format!("<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", encode(key)),
+        });
+    }
+    body.push_str("</ListBucketResult>");
+    Ok(xml(200, body))
+}
+
+/// Stores an object in the caller's own bucket, replacing the content of a file of the same key.
+fn put_object(caller: &Caller, owner: &PublicIdentity, key: &str, body: Vec<u8>) -> Result<File, S3Error> {
+    if owner.id != caller.identity.id {
+        return Err(FileError::PermissionDenied.into());
+    }
+    Ok(match find_object(caller, owner, key)? {
+        Some(file) => {
+            authorize(caller.identity.id, &file, Action::Modify)?;
+            crate::save_file_content(file.id, body)?
+        },
+        None => crate::store_new_file(new_file(caller, key.to_string(), body.len())?, Some(body))?,
+    })
+}
+
+fn get_object(caller: &Caller, owner: &PublicIdentity, key: &str, range: Option<&str>) -> S3Result {
+    let file = find_object(caller, owner, key)?.ok_or(FileError::FileNotFound)?;
+    authorize(caller.identity.id, &file, Action::Download)?;
+    let file = crate::get_file(file.id)?;
+    let content = match crate::read_file_content(file.id) {
+        Ok(content) => content,
+        Err(FileError::FileNotFound) => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let (status, content, content_range) = match range.and_then(|range| range.strip_prefix("bytes=")) {
+        Some(range) => {
+            let (start, end) = byte_range(range, content.len()).ok_or_else(|| S3Error::new(416, "InvalidRange", "The requested range is not satisfiable"))?;
+            (206, content[start..=end].to_vec(), Some(format!("bytes {}-{}/{}", start, end, content.len())))
+        },
+        None => (200, content, None),
+    };
+    let mut response = object_headers(Response::from_data(content).with_status_code(StatusCode(status)), &file);
+    if let Some(content_range) = content_range {
+        response = response.with_header(make_header("Content-Range", &content_range));
+    }
+    Ok(response)
+}
+
+fn head_object(caller: &Caller, owner: &PublicIdentity, key: &str) -> S3Result {
+    let file = find_object(caller, owner, key)?.ok_or(FileError::FileNotFound)?;
+    authorize(caller.identity.id, &file, Action::View)?;
+    let response = Response::new(StatusCode(200), Vec::new(), Cursor::new(Vec::new()), Some(file.size as usize), None);
+    Ok(object_headers(response, &file))
+}
+
+/// Deletes an object. Deleting a key that does not exist succeeds, as in S3.
+fn delete_object(caller: &Caller, owner: &PublicIdentity, key: &str) -> Result<(), S3Error> {
+    let Some(file) = find_object(caller, owner, key)? else { return Ok(()) };
+    authorize(caller.identity.id, &file, Action::Remove)?;
+    match crate::remove_file(file.id)? {
+        Removal::Removed => Ok(()),
+        Removal::Pending(pending) => Err(S3Error::new(409, "OperationAborted",
+            format!("Deleting this object needs {} approvals; it waits for them until {}", pending.threshold, pending.expires))),
+    }
+}
+
+fn delete_objects(caller: &Caller, owner: &PublicIdentity, body: &[u8]) -> S3Result {
+    let body = String::from_utf8_lossy(body);
+    let quiet = elements(&body, "Quiet").first().is_some_and(|quiet| quiet.trim() == "true");
+    let mut result = format!("<DeleteResult xmlns=\"{}\">", XMLNS);
+    for key in elements(&body, "Key").into_iter().map(|key| unescape_xml(&key)) {
+        match delete_object(caller, owner, &key) {
+            Ok(()) if quiet => {},
+            Ok(()) => result.push_str(&format!("<Deleted><Key>{}</Key></Deleted>", escape_xml(&key))),
+            Err(e) => result.push_str(&format!("<Error><Key>{}</Key><Code>{}</Code><Message>{}</Message></Error>", escape_xml(&key), e.code, escape_xml(&e.message))),
+        }
+    }
+    result.push_str("</DeleteResult>");
+    Ok(xml(200, result))
+}
+
+fn create_multipart_upload(caller: &Caller, bucket: &str, owner: &PublicIdentity, key: &str) -> S3Result {
+    if owner.id != caller.identity.id {
+        return Err(FileError::PermissionDenied.into());
+    }
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    let upload_id = hex::encode(id);
+    let path = crate::get_path();
+    let dir = get_upload_dir(&path).join(&upload_id);
+    fs::create_dir_all(&dir).map_err(FileError::IOError)?;
+    let upload = Upload { identity_id: caller.identity.id, bucket: bucket.to_string(), key: key.to_string() };
+    let record = serde_json::to_vec(&upload).map_err(|e| FileError::DeserializationError(e.to_string()))?;
+    fs::write(dir.join("upload"), crate::crypto::seal(&path, &record)?).map_err(FileError::IOError)?;
+    Ok(xml(200, format!("<InitiateMultipartUploadResult xmlns=\"{}\"><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+        XMLNS, bucket, escape_xml(key), upload_id)))
+}
+
+fn upload_part(caller: &Caller, bucket: &str, key: &str, upload_id: &str, part_number: &str, body: &[u8]) -> S3Result {
+    let dir = open_upload(caller, bucket, key, upload_id)?;
+    let part_number: u32 = part_number.parse().ok().filter(|number| (1..=MAX_PART_NUMBER).contains(number))
+        .ok_or_else(|| S3Error::new(400, "InvalidArgument", format!("Part numbers go from 1 to {}", MAX_PART_NUMBER)))?;
+    fs::write(dir.join(format!("part-{:05}", part_number)), crate::crypto::seal(&crate::get_path(), body)?).map_err(FileError::IOError)?;
+    Ok(empty(200).with_header(make_header("ETag", &format!("\"{}\"", content_hash(body)))))
+}
+
+/// Joins the listed parts, which must come in ascending order with the ETags they were
+/// uploaded with, into the object.
+fn complete_multipart_upload(caller: &Caller, bucket: &str, owner: &PublicIdentity, key: &str, upload_id: &str, body: &[u8]) -> S3Result {
+    let dir = open_upload(caller, bucket, key, upload_id)?;
+    let invalid_part = |message: &str| S3Error::new(400, "InvalidPart", message);
+    let request = String::from_utf8_lossy(body);
+    let mut content = Vec::new();
+    let mut previous = 0;
+    for part in elements(&request, "Part") {
+        let number: u32 = elements(&part, "PartNumber").first().and_then(|number| number.trim().parse().ok()).ok_or_else(|| invalid_part("A part has no number"))?;
+        let etag = elements(&part, "ETag").first().map(|etag| unescape_xml(etag).trim().trim_matches('"').to_string()).unwrap_or_default();
+        if number <= previous {
+            return Err(S3Error::new(400, "InvalidPartOrder", "Parts must be listed in ascending order"));
+        }
+        previous = number;
+        let sealed = fs::read(dir.join(format!("part-{:05}", number))).map_err(|_| invalid_part("A listed part was not uploaded"))?;
+        let data = crate::crypto::open(&crate::get_path(), &sealed)?;
+        if content_hash(&data) != etag {
+            return Err(invalid_part("A part does not match its ETag"));
+        }
+        if content.len() + data.len() > MAX_BODY_LEN {
+            return Err(FileError::InvalidFileSize.into());
+        }
+        content.extend_from_slice(&data);
+    }
+    if previous == 0 {
+        return Err(S3Error::new(400, "MalformedXML", "List the parts to join"));
+    }
+    let file = put_object(caller, owner, key, content)?;
+    fs::remove_dir_all(&dir).map_err(FileError::IOError)?;
+    Ok(xml(200, format!("<CompleteMultipartUploadResult xmlns=\"{}\"><Location>/{}/{}</Location><Bucket>{}</Bucket><Key>{}</Key><ETag>{}</ETag></CompleteMultipartUploadResult>",
+        XMLNS, bucket, escape_xml(&uri_encode(key)), bucket, escape_xml(key), escape_xml(&etag(&file)))))
+}
+
+fn abort_multipart_upload(caller: &Caller, bucket: &str, key: &str, upload_id: &str) -> S3Result {
+    let dir = open_upload(caller, bucket, key, upload_id)?;
+    fs::remove_dir_all(dir).map_err(FileError::IOError)?;
+    Ok(empty(204))
+}
+
+/// The directory of an upload the caller started for this bucket and key.
+fn open_upload(caller: &Caller, bucket: &str, key: &str, upload_id: &str) -> Result<PathBuf, S3Error> {
+    let no_such_upload = || S3Error::new(404, "NoSuchUpload", format!("There is no upload {}", upload_id));
+    if upload_id.is_empty() || !upload_id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(no_such_upload());
+    }
+    let path = crate::get_path();
+    let dir = get_upload_dir(&path).join(upload_id);
+    let sealed = fs::read(dir.join("upload")).map_err(|_| no_such_upload())?;
+    let upload: Upload = serde_json::from_slice(&crate::crypto::open(&path, &sealed)?).map_err(|e| FileError::DeserializationError(e.to_string()))?;
+    if upload != (Upload { identity_id: caller.identity.id, bucket: bucket.to_string(), key: key.to_string() }) {
+        return Err(no_such_upload());
+    }
+    Ok(dir)
+}
+
+/// Where the parts of multipart uploads wait until the upload completes or is aborted.
+pub fn get_upload_dir(catalog_path: &Path) -> PathBuf {
+    let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".uploads");
+    catalog_path.with_file_name(name)
+}
+
+/// Parses a single `first-last`, `first-` or `-suffix` range into inclusive offsets.
+fn byte_range(range: &str, len: usize) -> Option<(usize, usize)> {
+    let (start, end) = range.trim().split_once('-')?;
+    let (start, end) = match (start.parse::<usize>().ok(), end.parse::<usize>().ok()) {
+        (Some(start), Some(end)) => (start, end.min(len.checked_sub(1)?)),
+        (Some(start), None) if end.is_empty() => (start, len.checked_sub(1)?),
+        (None, Some(suffix)) if start.is_empty() && suffix > 0 => (len.saturating_sub(suffix), len.checked_sub(1)?),
+        _ => return None,
+    };
+    (start <= end).then_some((start, end))
+}
+
+fn object_headers(response: ApiResponse, file: &File) -> ApiResponse {
+    response.with_header(make_header("Content-Type", "application/octet-stream"))
+        .with_header(make_header("ETag", &etag(file)))
+        .with_header(make_header("Last-Modified", &last_modified(file).and_utc().format("%a, %d %b %Y %H:%M:%S GMT").to_string()))
+        .with_header(make_header("Accept-Ranges", "bytes"))
+}
+
+/// The content hash of a file. It is not an MD5 digest, so clients do not compare it with one.
+fn etag(file: &File) -> String {
+    format!("\"{}\"", file.content_hash.clone().unwrap_or_else(|| content_hash(b"")))
+}
+
+fn last_modified(file: &File) -> NaiveDateTime {
+    file.modified.unwrap_or(file.created)
+}
+
+fn iso_date(date: NaiveDateTime) -> String {
+    date.and_utc().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+fn not_implemented(operation: &str) -> S3Error {
+    S3Error::new(501, "NotImplemented", format!("{} is not supported", operation))
+}
+
+fn xml(status: u16, body: String) -> ApiResponse {
+    Response::from_data(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}", body).into_bytes())
+        .with_status_code(StatusCode(status))
+        .with_header(make_header("Content-Type", "application/xml"))
+}
+
+fn empty(status: u16) -> ApiResponse {
+    Response::from_data(Vec::new()).with_status_code(StatusCode(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use tempfile::tempdir;
+
+    use super::super::auth::S3Credentials;
+    use super::super::tests::send;
+    use crate::identity::LocalIdentity;
+    use crate::net::sigv4::sign_request;
+    use crate::testing::AssetsPath;
+
+    fn start_s3() -> S3Server {
+        S3Server::start(&S3Config { listen: "127.0.0.1:0".to_string(), workers: 2 }).unwrap()
+    }
+
+    fn register(id: i64, email: &str) -> S3Credentials {
+        let identity = LocalIdentity::generate(id, "Caller", email);
+        IdentityRegistry::open(&crate::get_path()).unwrap().register(&identity.public).unwrap();
+        crate::issue_s3_credentials(id).unwrap()
+    }
+
+    /// Sends a request signed the way an S3 client signs it.
+    fn call(s3: &S3Server, credentials: &S3Credentials, method: &str, target: &str, extra: &[(&str, &str)], body: &[u8]) -> (u16, String, String) {
+        let mut headers = vec![("host".to_string(), "localhost".to_string())];
+        headers.extend(extra.iter().map(|(name, value)| (name.to_string(), value.to_string())));
+        let signed = sign_request(&credentials.access_key_id, &credentials.secret_access_key, "us-east-1", method, target, &headers, body);
+        let sent: Vec<(&str, &str)> = signed.iter().filter(|(name, _)| name != "host").map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        let (status, head, body) = send(s3.local_addr(), method, target, &sent, body);
+        (status, head, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[test]
+    fn test_put_list_get_and_delete_objects() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let s3 = start_s3();
+        let owner = register(5, "owner@gmail.com");
+        let stranger = register(6, "stranger@gmail.com");
+
+        assert_eq!(call(&s3, &owner, "PUT", "/owner-5/reports/q3%20final.txt", &[], b"content").0, 200);
+        assert_eq!(call(&s3, &owner, "PUT", "/owner-5/notes.txt", &[], b"notes").0, 200);
+        let (status, _, listing) = call(&s3, &owner, "GET", "/owner-5?list-type=2&delimiter=%2F", &[], b"");
+        assert_eq!(status, 200);
+        assert!(listing.contains("<Key>notes.txt</Key>") && listing.contains("<Prefix>reports/</Prefix>") && !listing.contains("q3"));
+        let (_, _, page) = call(&s3, &owner, "GET", "/owner-5?list-type=2&max-keys=1", &[], b"");
+        assert!(page.contains("<Key>notes.txt</Key>") && page.contains("<IsTruncated>true</IsTruncated>"));
+        let token = elements(&page, "NextContinuationToken").remove(0);
+        let (_, _, next) = call(&s3, &owner, "GET", &format!("/owner-5?list-type=2&continuation-token={}", token), &[], b"");
+        assert!(next.contains("<Key>reports/q3 final.txt</Key>") && next.contains("<IsTruncated>false</IsTruncated>"));
+
+        assert_eq!(call(&s3, &owner, "GET", "/owner-5/reports/q3%20final.txt", &[], b"").2, "content");
+        let (status, head, partial) = call(&s3, &owner, "GET", "/owner-5/reports/q3%20final.txt", &[("range", "bytes=1-3")], b"");
+        assert_eq!((status, partial.as_str()), (206, "ont"));
+        assert!(head.contains("Content-Range: bytes 1-3/7"));
+        let (status, head, _) = call(&s3, &owner, "HEAD", "/owner-5/notes.txt", &[], b"");
+        assert!(status == 200 && head.contains("Content-Length: 5"));
+
+        assert_eq!(call(&s3, &stranger, "PUT", "/owner-5/intruder.txt", &[], b"x").0, 403);
+        assert_eq!(call(&s3, &stranger, "GET", "/owner-5/notes.txt", &[], b"").0, 404);
+        assert_eq!(call(&s3, &owner, "GET", "/owner-9?list-type=2", &[], b"").0, 404);
+        let forged = S3Credentials { secret_access_key: "0".repeat(40), ..owner.clone() };
+        assert_eq!(call(&s3, &forged, "GET", "/owner-5/notes.txt", &[], b"").0, 403);
+
+        assert_eq!(call(&s3, &owner, "DELETE", "/owner-5/notes.txt", &[], b"").0, 204);
+        assert_eq!(call(&s3, &owner, "GET", "/owner-5/notes.txt", &[], b"").0, 404);
+    }
+
+    #[test]
+    fn test_multipart_upload_joins_parts_in_order() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let s3 = start_s3();
+        let owner = register(5, "owner@gmail.com");
+
+        let (status, _, started) = call(&s3, &owner, "POST", "/owner-5/big.bin?uploads", &[], b"");
+        assert_eq!(status, 200);
+        let upload_id = elements(&started, "UploadId").remove(0);
+        let mut etags = Vec::new();
+        for (number, part) in [(1, "first "), (2, "second")] {
+            let (status, head, _) = call(&s3, &owner, "PUT", &format!("/owner-5/big.bin?partNumber={}&uploadId={}", number, upload_id), &[], part.as_bytes());
+            assert_eq!(status, 200);
+            etags.push(head.lines().find_map(|line| line.strip_prefix("ETag: ")).unwrap().to_string());
+        }
+        let complete = |parts: &[(u32, &String)]| parts.iter().map(|(number, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", number, etag)).collect::<String>();
+        let target = format!("/owner-5/big.bin?uploadId={}", upload_id);
+        let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", complete(&[(2, &etags[1]), (1, &etags[0])]));
+        assert_eq!(call(&s3, &owner, "POST", &target, &[], body.as_bytes()).0, 400);
+        let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", complete(&[(1, &etags[0]), (2, &etags[1])]));
+        assert_eq!(call(&s3, &owner, "POST", &target, &[], body.as_bytes()).0, 200);
+        assert_eq!(call(&s3, &owner, "GET", "/owner-5/big.bin", &[], b"").2, "first second");
+        assert_eq!(call(&s3, &owner, "POST", &target, &[], body.as_bytes()).0, 404);
+        assert!(fs::read_dir(get_upload_dir(&crate::get_path())).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_signatures_are_checked_before_the_body_is_read() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let s3 = start_s3();
+        let owner = register(5, "owner@gmail.com");
+
+        let mut stream = TcpStream::connect(s3.local_addr()).unwrap();
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+        let head = format!("PUT /owner-5/big.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n", MAX_BODY_LEN);
+        stream.write_all(head.as_bytes()).unwrap();
+        let mut response = [0u8; 12];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"HTTP/1.1 403");
+
+        let signed = sign_request(&owner.access_key_id, &owner.secret_access_key, "us-east-1", "PUT", "/owner-5/notes.txt", &[("host".to_string(), "localhost".to_string())], b"signed");
+        let sent: Vec<(&str, &str)> = signed.iter().filter(|(name, _)| name != "host").map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        assert_eq!(send(s3.local_addr(), "PUT", "/owner-5/notes.txt", &sent, b"forged").0, 403);
+        assert_eq!(send(s3.local_addr(), "PUT", "/owner-5/notes.txt", &sent, b"signed").0, 200);
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::Path;
+
+use chrono::{NaiveDateTime, Utc};
+
+use super::auth::s3_credentials;
+use crate::catalog::content_hash;
+use crate::identity::{IdentityRegistry, PublicIdentity};
+use crate::model::FileError;
+use crate::net::sigv4::{canonical_request, signing_key, string_to_sign, verify_hmac, ALGORITHM, DATE_FORMAT, STREAMING_PAYLOAD, STREAMING_UNSIGNED_PAYLOAD, UNSIGNED_PAYLOAD};
+
+/// How far `x-amz-date` may be from the server clock, in seconds.
+pub const MAX_CLOCK_SKEW_SECONDS: i64 = 900;
+
+/// The parts of a SigV4 `Authorization` header.
+#[derive(Debug, Clone, PartialEq)]
+struct Authorization {
+    access_key_id: String,
+    scope: String,
+    date: String,
+    region: String,
+    signed_headers: Vec<String>,
+    signature: String,
+}
+
+/// A request whose `Authorization` header, date and canonical-request signature have been
+/// checked, waiting for its body. The signature covers the declared `x-amz-content-sha256`,
+/// so the body only has to match it.
+pub struct SignedRequest {
+    identity: PublicIdentity,
+    key: Vec<u8>,
+    amz_date: String,
+    scope: String,
+    signature: String,
+    payload_hash: String,
+    decoded_length: Option<String>,
+}
+
+/// Checks the SigV4 signature of a request. `headers` holds every header with its name in
+/// lower case. Returns the identity the access key belongs to and the body, with any
+/// `aws-chunked` framing removed and each chunk signature checked.
+pub fn verify(catalog_path: &Path, method: &str, target: &str, headers: &[(String, String)], body: Vec<u8>) -> Result<(PublicIdentity, Vec<u8>), FileError> {
+    let signed = verify_headers(catalog_path, method, target, headers)?;
+    let body = signed.verify_body(body)?;
+    Ok((signed.identity, body))
+}
+
+/// Checks everything in the signature of a request but its body, so that a request that
+/// is not signed is turned away before the body is read.
+pub fn verify_headers(catalog_path: &Path, method: &str, target: &str, headers: &[(String, String)]) -> Result<SignedRequest, FileError> {
+    let header = |name: &str| headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.trim().to_string());
+    let authorization = header("authorization").ok_or_else(|| unauthenticated("Send a SigV4 Authorization header"))
+        .and_then(|authorization| parse_authorization(&authorization))?;
+    let credentials = s3_credentials(catalog_path).list()?.into_iter()
+        .find(|credentials| credentials.access_key_id == authorization.access_key_id)
+        .ok_or_else(|| unauthenticated("Unknown access key"))?;
+    let amz_date = header("x-amz-date").ok_or_else(|| unauthenticated("Send x-amz-date"))?;
+    let signed_at = NaiveDateTime::parse_from_str(&amz_date, DATE_FORMAT).map_err(|_| unauthenticated("Invalid x-amz-date"))?;
+    if (Utc::now().naive_utc() - signed_at).num_seconds().abs() > MAX_CLOCK_SKEW_SECONDS {
+        return Err(unauthenticated("The request time is too far from the server clock"));
+    }
+    if !amz_date.starts_with(&authorization.date) || !authorization.signed_headers.iter().any(|name| name == "host") {
+        return Err(unauthenticated("The credential scope or signed headers are invalid"));
+    }
+    let payload_hash = header("x-amz-content-sha256").ok_or_else(|| unauthenticated("Send x-amz-content-sha256"))?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let canonical = canonical_request(method, path, query, headers, &authorization.signed_headers, &payload_hash);
+    let key = signing_key(&credentials.secret_access_key, &authorization.date, &authorization.region);
+    if !verify_hmac(&key, string_to_sign(&amz_date, &authorization.scope, &canonical).as_bytes(), &authorization.signature) {
+        return Err(unauthenticated("The request signature does not match"));
+    }
+    let identity = IdentityRegistry::open(catalog_path)?.get(credentials.identity_id)?
+        .ok_or_else(|| unauthenticated(&format!("Identity {} is no longer registered", credentials.identity_id)))?;
+    Ok(SignedRequest {
+        identity, key, amz_date, scope: authorization.scope, signature: authorization.signature, payload_hash,
+        decoded_length: header("x-amz-decoded-content-length"),
+    })
+}
+
+impl SignedRequest {
+    pub fn identity(&self) -> &PublicIdentity {
+        &self.identity
+    }
+
+    /// Checks the body against the signed `x-amz-content-sha256` and returns it with any
+    /// `aws-chunked` framing removed and each chunk signature checked.
+    pub fn verify_body(&self, body: Vec<u8>) -> Result<Vec<u8>, FileError> {
+        let body = match self.payload_hash.as_str() {
+            UNSIGNED_PAYLOAD => body,
+            STREAMING_PAYLOAD => decode_chunks(&body, Some((&self.key, &self.amz_date, &self.scope, &self.signature)))?,
+            STREAMING_UNSIGNED_PAYLOAD => decode_chunks(&body, None)?,
+            hash if hash == content_hash(&body) => body,
+            _ => return Err(unauthenticated("The body does not match x-amz-content-sha256")),
+        };
+        if self.decoded_length.as_ref().is_some_and(|len| *len != body.len().to_string()) {
+            return Err(FileError::InputError("The body does not match x-amz-decoded-content-length".to_string()));
+        }
+        Ok(body)
+    }
+}
+
+fn parse_authorization(authorization: &str) -> Result<Authorization, FileError> {
+    let params = authorization.strip_prefix(ALGORITHM).ok_or_else(|| unauthenticated("Only AWS4-HMAC-SHA256 signatures are accepted"))?;
+    let param = |key: &str| params.split(',').filter_map(|param| param.trim().split_once('='))
+        .find(|(name, _)| *name == key).map(|(_, value)| value.trim().to_string());
+    let (Some(credential), Some(signed_headers), Some(signature)) = (param("Credential"), param("SignedHeaders"), param("Signature")) else {
+        return Err(unauthenticated("The Authorization header is incomplete"));
+    };
+    let [access_key_id, date, region, "s3", "aws4_request"] = credential.split('/').collect::<Vec<_>>()[..] else {
+        return Err(unauthenticated("The credential scope is invalid"));
+    };
+    Ok(Authorization {
+        access_key_id: access_key_id.to_string(), scope: format!("{}/{}/s3/aws4_request", date, region), date: date.to_string(),
+        region: region.to_string(), signed_headers: signed_headers.split(';').map(str::to_string).collect(), signature,
+    })
+}
+
+/// Removes `aws-chunked` framing. With `signing`, each chunk must carry the signature that
+/// chains from the signature of the request.
+fn decode_chunks(body: &[u8], signing: Option<(&[u8], &str, &str, &str)>) -> Result<Vec<u8>, FileError> {
+    let malformed = || FileError::InputError("Malformed aws-chunked body".to_string());
+    let mut previous = signing.map(|(_, _, _, signature)| signature.to_string());
+    let mut decoded = Vec::new();
+    let mut position = 0;
+    loop {
+        let line_end = body[position..].windows(2).position(|window| window == b"\r\n").ok_or_else(malformed)? + position;
+        let line = std::str::from_utf8(&body[position..line_end]).map_err(|_| malformed())?;
+        let (size, extension) = line.split_once(';').unwrap_or((line, ""));
+        let size = usize::from_str_radix(size.trim(), 16).map_err(|_| malformed())?;
+        let start = line_end + 2;
+        let chunk = body.get(start..start + size).ok_or_else(malformed)?;
+        if let (Some((key, amz_date, scope, _)), Some(previous)) = (signing, previous.as_mut()) {
+            let signature = extension.trim().strip_prefix("chunk-signature=").ok_or_else(malformed)?;
+            let string_to_sign = format!("{}-PAYLOAD\n{}\n{}\n{}\n{}\n{}", ALGORITHM, amz_date, scope, previous, content_hash(b""), content_hash(chunk));
+            if !verify_hmac(key, string_to_sign.as_bytes(), signature) {
+                return Err(unauthenticated("A chunk signature does not match"));
+            }
+            *previous = signature.to_string();
+        }
+        if size == 0 {
+            return Ok(decoded);
+        }
+        decoded.extend_from_slice(chunk);
+        position = start + size;
+        if body.get(position..position + 2) != Some(b"\r\n") {
+            return Err(malformed());
+        }
+        position += 2;
+    }
+}
+
+fn unauthenticated(reason: &str) -> FileError {
+    FileError::Unauthenticated(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use crate::identity::LocalIdentity;
+    use crate::net::sigv4::{hmac, sign_request};
+
+    #[test]
+    fn test_signatures_verify_only_unchanged_and_chunks_chain() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("assets");
+        let caller = LocalIdentity::generate(5, "Guest", "guest@gmail.com");
+        IdentityRegistry::open(&path).unwrap().register(&caller.public).unwrap();
+        let credentials = super::super::auth::issue_s3_credentials(&path, 5).unwrap();
+        let host = vec![("host".to_string(), "localhost:9000".to_string())];
+        let headers = sign_request(&credentials.access_key_id, &credentials.secret_access_key, "us-east-1", "PUT", "/owner-5/a%20b.txt?x-id=PutObject", &host, b"body");
+
+        let (identity, body) = verify(&path, "PUT", "/owner-5/a%20b.txt?x-id=PutObject", &headers, b"body".to_vec()).unwrap();
+        assert_eq!((identity.id, body), (5, b"body".to_vec()));
+        assert!(verify(&path, "PUT", "/owner-5/a%20b.txt?x-id=PutObject", &headers, b"tampered".to_vec()).is_err());
+        assert!(verify(&path, "GET", "/owner-5/a%20b.txt?x-id=PutObject", &headers, b"body".to_vec()).is_err());
+        assert!(verify(&path, "PUT", "/owner-5/c.txt?x-id=PutObject", &headers, b"body".to_vec()).is_err());
+
+        let key = signing_key(&credentials.secret_access_key, "20240101", "us-east-1");
+        let scope = "20240101/us-east-1/s3/aws4_request";
+        let chunk_signature = |previous: &str, chunk: &[u8]| hex::encode(hmac(&key, format!("{}-PAYLOAD\n20240101T000000Z\n{}\n{}\n{}\n{}", ALGORITHM, scope, previous, content_hash(b""), content_hash(chunk)).as_bytes()));
+        let first = chunk_signature("seed", b"hello");
+        let last = chunk_signature(&first, b"");
+        let body = format!("5;chunk-signature={}\r\nhello\r\n0;chunk-signature={}\r\n\r\n", first, last);
+        let signing = Some((key.as_slice(), "20240101T000000Z", scope, "seed"));
+        assert_eq!(decode_chunks(body.as_bytes(), signing).unwrap(), b"hello".to_vec());
+        assert!(decode_chunks(body.replace("hello", "jello").as_bytes(), signing).is_err());
+        assert_eq!(decode_chunks(b"5\r\nhello\r\n0\r\nx-amz-checksum-crc32:AAAA\r\n\r\n", None).unwrap(), b"hello".to_vec());
+    }
+}
@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDateTime, Utc};
+use rand::RngCore;
+use tiny_http::{Method, Response, StatusCode};
+
+use super::auth::Caller;
+use super::{checked_file, make_header, new_file, no_content, unique_names, ApiResponse};
+use crate::model::{File, FileError};
+use crate::net::percent_decode;
+use crate::net::xml::escape_xml;
+use crate::permissions::deletion::{self, Removal};
+use crate::permissions::{authorize, is_allowed, Action};
+
+/// Where the catalog is mounted in the API.
+pub const MOUNT: &str = "/dav/";
+
+/// Longest a lock is held without being refreshed.
+pub const MAX_LOCK_SECONDS: u64 = 3600;
+
+const ALLOWED_METHODS: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, DELETE, MOVE, LOCK, UNLOCK";
+
+/// Exclusive write locks taken by WebDAV clients, keyed by what they lock. Locks live only
+/// in memory and lapse when the server stops.
+#[derive(Default)]
+pub struct Locks {
+    held: Mutex<HashMap<LockKey, Lock>>,
+}
+
+/// What a lock holds: a file, whatever name each caller sees it by, or a name no file has
+/// yet, reserved for the file a later PUT creates.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum LockKey {
+    File(i64),
+    Unmapped(String),
+}
+
+struct Lock {
+    token: String,
+    expires: Instant,
+}
+
+impl Locks {
+    /// Whether a request may write `key`: it is not locked, or the request names the lock
+    /// token in its `If` header.
+    fn may_write(&self, key: &LockKey, if_header: Option<&str>) -> bool {
+        let mut held = self.held.lock().unwrap();
+        held.retain(|_, lock| lock.expires > Instant::now());
+        held.get(key).is_none_or(|lock| if_header.is_some_and(|condition| condition.contains(&lock.token)))
+    }
+
+    /// Takes or refreshes the lock on `key`. Returns the token, or `None` when someone
+    /// else holds the lock.
+    fn lock(&self, key: &LockKey, if_header: Option<&str>, timeout: Duration) -> Option<String> {
+        if !self.may_write(key, if_header) {
+            return None;
+        }
+        let mut held = self.held.lock().unwrap();
+        let token = match held.get(key) {
+            Some(lock) => lock.token.clone(),
+            None => {
+                let mut secret = [0u8; 16];
+                rand::thread_rng().fill_bytes(&mut secret);
+                format!("opaquelocktoken:{}", hex::encode(secret))
+            },
+        };
+        held.insert(key.clone(), Lock { token: token.clone(), expires: Instant::now() + timeout });
+        Some(token)
+    }
+
+    fn unlock(&self, key: &LockKey, token: &str) -> bool {
+        let mut held = self.held.lock().unwrap();
+        match held.get(key) {
+            Some(lock) if token.contains(&lock.token) => held.remove(key).is_some(),
+            _ => false,
+        }
+    }
+
+    fn release(&self, key: &LockKey) {
+        self.held.lock().unwrap().remove(key);
+    }
+
+    /// Moves the lock reserving `name` onto the file created there.
+    fn adopt(&self, name: &str, file_id: i64) {
+        let mut held = self.held.lock().unwrap();
+        if let Some(lock) = held.remove(&LockKey::Unmapped(name.to_string())) {
+            held.insert(LockKey::File(file_id), lock);
+        }
+    }
+}
+
+/// Serves the files the caller can see as a single WebDAV collection at [`MOUNT`], named
+/// by file name. Files that share a name with another visible file are named
+/// `{id}~{name}` instead. `rest` is the request path after the mount, still encoded.
+pub(super) fn handle(caller: &Caller, method: &Method, rest: &[&str], header: &dyn Fn(&'static str) -> Option<String>, body: Vec<u8>, locks: &Locks) -> Result<ApiResponse, FileError> {
+    let name = match rest {
+        [] => None,
+        [name] => Some(percent_decode(name, false)),
+        _ => return Ok(status(404)),
+    };
+    let if_header = header("If");
+    match (method.as_str(), name) {
+        ("OPTIONS", _) => Ok(status(200).with_header(make_header("DAV", "1, 2")).with_header(make_header("Allow", ALLOWED_METHODS)).with_header(make_header("MS-Author-Via", "DAV"))),
+        ("PROPFIND", None) => {
+            let files = visible_files(caller)?;
+            let mut responses = vec![collection_response()];
+            if header("Depth").as_deref().map(str::trim) != Some("0") {
+                responses.extend(unique_names(&files).into_iter().map(|(name, file)| file_response(&name, file)));
+            }
+            Ok(multistatus(&responses))
+        },
+        ("PROPFIND", Some(name)) => {
+            let file = resolve(caller, &name)?.ok_or(FileError::FileNotFound)?;
+            Ok(multistatus(&[file_response(&name, &file)]))
+        },
+        ("GET" | "HEAD", Some(name)) => {
+            let file = resolve(caller, &name)?.ok_or(FileError::FileNotFound)?;
+            authorize(caller.identity.id, &file, Action::Download)?;
+            let file = crate::get_file(file.id)?;
+            let content = match crate::read_file_content(file.id) {
+                Ok(content) => content,
+                Err(FileError::FileNotFound) => Vec::new(),
+                Err(e) => return Err(e),
+            };
+            Ok(Response::from_data(content)
+                .with_header(make_header("Content-Type", "application/octet-stream"))
+                .with_header(make_header("ETag", &etag(&file)))
+                .with_header(make_header("Last-Modified", &http_date(last_modified(&file)))))
+        },
+        ("PUT", Some(name)) => {
+            let file = resolve(caller, &name)?;
+            if !locks.may_write(&lock_key(&name, file.as_ref()), if_header.as_deref()) {
+                return Ok(status(423));
+            }
+            match file {
+                Some(file) => {
+                    checked_file(caller, file.id, Action::Modify)?;
+                    crate::save_file_content(file.id, body)?;
+                    Ok(no_content())
+                },
+                None => {
+                    let file = crate::store_new_file(new_file(caller, name.clone(), body.len())?, Some(body))?;
+                    locks.adopt(&name, file.id);
+                    Ok(status(201))
+                },
+            }
+        },
+        ("DELETE", Some(name)) => {
+            let file = resolve(caller, &name)?.ok_or(FileError::FileNotFound)?;
+            let key = LockKey::File(file.id);
+            if !locks.may_write(&key, if_header.as_deref()) {
+                return Ok(status(423));
+            }
+            authorize(caller.identity.id, &file, Action::Remove)?;
+            if let Removal::Pending(_) = crate::remove_file(file.id)? {
+                return Ok(status(202));
+            }
+            locks.release(&key);
+            Ok(no_content())
+        },
+        ("MOVE", Some(name)) => {
+            let destination = header("Destination").and_then(|destination| destination_name(&destination))
+                .ok_or_else(|| FileError::InputError(format!("MOVE needs a Destination inside {}", MOUNT)))?;
+            let overwrite = header("Overwrite").is_none_or(|overwrite| !overwrite.trim().eq_ignore_ascii_case("F"));
+            move_file(caller, &name, &destination, overwrite, if_header.as_deref(), locks)
+        },
+        ("LOCK", Some(name)) => {
+            let timeout = header("Timeout").and_then(|timeout| timeout.trim().strip_prefix("Second-").and_then(|seconds| seconds.parse().ok()))
+                .unwrap_or(MAX_LOCK_SECONDS).min(MAX_LOCK_SECONDS);
+            let file = resolve(caller, &name)?;
+            if file.as_ref().is_some_and(|file| !is_allowed(caller.identity.id, file, Action::Modify)) {
+                return Err(FileError::PermissionDenied);
+            }
+            let Some(token) = locks.lock(&lock_key(&name, file.as_ref()), if_header.as_deref(), Duration::from_secs(timeout)) else {
+                return Ok(status(423));
+            };
+            let body = format!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+<D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope><D:depth>0</D:depth>\
+<D:timeout>Second-{}</D:timeout><D:locktoken><D:href>{}</D:href></D:locktoken>\
+<D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock></D:lockdiscovery></D:prop>",
+                timeout, token, href(&name));
+            Ok(xml(200, body).with_header(make_header("Lock-Token", &format!("<{}>", token))))
+        },
+        ("UNLOCK", Some(name)) => {
+            let token = header("Lock-Token").unwrap_or_default();
+            let key = lock_key(&name, resolve(caller, &name)?.as_ref());
+            Ok(if locks.unlock(&key, &token) { no_content() } else { status(409) })
+        },
+        ("GET" | "HEAD" | "PUT" | "DELETE" | "MOVE" | "LOCK" | "UNLOCK", _) => Ok(status(405).with_header(make_header("Allow", ALLOWED_METHODS))),
+        _ => Ok(status(501)),
+    }
+}
+
+/// Renames a file, replacing a file of the destination name when `overwrite` allows it.
+fn move_file(caller: &Caller, name: &str, destination: &str, overwrite: bool, if_header: Option<&str>, locks: &Locks) -> Result<ApiResponse, FileError> {
+    let mut file = resolve(caller, name)?.ok_or(FileError::FileNotFound)?;
+    if name == destination {
+        return Err(FileError::PermissionDenied);
+    }
+    let key = LockKey::File(file.id);
+    let replaced = resolve(caller, destination)?;
+    if !locks.may_write(&key, if_header) || !locks.may_write(&lock_key(destination, replaced.as_ref()), if_header) {
+        return Ok(status(423));
+    }
+    authorize(caller.identity.id, &file, Action::Modify)?;
+    if let Some(replaced) = &replaced {
+        if !overwrite {
+            return Ok(status(412));
+        }
+        authorize(caller.identity.id, replaced, Action::Remove)?;
+        // A file that needs approvals to go cannot be replaced in one step, and asking
+        // would leave a deletion pending behind a failed MOVE.
+        if deletion::threshold(&crate::get_path(), replaced.id)? > 1 {
+            return Ok(status(423));
+        }
+        if let Removal::Pending(_) = crate::remove_file(replaced.id)? {
+            return Ok(status(423));
+        }
+    }
+    let now = Utc::now().naive_utc();
+    file.name = destination.to_string();
+    file.modified = Some(now);
+    file.accessed = Some(now);
+    crate::save_modified_file(file.id, file)?;
+    locks.release(&key);
+    Ok(if replaced.is_some() { no_content() } else { status(201) })
+}
+
+fn visible_files(caller: &Caller) -> Result<Vec<File>, FileError> {
+    let mut files = crate::get_all_files()?;
+    files.retain(|file| is_allowed(caller.identity.id, file, Action::View));
+    files.sort_by_key(|file| file.id);
+    Ok(files)
+}
+
+/// What a lock on the resource `name` holds, given the file it stands for.
+fn lock_key(name: &str, file: Option<&File>) -> LockKey {
+    match file {
+        Some(file) => LockKey::File(file.id),
+        None => LockKey::Unmapped(name.to_string()),
+    }
+}
+
+/// The visible file a resource name stands for, if any.
+fn resolve(caller: &Caller, name: &str) -> Result<Option<File>, FileError> {
+    let files = visible_files(caller)?;
+    Ok(unique_names(&files).into_iter().find(|(resource, _)| resource == name).map(|(_, file)| file.clone()))
+}
+
+/// The resource name in a `Destination` header, which may be an absolute URL.
+fn destination_name(destination: &str) -> Option<String> {
+    let path = match destination.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => destination,
+    };
+    let name = path.strip_prefix(MOUNT)?.trim_end_matches('/');
+    (!name.is_empty() && !name.contains('/')).then(|| percent_decode(name, false))
+}
+
+fn collection_response() -> String {
+    format!("<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>UniChain</D:displayname>\
+<D:resourcetype><D:collection/></D:resourcetype>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        MOUNT, supported_lock())
+}
+
+fn file_response(name: &str, file: &File) -> String {
+    format!("<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname><D:resourcetype/>\
+<D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>application/octet-stream</D:getcontenttype>\
+<D:creationdate>{}</D:creationdate><D:getlastmodified>{}</D:getlastmodified><D:getetag>{}</D:getetag>{}</D:prop>\
+<D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        href(name), escape_xml(name), file.size, file.created.and_utc().to_rfc3339(), http_date(last_modified(file)),
+        escape_xml(&etag(file)), supported_lock())
+}
+
+fn supported_lock() -> &'static str {
+    "<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>"
+}
+
+fn multistatus(responses: &[String]) -> ApiResponse {
+    xml(207, format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">{}</D:multistatus>", responses.concat()))
+}
+
+fn xml(code: u16, body: String) -> ApiResponse {
+    Response::from_data(body.into_bytes()).with_status_code(StatusCode(code)).with_header(make_header("Content-Type", "application/xml; charset=utf-8"))
+}
+
+fn status(code: u16) -> ApiResponse {
+    Response::from_data(Vec::new()).with_status_code(StatusCode(code))
+}
+
+fn last_modified(file: &File) -> NaiveDateTime {
+    file.modified.unwrap_or(file.created)
+}
+
+fn http_date(date: NaiveDateTime) -> String {
+    date.and_utc().format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn etag(file: &File) -> String {
+    format!("\"{}\"", file.onchain_txn_id)
+}
+
+fn href(name: &str) -> String {
+    let encoded: String = name.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+        byte => format!("%{:02X}", byte),
+    }).collect();
+    format!("{}{}", MOUNT, encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::super::tests::{log_in, send, start_api};
+    use crate::identity::IdentityRegistry;
+    use crate::model::File;
+    use crate::testing::AssetsPath;
+
+    #[test]
+    fn test_browse_write_lock_and_move_through_webdav() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let api = start_api();
+        let owner = format!("Basic {}", base64_credentials(&log_in(&api, 5, "owner@gmail.com")));
+        let stranger = log_in(&api, 7, "stranger@gmail.com");
+        let addr = api.local_addr();
+        let auth = ("Authorization", owner.as_str());
+
+        assert_eq!(send(addr, "PROPFIND", "/dav/", &[], b"").0, 401);
+        assert_eq!(send(addr, "PUT", "/dav/Q3%20report.txt", &[auth], b"first").0, 201);
+        let (status, _, listing) = send(addr, "PROPFIND", "/dav/", &[auth, ("Depth", "1")], b"");
+        let listing = String::from_utf8(listing).unwrap();
+        assert_eq!(status, 207);
+        assert!(listing.contains("<D:href>/dav/Q3%20report.txt</D:href>") && listing.contains("<D:getcontentlength>5</D:getcontentlength>"));
+        assert!(!String::from_utf8(send(addr, "PROPFIND", "/dav/", &[("Authorization", &stranger)], b"").2).unwrap().contains("report"));
+        assert_eq!(send(addr, "GET", "/dav/Q3%20report.txt", &[("Authorization", &stranger)], b"").0, 404);
+
+        let (status, head, _) = send(addr, "LOCK", "/dav/Q3%20report.txt", &[auth, ("Timeout", "Second-60")], b"");
+        assert_eq!(status, 200);
+        let token = head.lines().find_map(|line| line.strip_prefix("Lock-Token: ")).unwrap().to_string();
+        assert_eq!(send(addr, "PUT", "/dav/Q3%20report.txt", &[auth], b"second").0, 423);
+        let condition = format!("({})", token);
+        assert_eq!(send(addr, "PUT", "/dav/Q3%20report.txt", &[auth, ("If", &condition)], b"second").0, 204);
+        assert_eq!(send(addr, "UNLOCK", "/dav/Q3%20report.txt", &[auth, ("Lock-Token", &token)], b"").0, 204);
+        assert_eq!(send(addr, "GET", "/dav/Q3%20report.txt", &[auth], b"").2, b"second".to_vec());
+
+        let destination = format!("http://{}/dav/final.txt", addr);
+        assert_eq!(send(addr, "MOVE", "/dav/Q3%20report.txt", &[auth, ("Destination", &destination)], b"").0, 201);
+        assert_eq!(send(addr, "GET", "/dav/Q3%20report.txt", &[auth], b"").0, 404);
+        assert_eq!(send(addr, "GET", "/dav/final.txt", &[auth], b"").2, b"second".to_vec());
+        assert_eq!(send(addr, "DELETE", "/dav/final.txt", &[auth], b"").0, 204);
+        assert!(crate::get_all_files().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_locks_follow_files_and_moves_keep_approved_deletions() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let api = start_api();
+        let owner = format!("Basic {}", base64_credentials(&log_in(&api, 5, "owner@gmail.com")));
+        log_in(&api, 7, "guest@gmail.com");
+        let addr = api.local_addr();
+        let auth = ("Authorization", owner.as_str());
+
+        let (status, head, _) = send(addr, "LOCK", "/dav/draft.txt", &[auth, ("Timeout", "Second-60")], b"");
+        assert_eq!(status, 200);
+        let token = head.lines().find_map(|line| line.strip_prefix("Lock-Token: ")).unwrap().to_string();
+        let condition = format!("({})", token);
+        assert_eq!(send(addr, "PUT", "/dav/draft.txt", &[auth], b"first").0, 423);
+        assert_eq!(send(addr, "PUT", "/dav/draft.txt", &[auth, ("If", &condition)], b"first").0, 201);
+        assert_eq!(send(addr, "PUT", "/dav/other.txt", &[auth], b"other").0, 201);
+        let draft = crate::get_all_files().unwrap().into_iter().find(|file| file.name == "draft.txt").unwrap();
+        let other = crate::get_all_files().unwrap().into_iter().find(|file| file.name == "other.txt").unwrap();
+        crate::save_modified_file(other.id, File { name: "draft.txt".to_string(), ..other.clone() }).unwrap();
+        let renamed = format!("/dav/{}~draft.txt", draft.id);
+        assert_eq!(send(addr, "PUT", &renamed, &[auth], b"second").0, 423);
+        assert_eq!(send(addr, "PUT", &renamed, &[auth, ("If", &condition)], b"second").0, 204);
+        assert_eq!(send(addr, "UNLOCK", &renamed, &[auth, ("Lock-Token", &token)], b"").0, 204);
+
+        let guest = IdentityRegistry::open(&crate::get_path()).unwrap().get(7).unwrap().unwrap();
+        let draft = crate::find_file(draft.id).unwrap();
+        crate::save_modified_file(draft.id, File { people_with_access: vec![draft.owner.clone(), guest.as_person()], ..draft.clone() }).unwrap();
+        crate::set_deletion_threshold(draft.id, 2).unwrap();
+        let destination = format!("http://{}{}", addr, renamed);
+        assert_eq!(send(addr, "MOVE", &format!("/dav/{}~draft.txt", other.id), &[auth, ("Destination", &destination)], b"").0, 423);
+        assert!(crate::list_pending_deletions().unwrap().is_empty());
+        assert_eq!(crate::get_all_files().unwrap().len(), 2);
+    }
+
+    fn base64_credentials(bearer: &str) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::STANDARD.encode(format!("staff:{}", bearer.trim_start_matches("Bearer ")))
+    }
+}
@@ -0,0 +1,204 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use sha2::{Digest, Sha256};
+
+use crate::catalog::bucket::{Bucket, BucketConfig};
+use crate::catalog::ipfs::{CidIndex, IpfsConfig, IpfsNode};
+use crate::catalog::write_atomically;
+use crate::crypto;
+use crate::model::FileError;
+
+/// Stored file contents, one blob per file ID, in a directory next to the catalog, in an
+/// S3-compatible bucket when `BLOB_BACKEND=s3`, or on an IPFS node when `BLOB_BACKEND=ipfs`.
+/// Blobs are encrypted whenever the catalog is, except on IPFS: there a blob is added as
+/// given so that its CID is the same on every node.
+pub struct BlobStore {
+    catalog_path: PathBuf,
+    location: Location,
+}
+
+enum Location {
+    Disk(PathBuf),
+    Bucket(Bucket),
+    Ipfs(IpfsNode, CidIndex),
+}
+
+impl BlobStore {
+    pub fn open(catalog_path: &Path) -> Result<Self, FileError> {
+        if let Some(config) = BucketConfig::from_env()? {
+            return Self::open_bucket(catalog_path, config);
+        }
+        if let Some(config) = IpfsConfig::from_env() {
+            return Self::open_ipfs(catalog_path, config);
+        }
+        let dir = get_blob_dir(catalog_path);
+        fs::create_dir_all(&dir)?;
+        Ok(BlobStore { catalog_path: catalog_path.to_path_buf(), location: Location::Disk(dir) })
+    }
+
+    /// Keeps the blobs of a catalog in a bucket, whatever `BLOB_BACKEND` says.
+    pub fn open_bucket(catalog_path: &Path, config: BucketConfig) -> Result<Self, FileError> {
+        Ok(BlobStore { catalog_path: catalog_path.to_path_buf(), location: Location::Bucket(Bucket::new(config)?) })
+    }
+
+    /// Keeps the blobs of a catalog on an IPFS node, whatever `BLOB_BACKEND` says.
+    pub fn open_ipfs(catalog_path: &Path, config: IpfsConfig) -> Result<Self, FileError> {
+        let location = Location::Ipfs(IpfsNode::new(config)?, CidIndex::open(catalog_path));
+        Ok(BlobStore { catalog_path: catalog_path.to_path_buf(), location })
+    }
+
+    /// Tells whether blobs are addressed by their content, and so left unsealed.
+    pub fn is_content_addressed(&self) -> bool {
+        matches!(self.location, Location::Ipfs(..))
+    }
+
+    /// The CID `content` would be stored under, or `None` unless blobs are kept on IPFS.
+    pub fn content_id(&self, content: &[u8]) -> Result<Option<String>, FileError> {
+        match &self.location {
+            Location::Ipfs(node, _) => node.add(content, true).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn ids(&self) -> Result<Vec<i64>, FileError> {
+        let mut ids = Vec::new();
+        match &self.location {
+            Location::Disk(dir) => {
+                for entry in fs::read_dir(dir)? {
+                    if let Some(id) = entry?.file_name().to_str().and_then(|name| name.parse().ok()) {
+                        ids.push(id);
+                    }
+                }
+            },
+            Location::Bucket(bucket) => {
+                let prefix = &bucket.config().prefix;
+                ids.extend(bucket.list(prefix)?.iter().filter_map(|key| key.strip_prefix(prefix.as_str())).filter_map(|id| id.parse::<i64>().ok()));
+            },
+            Location::Ipfs(_, index) => ids.extend(index.entries()?.into_keys()),
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn write(&self, file_id: i64, content: &[u8]) -> Result<(), FileError> {
+        if self.is_content_addressed() {
+            return self.write_raw(file_id, content);
+        }
+        self.write_raw(file_id, &crypto::seal(&self.catalog_path, content)?)
+    }
+
+    pub fn read(&self, file_id: i64) -> Result<Option<Vec<u8>>, FileError> {
+        let content = self.read_raw(file_id)?;
+        if self.is_content_addressed() {
+            return Ok(content);
+        }
+        content.map(|content| crypto::open(&self.catalog_path, &content)).transpose()
+    }
+
+    /// Writes a blob as given, without sealing it.
+    pub fn write_raw(&self, file_id: i64, data: &[u8]) -> Result<(), FileError> {
+        match &self.location {
+            Location::Disk(dir) => write_atomically(&dir.join(file_id.to_string()), data),
+            Location::Bucket(bucket) => bucket.put(&format!("{}{}", bucket.config().prefix, file_id), data),
+            Location::Ipfs(node, index) => {
+                let cid = node.add(data, false)?;
+                node.pin(&cid)?;
+                let previous = index.set(file_id, Some(cid))?;
+                self.release(previous)
+            },
+        }
+    }
+
+    /// Reads a blob as stored, sealed or not.
+    pub fn read_raw(&self, file_id: i64) -> Result<Option<Vec<u8>>, FileError> {
+        match &self.location {
+            Location::Disk(dir) => match fs::read(dir.join(file_id.to_string())) {
+                Ok(content) => Ok(Some(content)),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(FileError::IOError(e)),
+            },
+            Location::Bucket(bucket) => bucket.get(&format!("{}{}", bucket.config().prefix, file_id)),
+            Location::Ipfs(node, index) => index.get(file_id)?.map(|cid| node.cat(&cid)).transpose(),
+        }
+    }
+
+    pub fn hash(&self, file_id: i64) -> Result<Option<String>, FileError> {
+        Ok(self.read(file_id)?.map(|content| content_hash(&content)))
+    }
+
+    pub fn remove(&self, file_id: i64) -> Result<(), FileError> {
+        match &self.location {
+            Location::Disk(dir) => match fs::remove_file(dir.join(file_id.to_string())) {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(FileError::IOError(e)),
+            },
+            Location::Bucket(bucket) => bucket.delete(&format!("{}{}", bucket.config().prefix, file_id)),
+            Location::Ipfs(_, index) => {
+                let previous = index.set(file_id, None)?;
+                self.release(previous)
+            },
+        }
+    }
+
+    /// Copies the blob of a file aside before it is overwritten, so that `restore_previous`
+    /// can put it back if the change does not go through. The copy is kept on local disk
+    /// whatever the backend, and only while the change is in flight.
+    pub fn keep_previous(&self, file_id: i64) -> Result<(), FileError> {
+        self.discard_previous(file_id)?;
+        let Some(data) = self.read_raw(file_id)? else { return Ok(()) };
+        fs::create_dir_all(get_blob_dir(&self.catalog_path))?;
+        write_atomically(&self.previous_path(file_id), &data)
+    }
+
+    /// Puts back the blob `keep_previous` set aside. Returns false when there was none,
+    /// which is the case for a file that had no blob before.
+    pub fn restore_previous(&self, file_id: i64) -> Result<bool, FileError> {
+        let data = match fs::read(self.previous_path(file_id)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(FileError::IOError(e)),
+        };
+        self.write_raw(file_id, &data)?;
+        self.discard_previous(file_id)?;
+        Ok(true)
+    }
+
+    pub fn discard_previous(&self, file_id: i64) -> Result<(), FileError> {
+        match fs::remove_file(self.previous_path(file_id)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(FileError::IOError(e)),
+        }
+    }
+
+    fn previous_path(&self, file_id: i64) -> PathBuf {
+        get_blob_dir(&self.catalog_path).join(format!("{}.previous", file_id))
+    }
+
+    /// Unpins a CID that no blob is stored under any more. Failing to unpin only leaves the
+    /// content on the node, so it is logged rather than reported.
+    fn release(&self, cid: Option<String>) -> Result<(), FileError> {
+        let (Location::Ipfs(node, index), Some(cid)) = (&self.location, cid) else { return Ok(()) };
+        if index.entries()?.values().any(|other| *other == cid) {
+            return Ok(());
+        }
+        if let Err(e) = node.unpin(&cid) {
+            warn!("Failed to unpin {} from the IPFS node: {}", cid, e);
+        }
+        Ok(())
+    }
+}
+
+pub fn get_blob_dir(catalog_path: &Path) -> PathBuf {
+    let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".blobs");
+    catalog_path.with_file_name(name)
+}
+
+pub fn content_hash(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
@@ -0,0 +1,232 @@
+use std::env;
+use std::io::Read;
+
+use log::warn;
+
+use crate::model::FileError;
+use crate::net::http::{agent, remote_error};
+use crate::net::sigv4::{sign_request, uri_encode};
+use crate::net::xml::{elements, unescape_xml};
+
+/// Size of the parts a large blob is uploaded in, unless `BLOB_S3_PART_SIZE` says otherwise.
+pub const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// S3 refuses parts under 5 MiB other than the last one.
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Largest response read from the bucket.
+const MAX_RESPONSE_LEN: u64 = 1024 * 1024 * 1024;
+
+/// Where blobs are kept when `BLOB_BACKEND=s3`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BucketConfig {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub prefix: String,
+    pub part_size: usize,
+}
+
+impl BucketConfig {
+    /// Reads the bucket settings from `BLOB_S3_ENDPOINT`, `BLOB_S3_BUCKET`, `BLOB_S3_REGION`,
+    /// `BLOB_S3_ACCESS_KEY_ID`, `BLOB_S3_SECRET_ACCESS_KEY`, `BLOB_S3_PREFIX` and
+    /// `BLOB_S3_PART_SIZE`. Returns `None` unless `BLOB_BACKEND` is `s3`.
+    pub fn from_env() -> Result<Option<Self>, FileError> {
+        if env::var("BLOB_BACKEND").map(|value| value.trim().to_lowercase()).ok().as_deref() != Some("s3") {
+            return Ok(None);
+        }
+        let required = |name: &str| env::var(name).ok().map(|value| value.trim().to_string()).filter(|value| !value.is_empty())
+            .ok_or_else(|| FileError::InputError(format!("{} must be set when BLOB_BACKEND=s3", name)));
+        let part_size = env::var("BLOB_S3_PART_SIZE").ok().and_then(|size| size.trim().parse().ok()).unwrap_or(DEFAULT_PART_SIZE);
+        Ok(Some(BucketConfig {
+            endpoint: required("BLOB_S3_ENDPOINT")?,
+            bucket: required("BLOB_S3_BUCKET")?,
+            region: env::var("BLOB_S3_REGION").ok().filter(|region| !region.trim().is_empty()).unwrap_or_else(|| "us-east-1".to_string()),
+            access_key_id: required("BLOB_S3_ACCESS_KEY_ID")?,
+            secret_access_key: required("BLOB_S3_SECRET_ACCESS_KEY")?,
+            prefix: env::var("BLOB_S3_PREFIX").unwrap_or_default(),
+            part_size: part_size.max(MIN_PART_SIZE),
+        }))
+    }
+}
+
+struct Reply {
+    status: u16,
+    etag: Option<String>,
+    body: Vec<u8>,
+}
+
+/// A bucket of an S3-compatible object store, addressed by path and signed with SigV4.
+pub struct Bucket {
+    config: BucketConfig,
+    origin: String,
+    host: String,
+    base_path: String,
+}
+
+impl Bucket {
+    pub fn new(config: BucketConfig) -> Result<Self, FileError> {
+        let (scheme, rest) = config.endpoint.split_once("://")
+            .filter(|(scheme, _)| matches!(*scheme, "http" | "https"))
+            .ok_or_else(|| FileError::InputError(format!("{} is not an http or https URL", config.endpoint)))?;
+        let (host, base_path) = rest.split_once('/').map_or((rest, ""), |(host, path)| (host, path));
+        let base_path = match base_path.trim_matches('/') {
+            "" => String::new(),
+            path => format!("/{}", path),
+        };
+        Ok(Bucket { origin: format!("{}://{}", scheme, host), host: host.to_string(), base_path, config })
+    }
+
+    pub fn config(&self) -> &BucketConfig {
+        &self.config
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, FileError> {
+        let reply = self.request("GET", key, "", &[])?;
+        match reply.status {
+            404 => Ok(None),
+            _ => self.expect_success("GET", reply).map(|reply| Some(reply.body)),
+        }
+    }
+
+    /// Uploads an object, in parts when it is larger than the part size.
+    pub fn put(&self, key: &str, data: &[u8]) -> Result<(), FileError> {
+        if data.len() <= self.config.part_size {
+            return self.expect_success("PUT", self.request("PUT", key, "", data)?).map(|_| ());
+        }
+        let started = self.expect_success("POST", self.request("POST", key, "uploads=", &[])?)?;
+        let upload_id = elements(&String::from_utf8_lossy(&started.body), "UploadId").into_iter().next()
+            .ok_or_else(|| remote_error("The bucket did not start a multipart upload"))?;
+        let result = self.put_parts(key, &upload_id, data);
+        if result.is_err() {
+            let query = format!("uploadId={}", uri_encode(&upload_id));
+            if let Err(e) = self.request("DELETE", key, &query, &[]) {
+                warn!("Failed to abort the multipart upload of {}: {}", key, e);
+            }
+        }
+        result
+    }
+
+    fn put_parts(&self, key: &str, upload_id: &str, data: &[u8]) -> Result<(), FileError> {
+        let mut completion = String::from("<CompleteMultipartUpload>");
+        for (index, part) in data.chunks(self.config.part_size).enumerate() {
+            let query = format!("partNumber={}&uploadId={}", index + 1, uri_encode(upload_id));
+            let reply = self.expect_success("PUT", self.request("PUT", key, &query, part)?)?;
+            let etag = reply.etag.ok_or_else(|| remote_error("The bucket did not return the ETag of a part"))?;
+            completion.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", index + 1, etag.replace('&', "&amp;").replace('"', "&quot;")));
+        }
+        completion.push_str("</CompleteMultipartUpload>");
+        let reply = self.expect_success("POST", self.request("POST", key, &format!("uploadId={}", uri_encode(upload_id)), completion.as_bytes())?)?;
+        match elements(&String::from_utf8_lossy(&reply.body), "Code").first() {
+            Some(code) => Err(remote_error(&format!("The bucket failed to join the parts of {}: {}", key, code))),
+            None => Ok(()),
+        }
+    }
+
+    /// Deletes an object. Deleting one that does not exist succeeds.
+    pub fn delete(&self, key: &str) -> Result<(), FileError> {
+        let reply = self.request("DELETE", key, "", &[])?;
+        match reply.status {
+            404 => Ok(()),
+            _ => self.expect_success("DELETE", reply).map(|_| ()),
+        }
+    }
+
+    /// The keys that start with `prefix`, across every page of the listing.
+    pub fn list(&self, prefix: &str) -> Result<Vec<String>, FileError> {
+        let mut keys = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = format!("list-type=2&prefix={}", uri_encode(prefix));
+            if let Some(token) = &continuation {
+                query.push_str(&format!("&continuation-token={}", uri_encode(token)));
+            }
+            let reply = self.expect_success("GET", self.request("GET", "", &query, &[])?)?;
+            let listing = String::from_utf8_lossy(&reply.body).into_owned();
+            keys.extend(elements(&listing, "Key").iter().map(|key| unescape_xml(key)));
+            continuation = elements(&listing, "NextContinuationToken").into_iter().next().map(|token| unescape_xml(&token));
+            if elements(&listing, "IsTruncated").first().map(|truncated| truncated.trim()) != Some("true") || continuation.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    fn request(&self, method: &str, key: &str, query: &str, body: &[u8]) -> Result<Reply, FileError> {
+        let key: Vec<String> = key.split('/').map(uri_encode).collect();
+        let mut target = format!("{}/{}", self.base_path, uri_encode(&self.config.bucket));
+        if !key.concat().is_empty() {
+            target.push_str(&format!("/{}", key.join("/")));
+        }
+        if !query.is_empty() {
+            target.push_str(&format!("?{}", query));
+        }
+        let headers = sign_request(&self.config.access_key_id, &self.config.secret_access_key, &self.config.region, method, &target,
+            &[("host".to_string(), self.host.clone())], body);
+        let mut request = agent().request(method, &format!("{}{}", self.origin, target));
+        for (name, value) in &headers {
+            request = request.set(name, value);
+        }
+        let response = match request.send_bytes(body) {
+            Ok(response) => response,
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => return Err(remote_error(&format!("Cannot reach the blob bucket: {}", e))),
+        };
+        let status = response.status();
+        let etag = response.header("ETag").map(str::to_string);
+        let mut body = Vec::new();
+        response.into_reader().take(MAX_RESPONSE_LEN).read_to_end(&mut body)?;
+        Ok(Reply { status, etag, body })
+    }
+
+    fn expect_success(&self, method: &str, reply: Reply) -> Result<Reply, FileError> {
+        if (200..300).contains(&reply.status) {
+            return Ok(reply);
+        }
+        let body = String::from_utf8_lossy(&reply.body);
+        let code = elements(&body, "Code").into_iter().next().unwrap_or_default();
+        Err(remote_error(&format!("The blob bucket {} refused a {} with {} {}", self.config.bucket, method, reply.status, code)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use crate::api::s3::{S3Config, S3Server};
+    use crate::catalog::{content_hash, BlobStore};
+    use crate::identity::{IdentityRegistry, LocalIdentity};
+    use crate::testing::AssetsPath;
+
+    #[test]
+    fn test_blobs_round_trip_through_a_bucket() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("store"));
+        let store = S3Server::start(&S3Config { listen: "127.0.0.1:0".to_string(), workers: 2 }).unwrap();
+        let owner = LocalIdentity::generate(5, "Owner", "owner@gmail.com");
+        IdentityRegistry::open(&crate::get_path()).unwrap().register(&owner.public).unwrap();
+        let credentials = crate::issue_s3_credentials(5).unwrap();
+        let config = BucketConfig {
+            endpoint: format!("http://{}", store.local_addr()), bucket: "owner-5".to_string(), region: "us-east-1".to_string(),
+            access_key_id: credentials.access_key_id, secret_access_key: credentials.secret_access_key, prefix: "blobs/".to_string(), part_size: 4,
+        };
+        let blobs = BlobStore::open_bucket(&dir.path().join("assets"), config).unwrap();
+
+        blobs.write(1, b"small").unwrap();
+        blobs.write(22, b"large enough for several parts").unwrap();
+        assert_eq!(blobs.ids().unwrap(), vec![1, 22]);
+        assert_eq!(blobs.read(22).unwrap(), Some(b"large enough for several parts".to_vec()));
+        assert_eq!(blobs.hash(1).unwrap(), Some(content_hash(b"small")));
+        assert_eq!(blobs.read(3).unwrap(), None);
+        blobs.write_raw(1, b"tampered").unwrap();
+        assert_ne!(blobs.hash(1).unwrap(), Some(content_hash(b"small")));
+
+        blobs.remove(1).unwrap();
+        blobs.remove(1).unwrap();
+        assert_eq!(blobs.ids().unwrap(), vec![22]);
+        assert!(crate::get_files_by_owner(5).unwrap().iter().all(|file| file.name.starts_with("blobs/")));
+    }
+}
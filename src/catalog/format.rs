@@ -0,0 +1,189 @@
+use std::fs::File as StdFile;
+use std::io::Read;
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto;
+use crate::model::{File, FileError, FileType};
+
+/// Every catalog written since format version 2 starts with these bytes. Version 1
+/// catalogs are a bare `Vec<File>`, whose leading length can never spell them.
+pub const MAGIC: &[u8; 8] = b"UNICHAIN";
+
+/// Format written by `encode_catalog`. When `File` changes, bump this, freeze the
+/// previous layout below and add its decoder to `decode_catalog`.
+pub const CURRENT_VERSION: u32 = 3;
+
+const V2_HEADER_LEN: usize = MAGIC.len() + 4;
+
+/// From version 3 on, the header also carries a random generation number that changes
+/// on every write, which lets a cached copy be checked without decoding the catalog.
+const HEADER_LEN: usize = V2_HEADER_LEN + 8;
+
+/// `File` as it was laid out in format versions 1 and 2, before it had a content hash.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct FileV1 {
+    id: i64,
+    name: String,
+    file_type: FileType,
+    size: u64,
+    created: NaiveDateTime,
+    modified: Option<NaiveDateTime>,
+    accessed: Option<NaiveDateTime>,
+    owner: (i64, String, String),
+    people_with_access: Vec<(i64, String, String)>,
+    ipfs_hash: String,
+    onchain_txn_id: String,
+    download_permission: bool,
+    description: Option<String>,
+}
+
+impl From<FileV1> for File {
+    fn from(file: FileV1) -> Self {
+        File {
+            id: file.id,
+            name: file.name,
+            file_type: file.file_type,
+            size: file.size,
+            created: file.created,
+            modified: file.modified,
+            accessed: file.accessed,
+            owner: file.owner,
+            people_with_access: file.people_with_access,
+            ipfs_hash: file.ipfs_hash,
+            onchain_txn_id: file.onchain_txn_id,
+            download_permission: file.download_permission,
+            description: file.description,
+            content_hash: None,
+        }
+    }
+}
+
+pub fn encode_catalog<'a>(files: impl IntoIterator<Item = &'a File>) -> Result<Vec<u8>, FileError> {
+    let files: Vec<&File> = files.into_iter().collect();
+    let payload = bincode::serialize(&files).map_err(|_| FileError::DeserializationError("Vec<File> serialization failed".to_string()))?;
+    let mut encoded = Vec::with_capacity(HEADER_LEN + payload.len());
+    encoded.extend_from_slice(MAGIC);
+    encoded.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+    encoded.extend_from_slice(&rand::random::<u64>().to_le_bytes());
+    encoded.extend_from_slice(&payload);
+    Ok(encoded)
+}
+
+/// Decodes a catalog written in any known format version and returns its records
+/// along with the version they were stored in.
+pub fn decode_catalog(encoded: &[u8]) -> Result<(Vec<File>, u32), FileError> {
+    if encoded.is_empty() {
+        return Ok((Vec::new(), CURRENT_VERSION));
+    }
+    let version = get_format_version(encoded);
+    let files = match version {
+        1 => deserialize::<Vec<FileV1>>(encoded, version)?.into_iter().map(File::from).collect(),
+        2 => deserialize::<Vec<FileV1>>(&encoded[V2_HEADER_LEN..], version)?.into_iter().map(File::from).collect(),
+        3 => deserialize::<Vec<File>>(encoded.get(HEADER_LEN..).unwrap_or_default(), version)?,
+        _ => return Err(FileError::DeserializationError(format!("Unsupported catalog format version {}", version))),
+    };
+    Ok((files, version))
+}
+
+pub fn get_format_version(encoded: &[u8]) -> u32 {
+    match (encoded.get(..MAGIC.len()), encoded.get(MAGIC.len()..V2_HEADER_LEN)) {
+        (Some(magic), Some(version)) if magic == MAGIC => u32::from_le_bytes([version[0], version[1], version[2], version[3]]),
+        _ => 1,
+    }
+}
+
+pub fn get_generation(encoded: &[u8]) -> Option<u64> {
+    if get_format_version(encoded) < 3 {
+        return None;
+    }
+    let generation = encoded.get(V2_HEADER_LEN..HEADER_LEN)?;
+    Some(u64::from_le_bytes(generation.try_into().ok()?))
+}
+
+/// Reads only the header of the catalog at `path` and returns its generation number,
+/// taken from the encryption nonce when the catalog is encrypted.
+pub fn read_generation(path: &Path) -> Option<u64> {
+    let mut header = [0u8; crypto::HEADER_LEN];
+    StdFile::open(path).ok()?.read_exact(&mut header).ok()?;
+    crypto::get_seal_generation(&header).or_else(|| get_generation(&header))
+}
+
+#[cfg(test)]
+impl From<&File> for FileV1 {
+    fn from(file: &File) -> Self {
+        FileV1 {
+            id: file.id, name: file.name.clone(), file_type: file.file_type.clone(), size: file.size,
+            created: file.created, modified: file.modified, accessed: file.accessed, owner: file.owner.clone(),
+            people_with_access: file.people_with_access.clone(), ipfs_hash: file.ipfs_hash.clone(),
+            onchain_txn_id: file.onchain_txn_id.clone(), download_permission: file.download_permission,
+            description: file.description.clone(),
+        }
+    }
+}
+
+/// Encodes records the way format version 1 stored them, to exercise the migration path.
+#[cfg(test)]
+pub(crate) fn encode_legacy_v1(files: &[File]) -> Vec<u8> {
+    let legacy: Vec<FileV1> = files.iter().map(FileV1::from).collect();
+    bincode::serialize(&legacy).unwrap()
+}
+
+fn deserialize<'a, T: Deserialize<'a>>(payload: &'a [u8], version: u32) -> Result<T, FileError> {
+    bincode::deserialize(payload).map_err(|_| FileError::DeserializationError(format!("Failed to deserialize Vec<File> (format version {})", version)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+
+    use crate::utils::generate_fake_hash;
+
+    fn get_test_file() -> File {
+        let owner = (1, String::from("Username"), String::from("username@gmail.com"));
+        File {
+            id: 1, name: "test-file".to_string(), file_type: FileType::Pdf, size: 100,
+            created: Utc::now().naive_utc(), modified: None, accessed: None, owner: owner.clone(),
+            people_with_access: vec![owner], ipfs_hash: generate_fake_hash(46),
+            onchain_txn_id: generate_fake_hash(64), download_permission: false, description: None,
+            content_hash: None
+        }
+    }
+
+    #[test]
+    fn test_round_trip_current_version() {
+        let files = vec![get_test_file()];
+        let encoded = encode_catalog(&files).unwrap();
+        assert_eq!(&encoded[..MAGIC.len()], MAGIC);
+        assert_eq!(decode_catalog(&encoded).unwrap(), (files.clone(), CURRENT_VERSION));
+        assert_ne!(get_generation(&encoded), get_generation(&encode_catalog(&files).unwrap()));
+    }
+
+    #[test]
+    fn test_decode_headerless_version_1() {
+        let files = vec![get_test_file()];
+        let encoded = encode_legacy_v1(&files);
+        assert_eq!(decode_catalog(&encoded).unwrap(), (files, 1));
+    }
+
+    #[test]
+    fn test_decode_version_2_without_content_hash() {
+        let files = vec![get_test_file()];
+        let mut encoded = MAGIC.to_vec();
+        encoded.extend_from_slice(&2u32.to_le_bytes());
+        encoded.extend_from_slice(&encode_legacy_v1(&files));
+        assert_eq!(decode_catalog(&encoded).unwrap(), (files, 2));
+    }
+
+    #[test]
+    fn test_reject_unknown_version() {
+        let mut encoded = MAGIC.to_vec();
+        encoded.extend_from_slice(&99u32.to_le_bytes());
+        let expected = FileError::DeserializationError("Unsupported catalog format version 99".to_string());
+        assert_eq!(decode_catalog(&encoded), Err(expected));
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::NaiveDateTime;
+
+use crate::catalog::format::read_generation;
+use crate::ledger::RecordPool;
+use crate::model::{File, FileError, FileType};
+
+/// How many reads are logged before their access times are written into the catalog.
+pub const ACCESS_BATCH: usize = 64;
+
+/// In-memory catalog keyed by file ID, with secondary indexes that are kept in step
+/// with every insert and removal so that no lookup has to scan the records.
+#[derive(Debug, Default, Clone)]
+pub struct IndexedCatalog {
+    files: BTreeMap<i64, File>,
+    by_owner: HashMap<i64, BTreeSet<i64>>,
+    by_name: HashMap<String, BTreeSet<i64>>,
+    by_type: HashMap<FileType, BTreeSet<i64>>,
+    by_content_hash: HashMap<String, BTreeSet<i64>>,
+}
+
+impl IndexedCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_files(files: Vec<File>) -> Self {
+        let mut catalog = Self::new();
+        for file in files {
+            catalog.upsert(file);
+        }
+        catalog
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn get(&self, file_id: i64) -> Option<&File> {
+        self.files.get(&file_id)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &File> {
+        self.files.values()
+    }
+
+    /// Inserts or replaces a record and returns the one it replaced.
+    pub fn upsert(&mut self, file: File) -> Option<File> {
+        let previous = self.remove(file.id);
+        add_to_index(&mut self.by_owner, file.owner.0, file.id);
+        add_to_index(&mut self.by_name, file.name.clone(), file.id);
+        add_to_index(&mut self.by_type, file.file_type.clone(), file.id);
+        if let Some(hash) = &file.content_hash {
+            add_to_index(&mut self.by_content_hash, hash.clone(), file.id);
+        }
+        self.files.insert(file.id, file);
+        previous
+    }
+
+    pub fn remove(&mut self, file_id: i64) -> Option<File> {
+        let file = self.files.remove(&file_id)?;
+        remove_from_index(&mut self.by_owner, &file.owner.0, file_id);
+        remove_from_index(&mut self.by_name, &file.name, file_id);
+        remove_from_index(&mut self.by_type, &file.file_type, file_id);
+        if let Some(hash) = &file.content_hash {
+            remove_from_index(&mut self.by_content_hash, hash, file_id);
+        }
+        Some(file)
+    }
+
+    pub fn by_owner(&self, owner_id: i64) -> Vec<&File> {
+        self.lookup(self.by_owner.get(&owner_id))
+    }
+
+    pub fn by_name(&self, name: &str) -> Vec<&File> {
+        self.lookup(self.by_name.get(name))
+    }
+
+    pub fn by_type(&self, file_type: &FileType) -> Vec<&File> {
+        self.lookup(self.by_type.get(file_type))
+    }
+
+    pub fn by_content_hash(&self, content_hash: &str) -> Vec<&File> {
+        self.lookup(self.by_content_hash.get(content_hash))
+    }
+
+    fn lookup(&self, ids: Option<&BTreeSet<i64>>) -> Vec<&File> {
+        ids.map(|ids| ids.iter().filter_map(|id| self.files.get(id)).collect()).unwrap_or_default()
+    }
+}
+
+fn add_to_index<K: Hash + Eq>(index: &mut HashMap<K, BTreeSet<i64>>, key: K, file_id: i64) {
+    index.entry(key).or_default().insert(file_id);
+}
+
+fn remove_from_index<K, Q>(index: &mut HashMap<K, BTreeSet<i64>>, key: &Q, file_id: i64)
+where
+    K: Hash + Eq + std::borrow::Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+{
+    if let Some(ids) = index.get_mut(key) {
+        ids.remove(&file_id);
+        if ids.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+/// Access times recorded since the catalog was last written, which keeps reads from
+/// rewriting the whole catalog.
+pub fn access_log(catalog_path: &Path) -> RecordPool<(i64, NaiveDateTime)> {
+    RecordPool::open(catalog_path, ".accessed")
+}
+
+/// Logs that `file` was read, and writes the logged access times into the catalog once
+/// `ACCESS_BATCH` of them have piled up. Call it only while holding the catalog lock.
+pub fn record_access(path: &Path, catalog: &IndexedCatalog, file: &File) -> Result<(), FileError> {
+    let log = access_log(path);
+    log.add(&[(file.id, file.accessed.unwrap_or_default())])?;
+    if log.list()?.len() >= ACCESS_BATCH {
+        crate::save_files_to_file(catalog.files(), path)?;
+    }
+    Ok(())
+}
+
+fn apply_access_log(path: &Path, catalog: &mut IndexedCatalog) -> Result<(), FileError> {
+    for (file_id, accessed) in access_log(path).list()? {
+        if let Some(file) = catalog.files.get_mut(&file_id) {
+            file.accessed = Some(accessed);
+        }
+    }
+    Ok(())
+}
+
+fn access_log_len(path: &Path) -> Option<u64> {
+    std::fs::metadata(access_log(path).path()).ok().map(|metadata| metadata.len())
+}
+
+struct CachedCatalog {
+    path: PathBuf,
+    generation: Option<u64>,
+    access_log_len: Option<u64>,
+    catalog: IndexedCatalog,
+}
+
+static CATALOG_CACHE: Mutex<Option<CachedCatalog>> = Mutex::new(None);
+
+/// Runs `f` against the process-wide copy of the catalog at `path`, loading it on first
+/// use and again only when the generation on disk, or the length of the access log, shows
+/// someone else wrote to them. A failed `f` discards the copy, since it may no longer match
+/// what is on disk.
+pub fn with_cached_catalog<T>(path: &Path, f: impl FnOnce(&mut IndexedCatalog) -> Result<T, FileError>) -> Result<T, FileError> {
+    let mut cache = CATALOG_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let generation = read_generation(path);
+    let log_len = access_log_len(path);
+    let is_fresh = matches!(cache.as_ref(),
+        Some(cached) if cached.path == path && cached.generation == generation && generation.is_some() && cached.access_log_len == log_len);
+    if !is_fresh {
+        let mut catalog = IndexedCatalog::from_files(crate::load_files_from_file(&path.to_path_buf())?);
+        apply_access_log(path, &mut catalog)?;
+        *cache = Some(CachedCatalog { path: path.to_path_buf(), generation, access_log_len: log_len, catalog });
+    }
+    let cached = cache.as_mut().expect("catalog cache was just filled");
+    match f(&mut cached.catalog) {
+        Ok(value) => {
+            cached.generation = read_generation(path);
+            cached.access_log_len = access_log_len(path);
+            Ok(value)
+        },
+        Err(e) => {
+            *cache = None;
+            Err(e)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+
+    fn get_test_file(id: i64, name: &str, owner_id: i64, file_type: FileType) -> File {
+        let owner = (owner_id, String::from("Username"), String::from("username@gmail.com"));
+        File {
+            id, name: name.to_string(), file_type, size: 100,
+            created: Utc::now().naive_utc(), modified: None, accessed: None, owner: owner.clone(),
+            people_with_access: vec![owner], ipfs_hash: String::new(), onchain_txn_id: String::new(),
+            download_permission: false, description: None, content_hash: Some(format!("hash-{}", name))
+        }
+    }
+
+    fn ids(files: Vec<&File>) -> Vec<i64> {
+        files.iter().map(|file| file.id).collect()
+    }
+
+    #[test]
+    fn test_secondary_indexes_follow_updates() {
+        let mut catalog = IndexedCatalog::from_files(vec![
+            get_test_file(1, "a", 10, FileType::Pdf),
+            get_test_file(2, "b", 10, FileType::Txt),
+            get_test_file(3, "a", 20, FileType::Pdf),
+        ]);
+        assert_eq!(ids(catalog.by_owner(10)), vec![1, 2]);
+        assert_eq!(ids(catalog.by_name("a")), vec![1, 3]);
+        assert_eq!(ids(catalog.by_type(&FileType::Pdf)), vec![1, 3]);
+        assert_eq!(ids(catalog.by_content_hash("hash-b")), vec![2]);
+        let previous = catalog.upsert(get_test_file(1, "c", 20, FileType::Txt));
+        assert_eq!(previous.map(|file| file.name), Some("a".to_string()));
+        assert_eq!(ids(catalog.by_owner(10)), vec![2]);
+        assert_eq!(ids(catalog.by_name("a")), vec![3]);
+        assert_eq!(ids(catalog.by_type(&FileType::Txt)), vec![1, 2]);
+        catalog.remove(3);
+        assert!(catalog.by_name("a").is_empty());
+        assert_eq!(catalog.len(), 2);
+    }
+}
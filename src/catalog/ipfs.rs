@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::Deserialize;
+
+use crate::net::http::{agent, remote_error};
+use crate::catalog::write_atomically;
+use crate::model::FileError;
+
+/// Largest object fetched back from the node.
+const MAX_CONTENT_LEN: u64 = 1024 * 1024 * 1024;
+
+/// The IPFS node blobs are added to when `BLOB_BACKEND=ipfs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpfsConfig {
+    pub api_url: String,
+}
+
+impl IpfsConfig {
+    /// Reads the address of the node's HTTP API from `IPFS_API_URL`, `http://127.0.0.1:5001`
+    /// by default. Returns `None` unless `BLOB_BACKEND` is `ipfs`.
+    pub fn from_env() -> Option<Self> {
+        if env::var("BLOB_BACKEND").map(|value| value.trim().to_lowercase()).ok().as_deref() != Some("ipfs") {
+            return None;
+        }
+        let api_url = env::var("IPFS_API_URL").ok().map(|url| url.trim().to_string()).filter(|url| !url.is_empty())
+            .unwrap_or_else(|| "http://127.0.0.1:5001".to_string());
+        Some(IpfsConfig { api_url })
+    }
+}
+
+#[derive(Deserialize)]
+struct Added {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+/// A Kubo node, reached through its `/api/v0` HTTP API.
+pub struct IpfsNode {
+    api_url: String,
+}
+
+impl IpfsNode {
+    pub fn new(config: IpfsConfig) -> Result<Self, FileError> {
+        let api_url = config.api_url.trim_end_matches('/').to_string();
+        if !api_url.starts_with("http://") && !api_url.starts_with("https://") {
+            return Err(FileError::InputError(format!("{} is not an http or https URL", config.api_url)));
+        }
+        Ok(IpfsNode { api_url })
+    }
+
+    /// Adds content to the node and returns its CID. With `only_hash`, the CID is computed
+    /// without storing anything.
+    pub fn add(&self, data: &[u8], only_hash: bool) -> Result<String, FileError> {
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let boundary = format!("unichain-{}", hex::encode(nonce));
+        let mut body = format!("--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"blob\"\r\n\
+            Content-Type: application/octet-stream\r\n\r\n", boundary).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        let query = format!("add?cid-version=1&pin=false&only-hash={}", only_hash);
+        let reply = self.call(&query, Some((&format!("multipart/form-data; boundary={}", boundary), &body)))?;
+        let added: Added = serde_json::from_slice(&reply)
+            .map_err(|e| remote_error(&format!("The IPFS node sent an unexpected reply to an add: {}", e)))?;
+        Ok(added.hash)
+    }
+
+    /// Pins content so that the node keeps it through garbage collection.
+    pub fn pin(&self, cid: &str) -> Result<(), FileError> {
+        self.call(&format!("pin/add?arg={}", cid), None).map(|_| ())
+    }
+
+    pub fn unpin(&self, cid: &str) -> Result<(), FileError> {
+        self.call(&format!("pin/rm?arg={}", cid), None).map(|_| ())
+    }
+
+    pub fn cat(&self, cid: &str) -> Result<Vec<u8>, FileError> {
+        self.call(&format!("cat?arg={}", cid), None)
+    }
+
+    /// Kubo takes every call as a POST and reports failures as a JSON object with a `Message`.
+    fn call(&self, command: &str, body: Option<(&str, &[u8])>) -> Result<Vec<u8>, FileError> {
+        let request = agent().post(&format!("{}/api/v0/{}", self.api_url, command));
+        let result = match body {
+            Some((content_type, body)) => request.set("Content-Type", content_type).send_bytes(body),
+            None => request.call(),
+        };
+        let response = match result {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, response)) => {
+                let message = response.into_string().ok()
+                    .and_then(|reply| serde_json::from_str::<serde_json::Value>(&reply).ok())
+                    .and_then(|reply| reply.get("Message").and_then(|message| message.as_str().map(str::to_string)))
+                    .unwrap_or_default();
+                let name = command.split('?').next().unwrap_or(command);
+                return Err(remote_error(&format!("The IPFS node refused {} with {} {}", name, status, message)));
+            },
+            Err(e) => return Err(remote_error(&format!("Cannot reach the IPFS node: {}", e))),
+        };
+        let mut data = Vec::new();
+        response.into_reader().take(MAX_CONTENT_LEN).read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// The CID each blob was added under, kept in `<ASSETS_PATH>.ipfs` as one `<id> <cid>` line
+/// per file.
+pub struct CidIndex {
+    path: PathBuf,
+}
+
+impl CidIndex {
+    pub fn open(catalog_path: &Path) -> Self {
+        CidIndex { path: get_cid_index_path(catalog_path) }
+    }
+
+    pub fn entries(&self) -> Result<BTreeMap<i64, String>, FileError> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(FileError::IOError(e)),
+        };
+        Ok(text.lines().filter_map(|line| line.split_once(' '))
+            .filter_map(|(id, cid)| Some((id.parse().ok()?, cid.trim().to_string())))
+            .collect())
+    }
+
+    pub fn get(&self, file_id: i64) -> Result<Option<String>, FileError> {
+        Ok(self.entries()?.remove(&file_id))
+    }
+
+    /// Records the CID of a blob, or forgets the blob when `cid` is `None`. Returns the
+    /// CID it had before.
+    pub fn set(&self, file_id: i64, cid: Option<String>) -> Result<Option<String>, FileError> {
+        let mut entries = self.entries()?;
+        let previous = match cid {
+            Some(cid) => entries.insert(file_id, cid),
+            None => entries.remove(&file_id),
+        };
+        let text: String = entries.iter().map(|(id, cid)| format!("{} {}\n", id, cid)).collect();
+        write_atomically(&self.path, text.as_bytes())?;
+        Ok(previous)
+    }
+}
+
+pub fn get_cid_index_path(catalog_path: &Path) -> PathBuf {
+    let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".ipfs");
+    catalog_path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use tempfile::tempdir;
+    use tiny_http::{Response, Server};
+
+    use crate::api::multipart;
+    use crate::catalog::{content_hash, BlobStore};
+
+    #[derive(Default)]
+    struct MockNode {
+        objects: HashMap<String, Vec<u8>>,
+        pins: HashSet<String>,
+    }
+
+    /// Serves the parts of the Kubo API the blob store uses, keeping objects in memory.
+    fn start_mock_node() -> (String, Arc<Mutex<MockNode>>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let node = Arc::new(Mutex::new(MockNode::default()));
+        let state = node.clone();
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let (command, query) = request.url().split_once('?').map_or((request.url().to_string(), String::new()), |(command, query)| (command.to_string(), query.to_string()));
+                let arg = query.split('&').find_map(|param| param.strip_prefix("arg=")).unwrap_or_default().to_string();
+                let mut body = Vec::new();
+                request.as_reader().read_to_end(&mut body).unwrap();
+                let mut node = state.lock().unwrap();
+                let reply = match command.as_str() {
+                    "/api/v0/add" => {
+                        let content_type = request.headers().iter().find(|header| header.field.equiv("Content-Type")).unwrap().value.to_string();
+                        let data = multipart::parse(body.as_slice(), &multipart::boundary(&content_type).unwrap()).unwrap().remove(0).data;
+                        let cid = format!("bafk{}", content_hash(&data));
+                        if !query.contains("only-hash=true") {
+                            node.objects.insert(cid.clone(), data);
+                        }
+                        Ok(format!("{{\"Name\":\"blob\",\"Hash\":\"{}\",\"Size\":\"0\"}}", cid).into_bytes())
+                    },
+                    "/api/v0/cat" => node.objects.get(&arg).cloned().ok_or("merkledag: not found"),
+                    "/api/v0/pin/add" if node.objects.contains_key(&arg) => {
+                        node.pins.insert(arg.clone());
+                        Ok(format!("{{\"Pins\":[\"{}\"]}}", arg).into_bytes())
+                    },
+                    "/api/v0/pin/rm" if node.pins.remove(&arg) => Ok(format!("{{\"Pins\":[\"{}\"]}}", arg).into_bytes()),
+                    _ => Err("not pinned or pinned indirectly"),
+                };
+                let response = match reply {
+                    Ok(data) => Response::from_data(data),
+                    Err(message) => Response::from_data(format!("{{\"Message\":\"{}\",\"Code\":0,\"Type\":\"error\"}}", message)).with_status_code(500),
+                };
+                request.respond(response).unwrap();
+            }
+        });
+        (url, node)
+    }
+
+    #[test]
+    fn test_blobs_round_trip_through_an_ipfs_node() {
+        let dir = tempdir().unwrap();
+        let (api_url, node) = start_mock_node();
+        let blobs = BlobStore::open_ipfs(&dir.path().join("assets"), IpfsConfig { api_url }).unwrap();
+
+        let cid = blobs.content_id(b"content").unwrap().unwrap();
+        assert!(node.lock().unwrap().objects.is_empty(), "Computing a CID stored the content");
+        blobs.write(1, b"content").unwrap();
+        blobs.write(2, b"content").unwrap();
+        blobs.write(3, b"other").unwrap();
+        assert_eq!(blobs.ids().unwrap(), vec![1, 2, 3]);
+        assert_eq!(CidIndex::open(&dir.path().join("assets")).get(1).unwrap(), Some(cid.clone()));
+        assert_eq!(blobs.read(2).unwrap(), Some(b"content".to_vec()));
+        assert_eq!(blobs.read(4).unwrap(), None);
+        assert!(node.lock().unwrap().pins.contains(&cid));
+
+        blobs.remove(1).unwrap();
+        assert!(node.lock().unwrap().pins.contains(&cid), "Unpinned content another blob still uses");
+        blobs.remove(2).unwrap();
+        assert!(!node.lock().unwrap().pins.contains(&cid));
+        blobs.write(3, b"changed").unwrap();
+        assert_eq!(node.lock().unwrap().pins.len(), 1);
+        assert_eq!(blobs.ids().unwrap(), vec![3]);
+
+        CidIndex::open(&dir.path().join("assets")).set(5, Some("bafkmissing".to_string())).unwrap();
+        assert!(matches!(blobs.read(5), Err(FileError::IOError(_))));
+    }
+}
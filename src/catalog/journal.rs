@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File as StdFile, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::format::FileV1;
+use crate::catalog::write_atomically;
+use crate::crypto;
+use crate::model::{File, FileError};
+use crate::permissions::deletion::{DeletionPolicy, PendingDeletion};
+use crate::permissions::transfer::OwnershipTransfer;
+
+/// A catalog change recorded before it is carried out. Each operation describes the
+/// intended end state, so replaying it after a crash is idempotent.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum JournalOp {
+    Store { file: Box<File>, content_hash: Option<String> },
+    /// Removes the file, along with the approvals its deletion policy asked for, if any.
+    Remove { file_id: i64, deletion: Option<Box<PendingDeletion>> },
+    /// Stores the file as its new owner holds it, along with the transfer both sides signed.
+    Transfer { file: Box<File>, content_hash: Option<String>, transfer: Box<OwnershipTransfer> },
+    /// Sets how many approvals deleting the file takes, leaving the file itself alone.
+    Policy { policy: DeletionPolicy },
+}
+
+impl JournalOp {
+    pub fn file_id(&self) -> i64 {
+        match self {
+            JournalOp::Store { file, .. } | JournalOp::Transfer { file, .. } => file.id,
+            JournalOp::Remove { file_id, .. } => *file_id,
+            JournalOp::Policy { policy } => policy.file_id,
+        }
+    }
+
+    /// The file record the operation stores and the hash of the blob stored with it, or
+    /// `None` for a removal or a policy.
+    pub fn stored(&self) -> Option<(&File, Option<&String>)> {
+        match self {
+            JournalOp::Store { file, content_hash } | JournalOp::Transfer { file, content_hash, .. } => Some((file, content_hash.as_ref())),
+            JournalOp::Remove { .. } | JournalOp::Policy { .. } => None,
+        }
+    }
+
+    /// Tells whether the operation sets what the file is, or that it is gone, rather than
+    /// a policy about it.
+    pub fn is_file_change(&self) -> bool {
+        !matches!(self, JournalOp::Policy { .. })
+    }
+}
+
+impl fmt::Display for JournalOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalOp::Store { file, .. } => write!(f, "store of file {}", file.id),
+            JournalOp::Remove { file_id, .. } => write!(f, "removal of file {}", file_id),
+            JournalOp::Transfer { file, transfer, .. } => write!(f, "transfer of file {} to identity {}", file.id, transfer.to),
+            JournalOp::Policy { policy } => write!(f, "deletion policy of file {}", policy.file_id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+enum JournalRecord {
+    Begin { seq: u64, op: JournalOp },
+    Done { seq: u64 },
+}
+
+/// `JournalOp` as it was laid out before `File` had a content hash.
+#[derive(Debug, Serialize, Deserialize)]
+enum JournalOpV1 {
+    Store { file: Box<FileV1>, content_hash: Option<String> },
+    Remove { file_id: i64 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum JournalRecordV1 {
+    Begin { seq: u64, op: JournalOpV1 },
+    Done { seq: u64 },
+}
+
+impl From<JournalRecordV1> for JournalRecord {
+    fn from(record: JournalRecordV1) -> Self {
+        match record {
+            JournalRecordV1::Begin { seq, op: JournalOpV1::Store { file, content_hash } } => {
+                JournalRecord::Begin { seq, op: JournalOp::Store { file: Box::new(File::from(*file)), content_hash } }
+            },
+            JournalRecordV1::Begin { seq, op: JournalOpV1::Remove { file_id } } => JournalRecord::Begin { seq, op: JournalOp::Remove { file_id, deletion: None } },
+            JournalRecordV1::Done { seq } => JournalRecord::Done { seq },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecoveryAction {
+    Completed(JournalOp),
+    RolledBack(JournalOp),
+}
+
+impl fmt::Display for RecoveryAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecoveryAction::Completed(op) => write!(f, "Completed interrupted {}.", op),
+            RecoveryAction::RolledBack(op) => write!(f, "Rolled back interrupted {}.", op),
+        }
+    }
+}
+
+/// Append-only write-ahead journal kept next to the catalog. Every record is framed
+/// by its length, so a record torn by a crash is detected and ignored, and is encrypted
+/// whenever the catalog is.
+pub struct Journal {
+    catalog_path: PathBuf,
+    file: StdFile,
+    next_seq: u64,
+    pending: BTreeMap<u64, JournalOp>,
+}
+
+impl Journal {
+    pub fn open(catalog_path: &Path) -> Result<Self, FileError> {
+        let path = get_journal_path(catalog_path);
+        let mut file = OpenOptions::new().create(true).truncate(false).read(true).append(true).open(&path)?;
+        let mut encoded = Vec::new();
+        file.read_to_end(&mut encoded)?;
+        let mut next_seq = 0;
+        let mut pending = BTreeMap::new();
+        let legacy = |payload: &[u8]| bincode::deserialize::<JournalRecordV1>(payload).ok().map(JournalRecord::from);
+        let (records, valid_len) = decode_records_with(catalog_path, &encoded, legacy)?;
+        if valid_len < encoded.len() {
+            file.set_len(valid_len as u64)?;
+        }
+        for record in records {
+            match record {
+                JournalRecord::Begin { seq, op } => {
+                    next_seq = next_seq.max(seq + 1);
+                    pending.insert(seq, op);
+                },
+                JournalRecord::Done { seq } => {
+                    pending.remove(&seq);
+                },
+            }
+        }
+        Ok(Journal { catalog_path: catalog_path.to_path_buf(), file, next_seq, pending })
+    }
+
+    pub fn pending(&self) -> Vec<(u64, JournalOp)> {
+        self.pending.iter().map(|(seq, op)| (*seq, op.clone())).collect()
+    }
+
+    pub fn begin(&mut self, op: &JournalOp) -> Result<u64, FileError> {
+        let seq = self.next_seq;
+        self.append(&JournalRecord::Begin { seq, op: op.clone() })?;
+        self.next_seq += 1;
+        self.pending.insert(seq, op.clone());
+        Ok(seq)
+    }
+
+    /// Marks an operation as settled, whether it was carried out or abandoned, and
+    /// empties the journal once nothing is left pending.
+    pub fn finish(&mut self, seq: u64) -> Result<(), FileError> {
+        self.append(&JournalRecord::Done { seq })?;
+        self.pending.remove(&seq);
+        if self.pending.is_empty() {
+            self.file.set_len(0)?;
+            self.file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, record: &JournalRecord) -> Result<(), FileError> {
+        self.file.write_all(&encode_record(&self.catalog_path, record)?)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Every record written since records were versioned starts with these bytes, once
+/// unsealed. Unversioned records start with a variant index or a height, which in
+/// practice never spell them.
+const RECORD_MAGIC: &[u8; 4] = b"UREC";
+
+/// Format written by `encode_record`. Unversioned records count as version 1. When a
+/// record type changes, bump this and give its reader a decoder for the previous layout.
+pub const RECORD_VERSION: u32 = 2;
+
+const RECORD_HEADER_LEN: usize = RECORD_MAGIC.len() + 4;
+
+/// Serializes a record for an append-only file next to the catalog, sealed whenever the
+/// catalog is and framed by its length.
+pub(crate) fn encode_record<T: Serialize>(catalog_path: &Path, record: &T) -> Result<Vec<u8>, FileError> {
+    let mut encoded = RECORD_MAGIC.to_vec();
+    encoded.extend_from_slice(&RECORD_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut encoded, record).map_err(|_| FileError::DeserializationError("Record serialization failed".to_string()))?;
+    let encoded = crypto::seal(catalog_path, &encoded)?;
+    let mut frame = (encoded.len() as u32).to_le_bytes().to_vec();
+    frame.extend_from_slice(&encoded);
+    Ok(frame)
+}
+
+/// Decodes the records of an append-only file and returns them with the length they take
+/// up, which leaves out a final record torn by a crash.
+pub(crate) fn decode_records<T: DeserializeOwned>(catalog_path: &Path, encoded: &[u8]) -> Result<(Vec<T>, usize), FileError> {
+    decode_records_with(catalog_path, encoded, |_| None)
+}
+
+/// Like `decode_records`, with `legacy` reading unversioned records whose layout is older
+/// than the current one. Only a frame cut short at the end counts as torn: a complete
+/// record that fails authentication or that no decoder can read is an error, so that it
+/// is never truncated away.
+pub(crate) fn decode_records_with<T: DeserializeOwned>(
+    catalog_path: &Path, encoded: &[u8], legacy: impl Fn(&[u8]) -> Option<T>,
+) -> Result<(Vec<T>, usize), FileError> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while let Some(header) = encoded.get(offset..offset + 4) {
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let Some(frame) = encoded.get(offset + 4..offset + 4 + len) else { break };
+        records.push(decode_record(&crypto::open(catalog_path, frame)?, &legacy)?);
+        offset += 4 + len;
+    }
+    Ok((records, offset))
+}
+
+/// Seals the records of the file at `path` that were written before the repository was
+/// encrypted, dropping a torn final record. Returns whether any needed sealing.
+pub(crate) fn seal_plain_records(catalog_path: &Path, path: &Path) -> Result<bool, FileError> {
+    let encoded = match std::fs::read(path) {
+        Ok(encoded) => encoded,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(FileError::IOError(e)),
+    };
+    let mut resealed = Vec::with_capacity(encoded.len());
+    let mut sealed_any = false;
+    let mut offset = 0;
+    while let Some(header) = encoded.get(offset..offset + 4) {
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let Some(frame) = encoded.get(offset + 4..offset + 4 + len) else { break };
+        let frame = if crypto::is_sealed(frame) {
+            frame.to_vec()
+        } else {
+            sealed_any = true;
+            crypto::seal(catalog_path, frame)?
+        };
+        resealed.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        resealed.extend_from_slice(&frame);
+        offset += 4 + len;
+    }
+    if sealed_any {
+        write_atomically(path, &resealed)?;
+    }
+    Ok(sealed_any)
+}
+
+fn decode_record<T: DeserializeOwned>(payload: &[u8], legacy: &impl Fn(&[u8]) -> Option<T>) -> Result<T, FileError> {
+    let version = get_record_version(payload);
+    match version {
+        1 => bincode::deserialize(payload).ok().or_else(|| legacy(payload))
+            .ok_or_else(|| FileError::DeserializationError("A record was written in an unversioned layout that can no longer be read".to_string())),
+        RECORD_VERSION => bincode::deserialize(&payload[RECORD_HEADER_LEN..])
+            .map_err(|_| FileError::DeserializationError(format!("Failed to deserialize a record (format version {})", version))),
+        _ => Err(FileError::DeserializationError(format!("Unsupported record format version {}", version))),
+    }
+}
+
+fn get_record_version(payload: &[u8]) -> u32 {
+    match (payload.get(..RECORD_MAGIC.len()), payload.get(RECORD_MAGIC.len()..RECORD_HEADER_LEN)) {
+        (Some(magic), Some(version)) if magic == RECORD_MAGIC => u32::from_le_bytes([version[0], version[1], version[2], version[3]]),
+        _ => 1,
+    }
+}
+
+pub fn get_journal_path(catalog_path: &Path) -> PathBuf {
+    let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".journal");
+    catalog_path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+    use tempfile::tempdir;
+
+    use crate::model::FileType;
+    use crate::utils::generate_fake_hash;
+
+    #[test]
+    fn test_pending_operations_survive_reopen() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        let mut journal = Journal::open(&catalog_path).unwrap();
+        let first = journal.begin(&JournalOp::Remove { file_id: 1, deletion: None }).unwrap();
+        journal.begin(&JournalOp::Remove { file_id: 2, deletion: None }).unwrap();
+        journal.finish(first).unwrap();
+        drop(journal);
+        let journal = Journal::open(&catalog_path).unwrap();
+        assert_eq!(journal.pending(), vec![(1, JournalOp::Remove { file_id: 2, deletion: None })]);
+    }
+
+    #[test]
+    fn test_torn_record_is_ignored_and_journal_empties() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        let mut journal = Journal::open(&catalog_path).unwrap();
+        let seq = journal.begin(&JournalOp::Remove { file_id: 1, deletion: None }).unwrap();
+        drop(journal);
+        let mut file = OpenOptions::new().append(true).open(get_journal_path(&catalog_path)).unwrap();
+        file.write_all(&[200, 0, 0, 0, 1, 2]).unwrap();
+        let mut journal = Journal::open(&catalog_path).unwrap();
+        assert_eq!(journal.pending().len(), 1);
+        journal.begin(&JournalOp::Remove { file_id: 2, deletion: None }).unwrap();
+        assert_eq!(Journal::open(&catalog_path).unwrap().pending().len(), 2);
+        journal.finish(seq).unwrap();
+        journal.finish(seq + 1).unwrap();
+        assert_eq!(std::fs::metadata(get_journal_path(&catalog_path)).unwrap().len(), 0);
+    }
+
+    fn append_raw(catalog_path: &Path, payload: &[u8]) {
+        let sealed = crypto::seal(catalog_path, payload).unwrap();
+        let mut file = OpenOptions::new().create(true).append(true).open(get_journal_path(catalog_path)).unwrap();
+        file.write_all(&(sealed.len() as u32).to_le_bytes()).unwrap();
+        file.write_all(&sealed).unwrap();
+    }
+
+    #[test]
+    fn test_unversioned_records_in_the_old_layout_are_read() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        let owner = (1, String::from("Username"), String::from("username@gmail.com"));
+        let file = File {
+            id: 1, name: "test-file".to_string(), file_type: FileType::Pdf, size: 100,
+            created: Utc::now().naive_utc(), modified: None, accessed: None, owner: owner.clone(),
+            people_with_access: vec![owner], ipfs_hash: generate_fake_hash(46),
+            onchain_txn_id: generate_fake_hash(64), download_permission: false, description: None,
+            content_hash: None,
+        };
+        let store = JournalRecordV1::Begin { seq: 4, op: JournalOpV1::Store { file: Box::new(FileV1::from(&file)), content_hash: Some("abc".to_string()) } };
+        append_raw(&catalog_path, &bincode::serialize(&store).unwrap());
+        append_raw(&catalog_path, &bincode::serialize(&JournalRecord::Begin { seq: 5, op: JournalOp::Remove { file_id: 2, deletion: None } }).unwrap());
+        let journal = Journal::open(&catalog_path).unwrap();
+        let expected = JournalOp::Store { file: Box::new(file), content_hash: Some("abc".to_string()) };
+        assert_eq!(journal.pending(), vec![(4, expected), (5, JournalOp::Remove { file_id: 2, deletion: None })]);
+    }
+
+    #[test]
+    fn test_unknown_record_version_fails_without_truncating() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        Journal::open(&catalog_path).unwrap().begin(&JournalOp::Remove { file_id: 1, deletion: None }).unwrap();
+        let mut payload = RECORD_MAGIC.to_vec();
+        payload.extend_from_slice(&(RECORD_VERSION + 1).to_le_bytes());
+        append_raw(&catalog_path, &payload);
+        let len = std::fs::metadata(get_journal_path(&catalog_path)).unwrap().len();
+        assert!(matches!(Journal::open(&catalog_path), Err(FileError::DeserializationError(_))));
+        assert_eq!(std::fs::metadata(get_journal_path(&catalog_path)).unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_tampered_record_fails_without_truncating() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        crypto::initialize(&catalog_path, "passphrase").unwrap();
+        let mut journal = Journal::open(&catalog_path).unwrap();
+        journal.begin(&JournalOp::Remove { file_id: 1, deletion: None }).unwrap();
+        journal.begin(&JournalOp::Remove { file_id: 2, deletion: None }).unwrap();
+        drop(journal);
+        let path = get_journal_path(&catalog_path);
+        let mut encoded = std::fs::read(&path).unwrap();
+        encoded[10] ^= 1;
+        std::fs::write(&path, &encoded).unwrap();
+        assert!(matches!(Journal::open(&catalog_path), Err(FileError::IntegrityError(_))));
+        assert_eq!(std::fs::read(&path).unwrap(), encoded);
+    }
+
+    #[test]
+    fn test_plain_records_are_sealed_when_the_repository_is_encrypted() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        Journal::open(&catalog_path).unwrap().begin(&JournalOp::Remove { file_id: 1, deletion: None }).unwrap();
+        crypto::initialize(&catalog_path, "passphrase").unwrap();
+        assert!(matches!(Journal::open(&catalog_path), Err(FileError::IntegrityError(_))));
+        assert!(seal_plain_records(&catalog_path, &get_journal_path(&catalog_path)).unwrap());
+        assert_eq!(Journal::open(&catalog_path).unwrap().pending(), vec![(0, JournalOp::Remove { file_id: 1, deletion: None })]);
+    }
+}
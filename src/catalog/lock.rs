@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fs::{File as StdFile, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use fs2::FileExt;
+
+use crate::model::FileError;
+
+/// How long a thread waits for another thread of the same process to release a catalog.
+const IN_PROCESS_WAIT: Duration = Duration::from_secs(30);
+
+/// The catalogs locked by this process, with the thread holding each. The file lock alone
+/// cannot tell threads apart, so threads queue here before taking it.
+static HELD: Mutex<Option<HashMap<PathBuf, ThreadId>>> = Mutex::new(None);
+static RELEASED: Condvar = Condvar::new();
+
+/// Exclusive advisory lock on a catalog, held for one read-modify-write cycle and
+/// released when the guard is dropped.
+pub struct CatalogLock {
+    file: StdFile,
+    path: PathBuf,
+}
+
+impl CatalogLock {
+    /// Takes the lock, waiting for other threads of this process that hold it. Fails at
+    /// once when another process holds it, or when this thread already does.
+    pub fn acquire(catalog_path: &Path) -> Result<Self, FileError> {
+        let path = get_lock_path(catalog_path);
+        let locked = || FileError::RepositoryLocked(path.display().to_string());
+        let deadline = Instant::now() + IN_PROCESS_WAIT;
+        let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            match held.get_or_insert_with(HashMap::new).get(&path) {
+                None => break,
+                Some(holder) if *holder == thread::current().id() => return Err(locked()),
+                Some(_) => {},
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(locked());
+            }
+            held = RELEASED.wait_timeout(held, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(&path)?;
+        match file.try_lock_exclusive() {
+            Ok(()) => {},
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() || e.kind() == io::ErrorKind::WouldBlock => return Err(locked()),
+            Err(e) => return Err(FileError::IOError(e)),
+        }
+        held.get_or_insert_with(HashMap::new).insert(path.clone(), thread::current().id());
+        Ok(CatalogLock { file, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for CatalogLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+        let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(held) = held.as_mut() {
+            held.remove(&self.path);
+        }
+        RELEASED.notify_all();
+    }
+}
+
+pub fn get_lock_path(catalog_path: &Path) -> PathBuf {
+    let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".lock");
+    catalog_path.with_file_name(name)
+}
+
+/// Replaces `path` with `contents` so that readers and crashes only ever observe the old
+/// or the new version: the bytes go to a sibling temporary file, are synced, and the
+/// temporary file is then renamed over the target.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), FileError> {
+    let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".tmp");
+    let temp_path = path.with_file_name(name);
+    {
+        let mut temp = StdFile::create(&temp_path)?;
+        temp.write_all(contents)?;
+        temp.sync_all()?;
+    }
+    std::fs::rename(&temp_path, path)?;
+    sync_parent_dir(path)
+}
+
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<(), FileError> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    StdFile::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<(), FileError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lock_fails_fast_when_taken_again() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        let lock = CatalogLock::acquire(&catalog_path).unwrap();
+        assert_eq!(lock.path(), dir.path().join("assets.lock"));
+        let second = CatalogLock::acquire(&catalog_path);
+        assert!(matches!(second, Err(FileError::RepositoryLocked(_))), "Expected a locked error: {:?}", second.err());
+        drop(lock);
+        assert!(CatalogLock::acquire(&catalog_path).is_ok());
+    }
+
+    #[test]
+    fn test_threads_wait_for_each_other() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        let lock = CatalogLock::acquire(&catalog_path).unwrap();
+        let waiter = {
+            let catalog_path = catalog_path.clone();
+            thread::spawn(move || CatalogLock::acquire(&catalog_path).map(drop))
+        };
+        thread::sleep(Duration::from_millis(50));
+        drop(lock);
+        assert!(waiter.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_write_atomically_replaces_contents() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("assets");
+        std::fs::write(&path, b"old").unwrap();
+        write_atomically(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!dir.path().join("assets.tmp").exists());
+    }
+}
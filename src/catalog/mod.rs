@@ -0,0 +1,54 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+pub mod blobs;
+pub mod bucket;
+pub mod format;
+pub mod index;
+pub mod ipfs;
+pub mod journal;
+pub mod lock;
+pub mod sqlite;
+
+pub use blobs::{content_hash, BlobStore};
+pub use index::IndexedCatalog;
+pub use journal::{Journal, JournalOp, RecoveryAction};
+pub use lock::{write_atomically, CatalogLock};
+pub use sqlite::{HistoryEntry, SqliteCatalog};
+
+use crate::model::FileError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Bincode,
+    Sqlite,
+}
+
+pub fn get_backend() -> Backend {
+    match env::var("CATALOG_BACKEND").map(|value| value.trim().to_lowercase()) {
+        Ok(value) if value == "sqlite" => Backend::Sqlite,
+        _ => Backend::Bincode,
+    }
+}
+
+pub fn get_database_path(bincode_path: &Path) -> PathBuf {
+    env::var("CATALOG_DB_PATH").map(PathBuf::from).unwrap_or_else(|_| bincode_path.with_extension("db"))
+}
+
+/// Opens the SQLite catalog, importing the bincode one on first use. Encrypted repositories
+/// are refused, since the database is stored in plain text.
+pub fn open_sqlite_catalog(bincode_path: &Path) -> Result<SqliteCatalog, FileError> {
+    if crate::crypto::is_encrypted(bincode_path) {
+        return Err(FileError::InputError("The repository is encrypted, which only the bincode catalog supports".to_string()));
+    }
+    let mut catalog = SqliteCatalog::open(&get_database_path(bincode_path))?;
+    if !catalog.is_migrated()? {
+        let imported = catalog.migrate_from_bincode(bincode_path)?;
+        if imported > 0 {
+            info!("Migrated {} files from {:?} into the SQLite catalog.", imported, bincode_path);
+        }
+    }
+    Ok(catalog)
+}
@@ -0,0 +1,322 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{NaiveDateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+
+use crate::load_files_from_file;
+use crate::model::{File, FileError, FileType};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS files (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        file_type TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        created TEXT NOT NULL,
+        modified TEXT,
+        accessed TEXT,
+        owner_id INTEGER NOT NULL,
+        owner_name TEXT NOT NULL,
+        owner_email TEXT NOT NULL,
+        ipfs_hash TEXT NOT NULL,
+        onchain_txn_id TEXT NOT NULL,
+        download_permission INTEGER NOT NULL,
+        description TEXT,
+        content_hash TEXT
+    );
+    CREATE INDEX IF NOT EXISTS idx_files_owner ON files(owner_id);
+    CREATE INDEX IF NOT EXISTS idx_files_name ON files(name);
+    CREATE TABLE IF NOT EXISTS access_grants (
+        file_id INTEGER NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        person_id INTEGER NOT NULL,
+        person_name TEXT NOT NULL,
+        person_email TEXT NOT NULL,
+        PRIMARY KEY (file_id, position)
+    );
+    CREATE INDEX IF NOT EXISTS idx_access_grants_person ON access_grants(person_id);
+    CREATE TABLE IF NOT EXISTS history (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        file_id INTEGER NOT NULL,
+        action TEXT NOT NULL,
+        at TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_history_file ON history(file_id);
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+const CONTENT_HASH_INDEX: &str = "CREATE INDEX IF NOT EXISTS idx_files_content_hash ON files(content_hash);";
+
+const FILE_COLUMNS: &str = "id, name, file_type, size, created, modified, accessed, owner_id, owner_name, owner_email, ipfs_hash, onchain_txn_id, download_permission, description, content_hash";
+
+const MIGRATED_KEY: &str = "migrated_from_bincode";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub file_id: i64,
+    pub action: String,
+    pub at: NaiveDateTime,
+}
+
+pub struct SqliteCatalog {
+    conn: Connection,
+}
+
+impl SqliteCatalog {
+    pub fn open(path: &Path) -> Result<Self, FileError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, FileError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, FileError> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.execute_batch(SCHEMA)?;
+        let has_content_hash = conn.prepare("SELECT content_hash FROM files LIMIT 0").is_ok();
+        if !has_content_hash {
+            conn.execute_batch("ALTER TABLE files ADD COLUMN content_hash TEXT;")?;
+        }
+        conn.execute_batch(CONTENT_HASH_INDEX)?;
+        Ok(SqliteCatalog { conn })
+    }
+
+    pub fn insert_file(&mut self, file: &File) -> Result<(), FileError> {
+        let tx = self.conn.transaction()?;
+        let exists: Option<i64> = tx.query_row("SELECT id FROM files WHERE id = ?1", [file.id], |row| row.get(0)).optional()?;
+        if exists.is_some() {
+            return Err(FileError::FileAlreadyExists);
+        }
+        write_file(&tx, file)?;
+        record_history(&tx, file.id, "created")?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_file(&self, file_id: i64) -> Result<Option<File>, FileError> {
+        let sql = format!("SELECT {} FROM files WHERE id = ?1", FILE_COLUMNS);
+        let file = self.conn.query_row(&sql, [file_id], read_file).optional()?;
+        match file {
+            Some(mut file) => {
+                file.people_with_access = read_access_grants(&self.conn, file.id)?;
+                Ok(Some(file))
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn touch_file(&mut self, file_id: i64) -> Result<File, FileError> {
+        let tx = self.conn.transaction()?;
+        let now = Utc::now().naive_utc();
+        if tx.execute("UPDATE files SET accessed = ?1 WHERE id = ?2", params![now, file_id])? == 0 {
+            return Err(FileError::FileNotFound);
+        }
+        record_history(&tx, file_id, "accessed")?;
+        tx.commit()?;
+        self.get_file(file_id)?.ok_or(FileError::FileNotFound)
+    }
+
+    pub fn update_file(&mut self, file: &File) -> Result<(), FileError> {
+        let tx = self.conn.transaction()?;
+        if tx.execute("DELETE FROM files WHERE id = ?1", [file.id])? == 0 {
+            return Err(FileError::FileNotFound);
+        }
+        write_file(&tx, file)?;
+        record_history(&tx, file.id, "modified")?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_file(&mut self, file_id: i64) -> Result<(), FileError> {
+        let tx = self.conn.transaction()?;
+        if tx.execute("DELETE FROM files WHERE id = ?1", [file_id])? == 0 {
+            return Err(FileError::FileNotFound);
+        }
+        record_history(&tx, file_id, "removed")?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn list_files(&self) -> Result<Vec<File>, FileError> {
+        self.query_files(&format!("SELECT {} FROM files ORDER BY id", FILE_COLUMNS), params![])
+    }
+
+    pub fn files_by_owner(&self, owner_id: i64) -> Result<Vec<File>, FileError> {
+        self.query_files(&format!("SELECT {} FROM files WHERE owner_id = ?1 ORDER BY id", FILE_COLUMNS), params![owner_id])
+    }
+
+    pub fn files_by_name(&self, name: &str) -> Result<Vec<File>, FileError> {
+        self.query_files(&format!("SELECT {} FROM files WHERE name = ?1 ORDER BY id", FILE_COLUMNS), params![name])
+    }
+
+    pub fn files_by_content_hash(&self, content_hash: &str) -> Result<Vec<File>, FileError> {
+        self.query_files(&format!("SELECT {} FROM files WHERE content_hash = ?1 ORDER BY id", FILE_COLUMNS), params![content_hash])
+    }
+
+    pub fn file_history(&self, file_id: i64) -> Result<Vec<HistoryEntry>, FileError> {
+        let mut stmt = self.conn.prepare("SELECT file_id, action, at FROM history WHERE file_id = ?1 ORDER BY seq")?;
+        let entries = stmt.query_map([file_id], |row| Ok(HistoryEntry { file_id: row.get(0)?, action: row.get(1)?, at: row.get(2)? }))?;
+        entries.collect::<Result<Vec<_>, _>>().map_err(FileError::from)
+    }
+
+    pub fn is_migrated(&self) -> Result<bool, FileError> {
+        let value: Option<String> = self.conn.query_row("SELECT value FROM meta WHERE key = ?1", [MIGRATED_KEY], |row| row.get(0)).optional()?;
+        Ok(value.is_some())
+    }
+
+    /// Imports every record of a bincode catalog in a single transaction and marks the
+    /// database as migrated, so the import only ever runs once per database.
+    pub fn migrate_from_bincode(&mut self, path: &Path) -> Result<usize, FileError> {
+        let files = if path.exists() { load_files_from_file(&path.to_path_buf())? } else { Vec::new() };
+        let tx = self.conn.transaction()?;
+        let mut imported = 0;
+        for file in &files {
+            let exists: Option<i64> = tx.query_row("SELECT id FROM files WHERE id = ?1", [file.id], |row| row.get(0)).optional()?;
+            if exists.is_none() {
+                write_file(&tx, file)?;
+                record_history(&tx, file.id, "migrated")?;
+                imported += 1;
+            }
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+            params![MIGRATED_KEY, Utc::now().naive_utc().to_string()],
+        )?;
+        tx.commit()?;
+        Ok(imported)
+    }
+
+    fn query_files(&self, sql: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<File>, FileError> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let mut files = stmt.query_map(params, read_file)?.collect::<Result<Vec<_>, _>>()?;
+        for file in files.iter_mut() {
+            file.people_with_access = read_access_grants(&self.conn, file.id)?;
+        }
+        Ok(files)
+    }
+}
+
+fn write_file(tx: &Transaction, file: &File) -> Result<(), FileError> {
+    tx.execute(
+        &format!("INSERT INTO files ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)", FILE_COLUMNS),
+        params![
+            file.id, file.name, file.file_type.to_string(), file.size as i64, file.created, file.modified, file.accessed,
+            file.owner.0, file.owner.1, file.owner.2, file.ipfs_hash, file.onchain_txn_id, file.download_permission, file.description,
+            file.content_hash
+        ],
+    )?;
+    for (position, (person_id, name, email)) in file.people_with_access.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO access_grants (file_id, position, person_id, person_name, person_email) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![file.id, position as i64, person_id, name, email],
+        )?;
+    }
+    Ok(())
+}
+
+fn record_history(tx: &Transaction, file_id: i64, action: &str) -> Result<(), FileError> {
+    tx.execute("INSERT INTO history (file_id, action, at) VALUES (?1, ?2, ?3)", params![file_id, action, Utc::now().naive_utc()])?;
+    Ok(())
+}
+
+fn read_file(row: &Row) -> rusqlite::Result<File> {
+    let file_type: String = row.get(2)?;
+    let size: i64 = row.get(3)?;
+    Ok(File {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        file_type: FileType::from_str(&file_type).unwrap_or(FileType::Unknown),
+        size: size as u64,
+        created: row.get(4)?,
+        modified: row.get(5)?,
+        accessed: row.get(6)?,
+        owner: (row.get(7)?, row.get(8)?, row.get(9)?),
+        people_with_access: Vec::new(),
+        ipfs_hash: row.get(10)?,
+        onchain_txn_id: row.get(11)?,
+        download_permission: row.get(12)?,
+        description: row.get(13)?,
+        content_hash: row.get(14)?,
+    })
+}
+
+fn read_access_grants(conn: &Connection, file_id: i64) -> Result<Vec<(i64, String, String)>, FileError> {
+    let mut stmt = conn.prepare("SELECT person_id, person_name, person_email FROM access_grants WHERE file_id = ?1 ORDER BY position")?;
+    let grants = stmt.query_map([file_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    grants.collect::<Result<Vec<_>, _>>().map_err(FileError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use crate::utils::generate_fake_hash;
+
+    fn get_test_file(id: i64, name: &str, owner_id: i64) -> File {
+        let owner = (owner_id, String::from("Username"), String::from("username@gmail.com"));
+        File {
+            id, name: name.to_string(), file_type: FileType::Pdf, size: 100,
+            created: Utc::now().naive_utc(), modified: None, accessed: None, owner: owner.clone(),
+            people_with_access: vec![owner, (7, String::from("Guest"), String::from("guest@gmail.com"))],
+            ipfs_hash: generate_fake_hash(46), onchain_txn_id: generate_fake_hash(64),
+            download_permission: true, description: Some("report".to_string()),
+            content_hash: Some(generate_fake_hash(64))
+        }
+    }
+
+    #[test]
+    fn test_insert_and_get_file() {
+        let mut catalog = SqliteCatalog::open_in_memory().unwrap();
+        let file = get_test_file(1, "test-file", 1);
+        catalog.insert_file(&file).unwrap();
+        assert_eq!(catalog.get_file(1).unwrap(), Some(file.clone()));
+        assert_eq!(catalog.insert_file(&file), Err(FileError::FileAlreadyExists));
+    }
+
+    #[test]
+    fn test_indexed_queries() {
+        let mut catalog = SqliteCatalog::open_in_memory().unwrap();
+        catalog.insert_file(&get_test_file(1, "a", 10)).unwrap();
+        catalog.insert_file(&get_test_file(2, "b", 10)).unwrap();
+        catalog.insert_file(&get_test_file(3, "a", 20)).unwrap();
+        let by_owner: Vec<i64> = catalog.files_by_owner(10).unwrap().iter().map(|f| f.id).collect();
+        let by_name: Vec<i64> = catalog.files_by_name("a").unwrap().iter().map(|f| f.id).collect();
+        assert_eq!(by_owner, vec![1, 2]);
+        assert_eq!(by_name, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_update_touch_remove_and_history() {
+        let mut catalog = SqliteCatalog::open_in_memory().unwrap();
+        let mut file = get_test_file(1, "test-file", 1);
+        catalog.insert_file(&file).unwrap();
+        file.people_with_access.truncate(1);
+        catalog.update_file(&file).unwrap();
+        assert_eq!(catalog.touch_file(1).unwrap().people_with_access.len(), 1);
+        catalog.remove_file(1).unwrap();
+        assert_eq!(catalog.get_file(1).unwrap(), None);
+        assert_eq!(catalog.remove_file(1), Err(FileError::FileNotFound));
+        let actions: Vec<String> = catalog.file_history(1).unwrap().into_iter().map(|entry| entry.action).collect();
+        assert_eq!(actions, vec!["created", "modified", "accessed", "removed"]);
+    }
+
+    #[test]
+    fn test_migrate_from_bincode_runs_once() {
+        let dir = tempdir().unwrap();
+        let bincode_path = dir.path().join("assets");
+        let files = vec![get_test_file(1, "a", 1), get_test_file(2, "b", 1)];
+        std::fs::write(&bincode_path, crate::catalog::format::encode_catalog(&files).unwrap()).unwrap();
+        let mut catalog = SqliteCatalog::open(&dir.path().join("assets.db")).unwrap();
+        assert!(!catalog.is_migrated().unwrap());
+        assert_eq!(catalog.migrate_from_bincode(&bincode_path).unwrap(), 2);
+        assert!(catalog.is_migrated().unwrap());
+        assert_eq!(catalog.list_files().unwrap(), files);
+    }
+}
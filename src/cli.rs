@@ -1,46 +1,53 @@
 use std::io::{self, Write};
 use log::{info, warn};
 
-use unichain::commands::{list_files, view_file, store_file, update_file, delete_file};
+use unichain::commands::{list_files, view_file, store_file, update_file, delete_file, prove_anchor, review_deletions, require_approvals, transfer_ownership, review_transfers, review_access_requests, report_access_outcomes, save_file_receipt};
 use unichain::model::FileError;
 use unichain::utils::get_system_owner;
 
 pub fn run() -> Result<(), FileError> {
     let (_, username, email) = get_system_owner();
     println!("\n\t\tWelcome to your UniChain!\n\nusername: {}\ne-mail: {} ", username, email);
+    report_access_outcomes()?;
     loop {
         print_menu_options();
         match get_choosed_option()? {
-            option => match option {
-                0 => return Ok({
-                    print!("\n");
-                    info!("Exiting.\n")
-                }),
-                1 => list_files()?,
-                2 => view_file()?,
-                3 => store_file()?,
-                4 => update_file()?,
-                5 => delete_file()?,
-                _ => unreachable!(),
-            }
+            0 => {
+                println!();
+                info!("Exiting.\n");
+                return Ok(());
+            },
+            1 => list_files()?,
+            2 => view_file()?,
+            3 => store_file()?,
+            4 => update_file()?,
+            5 => delete_file()?,
+            6 => prove_anchor()?,
+            7 => review_deletions()?,
+            8 => require_approvals()?,
+            9 => transfer_ownership()?,
+            10 => review_transfers()?,
+            11 => review_access_requests()?,
+            12 => save_file_receipt()?,
+            _ => unreachable!(),
         }
     }
 }
 
 fn print_menu_options() {
     println!("\nWhat do you want to do?\n");
-    println!("1. View list of stored files\n2. View a specific file\n3. Store a new file\n4. Update an existing file\n5. Move a file to trash\n0. Exit");
+    println!("1. View list of stored files\n2. View a specific file\n3. Store a new file\n4. Update an existing file\n5. Move a file to trash\n6. Prove a change was anchored\n7. Review pending deletions\n8. Require approvals to delete a file\n9. Transfer ownership of a file\n10. Review ownership transfers\n11. Review access requests\n12. Save the receipt of a file\n0. Exit");
 }
 
 fn get_choosed_option() -> Result<u8, FileError> {
     loop {
-        print!("\nChoose an option (0-5): ");
-        io::stdout().flush().map_err(|e| FileError::IOError(e))?;
+        print!("\nChoose an option (0-12): ");
+        io::stdout().flush().map_err(FileError::IOError)?;
         let mut choosed_option = String::new();
-        io::stdin().read_line(&mut choosed_option).map_err(|e| FileError::IOError(e))?;
+        io::stdin().read_line(&mut choosed_option).map_err(FileError::IOError)?;
         match choosed_option.trim().parse::<u8>() {
-            Ok(num) if (0..=5).contains(&num) => return Ok(num),
-            Ok(_) => warn!("The number must be between 0 and 5."),
+            Ok(num) if (0..=12).contains(&num) => return Ok(num),
+            Ok(_) => warn!("The number must be between 0 and 12."),
             Err(_) => warn!("Invalid digit found in string, please enter a number.")
         }
     }
@@ -0,0 +1,64 @@
+use log::{info, warn};
+
+use crate::{decide_access_request, find_file, list_access_requests, request_access, take_access_outcomes};
+use crate::commands::update::ask_yes_no;
+use crate::model::FileError;
+use crate::permissions::access::AccessStatus;
+use crate::utils::{get_system_owner, process_input};
+
+/// Offers to ask the owner of a file the user cannot see for access to it.
+pub(crate) fn offer_access_request(file_id: i64) -> Result<(), FileError> {
+    if !ask_yes_no("Do you want to ask the owner for access? (Y/N): ")? {
+        return Ok(());
+    }
+    let reason = process_input("Reason: ", false)?.unwrap_or_default();
+    println!();
+    match request_access(file_id, get_system_owner(), &reason) {
+        Ok(_) => info!("Your request for file ID {:?} was sent to its owner.", file_id),
+        Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+        Err(e) => warn!("The request could not be sent: {}", e),
+    }
+    Ok(())
+}
+
+/// Lists the pending requests for access to the user's files, and approves or denies one.
+pub fn review_access_requests() -> Result<(), FileError> {
+    let owner_id = get_system_owner().0;
+    let pending: Vec<_> = list_access_requests()?.into_iter()
+        .filter(|request| request.status == AccessStatus::Pending)
+        .filter(|request| find_file(request.file_id).is_ok_and(|file| file.owner.0 == owner_id))
+        .collect();
+    if pending.is_empty() {
+        println!();
+        info!("No access requests are waiting for you.");
+        return Ok(());
+    }
+    println!("\nPending access requests:\n{}", serde_json::to_string_pretty(&pending).unwrap());
+    let Some(request_id) = process_input("\nRequest ID to answer (leave empty to go back): ", true)? else { return Ok(()) };
+    let Ok(request_id) = request_id.parse::<i64>() else {
+        println!();
+        warn!("Invalid ID number.");
+        return Ok(());
+    };
+    let approved = ask_yes_no("Grant access? Answering N denies it. (Y/N): ")?;
+    println!();
+    match decide_access_request(request_id, owner_id, approved) {
+        Ok(request) if approved => info!("{} <{}> can now view file ID {:?}.", request.requester.1, request.requester.2, request.file_id),
+        Ok(request) => info!("The request of {} <{}> was denied.", request.requester.1, request.requester.2),
+        Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+        Err(FileError::PermissionDenied) => warn!("Only the owner can answer this request."),
+        Err(e) => warn!("The request could not be answered: {}", e),
+    }
+    Ok(())
+}
+
+/// Tells the user how the owners answered their access requests since they last looked.
+pub fn report_access_outcomes() -> Result<(), FileError> {
+    for request in take_access_outcomes(get_system_owner().0)? {
+        match request.status {
+            AccessStatus::Approved => info!("Your request for file ID {:?} was approved.", request.file_id),
+            _ => warn!("Your request for file ID {:?} was denied.", request.file_id),
+        }
+    }
+    Ok(())
+}
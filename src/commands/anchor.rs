@@ -0,0 +1,32 @@
+use log::{info, warn};
+use serde_json;
+
+use crate::ledger::anchor::AnchorConfig;
+use crate::model::FileError;
+use crate::prove_anchored;
+use crate::utils::process_input;
+
+pub fn prove_anchor() -> Result<(), FileError> {
+    let Some(config) = AnchorConfig::from_env() else {
+        println!();
+        warn!("Set UNICHAIN_ANCHOR_RPC to the EVM node the ledger is anchored in.");
+        return Ok(());
+    };
+    let transaction_id = process_input("\nInsert the onchain transaction ID: ", false)?.unwrap_or_default();
+    match prove_anchored(&config, &transaction_id) {
+        Ok(Some(proof)) => {
+            println!("\nAnchor proof:\n{}", serde_json::to_string_pretty(&proof).unwrap());
+            info!("Block {} was anchored in transaction {} at {}.", proof.block_height, proof.anchor.transaction_hash, proof.chain_timestamp);
+        },
+        Ok(None) => {
+            println!();
+            warn!("No anchor covers this transaction yet.");
+        },
+        Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+        Err(e) => {
+            println!();
+            warn!("The anchor could not be proven: {}", e);
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use log::{info, warn};
+
+use crate::{approve_deletion, find_file, list_pending_deletions, set_deletion_threshold};
+use crate::commands::delete::report_pending;
+use crate::model::FileError;
+use crate::permissions::deletion::{approvers, Removal};
+use crate::permissions::{authorize, is_allowed, Action};
+use crate::utils::{get_system_owner, process_input, prompt_for_file_id};
+
+/// Lists the deletions waiting for approvals on files the user can see, and approves one.
+pub fn review_deletions() -> Result<(), FileError> {
+    let owner_id = get_system_owner().0;
+    let pending: Vec<_> = list_pending_deletions()?.into_iter()
+        .filter(|deletion| find_file(deletion.file_id).is_ok_and(|file| is_allowed(owner_id, &file, Action::View)))
+        .collect();
+    if pending.is_empty() {
+        println!();
+        info!("No deletions are waiting for approval.");
+        return Ok(());
+    }
+    println!("\nPending deletions:\n{}", serde_json::to_string_pretty(&pending).unwrap());
+    let Some(file_id) = process_input("\nFile ID to approve the deletion of (leave empty to go back): ", true)? else { return Ok(()) };
+    let Ok(file_id) = file_id.parse::<i64>() else {
+        println!();
+        warn!("Invalid ID number.");
+        return Ok(());
+    };
+    println!();
+    match approve_deletion(file_id) {
+        Ok(Removal::Removed) => info!("File ID {:?} was moved to the trash.", file_id),
+        Ok(Removal::Pending(pending)) => report_pending(&pending),
+        Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+        Err(FileError::PermissionDenied) => warn!("Only the owner and the people with access can approve this deletion."),
+        Err(e) => warn!("The deletion could not be approved: {}", e),
+    }
+    Ok(())
+}
+
+/// Sets how many of the people who rely on a file must approve its deletion.
+pub fn require_approvals() -> Result<(), FileError> {
+    let file_id = prompt_for_file_id()?;
+    let file = match find_file(file_id).and_then(|file| authorize(get_system_owner().0, &file, Action::Remove).map(|_| file)) {
+        Ok(file) => file,
+        Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+        Err(FileError::PermissionDenied) => {
+            println!();
+            warn!("Only the owner can change who must approve the deletion of this file.");
+            return Ok(());
+        },
+        Err(_) => {
+            println!();
+            warn!("File not found. Please check if ID is correct.");
+            return Ok(());
+        },
+    };
+    let prompt = format!("\nApprovals needed to delete it, out of {} people (1 for none): ", approvers(&file).len());
+    let Ok(threshold) = process_input(&prompt, false)?.unwrap_or_default().parse::<usize>() else {
+        println!();
+        warn!("Invalid number.");
+        return Ok(());
+    };
+    println!();
+    match set_deletion_threshold(file_id, threshold) {
+        Ok(()) => info!("Deleting file ID {:?} now needs {} approvals.", file_id, threshold.max(1)),
+        Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+        Err(e) => warn!("{}", e),
+    }
+    Ok(())
+}
@@ -1,23 +1,44 @@
 use log::{info, warn};
 
-use crate::remove_file;
+use crate::{approve_deletion, find_file, remove_file};
 use crate::model::FileError;
-use crate::utils::prompt_for_file_id;
+use crate::permissions::deletion::{PendingDeletion, Removal};
+use crate::permissions::{authorize, Action};
+use crate::utils::{get_system_owner, prompt_for_file_id};
 
 pub fn delete_file() -> Result<(), FileError> {
     loop {
         let file_id = prompt_for_file_id()?;
-        match remove_file(file_id) {
-            Ok(file_id) => {
-                print!("\n");
+        match find_file(file_id).and_then(|file| authorize(get_system_owner().0, &file, Action::Remove)).and_then(|_| remove_file(file_id)) {
+            Ok(Removal::Removed) => {
+                println!();
                 info!("File ID {:?} was moved to the trash.", file_id);
                 return Ok(());
             },
+            Ok(Removal::Pending(_)) => {
+                println!();
+                match approve_deletion(file_id)? {
+                    Removal::Removed => info!("File ID {:?} was moved to the trash.", file_id),
+                    Removal::Pending(pending) => report_pending(&pending),
+                }
+                return Ok(());
+            },
+            Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+            Err(FileError::PermissionDenied) => {
+                println!();
+                warn!("Only the owner can remove this file.");
+                continue;
+            },
             Err(_) => {
-                print!("\n");
+                println!();
                 warn!("File not found. Please check if ID is correct.");
                 continue;
             }
         };
     }
-}
\ No newline at end of file
+}
+
+pub(crate) fn report_pending(pending: &PendingDeletion) {
+    info!("Deleting file ID {:?} needs {} approvals and has {}. It waits for the rest until {}.",
+        pending.file_id, pending.threshold, pending.approvals.len(), pending.expires);
+}
@@ -2,15 +2,18 @@ use log::info;
 use serde_json;
 
 use crate::model::{File,FileError};
-use crate::{load_files_from_file, get_path};
+use crate::get_all_files;
+use crate::permissions::{is_allowed, Action};
+use crate::utils::get_system_owner;
 
 pub fn list_files() -> Result<(), FileError> {
-    print!("\n");
+    println!();
     info!("Fetching all the files.");
-    let files: Vec<File> = match load_files_from_file(&get_path()) {
+    let mut files: Vec<File> = match get_all_files() {
         Ok(files) => files,
         Err(_) => Err(FileError::FileNotFound)?
     };
+    files.retain(|file| is_allowed(get_system_owner().0, file, Action::View));
     info!("Successfully fetched {} files.", files.len());
     println!("\nFiles:\n{}", serde_json::to_string_pretty(&files).unwrap());
     Ok(())
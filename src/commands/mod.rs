@@ -3,9 +3,21 @@ mod view;
 mod store;
 mod update;
 mod delete;
+mod anchor;
+mod verify_receipt;
+mod receipt;
+mod approvals;
+mod transfer;
+mod access;
 
 pub use list::list_files;
 pub use view::view_file;
 pub use store::store_file;
 pub use update::update_file;
-pub use delete::delete_file;
\ No newline at end of file
+pub use delete::delete_file;
+pub use anchor::prove_anchor;
+pub use verify_receipt::verify_receipt;
+pub use receipt::save_file_receipt;
+pub use approvals::{require_approvals, review_deletions};
+pub use transfer::{review_transfers, transfer_ownership};
+pub use access::{report_access_outcomes, review_access_requests};
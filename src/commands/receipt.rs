@@ -0,0 +1,44 @@
+use std::fs;
+use std::path::PathBuf;
+
+use log::{info, warn};
+
+use crate::{find_file, issue_receipt};
+use crate::model::FileError;
+use crate::permissions::{authorize, Action};
+use crate::utils::{get_system_owner, process_input, prompt_for_file_id};
+
+/// Saves the receipt of the latest store of a file the user may view, to a path they
+/// choose.
+pub fn save_file_receipt() -> Result<(), FileError> {
+    let file_id = prompt_for_file_id()?;
+    match find_file(file_id).and_then(|file| authorize(get_system_owner().0, &file, Action::View).map(|_| file)) {
+        Ok(file) => save_receipt(&file.onchain_txn_id),
+        Err(e @ FileError::RepositoryLocked(_)) => Err(e),
+        Err(FileError::PermissionDenied) => {
+            println!();
+            warn!("You do not have access to this file.");
+            Ok(())
+        },
+        Err(_) => {
+            println!();
+            warn!("File not found.");
+            Ok(())
+        },
+    }
+}
+
+/// Issues the receipt of the store with the given `onchain_txn_id` and writes it where the
+/// user asks, or nowhere when they leave the path empty.
+pub(crate) fn save_receipt(transaction_id: &str) -> Result<(), FileError> {
+    let Some(receipt) = issue_receipt(transaction_id)? else {
+        info!("The file is waiting to enter the chain; save its receipt from the menu once it is in a block.");
+        return Ok(());
+    };
+    let Some(receipt_path) = process_input("\nSave the receipt to (leave empty to skip): ", true)?.map(PathBuf::from) else {
+        return Ok(());
+    };
+    fs::write(&receipt_path, serde_json::to_string_pretty(&receipt).unwrap())?;
+    info!("Saved the receipt of the file to {}.", receipt_path.display());
+    Ok(())
+}
@@ -1,21 +1,22 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use log::warn;
 
 use crate::create_new_file;
+use crate::commands::receipt::save_receipt;
 use crate::model::{FileData, FileError};
 use crate::utils::{get_system_owner, process_input, handle_input};
 
 pub fn store_file() -> Result<(), FileError> {
-    let file_path = setup_input("\nInsert file path you want to store: ", None)?;
+    let file_path: PathBuf = setup_input("\nInsert file path you want to store: ", None)?;
     let filename = extract_filename(&file_path)?;
-    let final_name = setup_input(&format!("\nYour current file name is: {}. Do you want to change it? (Y/N): ", filename.display()), Some(&filename))?;
+    let final_name = setup_input(&format!("\nYour current file name is: {}. Do you want to change it? (Y/N): ", filename.display()), Some(filename.as_path()))?;
     let file_data = FileData { owner: get_system_owner(), name: final_name };
-    create_new_file(file_data, &file_path)?;
-    Ok(())
+    let file = create_new_file(file_data, &file_path)?;
+    save_receipt(&file.onchain_txn_id)
 }
 
-fn setup_input<T: From<String>>(prompt: &str, file_name: Option<&PathBuf>) -> Result<T, FileError> {
+fn setup_input<T: From<String>>(prompt: &str, file_name: Option<&Path>) -> Result<T, FileError> {
     loop {
         print!("{}", prompt);
         let response = handle_input()?;
@@ -30,14 +31,14 @@ fn setup_input<T: From<String>>(prompt: &str, file_name: Option<&PathBuf>) -> Re
     }
 }
 
-fn extract_filename(path: &PathBuf) -> Result<PathBuf, FileError> {
+fn extract_filename(path: &Path) -> Result<PathBuf, FileError> {
     match path.file_name() {
         Some(name) => Ok(name.to_string_lossy().into_owned().into()),
         None => Err(FileError::InputError("Invalid file path".to_string()))
     }
 }
 
-fn change_filename(choosed_option: &String, current_name: &PathBuf) -> Result<String, FileError> {
+fn change_filename(choosed_option: &str, current_name: &Path) -> Result<String, FileError> {
     let option = choosed_option.trim().to_lowercase();
     if option == "y" {
         match process_input("Add new file name: ", false)? {
@@ -49,16 +50,16 @@ fn change_filename(choosed_option: &String, current_name: &PathBuf) -> Result<St
     }
 }
 
-fn get_file_path(file_path: &String) -> Result<PathBuf, FileError> {
+fn get_file_path(file_path: &str) -> Result<PathBuf, FileError> {
     let path = file_path.trim();
     if path.is_empty() {
-        print!("\n");
+        println!();
         warn!("File path cannot be empty. Please try again.");
         return Err(FileError::InputError("Empty path".to_string()));
     }
     let path_buf = PathBuf::from(path);
     if !path_buf.exists() {
-        print!("\n");
+        println!();
         warn!("File not found at path: {:?}. Please try again.", path_buf);
         return Err(FileError::FileNotFound);
     }
@@ -0,0 +1,71 @@
+use log::{info, warn};
+
+use crate::{accept_transfer, find_file, find_identity_by_email, list_transfer_proposals, propose_transfer, withdraw_transfer};
+use crate::commands::update::ask_yes_no;
+use crate::model::FileError;
+use crate::permissions::{authorize, Action};
+use crate::utils::{get_system_owner, process_input, prompt_for_file_id};
+
+/// Offers one of the user's files to someone else, who becomes its owner once they accept.
+pub fn transfer_ownership() -> Result<(), FileError> {
+    let file_id = prompt_for_file_id()?;
+    match find_file(file_id).and_then(|file| authorize(get_system_owner().0, &file, Action::Modify)) {
+        Ok(()) => {},
+        Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+        Err(FileError::PermissionDenied) => {
+            println!();
+            warn!("Only the owner can hand this file over.");
+            return Ok(());
+        },
+        Err(_) => {
+            println!();
+            warn!("File not found. Please check if ID is correct.");
+            return Ok(());
+        },
+    }
+    let email = process_input("\nE-mail of the new owner: ", false)?.unwrap_or_default();
+    let Some(recipient) = find_identity_by_email(&email)? else {
+        println!();
+        warn!("{} has no registered public key and cannot accept the file.", email);
+        return Ok(());
+    };
+    println!();
+    match propose_transfer(file_id, recipient.id) {
+        Ok(proposal) => info!("File ID {:?} is offered to {} until {}.", file_id, email, proposal.expires),
+        Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+        Err(e) => warn!("The transfer could not be proposed: {}", e),
+    }
+    Ok(())
+}
+
+/// Lists the transfers the user offered or was offered, and accepts or declines one.
+pub fn review_transfers() -> Result<(), FileError> {
+    let owner_id = get_system_owner().0;
+    let proposals: Vec<_> = list_transfer_proposals()?.into_iter()
+        .filter(|proposal| proposal.from == owner_id || proposal.to == owner_id)
+        .collect();
+    if proposals.is_empty() {
+        println!();
+        info!("No transfers are waiting to be accepted.");
+        return Ok(());
+    }
+    println!("\nPending transfers:\n{}", serde_json::to_string_pretty(&proposals).unwrap());
+    let Some(file_id) = process_input("\nFile ID of the transfer to settle (leave empty to go back): ", true)? else { return Ok(()) };
+    let Some(proposal) = file_id.parse::<i64>().ok().and_then(|file_id| proposals.iter().find(|proposal| proposal.file_id == file_id)) else {
+        println!();
+        warn!("No transfer of that file is waiting.");
+        return Ok(());
+    };
+    let accepted = proposal.to == owner_id && ask_yes_no("Accept the file? Answering N declines it. (Y/N): ")?;
+    println!();
+    let result = match accepted {
+        true => accept_transfer(proposal.file_id).map(|_| info!("File ID {:?} is now yours.", proposal.file_id)),
+        false => withdraw_transfer(proposal.file_id, owner_id).map(|_| info!("The transfer of file ID {:?} was called off.", proposal.file_id)),
+    };
+    match result {
+        Ok(()) => {},
+        Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+        Err(e) => warn!("The transfer could not be settled: {}", e),
+    }
+    Ok(())
+}
@@ -1,17 +1,24 @@
 use std::io::{self, Write};
 use log::{info, warn};
 
-use crate::{get_file, modify_file};
+use crate::{find_file, find_identity_by_email, get_file, modify_file};
 use crate::model::{File, FileError};
-use crate::utils::{process_input, generate_id, prompt_for_file_id};
+use crate::permissions::{authorize, Action};
+use crate::utils::{get_system_owner, process_input, generate_id, prompt_for_file_id};
 
 pub fn update_file() -> Result<(), FileError> {
     loop {
         let file_id = prompt_for_file_id()?;
-        let mut file = match get_file(file_id) {
+        let mut file = match find_file(file_id).and_then(|file| authorize(get_system_owner().0, &file, Action::Modify)).and_then(|_| get_file(file_id)) {
             Ok(file) => file,
+            Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+            Err(FileError::PermissionDenied) => {
+                println!();
+                warn!("Only the owner can modify this file.");
+                continue;
+            },
             Err(_) => {
-                print!("\n");
+                println!();
                 warn!("File not found.");
                 continue;
             }
@@ -28,12 +35,12 @@ pub fn update_file() -> Result<(), FileError> {
     }
 }
 
-fn ask_yes_no(prompt: &str) -> Result<bool, FileError> {
+pub(crate) fn ask_yes_no(prompt: &str) -> Result<bool, FileError> {
     loop {
         print!("{}", prompt);
-        io::stdout().flush().map_err(|e| FileError::IOError(e))?;
+        io::stdout().flush().map_err(FileError::IOError)?;
         let mut response = String::new();
-        io::stdin().read_line(&mut response).map_err(|e| FileError::IOError(e))?;
+        io::stdin().read_line(&mut response).map_err(FileError::IOError)?;
         match response.trim().to_lowercase().as_str() {
             "y" => return Ok(true),
             "n" => return Ok(false),
@@ -47,7 +54,14 @@ fn update_people_with_access(file: &mut File) -> Result<(), FileError> {
         println!("\tEnter the new person information:");
         let name = process_input("Name: ", false)?.unwrap();
         let email = process_input("E-mail: ", false)?.unwrap();
-        file.people_with_access.push((generate_id()?, name, email));
+        let person_id = match find_identity_by_email(&email)? {
+            Some(identity) => identity.id,
+            None => {
+                warn!("{} has no registered public key yet and will not be able to decrypt the file.", email);
+                generate_id()?
+            }
+        };
+        file.people_with_access.push((person_id, name, email));
         if !ask_yes_no("Do you want to add another person? (Y/N): ")? {
             break;
         }
@@ -0,0 +1,59 @@
+use std::fs;
+
+use log::{info, warn};
+
+use crate::identity::PublicIdentity;
+use crate::ledger::anchor::{AnchorConfig, EthClient};
+use crate::ledger::receipt::{self, Receipt};
+use crate::model::FileError;
+
+const USAGE: &str = "Usage: unichain verify-receipt <receipt.json> [<file>] [--signer <identity.json>] [--rpc <url>]";
+
+/// Runs `unichain verify-receipt`, which needs nothing but the receipt and what the
+/// arguments name: the original file, the public identity the receipt must be signed by,
+/// and the EVM node to check the anchor with (`UNICHAIN_ANCHOR_RPC` by default).
+pub fn verify_receipt(args: &[String]) -> Result<(), FileError> {
+    let mut paths = Vec::new();
+    let (mut signer_path, mut rpc_url) = (None, AnchorConfig::from_env().map(|config| config.rpc_url));
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--signer" => signer_path = Some(args.next().ok_or_else(|| FileError::InputError(USAGE.to_string()))?),
+            "--rpc" => rpc_url = Some(args.next().ok_or_else(|| FileError::InputError(USAGE.to_string()))?.clone()),
+            _ => paths.push(arg),
+        }
+    }
+    let (receipt_path, file_path) = match paths.as_slice() {
+        [receipt] => (*receipt, None),
+        [receipt, file] => (*receipt, Some(*file)),
+        _ => return Err(FileError::InputError(USAGE.to_string())),
+    };
+    let receipt: Receipt = serde_json::from_slice(&fs::read(receipt_path)?)
+        .map_err(|e| FileError::DeserializationError(format!("{} is not a receipt: {}", receipt_path, e)))?;
+    let content = file_path.map(fs::read).transpose()?;
+    let signer: Option<PublicIdentity> = signer_path.map(|path| -> Result<PublicIdentity, FileError> {
+        serde_json::from_slice(&fs::read(path)?).map_err(|e| FileError::DeserializationError(format!("{} is not a public identity: {}", path, e)))
+    }).transpose()?;
+    let client = rpc_url.as_deref().map(EthClient::new);
+
+    let verification = receipt::verify(&receipt, content.as_deref(), signer.as_ref(), client.as_ref())?;
+    println!("\nSigned by: {} <{}> (identity {})", verification.signer.name, verification.signer.email, verification.signer.id);
+    println!("File: {} ({}), transaction {}", receipt.file.name, receipt.file.id, receipt.transaction_id);
+    println!("Block: {} at height {}, {}", receipt.block_hash, receipt.block.height, receipt.block.timestamp);
+    match &receipt.content_hash {
+        Some(hash) if verification.content_checked => println!("Content: matches {}", hash),
+        Some(hash) => println!("Content: {} (no file given to check)", hash),
+        None => println!("Content: none recorded"),
+    }
+    match (&receipt.anchor, &verification.anchor) {
+        (Some(anchor), Some(confirmation)) => println!("Anchor: transaction {} mined in EVM block {} at {}", anchor.transaction_hash, confirmation.block_number, confirmation.timestamp),
+        (Some(anchor), None) if client.is_some() => println!("Anchor: transaction {} is not mined yet", anchor.transaction_hash),
+        (Some(anchor), None) => println!("Anchor: transaction {} on chain {} (no EVM node given to check)", anchor.transaction_hash, anchor.chain_id),
+        (None, _) => println!("Anchor: none yet"),
+    }
+    if signer.is_none() {
+        warn!("No trusted signer was given; check that the signer's key belongs to the node you expect.");
+    }
+    info!("The receipt is valid.");
+    Ok(())
+}
@@ -1,20 +1,28 @@
 use log::warn;
 use serde_json;
 
-use crate::get_file;
+use crate::{find_file, get_file};
+use crate::commands::access::offer_access_request;
 use crate::model::FileError;
-use crate::utils::prompt_for_file_id;
+use crate::permissions::{authorize, Action};
+use crate::utils::{get_system_owner, prompt_for_file_id};
 
 pub fn view_file() -> Result<(), FileError> {
     loop {
         let file_id = prompt_for_file_id()?;
-        match get_file(file_id) {
+        match find_file(file_id).and_then(|file| authorize(get_system_owner().0, &file, Action::View)).and_then(|_| get_file(file_id)) {
             Ok(file) => {
                 println!("\nFiles:\n{}", serde_json::to_string_pretty(&file).unwrap());
                 return Ok(());
             },
+            Err(e @ FileError::RepositoryLocked(_)) => return Err(e),
+            Err(FileError::PermissionDenied) => {
+                println!();
+                warn!("You do not have access to this file.");
+                return offer_access_request(file_id);
+            },
             Err(_) => {
-                print!("\n");
+                println!();
                 warn!("File not found.");
                 continue;
             }
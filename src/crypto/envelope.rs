@@ -0,0 +1,139 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, SharedSecret, StaticSecret};
+
+use crate::crypto::{decrypt, encrypt};
+use crate::identity::PublicIdentity;
+use crate::model::FileError;
+
+/// Stored blobs that carry their own per-file key start with these bytes.
+pub const MAGIC: &[u8; 8] = b"UNIENV01";
+
+const WRAP_INFO: &[u8] = b"unichain-key-wrap";
+
+/// A file's data key, encrypted for one person with a key agreed between a one-off
+/// X25519 key and the person's public encryption key.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WrappedKey {
+    pub person_id: i64,
+    pub ephemeral_public: [u8; 32],
+    pub nonce: [u8; 24],
+    pub sealed_key: Vec<u8>,
+}
+
+/// File content encrypted under its own data key, together with that key wrapped for
+/// every person allowed to read it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Envelope {
+    pub wrapped_keys: Vec<WrappedKey>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl Envelope {
+    /// Encrypts `content` under a fresh data key wrapped for each of `recipients`.
+    pub fn seal(content: &[u8], recipients: &[PublicIdentity]) -> Result<Self, FileError> {
+        let mut data_key = [0u8; 32];
+        OsRng.fill_bytes(&mut data_key);
+        let mut envelope = Envelope { wrapped_keys: Vec::new(), ciphertext: encrypt(&data_key, content)? };
+        for recipient in recipients {
+            envelope.grant(&data_key, recipient)?;
+        }
+        Ok(envelope)
+    }
+
+    pub fn recipients(&self) -> Vec<i64> {
+        self.wrapped_keys.iter().map(|wrapped| wrapped.person_id).collect()
+    }
+
+    /// Wraps the data key for one more person, replacing any key they already had.
+    pub fn grant(&mut self, data_key: &[u8; 32], recipient: &PublicIdentity) -> Result<(), FileError> {
+        self.wrapped_keys.retain(|wrapped| wrapped.person_id != recipient.id);
+        let ephemeral = StaticSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let recipient_key = recipient.encryption_key();
+        let shared = ephemeral.diffie_hellman(&recipient_key);
+        let cipher = XChaCha20Poly1305::new(&derive_wrapping_key(&shared, &ephemeral_public, &recipient_key));
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed_key = cipher.encrypt(&nonce, data_key.as_slice()).map_err(|_| FileError::IntegrityError("Key wrapping failed".to_string()))?;
+        self.wrapped_keys.push(WrappedKey { person_id: recipient.id, ephemeral_public: ephemeral_public.to_bytes(), nonce: nonce.into(), sealed_key });
+        Ok(())
+    }
+
+    /// Recovers the data key with the secret encryption key of `person_id`.
+    pub fn unwrap_key(&self, person_id: i64, secret: &StaticSecret) -> Result<[u8; 32], FileError> {
+        let wrapped = self.wrapped_keys.iter().find(|wrapped| wrapped.person_id == person_id).ok_or(FileError::PermissionDenied)?;
+        let ephemeral_public = PublicKey::from(wrapped.ephemeral_public);
+        let shared = secret.diffie_hellman(&ephemeral_public);
+        let cipher = XChaCha20Poly1305::new(&derive_wrapping_key(&shared, &ephemeral_public, &PublicKey::from(secret)));
+        let data_key = cipher.decrypt(XNonce::from_slice(&wrapped.nonce), wrapped.sealed_key.as_slice()).map_err(|_| FileError::PermissionDenied)?;
+        data_key.try_into().map_err(|_| FileError::IntegrityError("Unwrapped data key has the wrong length".to_string()))
+    }
+
+    pub fn open(&self, person_id: i64, secret: &StaticSecret) -> Result<Vec<u8>, FileError> {
+        self.open_with_key(&self.unwrap_key(person_id, secret)?)
+    }
+
+    pub fn open_with_key(&self, data_key: &[u8; 32]) -> Result<Vec<u8>, FileError> {
+        decrypt(data_key, &self.ciphertext)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FileError> {
+        let encoded = bincode::serialize(self).map_err(|_| FileError::DeserializationError("Envelope serialization failed".to_string()))?;
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&encoded);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FileError> {
+        let encoded = bytes.strip_prefix(MAGIC.as_slice()).ok_or_else(|| FileError::DeserializationError("Not an envelope".to_string()))?;
+        bincode::deserialize(encoded).map_err(|_| FileError::DeserializationError("Failed to deserialize envelope".to_string()))
+    }
+}
+
+pub fn is_envelope(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn derive_wrapping_key(shared: &SharedSecret, ephemeral_public: &PublicKey, recipient_key: &PublicKey) -> Key {
+    let mut salt = ephemeral_public.as_bytes().to_vec();
+    salt.extend_from_slice(recipient_key.as_bytes());
+    let mut wrapping_key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(WRAP_INFO, &mut wrapping_key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    *Key::from_slice(&wrapping_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::identity::LocalIdentity;
+
+    #[test]
+    fn test_each_recipient_opens_their_own_key() {
+        let owner = LocalIdentity::generate(1, "Owner", "owner@gmail.com");
+        let guest = LocalIdentity::generate(2, "Guest", "guest@gmail.com");
+        let outsider = LocalIdentity::generate(3, "Outsider", "outsider@gmail.com");
+        let envelope = Envelope::from_bytes(&Envelope::seal(b"content", &[owner.public.clone(), guest.public.clone()]).unwrap().to_bytes().unwrap()).unwrap();
+        assert_eq!(envelope.recipients(), vec![1, 2]);
+        assert_eq!(envelope.open(1, &owner.encryption_secret()).unwrap(), b"content");
+        assert_eq!(envelope.open(2, &guest.encryption_secret()).unwrap(), b"content");
+        assert_eq!(envelope.open(3, &outsider.encryption_secret()), Err(FileError::PermissionDenied));
+        assert_eq!(envelope.open(2, &owner.encryption_secret()), Err(FileError::PermissionDenied));
+    }
+
+    #[test]
+    fn test_grant_shares_the_same_data_key() {
+        let owner = LocalIdentity::generate(1, "Owner", "owner@gmail.com");
+        let guest = LocalIdentity::generate(2, "Guest", "guest@gmail.com");
+        let mut envelope = Envelope::seal(b"content", std::slice::from_ref(&owner.public)).unwrap();
+        let data_key = envelope.unwrap_key(1, &owner.encryption_secret()).unwrap();
+        envelope.grant(&data_key, &guest.public).unwrap();
+        assert_eq!(envelope.open(2, &guest.encryption_secret()).unwrap(), b"content");
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::write_atomically;
+use crate::model::FileError;
+
+pub mod envelope;
+
+pub use envelope::Envelope;
+
+/// Every encrypted file starts with these bytes, followed by the nonce and the sealed data.
+pub const MAGIC: &[u8; 8] = b"UNIENC01";
+
+const NONCE_LEN: usize = 24;
+
+pub const HEADER_LEN: usize = MAGIC.len() + NONCE_LEN;
+
+const CHECK_PLAINTEXT: &[u8] = b"unichain";
+
+/// Key derivation settings stored next to an encrypted catalog. The passphrase itself
+/// is never stored; `check` is a known value sealed with the derived key, which tells a
+/// wrong passphrase apart from tampered data.
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyFile {
+    salt: [u8; 16],
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    check: Vec<u8>,
+}
+
+static SESSION_KEYS: Mutex<Option<HashMap<PathBuf, [u8; 32]>>> = Mutex::new(None);
+
+pub fn get_key_path(catalog_path: &Path) -> PathBuf {
+    let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".key");
+    catalog_path.with_file_name(name)
+}
+
+pub fn is_encrypted(catalog_path: &Path) -> bool {
+    get_key_path(catalog_path).exists()
+}
+
+pub fn is_unlocked(catalog_path: &Path) -> bool {
+    get_session_key(catalog_path).is_some()
+}
+
+/// Creates the key file for a repository that is not encrypted yet and unlocks it.
+pub fn initialize(catalog_path: &Path, passphrase: &str) -> Result<(), FileError> {
+    let params = Params::default();
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt, params.m_cost(), params.t_cost(), params.p_cost())?;
+    let key_file = KeyFile {
+        salt, m_cost: params.m_cost(), t_cost: params.t_cost(), p_cost: params.p_cost(),
+        check: encrypt(&key, CHECK_PLAINTEXT)?,
+    };
+    let encoded = bincode::serialize(&key_file).map_err(|_| FileError::DeserializationError("Key file serialization failed".to_string()))?;
+    write_atomically(&get_key_path(catalog_path), &encoded)?;
+    set_session_key(catalog_path, Some(key));
+    Ok(())
+}
+
+/// Derives the key from `passphrase` and keeps it for the rest of the session.
+pub fn unlock(catalog_path: &Path, passphrase: &str) -> Result<(), FileError> {
+    let encoded = fs::read(get_key_path(catalog_path))?;
+    let key_file: KeyFile = bincode::deserialize(&encoded).map_err(|_| FileError::IntegrityError("The key file is corrupted".to_string()))?;
+    let key = derive_key(passphrase, &key_file.salt, key_file.m_cost, key_file.t_cost, key_file.p_cost)?;
+    match decrypt(&key, &key_file.check) {
+        Ok(check) if check == CHECK_PLAINTEXT => {
+            set_session_key(catalog_path, Some(key));
+            Ok(())
+        },
+        _ => Err(FileError::WrongPassphrase),
+    }
+}
+
+pub fn lock(catalog_path: &Path) {
+    set_session_key(catalog_path, None);
+}
+
+/// Prepares data belonging to the repository at `catalog_path` for disk: encrypted when
+/// the repository is, unchanged otherwise.
+pub fn seal(catalog_path: &Path, plaintext: &[u8]) -> Result<Vec<u8>, FileError> {
+    if !is_encrypted(catalog_path) {
+        return Ok(plaintext.to_vec());
+    }
+    let key = get_session_key(catalog_path).ok_or(FileError::PassphraseRequired)?;
+    encrypt(&key, plaintext)
+}
+
+/// Reverses `seal`. Plain data in an encrypted repository is refused, since it can only
+/// have been put there by someone without the key.
+pub fn open(catalog_path: &Path, data: &[u8]) -> Result<Vec<u8>, FileError> {
+    if !is_sealed(data) {
+        if is_encrypted(catalog_path) && !data.is_empty() {
+            return Err(FileError::IntegrityError("Found unencrypted data in an encrypted repository".to_string()));
+        }
+        return Ok(data.to_vec());
+    }
+    let key = get_session_key(catalog_path).ok_or(FileError::PassphraseRequired)?;
+    decrypt(&key, data)
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Returns a value that changes every time sealed data is rewritten, read from its nonce.
+pub fn get_seal_generation(data: &[u8]) -> Option<u64> {
+    if !is_sealed(data) {
+        return None;
+    }
+    let nonce = data.get(MAGIC.len()..MAGIC.len() + 8)?;
+    Some(u64::from_le_bytes(nonce.try_into().ok()?))
+}
+
+pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, FileError> {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext).map_err(|_| FileError::IntegrityError("Encryption failed".to_string()))?;
+    let mut sealed = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+pub fn decrypt(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>, FileError> {
+    if !is_sealed(sealed) || sealed.len() < HEADER_LEN {
+        return Err(FileError::IntegrityError("Encrypted data is truncated".to_string()));
+    }
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = XNonce::from_slice(&sealed[MAGIC.len()..HEADER_LEN]);
+    cipher.decrypt(nonce, &sealed[HEADER_LEN..])
+        .map_err(|_| FileError::IntegrityError("Encrypted data failed authentication; it was modified or sealed with another key".to_string()))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], m_cost: u32, t_cost: u32, p_cost: u32) -> Result<[u8; 32], FileError> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(32)).map_err(|e| FileError::InputError(format!("Invalid key derivation settings: {}", e)))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| FileError::InputError(format!("Key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+fn get_session_key(catalog_path: &Path) -> Option<[u8; 32]> {
+    let keys = SESSION_KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    keys.as_ref()?.get(&get_key_path(catalog_path)).copied()
+}
+
+fn set_session_key(catalog_path: &Path, key: Option<[u8; 32]>) {
+    let mut keys = SESSION_KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    let keys = keys.get_or_insert_with(HashMap::new);
+    match key {
+        Some(key) => keys.insert(get_key_path(catalog_path), key),
+        None => keys.remove(&get_key_path(catalog_path)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_seal_and_open_with_unlocked_key() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        assert_eq!(seal(&catalog_path, b"catalog").unwrap(), b"catalog");
+        initialize(&catalog_path, "correct horse").unwrap();
+        let sealed = seal(&catalog_path, b"catalog").unwrap();
+        assert!(is_sealed(&sealed));
+        assert_eq!(open(&catalog_path, &sealed).unwrap(), b"catalog");
+        lock(&catalog_path);
+        assert_eq!(open(&catalog_path, &sealed), Err(FileError::PassphraseRequired));
+        assert_eq!(unlock(&catalog_path, "wrong horse"), Err(FileError::WrongPassphrase));
+        unlock(&catalog_path, "correct horse").unwrap();
+        assert_eq!(open(&catalog_path, &sealed).unwrap(), b"catalog");
+    }
+
+    #[test]
+    fn test_tampered_and_plain_data_are_refused() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        initialize(&catalog_path, "passphrase").unwrap();
+        let mut sealed = seal(&catalog_path, b"catalog").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(matches!(open(&catalog_path, &sealed), Err(FileError::IntegrityError(_))));
+        assert!(matches!(open(&catalog_path, b"catalog"), Err(FileError::IntegrityError(_))));
+    }
+}
@@ -0,0 +1,196 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::OsRng;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::catalog::write_atomically;
+use crate::crypto;
+use crate::model::FileError;
+use crate::utils::get_system_owner;
+
+/// What everyone may know about an identity: who it is, the X25519 key that file keys
+/// are wrapped for, and the Ed25519 key its signatures are checked against.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PublicIdentity {
+    pub id: i64,
+    pub name: String,
+    pub email: String,
+    pub encryption_key: [u8; 32],
+    pub verifying_key: [u8; 32],
+}
+
+impl PublicIdentity {
+    pub fn as_person(&self) -> (i64, String, String) {
+        (self.id, self.name.clone(), self.email.clone())
+    }
+
+    pub fn encryption_key(&self) -> PublicKey {
+        PublicKey::from(self.encryption_key)
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), FileError> {
+        let verifying_key = VerifyingKey::from_bytes(&self.verifying_key).map_err(|_| FileError::IntegrityError(format!("Identity {} has an invalid verifying key", self.id)))?;
+        let signature = Signature::from_slice(signature).map_err(|_| FileError::IntegrityError("Malformed signature".to_string()))?;
+        verifying_key.verify(message, &signature).map_err(|_| FileError::IntegrityError(format!("Signature does not match identity {}", self.id)))
+    }
+}
+
+/// Checks a signature against a bare Ed25519 verifying key, for parties known by their
+/// key rather than by a registered identity.
+pub fn verify_with_key(verifying_key: &[u8; 32], message: &[u8], signature: &[u8]) -> Result<(), FileError> {
+    let verifying_key = VerifyingKey::from_bytes(verifying_key).map_err(|_| FileError::IntegrityError("Invalid verifying key".to_string()))?;
+    let signature = Signature::from_slice(signature).map_err(|_| FileError::IntegrityError("Malformed signature".to_string()))?;
+    verifying_key.verify(message, &signature).map_err(|_| FileError::IntegrityError(format!("Signature does not match key {}", hex::encode(verifying_key.as_bytes()))))
+}
+
+/// The identity this process acts as, including its secret keys.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LocalIdentity {
+    pub public: PublicIdentity,
+    encryption_secret: [u8; 32],
+    signing_secret: [u8; 32],
+}
+
+impl LocalIdentity {
+    pub fn generate(id: i64, name: &str, email: &str) -> Self {
+        let encryption_secret = StaticSecret::random_from_rng(OsRng);
+        let signing_key = SigningKey::generate(&mut OsRng);
+        LocalIdentity {
+            public: PublicIdentity {
+                id, name: name.to_string(), email: email.to_string(),
+                encryption_key: PublicKey::from(&encryption_secret).to_bytes(),
+                verifying_key: signing_key.verifying_key().to_bytes(),
+            },
+            encryption_secret: encryption_secret.to_bytes(),
+            signing_secret: signing_key.to_bytes(),
+        }
+    }
+
+    pub fn id(&self) -> i64 {
+        self.public.id
+    }
+
+    pub fn encryption_secret(&self) -> StaticSecret {
+        StaticSecret::from(self.encryption_secret)
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        SigningKey::from_bytes(&self.signing_secret).sign(message).to_bytes().to_vec()
+    }
+}
+
+/// Public identities known to this repository, one JSON file per identity so that they
+/// can be exchanged and dropped in by hand.
+pub struct IdentityRegistry {
+    dir: PathBuf,
+}
+
+impl IdentityRegistry {
+    pub fn open(catalog_path: &Path) -> Result<Self, FileError> {
+        let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+        name.push(".identities");
+        let dir = catalog_path.with_file_name(name);
+        fs::create_dir_all(&dir)?;
+        Ok(IdentityRegistry { dir })
+    }
+
+    pub fn register(&self, identity: &PublicIdentity) -> Result<(), FileError> {
+        let encoded = serde_json::to_vec_pretty(identity).map_err(|e| FileError::DeserializationError(e.to_string()))?;
+        write_atomically(&self.dir.join(format!("{}.json", identity.id)), &encoded)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<PublicIdentity>, FileError> {
+        match fs::read(self.dir.join(format!("{}.json", id))) {
+            Ok(encoded) => serde_json::from_slice(&encoded).map(Some).map_err(|e| FileError::DeserializationError(e.to_string())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(FileError::IOError(e)),
+        }
+    }
+
+    pub fn list(&self) -> Result<Vec<PublicIdentity>, FileError> {
+        let mut identities = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.path().extension().is_some_and(|extension| extension == "json") {
+                let encoded = fs::read(entry.path())?;
+                identities.push(serde_json::from_slice(&encoded).map_err(|e| FileError::DeserializationError(e.to_string()))?);
+            }
+        }
+        identities.sort_by_key(|identity: &PublicIdentity| identity.id);
+        Ok(identities)
+    }
+
+    pub fn find_by_email(&self, email: &str) -> Result<Option<PublicIdentity>, FileError> {
+        Ok(self.list()?.into_iter().find(|identity| identity.email.eq_ignore_ascii_case(email)))
+    }
+
+    /// Looks a person from an access list up by ID, falling back to their e-mail address.
+    pub fn resolve(&self, person: &(i64, String, String)) -> Result<Option<PublicIdentity>, FileError> {
+        match self.get(person.0)? {
+            Some(identity) => Ok(Some(identity)),
+            None => self.find_by_email(&person.2),
+        }
+    }
+}
+
+pub fn get_local_identity_path(catalog_path: &Path) -> PathBuf {
+    let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".identity");
+    catalog_path.with_file_name(name)
+}
+
+/// Loads the keys of the system owner, generating and registering them on first use.
+/// The secret keys are encrypted whenever the repository is.
+pub fn load_or_create_local_identity(catalog_path: &Path) -> Result<LocalIdentity, FileError> {
+    let path = get_local_identity_path(catalog_path);
+    match fs::read(&path) {
+        Ok(encoded) => {
+            let encoded = crypto::open(catalog_path, &encoded)?;
+            bincode::deserialize(&encoded).map_err(|_| FileError::DeserializationError("Failed to deserialize the local identity".to_string()))
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let (id, name, email) = get_system_owner();
+            let identity = LocalIdentity::generate(id, &name, &email);
+            save_local_identity(catalog_path, &identity)?;
+            Ok(identity)
+        },
+        Err(e) => Err(FileError::IOError(e)),
+    }
+}
+
+/// Makes `identity` the one this repository acts as and registers its public keys.
+pub fn save_local_identity(catalog_path: &Path, identity: &LocalIdentity) -> Result<(), FileError> {
+    let encoded = bincode::serialize(identity).map_err(|_| FileError::DeserializationError("Local identity serialization failed".to_string()))?;
+    write_atomically(&get_local_identity_path(catalog_path), &crypto::seal(catalog_path, &encoded)?)?;
+    IdentityRegistry::open(catalog_path)?.register(&identity.public)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_registry_resolves_by_id_and_email() {
+        let dir = tempdir().unwrap();
+        let registry = IdentityRegistry::open(&dir.path().join("assets")).unwrap();
+        let identity = LocalIdentity::generate(5, "Guest", "guest@gmail.com");
+        registry.register(&identity.public).unwrap();
+        assert_eq!(registry.resolve(&(5, String::new(), String::new())).unwrap(), Some(identity.public.clone()));
+        assert_eq!(registry.resolve(&(99, String::new(), "GUEST@gmail.com".to_string())).unwrap(), Some(identity.public));
+        assert_eq!(registry.resolve(&(99, String::new(), "nobody@gmail.com".to_string())).unwrap(), None);
+    }
+
+    #[test]
+    fn test_signatures_verify_against_public_identity() {
+        let identity = LocalIdentity::generate(5, "Guest", "guest@gmail.com");
+        let signature = identity.sign(b"message");
+        assert!(identity.public.verify(b"message", &signature).is_ok());
+        assert!(identity.public.verify(b"other message", &signature).is_err());
+    }
+}
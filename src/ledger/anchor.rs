@@ -0,0 +1,335 @@
+use std::env;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::net::http::{agent, remote_error};
+use crate::catalog::CatalogLock;
+use crate::ledger::{find_transaction, Block, Ledger, RecordPool};
+use crate::model::FileError;
+
+const DEFAULT_ANCHOR_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How long to wait for an anchoring transaction to be mined before leaving it for later.
+const RECEIPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Start of the data of every anchoring transaction, followed by the height of the anchored
+/// block as 8 big-endian bytes and its 32-byte hash.
+pub const ANCHOR_TAG: &[u8; 8] = b"UNICHAIN";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnchorConfig {
+    pub rpc_url: String,
+    pub account: Option<String>,
+    pub interval: Duration,
+}
+
+impl AnchorConfig {
+    /// Reads the anchoring settings from `UNICHAIN_ANCHOR_RPC` (the JSON-RPC URL of an EVM
+    /// node), `UNICHAIN_ANCHOR_ACCOUNT` (the account that sends the transactions, the node's
+    /// first account by default) and `UNICHAIN_ANCHOR_INTERVAL` (seconds). Returns `None`
+    /// when no node is set, in which case the ledger is not anchored.
+    pub fn from_env() -> Option<Self> {
+        let rpc_url = env::var("UNICHAIN_ANCHOR_RPC").ok().map(|url| url.trim().to_string()).filter(|url| !url.is_empty())?;
+        let account = env::var("UNICHAIN_ANCHOR_ACCOUNT").ok().map(|account| account.trim().to_string()).filter(|account| !account.is_empty());
+        let interval = env::var("UNICHAIN_ANCHOR_INTERVAL").ok()
+            .and_then(|seconds| seconds.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_ANCHOR_INTERVAL);
+        Some(AnchorConfig { rpc_url, account, interval })
+    }
+}
+
+/// A ledger block whose hash was published in a transaction on an EVM chain.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Anchor {
+    pub height: u64,
+    pub block_hash: String,
+    pub chain_id: u64,
+    pub transaction_hash: String,
+    pub submitted: NaiveDateTime,
+}
+
+/// Evidence that the block recording a transaction was anchored: the anchored block follows
+/// it in the local chain, and the anchoring transaction was mined with that block's hash.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AnchorProof {
+    pub transaction_id: String,
+    pub block_height: u64,
+    pub block_hash: String,
+    pub anchor: Anchor,
+    pub chain_block_number: u64,
+    pub chain_block_hash: String,
+    pub chain_timestamp: DateTime<Utc>,
+}
+
+/// The EVM block an anchoring transaction was mined in.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AnchorConfirmation {
+    pub block_number: u64,
+    pub block_hash: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The anchors this node submitted, oldest first.
+pub fn anchors(catalog_path: &Path) -> RecordPool<Anchor> {
+    RecordPool::open(catalog_path, ".anchors")
+}
+
+/// A client for the Ethereum JSON-RPC API of an EVM node.
+pub struct EthClient {
+    url: String,
+}
+
+impl EthClient {
+    pub fn new(url: &str) -> Self {
+        EthClient { url: url.to_string() }
+    }
+
+    pub fn call(&self, method: &str, params: Value) -> Result<Value, FileError> {
+        let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        let reply = match agent().post(&self.url).set("Content-Type", "application/json").send_string(&request.to_string()) {
+            Ok(response) => response.into_string()?,
+            Err(ureq::Error::Status(status, _)) => return Err(remote_error(&format!("The EVM node refused {} with {}", method, status))),
+            Err(e) => return Err(remote_error(&format!("Cannot reach the EVM node: {}", e))),
+        };
+        let mut reply: Value = serde_json::from_str(&reply)
+            .map_err(|e| remote_error(&format!("The EVM node sent an unexpected reply to {}: {}", method, e)))?;
+        if let Some(error) = reply.get("error") {
+            let message = error.get("message").and_then(Value::as_str).unwrap_or_default();
+            return Err(remote_error(&format!("The EVM node failed {}: {}", method, message)));
+        }
+        Ok(reply["result"].take())
+    }
+
+    pub fn chain_id(&self) -> Result<u64, FileError> {
+        quantity(&self.call("eth_chainId", json!([]))?)
+    }
+
+    fn default_account(&self) -> Result<String, FileError> {
+        self.call("eth_accounts", json!([]))?.get(0).and_then(Value::as_str).map(str::to_string)
+            .ok_or_else(|| FileError::InputError("The EVM node has no account to anchor with; set UNICHAIN_ANCHOR_ACCOUNT".to_string()))
+    }
+}
+
+/// Publishes the hash of the tip of the ledger unless it is anchored already, and waits for
+/// the transaction to be mined. Returns the new anchor, if any.
+pub fn anchor_tip(catalog_path: &Path, client: &EthClient, account: Option<&str>) -> Result<Option<Anchor>, FileError> {
+    let Some(tip) = Ledger::read_blocks(catalog_path)?.pop() else { return Ok(None) };
+    if anchors(catalog_path).list()?.iter().any(|anchor| anchor.block_hash == tip.hash) {
+        return Ok(None);
+    }
+    let from = match account {
+        Some(account) => account.to_string(),
+        None => client.default_account()?,
+    };
+    let chain_id = client.chain_id()?;
+    let transaction = json!({"from": from, "to": from, "value": "0x0", "data": format!("0x{}", hex::encode(anchor_data(tip.height, &tip.hash)?))});
+    let transaction_hash = client.call("eth_sendTransaction", json!([transaction]))?.as_str()
+        .ok_or_else(|| remote_error("The EVM node did not return a transaction hash"))?.to_string();
+    let anchor = Anchor { height: tip.height, block_hash: tip.hash, chain_id, transaction_hash, submitted: Utc::now().naive_utc() };
+    {
+        let _lock = CatalogLock::acquire(catalog_path)?;
+        anchors(catalog_path).add(std::slice::from_ref(&anchor))?;
+    }
+    let started = Instant::now();
+    while receipt(client, &anchor.transaction_hash)?.is_none() {
+        if started.elapsed() > RECEIPT_TIMEOUT {
+            warn!("The anchoring transaction {} is not mined yet.", anchor.transaction_hash);
+            break;
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    Ok(Some(anchor))
+}
+
+/// Finds the earliest anchor covering the block that recorded `transaction_id` and checks it
+/// against the EVM chain. Returns `None` while the transaction is not in the chain or no
+/// anchor covers it yet.
+pub fn prove(catalog_path: &Path, client: &EthClient, transaction_id: &str) -> Result<Option<AnchorProof>, FileError> {
+    let blocks = Ledger::read_blocks(catalog_path)?;
+    let Some(block) = find_transaction(&blocks, transaction_id) else { return Ok(None) };
+    let Some(anchor) = covering_anchor(catalog_path, &blocks, block.height)? else { return Ok(None) };
+    for height in block.height..=anchor.height {
+        let parent = height.checked_sub(1).map(|parent| &blocks[parent as usize]);
+        blocks[height as usize].verify_child_of(parent)?;
+    }
+    let Some(confirmation) = confirm(client, &anchor)? else { return Ok(None) };
+    Ok(Some(AnchorProof {
+        transaction_id: transaction_id.to_string(),
+        block_height: block.height,
+        block_hash: block.hash.clone(),
+        chain_block_number: confirmation.block_number,
+        chain_block_hash: confirmation.block_hash,
+        chain_timestamp: confirmation.timestamp,
+        anchor,
+    }))
+}
+
+/// Checks with the EVM chain that the anchoring transaction carries the anchored block's
+/// height and hash and was mined successfully. Returns the EVM block it was mined in, or
+/// `None` while it is not mined yet. Needs nothing but the anchor and the EVM node.
+pub fn confirm(client: &EthClient, anchor: &Anchor) -> Result<Option<AnchorConfirmation>, FileError> {
+    let chain_id = client.chain_id()?;
+    if chain_id != anchor.chain_id {
+        return Err(FileError::IntegrityError(format!("The anchor is on chain {} but the EVM node serves chain {}", anchor.chain_id, chain_id)));
+    }
+    let published = client.call("eth_getTransactionByHash", json!([anchor.transaction_hash]))?;
+    let input = published.get("input").or_else(|| published.get("data")).and_then(Value::as_str).unwrap_or_default();
+    if input.trim_start_matches("0x") != hex::encode(anchor_data(anchor.height, &anchor.block_hash)?) {
+        return Err(FileError::IntegrityError(format!("Transaction {} does not carry the hash of block {}", anchor.transaction_hash, anchor.height)));
+    }
+    let Some(receipt) = receipt(client, &anchor.transaction_hash)? else { return Ok(None) };
+    if receipt.get("status").map(quantity).transpose()? == Some(0) {
+        return Err(FileError::IntegrityError(format!("Transaction {} failed on the EVM chain", anchor.transaction_hash)));
+    }
+    let chain_block = client.call("eth_getBlockByNumber", json!([receipt["blockNumber"], false]))?;
+    let timestamp = quantity(&chain_block["timestamp"])?;
+    Ok(Some(AnchorConfirmation {
+        block_number: quantity(&receipt["blockNumber"])?,
+        block_hash: chain_block["hash"].as_str().unwrap_or_default().to_string(),
+        timestamp: DateTime::from_timestamp(timestamp as i64, 0).ok_or_else(|| remote_error("The EVM node returned an invalid block timestamp"))?,
+    }))
+}
+
+/// The earliest anchor of a block at or after `height` that is still in `blocks`.
+pub fn covering_anchor(catalog_path: &Path, blocks: &[Block], height: u64) -> Result<Option<Anchor>, FileError> {
+    Ok(anchors(catalog_path).list()?.into_iter()
+        .filter(|anchor| anchor.height >= height)
+        .find(|anchor| blocks.get(anchor.height as usize).is_some_and(|anchored| anchored.hash == anchor.block_hash)))
+}
+
+/// Anchors the tip of the ledger every `interval` until it is dropped.
+pub struct Anchorer {
+    stop: Arc<AtomicBool>,
+}
+
+impl Anchorer {
+    pub fn start(catalog_path: &Path, config: &AnchorConfig) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (path, config, running) = (catalog_path.to_path_buf(), config.clone(), stop.clone());
+        thread::spawn(move || {
+            let client = EthClient::new(&config.rpc_url);
+            while !running.load(Ordering::SeqCst) {
+                match anchor_tip(&path, &client, config.account.as_deref()) {
+                    Ok(Some(anchor)) => info!("Anchored block {} in transaction {}.", anchor.height, anchor.transaction_hash),
+                    Ok(None) => {},
+                    Err(e) => warn!("Anchoring the ledger failed: {}", e),
+                }
+                thread::sleep(config.interval);
+            }
+        });
+        Anchorer { stop }
+    }
+}
+
+impl Drop for Anchorer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn anchor_data(height: u64, block_hash: &str) -> Result<Vec<u8>, FileError> {
+    let hash = hex::decode(block_hash).map_err(|_| FileError::IntegrityError(format!("Block {} has a malformed hash", height)))?;
+    Ok([ANCHOR_TAG.as_slice(), &height.to_be_bytes(), &hash].concat())
+}
+
+fn receipt(client: &EthClient, transaction_hash: &str) -> Result<Option<Value>, FileError> {
+    let receipt = client.call("eth_getTransactionReceipt", json!([transaction_hash]))?;
+    Ok(Some(receipt).filter(|receipt| !receipt.is_null() && !receipt["blockNumber"].is_null()))
+}
+
+/// Parses a hex-encoded JSON-RPC quantity such as `"0x1a"`.
+fn quantity(value: &Value) -> Result<u64, FileError> {
+    value.as_str().and_then(|value| u64::from_str_radix(value.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| remote_error(&format!("The EVM node returned {} where a quantity was expected", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use tempfile::tempdir;
+    use tiny_http::{Response, Server};
+
+    use crate::testing::{AssetsPath, TestFile};
+
+    const ACCOUNT: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+
+    /// Answers the calls anchoring makes the way a dev node with automining does: every
+    /// transaction is mined in a block of its own.
+    fn start_mock_evm_node() -> (String, Arc<Mutex<HashMap<String, String>>>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let transactions = Arc::new(Mutex::new(HashMap::new()));
+        let state = transactions.clone();
+        thread::spawn(move || {
+            let mut mined: Vec<String> = Vec::new();
+            for mut request in server.incoming_requests() {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                let call: Value = serde_json::from_str(&body).unwrap();
+                let param = &call["params"][0];
+                let mut transactions = state.lock().unwrap();
+                let result = match call["method"].as_str().unwrap() {
+                    "eth_chainId" => json!("0x7a69"),
+                    "eth_accounts" => json!([ACCOUNT]),
+                    "eth_sendTransaction" => {
+                        assert_eq!(param["from"], ACCOUNT);
+                        let hash = format!("0x{:064x}", mined.len() + 1);
+                        transactions.insert(hash.clone(), param["data"].as_str().unwrap().to_string());
+                        mined.push(hash.clone());
+                        json!(hash)
+                    },
+                    "eth_getTransactionByHash" => json!({"hash": param, "input": transactions.get(param.as_str().unwrap())}),
+                    "eth_getTransactionReceipt" => match mined.iter().position(|hash| hash == param) {
+                        Some(index) => json!({"transactionHash": param, "status": "0x1", "blockNumber": format!("0x{:x}", index + 1)}),
+                        None => Value::Null,
+                    },
+                    "eth_getBlockByNumber" => json!({"number": param, "hash": format!("0x{:064x}", 0xb10c), "timestamp": "0x6553f100"}),
+                    method => panic!("Unexpected call to {}", method),
+                };
+                let reply = json!({"jsonrpc": "2.0", "id": call["id"], "result": result});
+                request.respond(Response::from_string(reply.to_string())).unwrap();
+            }
+        });
+        (url, transactions)
+    }
+
+    #[test]
+    fn test_anchor_the_tip_and_prove_a_transaction() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        crate::save_files_to_file(&[], &path).unwrap();
+        let (url, transactions) = start_mock_evm_node();
+        let client = EthClient::new(&url);
+
+        let first = crate::store_new_file(TestFile::new(1).build(), None).unwrap();
+        let anchor = anchor_tip(&path, &client, None).unwrap().expect("The tip was not anchored");
+        assert_eq!((anchor.height, anchor.chain_id), (0, 31337));
+        assert_eq!(anchor_tip(&path, &client, None).unwrap(), None);
+
+        let second = crate::store_new_file(TestFile::new(2).build(), None).unwrap();
+        assert_eq!(prove(&path, &client, &second.onchain_txn_id).unwrap(), None);
+        let proof = prove(&path, &client, &first.onchain_txn_id).unwrap().expect("The first store was not proven");
+        assert_eq!((proof.block_height, proof.chain_block_number), (0, 1));
+        assert_eq!(proof.chain_timestamp.timestamp(), 0x6553f100);
+        assert_eq!(prove(&path, &client, "unknown").unwrap(), None);
+
+        anchor_tip(&path, &client, Some(ACCOUNT)).unwrap().expect("The new tip was not anchored");
+        assert_eq!(prove(&path, &client, &second.onchain_txn_id).unwrap().unwrap().anchor.height, 1);
+        transactions.lock().unwrap().insert(anchor.transaction_hash.clone(), format!("0x{}", hex::encode(b"something else")));
+        assert!(matches!(prove(&path, &client, &first.onchain_txn_id), Err(FileError::IntegrityError(_))));
+    }
+}
@@ -0,0 +1,278 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::catalog::write_atomically;
+use crate::identity::{LocalIdentity, PublicIdentity};
+use crate::ledger::Block;
+use crate::model::FileError;
+
+/// A change to the set of validators, recorded in the block of the validator that
+/// proposed it and in force from the next block on.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ValidatorChange {
+    Add(PublicIdentity),
+    Remove(i64),
+}
+
+/// How a network decides who may add the next block.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Consensus {
+    /// Every node adds a block for each change as soon as it is made.
+    #[default]
+    Open,
+    /// Only validators add blocks, taking turns in the order they were listed and added,
+    /// and each block carries the signature of the validator whose turn it was.
+    Authority { validators: Vec<PublicIdentity> },
+}
+
+/// Settings of the proof-of-work seal. Difficulty is the number of leading zero bits a
+/// block hash needs. Every `retarget_interval` blocks it goes up by one when those blocks
+/// came more than twice as fast as `target_block_seconds`, and down by one when they
+/// came more than twice as slow.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProofOfWork {
+    pub initial_difficulty: u32,
+    pub target_block_seconds: u64,
+    pub retarget_interval: u64,
+}
+
+/// Hardest difficulty retargeting can reach.
+pub const MAX_DIFFICULTY: u32 = 128;
+
+/// How far ahead of the local clock a sealed block may be dated.
+const MAX_CLOCK_DRIFT_SECONDS: i64 = 2 * 60 * 60;
+
+/// The settings a network starts from, shared by all of its nodes. A proof-of-work seal
+/// can be required on top of either consensus mode.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Genesis {
+    pub network: String,
+    pub consensus: Consensus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof_of_work: Option<ProofOfWork>,
+}
+
+impl Default for Genesis {
+    fn default() -> Self {
+        Genesis { network: "unichain".to_string(), consensus: Consensus::default(), proof_of_work: None }
+    }
+}
+
+impl Genesis {
+    /// Reads the genesis file next to the catalog, falling back to an open network when
+    /// there is none.
+    pub fn load(catalog_path: &Path) -> Result<Self, FileError> {
+        let genesis: Genesis = match fs::read(get_genesis_path(catalog_path)) {
+            Ok(encoded) => serde_json::from_slice(&encoded).map_err(|e| FileError::DeserializationError(format!("Invalid genesis file :: {}", e)))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Genesis::default()),
+            Err(e) => return Err(FileError::IOError(e)),
+        };
+        if genesis.proof_of_work.as_ref().is_some_and(|work| work.retarget_interval < 2 || work.initial_difficulty > MAX_DIFFICULTY) {
+            return Err(FileError::InputError("The proof-of-work retarget interval must be at least 2 blocks and the difficulty at most 128 bits.".to_string()));
+        }
+        Ok(genesis)
+    }
+
+    pub fn save(&self, catalog_path: &Path) -> Result<(), FileError> {
+        let encoded = serde_json::to_vec_pretty(self).map_err(|e| FileError::DeserializationError(e.to_string()))?;
+        write_atomically(&get_genesis_path(catalog_path), &encoded)
+    }
+
+    /// Identifies the network, so that nodes started from different genesis files never
+    /// exchange blocks.
+    pub fn hash(&self) -> Result<String, FileError> {
+        let encoded = serde_json::to_vec(self).map_err(|e| FileError::DeserializationError(e.to_string()))?;
+        Ok(hex::encode(Sha256::digest(encoded)))
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.consensus == Consensus::Open
+    }
+
+    /// The validators in force after `blocks`.
+    pub fn validators_after(&self, blocks: &[Block]) -> Vec<PublicIdentity> {
+        let Consensus::Authority { validators } = &self.consensus else { return Vec::new() };
+        let mut validators = validators.clone();
+        for change in blocks.iter().flat_map(|block| block.validator_changes.iter()) {
+            match change {
+                ValidatorChange::Add(identity) => match validators.iter_mut().find(|validator| validator.id == identity.id) {
+                    Some(validator) => *validator = identity.clone(),
+                    None => validators.push(identity.clone()),
+                },
+                ValidatorChange::Remove(id) => validators.retain(|validator| validator.id != *id),
+            }
+        }
+        validators
+    }
+
+    /// The validator whose turn it is to add the block after `blocks`.
+    pub fn expected_signer(&self, blocks: &[Block]) -> Option<PublicIdentity> {
+        let validators = self.validators_after(blocks);
+        if validators.is_empty() {
+            return None;
+        }
+        validators.get(blocks.len() % validators.len()).cloned()
+    }
+
+    /// The difficulty the block after `parents` must be sealed with.
+    pub fn expected_difficulty(&self, parents: &[Block]) -> u32 {
+        let Some(work) = &self.proof_of_work else { return 0 };
+        let Some(previous) = parents.last() else { return work.initial_difficulty };
+        let interval = work.retarget_interval as usize;
+        if !parents.len().is_multiple_of(interval) {
+            return previous.difficulty;
+        }
+        let window = &parents[parents.len() - interval..];
+        let elapsed = (window[interval - 1].timestamp - window[0].timestamp).num_seconds().max(0) as u64;
+        let expected = work.target_block_seconds * (interval as u64 - 1);
+        if elapsed * 2 < expected {
+            (previous.difficulty + 1).min(MAX_DIFFICULTY)
+        } else if elapsed > expected * 2 {
+            previous.difficulty.saturating_sub(1).max(1)
+        } else {
+            previous.difficulty
+        }
+    }
+
+    /// Finishes a block that follows `parents`: names `signer` as its signer, searches for
+    /// a nonce that meets the expected difficulty, then signs the resulting hash.
+    pub fn seal(&self, parents: &[Block], block: &mut Block, signer: Option<&LocalIdentity>) -> Result<(), FileError> {
+        block.signer = signer.map(LocalIdentity::id);
+        block.difficulty = self.expected_difficulty(parents);
+        block.nonce = 0;
+        let mut header = block.header()?;
+        block.hash = header.hash()?;
+        while !block.meets_difficulty() {
+            header.nonce += 1;
+            block.nonce = header.nonce;
+            block.hash = header.hash()?;
+        }
+        block.signature = signer.map(|signer| signer.sign(block.hash.as_bytes())).unwrap_or_default();
+        Ok(())
+    }
+
+    /// Checks that `block` may follow `parents` under this network's consensus and, when
+    /// required, carries enough proof of work.
+    pub fn verify_seal(&self, parents: &[Block], block: &Block) -> Result<(), FileError> {
+        if self.proof_of_work.is_some() {
+            self.verify_work(parents, block)?;
+        }
+        if self.is_open() {
+            return Ok(());
+        }
+        let expected = self.expected_signer(parents).ok_or_else(|| FileError::IntegrityError("The network has no validators left".to_string()))?;
+        match block.signer {
+            Some(signer) if signer == expected.id => expected.verify(block.hash.as_bytes(), &block.signature),
+            Some(signer) if self.validators_after(parents).iter().any(|validator| validator.id == signer) => {
+                Err(FileError::IntegrityError(format!("Block {} was signed by validator {} out of turn; it was the turn of {}", block.height, signer, expected.id)))
+            },
+            Some(signer) => Err(FileError::IntegrityError(format!("Block {} was signed by {}, who is not a validator", block.height, signer))),
+            None => Err(FileError::IntegrityError(format!("Block {} is not signed", block.height))),
+        }
+    }
+
+    /// Checks that `block` carries the proof of work the chain expects after `parents`,
+    /// and that its time is plausible.
+    fn verify_work(&self, parents: &[Block], block: &Block) -> Result<(), FileError> {
+        let difficulty = self.expected_difficulty(parents);
+        if block.difficulty != difficulty || !block.meets_difficulty() {
+            return Err(FileError::IntegrityError(format!("Block {} lacks the proof of work for difficulty {}", block.height, difficulty)));
+        }
+        if parents.last().is_some_and(|parent| block.timestamp < parent.timestamp) {
+            return Err(FileError::IntegrityError(format!("Block {} is dated before its parent", block.height)));
+        }
+        if block.timestamp > Utc::now().naive_utc() + Duration::seconds(MAX_CLOCK_DRIFT_SECONDS) {
+            return Err(FileError::IntegrityError(format!("Block {} is dated in the future", block.height)));
+        }
+        Ok(())
+    }
+}
+
+pub fn get_genesis_path(catalog_path: &Path) -> PathBuf {
+    let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".genesis.json");
+    catalog_path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::catalog::JournalOp;
+
+    #[test]
+    fn test_validators_sign_in_turn() {
+        let (first, second, outsider) = (LocalIdentity::generate(1, "First", "first@gmail.com"), LocalIdentity::generate(2, "Second", "second@gmail.com"), LocalIdentity::generate(3, "Outsider", "outsider@gmail.com"));
+        let genesis = Genesis { network: "test".to_string(), consensus: Consensus::Authority { validators: vec![first.public.clone(), second.public.clone()] }, proof_of_work: None };
+        let mut block = Block::new(None, vec![JournalOp::Remove { file_id: 1, deletion: None }]).unwrap();
+        block.sign(&second).unwrap();
+        assert!(matches!(genesis.verify_seal(&[], &block), Err(FileError::IntegrityError(message)) if message.contains("out of turn")));
+        block.sign(&outsider).unwrap();
+        assert!(matches!(genesis.verify_seal(&[], &block), Err(FileError::IntegrityError(message)) if message.contains("not a validator")));
+        block.validator_changes = vec![ValidatorChange::Add(outsider.public.clone()), ValidatorChange::Remove(2)];
+        block.sign(&first).unwrap();
+        genesis.verify_seal(&[], &block).unwrap();
+        block.signature[0] ^= 1;
+        assert!(genesis.verify_seal(&[], &block).is_err());
+        let ids: Vec<i64> = genesis.validators_after(&[block.clone()]).iter().map(|validator| validator.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(genesis.expected_signer(&[block]).map(|validator| validator.id), Some(3));
+    }
+
+    fn work_genesis(consensus: Consensus) -> Genesis {
+        let proof_of_work = ProofOfWork { initial_difficulty: 6, target_block_seconds: 60, retarget_interval: 2 };
+        Genesis { network: "test".to_string(), consensus, proof_of_work: Some(proof_of_work) }
+    }
+
+    /// Moves the block to the first nonce after its own whose hash misses the target.
+    fn miss_target(block: &mut Block) {
+        loop {
+            block.nonce += 1;
+            block.hash = block.compute_hash().unwrap();
+            if !block.meets_difficulty() {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_proof_of_work_seal_and_retarget() {
+        let genesis = work_genesis(Consensus::Open);
+        let mut first = Block::new(None, vec![JournalOp::Remove { file_id: 1, deletion: None }]).unwrap();
+        genesis.seal(&[], &mut first, None).unwrap();
+        assert_eq!(first.difficulty, 6);
+        genesis.verify_seal(&[], &first).unwrap();
+        let mut unsealed = first.clone();
+        miss_target(&mut unsealed);
+        assert!(matches!(genesis.verify_seal(&[], &unsealed), Err(FileError::IntegrityError(message)) if message.contains("proof of work")));
+        let mut second = Block::new(Some(&first), Vec::new()).unwrap();
+        genesis.seal(&[first.clone()], &mut second, None).unwrap();
+        let chain = vec![first.clone(), second.clone()];
+        assert_eq!(genesis.expected_difficulty(&chain), 7, "Two blocks in the same minute should raise the difficulty");
+        let mut slow = second.clone();
+        slow.timestamp = first.timestamp + Duration::seconds(600);
+        assert_eq!(genesis.expected_difficulty(&[first, slow]), 5);
+        let mut easy = Block::new(Some(&second), Vec::new()).unwrap();
+        genesis.seal(&chain[..1], &mut easy, None).unwrap();
+        assert!(genesis.verify_seal(&chain, &easy).is_err());
+    }
+
+    #[test]
+    fn test_proof_of_work_on_top_of_authority() {
+        let validator = LocalIdentity::generate(1, "Validator", "validator@gmail.com");
+        let genesis = work_genesis(Consensus::Authority { validators: vec![validator.public.clone()] });
+        let mut block = Block::new(None, Vec::new()).unwrap();
+        genesis.seal(&[], &mut block, Some(&validator)).unwrap();
+        genesis.verify_seal(&[], &block).unwrap();
+        miss_target(&mut block);
+        block.sign(&validator).unwrap();
+        assert!(!block.meets_difficulty());
+        assert!(matches!(genesis.verify_seal(&[], &block), Err(FileError::IntegrityError(message)) if message.contains("proof of work")));
+    }
+}
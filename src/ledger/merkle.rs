@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::catalog::JournalOp;
+use crate::ledger::transaction_id;
+use crate::model::FileError;
+
+/// One level of a Merkle inclusion proof: the hash next to the running hash, and whether
+/// it sits on the left.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MerkleStep {
+    pub hash: String,
+    pub left: bool,
+}
+
+/// The leaf an operation contributes to its block's Merkle tree: the transaction ID of the
+/// file record a store or transfer leaves behind, which anyone holding that record can
+/// recompute, or the hash of the encoded operation otherwise.
+pub fn operation_leaf(op: &JournalOp) -> Result<String, FileError> {
+    match op.stored() {
+        Some((file, _)) => transaction_id(file),
+        None => {
+            let encoded = bincode::serialize(op).map_err(|_| FileError::DeserializationError("Operation serialization failed".to_string()))?;
+            Ok(hex::encode(Sha256::digest(encoded)))
+        },
+    }
+}
+
+/// Hashes leaves pairwise up to a single root. A node without a partner moves up a level
+/// unchanged, so no leaf is ever paired with a copy of itself. The root of no leaves is the
+/// hash of nothing.
+pub fn root(leaves: &[String]) -> String {
+    let mut level = leaves.to_vec();
+    if level.is_empty() {
+        return hex::encode(Sha256::digest([]));
+    }
+    while level.len() > 1 {
+        level = level.chunks(2).map(|pair| match pair {
+            [left, right] => join(left, right),
+            [single] => single.clone(),
+            _ => unreachable!(),
+        }).collect();
+    }
+    level.remove(0)
+}
+
+/// The steps from the leaf at `index` up to the root, or `None` when there is no such leaf.
+pub fn proof(leaves: &[String], mut index: usize) -> Option<Vec<MerkleStep>> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut level = leaves.to_vec();
+    let mut steps = Vec::new();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if let Some(hash) = level.get(sibling) {
+            steps.push(MerkleStep { hash: hash.clone(), left: sibling < index });
+        }
+        level = level.chunks(2).map(|pair| match pair {
+            [left, right] => join(left, right),
+            [single] => single.clone(),
+            _ => unreachable!(),
+        }).collect();
+        index /= 2;
+    }
+    Some(steps)
+}
+
+/// Tells whether `steps` lead from `leaf` to `root`.
+pub fn verify(leaf: &str, steps: &[MerkleStep], root: &str) -> bool {
+    let computed = steps.iter().fold(leaf.to_string(), |hash, step| match step.left {
+        true => join(&step.hash, &hash),
+        false => join(&hash, &step.hash),
+    });
+    computed == root
+}
+
+fn join(left: &str, right: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_leaf_proves_its_inclusion() {
+        for count in 1..=7 {
+            let leaves: Vec<String> = (0..count).map(|leaf| hex::encode(Sha256::digest([leaf as u8]))).collect();
+            let root = root(&leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let steps = proof(&leaves, index).unwrap();
+                assert!(verify(leaf, &steps, &root), "Leaf {} of {} did not prove its inclusion", index, count);
+                assert!(!verify(&leaves[(index + 1) % count], &steps, &root) || count == 1);
+            }
+            assert_eq!(proof(&leaves, count), None);
+        }
+        assert_eq!(root(&["a".to_string()]), "a");
+    }
+}
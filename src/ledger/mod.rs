@@ -0,0 +1,365 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File as StdFile, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::catalog::journal::{decode_records, encode_record};
+use crate::catalog::{write_atomically, JournalOp};
+use crate::identity::LocalIdentity;
+use crate::model::{File, FileError};
+
+pub mod anchor;
+pub mod consensus;
+pub mod merkle;
+pub mod pool;
+pub mod producer;
+pub mod receipt;
+
+pub use consensus::{Consensus, Genesis, ProofOfWork, ValidatorChange};
+pub use pool::{baseline_files, orphaned_operations, pending_operations, validator_proposals, PendingOperation, RecordPool};
+
+/// Parent hash of the first block of every chain.
+pub const GENESIS_PARENT: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// What a block's hash covers. The operations enter it through the root of their Merkle
+/// tree and the hash of their encoding, so a receipt can tie one operation to the block
+/// with the header alone.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BlockHeader {
+    pub height: u64,
+    pub parent: String,
+    pub timestamp: NaiveDateTime,
+    pub merkle_root: String,
+    pub body_hash: String,
+    pub signer: Option<i64>,
+    pub difficulty: u32,
+    pub nonce: u64,
+}
+
+impl BlockHeader {
+    pub fn hash(&self) -> Result<String, FileError> {
+        let encoded = bincode::serialize(self).map_err(|_| FileError::DeserializationError("Block header serialization failed".to_string()))?;
+        Ok(hex::encode(Sha256::digest(encoded)))
+    }
+}
+
+/// A batch of catalog operations, linked to the block before it by that block's hash.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Block {
+    pub height: u64,
+    pub parent: String,
+    pub timestamp: NaiveDateTime,
+    pub operations: Vec<JournalOp>,
+    pub validator_changes: Vec<ValidatorChange>,
+    pub signer: Option<i64>,
+    pub difficulty: u32,
+    pub nonce: u64,
+    pub hash: String,
+    pub signature: Vec<u8>,
+}
+
+impl Block {
+    pub fn new(parent: Option<&Block>, operations: Vec<JournalOp>) -> Result<Self, FileError> {
+        let mut block = Block {
+            height: parent.map_or(0, |parent| parent.height + 1),
+            parent: parent.map_or_else(|| GENESIS_PARENT.to_string(), |parent| parent.hash.clone()),
+            timestamp: Utc::now().naive_utc(),
+            operations,
+            validator_changes: Vec::new(),
+            signer: None,
+            difficulty: 0,
+            nonce: 0,
+            hash: String::new(),
+            signature: Vec::new(),
+        };
+        block.hash = block.compute_hash()?;
+        Ok(block)
+    }
+
+    /// The header of the block as it stands, whose hash is the block's hash.
+    pub fn header(&self) -> Result<BlockHeader, FileError> {
+        let leaves = self.operations.iter().map(merkle::operation_leaf).collect::<Result<Vec<_>, _>>()?;
+        let body = bincode::serialize(&(&self.operations, &self.validator_changes))
+            .map_err(|_| FileError::DeserializationError("Block serialization failed".to_string()))?;
+        Ok(BlockHeader {
+            height: self.height,
+            parent: self.parent.clone(),
+            timestamp: self.timestamp,
+            merkle_root: merkle::root(&leaves),
+            body_hash: hex::encode(Sha256::digest(body)),
+            signer: self.signer,
+            difficulty: self.difficulty,
+            nonce: self.nonce,
+        })
+    }
+
+    /// Hashes the header, which covers everything in the block except the hash and the
+    /// signature.
+    pub fn compute_hash(&self) -> Result<String, FileError> {
+        self.header()?.hash()
+    }
+
+    /// Tells whether the hash starts with as many zero bits as the block's difficulty.
+    pub fn meets_difficulty(&self) -> bool {
+        let Ok(hash) = hex::decode(&self.hash) else { return false };
+        let mut zeros = 0;
+        for byte in hash {
+            zeros += byte.leading_zeros();
+            if byte != 0 {
+                break;
+            }
+        }
+        zeros >= self.difficulty
+    }
+
+    /// How much work the block represents; a block without proof of work counts as one.
+    pub fn work(&self) -> u128 {
+        1u128 << self.difficulty.min(127)
+    }
+
+    /// Names `identity` as the block's signer and signs the resulting hash.
+    pub fn sign(&mut self, identity: &LocalIdentity) -> Result<(), FileError> {
+        self.signer = Some(identity.id());
+        self.hash = self.compute_hash()?;
+        self.signature = identity.sign(self.hash.as_bytes());
+        Ok(())
+    }
+
+    /// Checks that the block is intact and follows `parent`, or starts the chain when
+    /// there is no parent.
+    pub fn verify_child_of(&self, parent: Option<&Block>) -> Result<(), FileError> {
+        if self.hash != self.compute_hash()? {
+            return Err(FileError::IntegrityError(format!("Block {} does not match its hash", self.height)));
+        }
+        let (height, parent_hash) = parent.map_or((0, GENESIS_PARENT), |parent| (parent.height + 1, parent.hash.as_str()));
+        if self.height != height || self.parent != parent_hash {
+            return Err(FileError::IntegrityError(format!("Block {} does not follow block {}", self.hash, parent_hash)));
+        }
+        Ok(())
+    }
+}
+
+/// The chain of blocks recording every catalog change, kept in an append-only file next
+/// to the catalog and encrypted whenever the catalog is. Blocks are checked against the
+/// consensus of the network's genesis as they are added.
+pub struct Ledger {
+    catalog_path: PathBuf,
+    file: StdFile,
+    genesis: Genesis,
+    blocks: Vec<Block>,
+}
+
+impl Ledger {
+    /// Opens the ledger for appending and drops a block torn by a crash. Call it only
+    /// while holding the catalog lock.
+    pub fn open(catalog_path: &Path) -> Result<Self, FileError> {
+        let mut file = OpenOptions::new().create(true).truncate(false).read(true).append(true).open(get_ledger_path(catalog_path))?;
+        let mut encoded = Vec::new();
+        file.read_to_end(&mut encoded)?;
+        let (blocks, valid_len) = decode_records(catalog_path, &encoded)?;
+        if valid_len < encoded.len() {
+            file.set_len(valid_len as u64)?;
+        }
+        Ok(Ledger { catalog_path: catalog_path.to_path_buf(), file, genesis: Genesis::load(catalog_path)?, blocks })
+    }
+
+    /// Reads the intact blocks without taking the catalog lock or repairing the file.
+    pub fn read_blocks(catalog_path: &Path) -> Result<Vec<Block>, FileError> {
+        match fs::read(get_ledger_path(catalog_path)) {
+            Ok(encoded) => decode_records(catalog_path, &encoded).map(|(blocks, _)| blocks),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(FileError::IOError(e)),
+        }
+    }
+
+    pub fn genesis(&self) -> &Genesis {
+        &self.genesis
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Number of blocks, which is also the height of the next one.
+    pub fn height(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    pub fn tip(&self) -> Option<&Block> {
+        self.blocks.last()
+    }
+
+    pub fn get(&self, height: u64) -> Option<&Block> {
+        self.blocks.get(height as usize)
+    }
+
+    /// Tells whether `op` was recorded in any block, searching from the tip.
+    pub fn contains_operation(&self, op: &JournalOp) -> bool {
+        self.blocks.iter().rev().any(|block| block.operations.contains(op))
+    }
+
+    pub fn append(&mut self, block: Block) -> Result<(), FileError> {
+        block.verify_child_of(self.tip())?;
+        self.genesis.verify_seal(&self.blocks, &block)?;
+        self.file.write_all(&encode_record(&self.catalog_path, &block)?)?;
+        self.file.sync_all()?;
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    /// Records `operations` in a new block on top of the chain, sealed as the genesis
+    /// requires.
+    pub fn produce(&mut self, operations: Vec<JournalOp>) -> Result<Block, FileError> {
+        let mut block = Block::new(self.tip(), operations)?;
+        self.genesis.seal(&self.blocks, &mut block, None)?;
+        self.append(block.clone())?;
+        Ok(block)
+    }
+
+    /// Checks that `blocks` form a valid chain on top of the first `fork_height` blocks.
+    pub fn verify_extension(&self, fork_height: u64, blocks: &[Block]) -> Result<(), FileError> {
+        let mut chain = self.blocks[..(fork_height as usize).min(self.blocks.len())].to_vec();
+        for block in blocks {
+            block.verify_child_of(chain.last())?;
+            self.genesis.verify_seal(&chain, block)?;
+            chain.push(block.clone());
+        }
+        Ok(())
+    }
+
+    /// Replaces every block from `fork_height` on with `blocks` and returns the blocks
+    /// that were dropped.
+    pub fn replace_from(&mut self, fork_height: u64, blocks: Vec<Block>) -> Result<Vec<Block>, FileError> {
+        self.verify_extension(fork_height, &blocks)?;
+        let mut kept = self.blocks[..(fork_height as usize).min(self.blocks.len())].to_vec();
+        let mut encoded = Vec::new();
+        for block in &kept {
+            encoded.extend(encode_record(&self.catalog_path, block)?);
+        }
+        for block in blocks {
+            encoded.extend(encode_record(&self.catalog_path, &block)?);
+            kept.push(block);
+        }
+        let path = get_ledger_path(&self.catalog_path);
+        write_atomically(&path, &encoded)?;
+        self.file = OpenOptions::new().read(true).append(true).open(&path)?;
+        let dropped = self.blocks.split_off((fork_height as usize).min(self.blocks.len()));
+        self.blocks = kept;
+        Ok(dropped)
+    }
+}
+
+/// The fork choice rule, applied to the blocks each chain has past the fork: the chain
+/// with more work wins, which without proof of work means the longer one, and between
+/// chains with the same work, the one whose tip has the lower hash. Every node therefore
+/// settles on the same chain whatever order it saw the blocks in.
+pub fn is_preferred(candidate: &[Block], current: &[Block]) -> bool {
+    let score = |blocks: &[Block]| (blocks.iter().map(Block::work).sum::<u128>(), blocks.last().map(|tip| Reverse(tip.hash.clone())));
+    score(candidate) > score(current)
+}
+
+/// Finds the block holding the store or transfer whose file carries `transaction_id` as
+/// its `onchain_txn_id`.
+pub fn find_transaction<'a>(blocks: &'a [Block], transaction_id: &str) -> Option<&'a Block> {
+    blocks.iter().rev().find(|block| block.operations.iter().any(|op| op.stored().is_some_and(|(file, _)| file.onchain_txn_id == transaction_id)))
+}
+
+/// Identifies a store in the ledger: the hash of the stored file record with its
+/// `onchain_txn_id` left blank.
+pub fn transaction_id(file: &File) -> Result<String, FileError> {
+    let mut file = file.clone();
+    file.onchain_txn_id = String::new();
+    let encoded = bincode::serialize(&file).map_err(|_| FileError::DeserializationError("File serialization failed".to_string()))?;
+    Ok(hex::encode(Sha256::digest(encoded)))
+}
+
+/// Works out what the catalog must look like after switching from `local` to the chain
+/// made of its first `fork_height` blocks followed by `blocks`. Returns one operation
+/// per file changed on either side of the fork: the last change the new chain recorded
+/// for it, or else the store in `baseline` that puts back the file as it was before the
+/// ledger first changed it, or else a removal. Policies are left to the caller.
+pub fn fork_targets(local: &[Block], fork_height: u64, blocks: &[Block], baseline: &[JournalOp]) -> Vec<JournalOp> {
+    let fork_height = (fork_height as usize).min(local.len());
+    let touched: BTreeSet<i64> = local[fork_height..].iter().chain(blocks)
+        .flat_map(|block| block.operations.iter().filter(|op| op.is_file_change()).map(JournalOp::file_id))
+        .collect();
+    let mut latest = BTreeMap::new();
+    for op in baseline.iter().chain(local[..fork_height].iter().chain(blocks).flat_map(|block| block.operations.iter())) {
+        if op.is_file_change() && touched.contains(&op.file_id()) {
+            latest.insert(op.file_id(), op.clone());
+        }
+    }
+    touched.into_iter()
+        .map(|file_id| latest.remove(&file_id).unwrap_or(JournalOp::Remove { file_id, deletion: None }))
+        .collect()
+}
+
+/// What a sync did to the local chain: how many blocks it took from the peer and, when
+/// it switched to a better chain, where the chains forked and which local operations
+/// were left out of the chain that won.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChainUpdate {
+    pub imported: usize,
+    pub fork_height: Option<u64>,
+    pub orphaned: Vec<JournalOp>,
+}
+
+pub fn get_ledger_path(catalog_path: &Path) -> PathBuf {
+    let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".ledger");
+    catalog_path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_blocks_chain_and_survive_reopen() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        let mut ledger = Ledger::open(&catalog_path).unwrap();
+        let first = ledger.produce(vec![JournalOp::Remove { file_id: 1, deletion: None }]).unwrap();
+        let second = ledger.produce(vec![JournalOp::Remove { file_id: 2, deletion: None }]).unwrap();
+        assert_eq!((first.height, second.height), (0, 1));
+        assert_eq!(second.parent, first.hash);
+        let orphan = Block::new(None, vec![JournalOp::Remove { file_id: 3, deletion: None }]).unwrap();
+        assert!(matches!(ledger.append(orphan), Err(FileError::IntegrityError(_))));
+        let mut tampered = Block::new(Some(&second), vec![JournalOp::Remove { file_id: 3, deletion: None }]).unwrap();
+        tampered.operations.clear();
+        assert!(matches!(ledger.append(tampered), Err(FileError::IntegrityError(_))));
+        drop(ledger);
+        assert_eq!(Ledger::open(&catalog_path).unwrap().blocks(), &[first, second]);
+    }
+
+    #[test]
+    fn test_fork_choice_and_targets() {
+        let dir = tempdir().unwrap();
+        let catalog_path = dir.path().join("assets");
+        let mut ledger = Ledger::open(&catalog_path).unwrap();
+        let root = ledger.produce(vec![JournalOp::Remove { file_id: 1, deletion: None }]).unwrap();
+        let local = ledger.produce(vec![JournalOp::Remove { file_id: 2, deletion: None }]).unwrap();
+        let rival = Block::new(Some(&root), vec![JournalOp::Remove { file_id: 3, deletion: None }]).unwrap();
+        let longer = Block::new(Some(&rival), vec![JournalOp::Remove { file_id: 4, deletion: None }]).unwrap();
+        assert!(is_preferred(&[rival.clone(), longer.clone()], std::slice::from_ref(&local)));
+        assert!(!is_preferred(&[], std::slice::from_ref(&local)));
+        assert_ne!(is_preferred(std::slice::from_ref(&rival), std::slice::from_ref(&local)), is_preferred(std::slice::from_ref(&local), std::slice::from_ref(&rival)));
+        let mut heavy = local.clone();
+        heavy.difficulty = 2;
+        assert!(is_preferred(&[heavy], &[rival.clone(), longer.clone()]), "More work should beat more blocks");
+        let targets = fork_targets(ledger.blocks(), 1, &[rival.clone(), longer.clone()], &[]);
+        assert_eq!(targets, (2..=4).map(|file_id| JournalOp::Remove { file_id, deletion: None }).collect::<Vec<_>>());
+        let dropped = ledger.replace_from(1, vec![rival.clone(), longer.clone()]).unwrap();
+        assert_eq!(dropped, vec![local]);
+        ledger.produce(vec![JournalOp::Remove { file_id: 5, deletion: None }]).unwrap();
+        drop(ledger);
+        assert_eq!(&Ledger::open(&catalog_path).unwrap().blocks()[..3], &[root, rival, longer]);
+    }
+}
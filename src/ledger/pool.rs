@@ -0,0 +1,104 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::journal::{decode_records, encode_record};
+use crate::catalog::{write_atomically, JournalOp};
+use crate::ledger::consensus::ValidatorChange;
+use crate::model::FileError;
+
+/// A catalog operation that is not in the chain, kept with the content it stores so it
+/// can still be put in a block.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PendingOperation {
+    pub op: JournalOp,
+    pub content: Option<Vec<u8>>,
+}
+
+/// Records waiting outside the chain, in a file next to the catalog that is encrypted
+/// whenever the catalog is. Call the methods that change it only while holding the
+/// catalog lock.
+pub struct RecordPool<T> {
+    catalog_path: PathBuf,
+    path: PathBuf,
+    records: PhantomData<T>,
+}
+
+/// Local operations whose block lost to another chain.
+pub fn orphaned_operations(catalog_path: &Path) -> RecordPool<PendingOperation> {
+    RecordPool::open(catalog_path, ".orphans")
+}
+
+/// Operations waiting for the validator whose turn it is to put them in a block.
+pub fn pending_operations(catalog_path: &Path) -> RecordPool<PendingOperation> {
+    RecordPool::open(catalog_path, ".pending")
+}
+
+/// Files as they stood, with their content, before the ledger first recorded a change to
+/// them, for a fork that abandons that change to put them back.
+pub fn baseline_files(catalog_path: &Path) -> RecordPool<PendingOperation> {
+    RecordPool::open(catalog_path, ".baseline")
+}
+
+/// Validator set changes this node puts in the next block it signs.
+pub fn validator_proposals(catalog_path: &Path) -> RecordPool<ValidatorChange> {
+    RecordPool::open(catalog_path, ".proposals")
+}
+
+impl<T: Serialize + DeserializeOwned> RecordPool<T> {
+    pub(crate) fn open(catalog_path: &Path, suffix: &str) -> Self {
+        let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+        name.push(suffix);
+        RecordPool { catalog_path: catalog_path.to_path_buf(), path: catalog_path.with_file_name(name), records: PhantomData }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn add(&self, records: &[T]) -> Result<(), FileError> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        for record in records {
+            file.write_all(&encode_record(&self.catalog_path, record)?)?;
+        }
+        file.sync_all()?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Result<Vec<T>, FileError> {
+        match fs::read(&self.path) {
+            Ok(encoded) => decode_records(&self.catalog_path, &encoded).map(|(records, _)| records),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(FileError::IOError(e)),
+        }
+    }
+
+    /// Keeps only the records for which `keep` returns true.
+    pub fn retain(&self, mut keep: impl FnMut(&T) -> bool) -> Result<(), FileError> {
+        let records = self.list()?;
+        let count = records.len();
+        let mut encoded = Vec::new();
+        let mut kept = 0;
+        for record in records.iter().filter(|record| keep(record)) {
+            encoded.extend(encode_record(&self.catalog_path, record)?);
+            kept += 1;
+        }
+        match kept {
+            0 => self.clear(),
+            kept if kept == count => Ok(()),
+            _ => write_atomically(&self.path, &encoded),
+        }
+    }
+
+    pub fn clear(&self) -> Result<(), FileError> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(FileError::IOError(e)),
+        }
+    }
+}
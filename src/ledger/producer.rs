@@ -0,0 +1,42 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+/// How long the producer waits between two looks at the pending operations.
+pub const PRODUCER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Seals the pending operations into blocks in the background until it is dropped, for
+/// networks whose blocks need proof of work.
+pub struct BlockProducer {
+    stop: Arc<AtomicBool>,
+}
+
+impl BlockProducer {
+    pub fn start(catalog_path: &Path, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (path, running) = (catalog_path.to_path_buf(), stop.clone());
+        thread::spawn(move || {
+            while !running.load(Ordering::SeqCst) {
+                match crate::produce_block(&path) {
+                    Ok(Some(block)) => info!("Sealed block {} with {} operations.", block.height, block.operations.len()),
+                    Ok(None) => thread::sleep(interval),
+                    Err(e) => {
+                        warn!("Failed to seal a block: {}", e);
+                        thread::sleep(interval);
+                    },
+                }
+            }
+        });
+        BlockProducer { stop }
+    }
+}
+
+impl Drop for BlockProducer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
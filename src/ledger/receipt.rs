@@ -0,0 +1,250 @@
+use std::path::Path;
+
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::content_hash;
+use crate::identity::{load_or_create_local_identity, PublicIdentity};
+use crate::ledger::anchor::{self, covering_anchor, Anchor, AnchorConfirmation, EthClient};
+use crate::ledger::merkle::{self, MerkleStep};
+use crate::ledger::{find_transaction, transaction_id, BlockHeader, Ledger};
+use crate::model::{File, FileError};
+
+/// Format of the receipts this version issues.
+pub const RECEIPT_VERSION: u32 = 1;
+
+/// Portable evidence that a file record, and the content with its hash, was in a ledger
+/// block at the block's time: the record, the block's header and hash, a Merkle proof of
+/// the store up to the root in the header, the anchor covering the block if there is one
+/// yet with the headers leading to the anchored block, all signed by the node that issued
+/// it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Receipt {
+    pub version: u32,
+    pub file: File,
+    pub content_hash: Option<String>,
+    pub transaction_id: String,
+    pub block: BlockHeader,
+    pub block_hash: String,
+    pub merkle_proof: Vec<MerkleStep>,
+    pub anchor: Option<Anchor>,
+    pub anchor_path: Vec<BlockHeader>,
+    pub issued: NaiveDateTime,
+    pub signer: PublicIdentity,
+    pub signature: String,
+}
+
+impl Receipt {
+    /// Encodes everything in the receipt except the signature.
+    pub fn signing_message(&self) -> Result<Vec<u8>, FileError> {
+        bincode::serialize(&(
+            self.version, &self.file, &self.content_hash, &self.transaction_id, &self.block, &self.block_hash,
+            &self.merkle_proof, &self.anchor, &self.anchor_path, self.issued, &self.signer,
+        )).map_err(|_| FileError::DeserializationError("Receipt serialization failed".to_string()))
+    }
+}
+
+/// What `verify` established about a receipt besides its signature and Merkle proof.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiptVerification {
+    pub signer: PublicIdentity,
+    pub content_checked: bool,
+    pub anchor: Option<AnchorConfirmation>,
+}
+
+/// Checks a receipt on its own, without the repository that issued it: the signature, the
+/// transaction ID against the file record, the block hash against the header, the Merkle
+/// proof against the root in the header, the chain of headers from the block to the
+/// anchored block, the content hash against `content` when it is given, and the anchor
+/// against the EVM chain when a `client` is given. A receipt signed by anyone but
+/// `trusted_signer`, when one is given, is refused. Any failed check is reported as an
+/// `IntegrityError`.
+pub fn verify(receipt: &Receipt, content: Option<&[u8]>, trusted_signer: Option<&PublicIdentity>, client: Option<&EthClient>) -> Result<ReceiptVerification, FileError> {
+    let invalid = |reason: &str| Err(FileError::IntegrityError(reason.to_string()));
+    if receipt.version != RECEIPT_VERSION {
+        return invalid(&format!("Receipt version {} is not supported", receipt.version));
+    }
+    if trusted_signer.is_some_and(|trusted| trusted.verifying_key != receipt.signer.verifying_key) {
+        return invalid(&format!("The receipt was signed by {} <{}>, not by the trusted signer", receipt.signer.name, receipt.signer.email));
+    }
+    let signature = hex::decode(&receipt.signature).map_err(|_| FileError::IntegrityError("The receipt signature is not hex".to_string()))?;
+    receipt.signer.verify(&receipt.signing_message()?, &signature)
+        .map_err(|_| FileError::IntegrityError("The receipt signature is invalid".to_string()))?;
+    if transaction_id(&receipt.file)? != receipt.transaction_id || receipt.file.onchain_txn_id != receipt.transaction_id {
+        return invalid("The transaction ID does not match the file record");
+    }
+    if receipt.file.content_hash != receipt.content_hash {
+        return invalid("The content hash does not match the file record");
+    }
+    if receipt.block.hash()? != receipt.block_hash {
+        return invalid("The block header does not match the block hash");
+    }
+    if !merkle::verify(&receipt.transaction_id, &receipt.merkle_proof, &receipt.block.merkle_root) {
+        return invalid("The Merkle proof does not lead to the Merkle root of the block");
+    }
+    let (mut height, mut hash) = (receipt.block.height, receipt.block_hash.clone());
+    for header in &receipt.anchor_path {
+        if header.height != height + 1 || header.parent != hash {
+            return invalid(&format!("The header at height {} does not follow block {}", header.height, hash));
+        }
+        (height, hash) = (header.height, header.hash()?);
+    }
+    if let Some(content) = content {
+        if receipt.content_hash.as_deref() != Some(content_hash(content).as_str()) {
+            return invalid("The file does not match the content hash in the receipt");
+        }
+    }
+    let confirmation = match (&receipt.anchor, client) {
+        (Some(anchor), _) if anchor.height != height || anchor.block_hash != hash => {
+            return invalid("The anchor is not of the block the headers lead to");
+        },
+        (None, _) if !receipt.anchor_path.is_empty() => return invalid("The receipt has headers after the block but no anchor"),
+        (Some(anchor), Some(client)) => anchor::confirm(client, anchor)?,
+        _ => None,
+    };
+    Ok(ReceiptVerification { signer: receipt.signer.clone(), content_checked: content.is_some(), anchor: confirmation })
+}
+
+/// Issues a receipt for the store with the given `onchain_txn_id`, signed by the local
+/// identity. Returns `None` while the store is still waiting to enter the chain.
+pub fn issue(catalog_path: &Path, transaction_id: &str) -> Result<Option<Receipt>, FileError> {
+    let blocks = Ledger::read_blocks(catalog_path)?;
+    let Some(block) = find_transaction(&blocks, transaction_id) else { return Ok(None) };
+    let (index, file) = block.operations.iter().enumerate().rev()
+        .find_map(|(index, op)| match op.stored() {
+            Some((file, _)) if file.onchain_txn_id == transaction_id => Some((index, file.clone())),
+            _ => None,
+        })
+        .ok_or(FileError::FileNotFound)?;
+    let leaves = block.operations.iter().map(merkle::operation_leaf).collect::<Result<Vec<String>, FileError>>()?;
+    let anchor = covering_anchor(catalog_path, &blocks, block.height)?;
+    let anchor_path = match &anchor {
+        Some(anchor) => blocks[block.height as usize + 1..=anchor.height as usize].iter().map(|block| block.header()).collect::<Result<_, _>>()?,
+        None => Vec::new(),
+    };
+    let local = load_or_create_local_identity(catalog_path)?;
+    let mut receipt = Receipt {
+        version: RECEIPT_VERSION,
+        content_hash: file.content_hash.clone(),
+        transaction_id: transaction_id.to_string(),
+        block: block.header()?,
+        block_hash: block.hash.clone(),
+        merkle_proof: merkle::proof(&leaves, index).ok_or(FileError::FileNotFound)?,
+        anchor,
+        anchor_path,
+        issued: Utc::now().naive_utc(),
+        signer: local.public.clone(),
+        signature: String::new(),
+        file,
+    };
+    receipt.signature = hex::encode(local.sign(&receipt.signing_message()?));
+    Ok(Some(receipt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use crate::testing::{AssetsPath, TestFile};
+
+    #[test]
+    fn test_issue_a_signed_receipt_for_a_store() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        crate::save_files_to_file(&[], &path).unwrap();
+        let stored = crate::store_new_file(TestFile::new(1).build(), Some(b"content".to_vec())).unwrap();
+
+        let receipt = issue(&path, &stored.onchain_txn_id).unwrap().expect("No receipt was issued");
+        assert_eq!(receipt.file, stored);
+        assert_eq!(receipt.content_hash, Some(crate::catalog::content_hash(b"content")));
+        let block = Ledger::read_blocks(&path).unwrap().remove(receipt.block.height as usize);
+        assert_eq!((receipt.block.clone(), receipt.block_hash.clone()), (block.header().unwrap(), block.hash));
+        assert!(merkle::verify(&receipt.transaction_id, &receipt.merkle_proof, &receipt.block.merkle_root));
+        assert_eq!(receipt.signer, load_or_create_local_identity(&path).unwrap().public);
+        assert_eq!(receipt.anchor, None);
+
+        let parsed: Receipt = serde_json::from_str(&serde_json::to_string(&receipt).unwrap()).unwrap();
+        let signature = hex::decode(&parsed.signature).unwrap();
+        parsed.signer.verify(&parsed.signing_message().unwrap(), &signature).unwrap();
+        assert_eq!(issue(&path, "unknown").unwrap(), None);
+    }
+
+    #[test]
+    fn test_verify_a_receipt_without_the_repository() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        crate::save_files_to_file(&[], &path).unwrap();
+        crate::store_new_file(TestFile::new(1).build(), None).unwrap();
+        let stored = crate::store_new_file(TestFile::new(2).build(), Some(b"content".to_vec())).unwrap();
+        let receipt = issue(&path, &stored.onchain_txn_id).unwrap().unwrap();
+        let local = load_or_create_local_identity(&path).unwrap();
+        drop(dir);
+
+        let verification = verify(&receipt, Some(b"content"), Some(&local.public), None).unwrap();
+        assert!(verification.content_checked);
+        assert_eq!((verification.signer, verification.anchor), (local.public.clone(), None));
+        assert!(!verify(&receipt, None, None, None).unwrap().content_checked);
+        assert!(matches!(verify(&receipt, Some(b"other"), None, None), Err(FileError::IntegrityError(_))));
+        let stranger = crate::identity::LocalIdentity::generate(9, "Stranger", "stranger@gmail.com");
+        assert!(matches!(verify(&receipt, None, Some(&stranger.public), None), Err(FileError::IntegrityError(_))));
+
+        let mut renamed = receipt.clone();
+        renamed.file.name = "forged".to_string();
+        assert!(matches!(verify(&renamed, None, None, None), Err(FileError::IntegrityError(_))));
+        let resign = |mut receipt: Receipt| {
+            receipt.signature = hex::encode(local.sign(&receipt.signing_message().unwrap()));
+            receipt
+        };
+        let mut misplaced = receipt.clone();
+        misplaced.block.merkle_root = "00".repeat(32);
+        assert!(matches!(verify(&resign(misplaced.clone()), None, None, None), Err(FileError::IntegrityError(_))));
+        misplaced.block_hash = misplaced.block.hash().unwrap();
+        assert!(matches!(verify(&resign(misplaced), None, None, None), Err(FileError::IntegrityError(_))));
+        let mut forged = receipt.clone();
+        forged.file.name = "forged".to_string();
+        forged.file.onchain_txn_id = transaction_id(&forged.file).unwrap();
+        forged.transaction_id = forged.file.onchain_txn_id.clone();
+        assert!(matches!(verify(&resign(forged), None, None, None), Err(FileError::IntegrityError(_))));
+        let mut reanchored = receipt.clone();
+        reanchored.anchor = Some(Anchor {
+            height: receipt.block.height, block_hash: "00".repeat(32), chain_id: 31337, transaction_hash: "0x01".to_string(), submitted: Utc::now().naive_utc(),
+        });
+        assert!(matches!(verify(&resign(reanchored), None, None, None), Err(FileError::IntegrityError(_))));
+    }
+
+    #[test]
+    fn test_chain_a_receipt_to_a_later_anchor() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        crate::save_files_to_file(&[], &path).unwrap();
+        let stored = crate::store_new_file(TestFile::new(1).build(), Some(b"content".to_vec())).unwrap();
+        crate::store_new_file(TestFile::new(2).build(), None).unwrap();
+        crate::store_new_file(TestFile::new(3).build(), None).unwrap();
+        let tip = Ledger::read_blocks(&path).unwrap().pop().unwrap();
+        anchor::anchors(&path).add(&[Anchor {
+            height: tip.height, block_hash: tip.hash.clone(), chain_id: 31337, transaction_hash: "0x01".to_string(), submitted: Utc::now().naive_utc(),
+        }]).unwrap();
+        let receipt = issue(&path, &stored.onchain_txn_id).unwrap().unwrap();
+        let local = load_or_create_local_identity(&path).unwrap();
+
+        assert_eq!(receipt.anchor_path.len() as u64, tip.height - receipt.block.height);
+        assert_eq!(receipt.anchor_path.last().unwrap().hash().unwrap(), tip.hash);
+        verify(&receipt, Some(b"content"), Some(&local.public), None).unwrap();
+
+        let resign = |mut receipt: Receipt| {
+            receipt.signature = hex::encode(local.sign(&receipt.signing_message().unwrap()));
+            receipt
+        };
+        let mut shortened = receipt.clone();
+        shortened.anchor_path.pop();
+        assert!(matches!(verify(&resign(shortened), None, None, None), Err(FileError::IntegrityError(_))));
+        let mut detached = receipt.clone();
+        detached.anchor_path[0].parent = "00".repeat(32);
+        assert!(matches!(verify(&resign(detached), None, None, None), Err(FileError::IntegrityError(_))));
+    }
+}
@@ -1,15 +1,37 @@
-use std::fs::File as StdFile;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File as StdFile};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::env;
 
-use bincode;
-
 pub mod model;
+pub mod api;
+pub mod catalog;
 pub mod commands;
+pub mod crypto;
+pub mod identity;
+pub mod ledger;
+pub mod net;
+pub mod node;
+pub mod permissions;
+pub mod raft;
+pub mod rpc;
 pub mod utils;
 
+#[cfg(test)]
+mod testing;
+
+use catalog::format::{decode_catalog, encode_catalog, CURRENT_VERSION};
+use catalog::index::{access_log, record_access, with_cached_catalog};
+use catalog::{content_hash, get_backend, open_sqlite_catalog, write_atomically, Backend, BlobStore, CatalogLock, Journal, JournalOp, RecoveryAction};
+use crypto::envelope::{is_envelope, Envelope};
+use identity::{load_or_create_local_identity, IdentityRegistry, LocalIdentity};
+use ledger::{baseline_files, fork_targets, is_preferred, orphaned_operations, pending_operations, validator_proposals, Block, ChainUpdate, Genesis, Ledger, PendingOperation, ValidatorChange};
+use log::warn;
 use model::{File, FileData, FileError};
+use permissions::access::{self, AccessRequest, AccessStatus};
+use permissions::deletion::{self, DeletionPolicy, PendingDeletion, Removal};
+use permissions::transfer::{self, TransferProposal};
 use utils::{get_default_file, process_modified_file, update_accessed_file_date};
 
 const DEFAULT_PATH: &str = "../assets";
@@ -19,73 +41,1034 @@ fn get_path() -> PathBuf {
 }
 
 pub fn load_files_from_file(path: &PathBuf) -> Result<Vec<File>, FileError> {
-    let mut file = StdFile::open(&path).map_err(|e| FileError::IOError(e))?;
+    let mut file = StdFile::open(path).map_err(FileError::IOError)?;
     let mut encoded = Vec::new();
-    file.read_to_end(&mut encoded).map_err(|e| FileError::IOError(e))?;
-    if encoded.is_empty() {
-        return Ok(Vec::new());
+    file.read_to_end(&mut encoded).map_err(FileError::IOError)?;
+    decode_catalog(&crypto::open(path, &encoded)?).map(|(files, _)| files)
+}
+
+/// Writes the catalog out in full. The access times logged since the last write are
+/// superseded by the ones in `files`, so the log starts over.
+fn save_files_to_file<'a>(files: impl IntoIterator<Item = &'a File>, path: &Path) -> Result<(), FileError> {
+    write_atomically(path, &crypto::seal(path, &encode_catalog(files)?)?)?;
+    access_log(path).clear()
+}
+
+/// Rewrites a catalog stored in an older format version in the current one, after
+/// copying the original next to it. Returns the old version and the backup path.
+pub fn migrate_catalog_format() -> Result<Option<(u32, PathBuf)>, FileError> {
+    let path = get_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+    let _lock = CatalogLock::acquire(&path)?;
+    let encoded = crypto::open(&path, &fs::read(&path)?)?;
+    let (files, version) = decode_catalog(&encoded)?;
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+    let mut backup_name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    backup_name.push(format!(".v{}.bak", version));
+    let backup_path = path.with_file_name(backup_name);
+    if !backup_path.exists() {
+        fs::copy(&path, &backup_path)?;
     }
-    bincode::deserialize(&encoded).map_err(|_| FileError::DeserializationError("Failed to deserialize Vec<File>".to_string()))
+    save_files_to_file(&files, &path)?;
+    Ok(Some((version, backup_path)))
 }
 
-fn save_files_to_file(files: &Vec<File>, path: &PathBuf) -> Result<(), FileError> {
-    let encoded = bincode::serialize(files).map_err(|_| FileError::DeserializationError("Vec<File> serialization failed".to_string()))?;
-    let mut file = StdFile::create(&path).map_err(|e| FileError::IOError(e))?;
-    file.write_all(&encoded).map_err(|e| FileError::IOError(e))?;
-    Ok(())
+/// Starts replicating the catalog with the peers in `config` and keeps syncing with
+/// them in the background until the returned node is dropped.
+pub fn start_node(config: &node::NodeConfig) -> Result<node::Node, FileError> {
+    let node = node::Node::start(&get_path(), config)?;
+    node.spawn_sync_loop(config.sync_interval);
+    Ok(node)
 }
 
-pub fn create_new_file(file_data: FileData, file_path: &PathBuf) -> Result<(), FileError> {
+/// Publishes the hash of the tip of the ledger to the EVM chain in `config` every
+/// `config.interval` until the returned handle is dropped.
+pub fn start_anchoring(config: &ledger::anchor::AnchorConfig) -> ledger::anchor::Anchorer {
+    ledger::anchor::Anchorer::start(&get_path(), config)
+}
+
+/// Seals the pending changes into blocks in the background when the genesis asks for
+/// proof of work, until the returned handle is dropped. Returns `None` on networks whose
+/// blocks need none.
+pub fn start_block_producer() -> Result<Option<ledger::producer::BlockProducer>, FileError> {
     let path = get_path();
-    let mut files = load_files_from_file(&path)?;
+    Ok(Genesis::load(&path)?.proof_of_work.map(|_| ledger::producer::BlockProducer::start(&path, ledger::producer::PRODUCER_INTERVAL)))
+}
+
+/// Proves with the EVM chain in `config` that the store with the given `onchain_txn_id` was
+/// anchored. Returns `None` while no anchor covers it yet.
+pub fn prove_anchored(config: &ledger::anchor::AnchorConfig, transaction_id: &str) -> Result<Option<ledger::anchor::AnchorProof>, FileError> {
+    ledger::anchor::prove(&get_path(), &ledger::anchor::EthClient::new(&config.rpc_url), transaction_id)
+}
+
+/// Joins the Raft cluster described by `config`, after which the functions that change the
+/// catalog replicate their changes through it. The member runs until the returned handle
+/// is dropped.
+pub fn start_raft(config: &raft::RaftConfig) -> Result<raft::RaftNode, FileError> {
+    let listen = config.listen_address().ok_or_else(|| FileError::InputError(format!("Raft node {} has no address", config.id)))?;
+    let listener = std::net::TcpListener::bind(listen)?;
+    let members: Vec<raft::NodeId> = config.members.iter().map(|(id, _)| *id).collect();
+    let transport = raft::TcpTransport::new(config);
+    let node = raft::RaftNode::start(&get_path(), config.id, &members, transport.clone(), config.tick)?;
+    transport.serve(listener, &node);
+    node.activate();
+    Ok(node)
+}
+
+/// Issues an API bearer token for a registered identity, valid for `ttl` or until revoked.
+pub fn issue_api_token(identity_id: i64, ttl: Option<chrono::Duration>) -> Result<String, FileError> {
+    api::auth::issue_token(&get_path(), identity_id, ttl).map(|(token, _)| token)
+}
+
+/// Issues an access key for the S3 endpoint to a registered identity.
+pub fn issue_s3_credentials(identity_id: i64) -> Result<api::auth::S3Credentials, FileError> {
+    api::auth::issue_s3_credentials(&get_path(), identity_id)
+}
+
+pub fn is_repository_encrypted() -> bool {
+    crypto::is_encrypted(&get_path())
+}
+
+/// Unlocks an encrypted repository for the rest of the session, then finishes encrypting
+/// anything an interrupted `encrypt_repository` left in plain text. Returns how many
+/// files it encrypted.
+pub fn unlock_repository(passphrase: &str) -> Result<usize, FileError> {
+    let path = get_path();
+    let _lock = CatalogLock::acquire(&path)?;
+    crypto::unlock(&path, passphrase)?;
+    encrypt_plain_files(&path)
+}
+
+/// Turns on encryption at rest with a key derived from `passphrase` and encrypts the
+/// catalog, its record files and every stored blob. Returns how many files it encrypted.
+pub fn encrypt_repository(passphrase: &str) -> Result<usize, FileError> {
+    let path = get_path();
+    let _lock = CatalogLock::acquire(&path)?;
+    if crypto::is_encrypted(&path) {
+        return Err(FileError::InputError("The repository is already encrypted.".to_string()));
+    }
+    if get_backend() == Backend::Sqlite {
+        return Err(FileError::InputError("The SQLite catalog cannot be encrypted; switch to the bincode backend first.".to_string()));
+    }
+    crypto::initialize(&path, passphrase)?;
+    encrypt_plain_files(&path)
+}
+
+fn encrypt_plain_files(path: &Path) -> Result<usize, FileError> {
+    let blobs = BlobStore::open(path)?;
+    let mut encrypted = 0;
+    let file_ids = if blobs.is_content_addressed() { Vec::new() } else { blobs.ids()? };
+    for file_id in file_ids {
+        let Some(data) = blobs.read_raw(file_id)? else { continue };
+        if data.is_empty() || crypto::is_sealed(&data) {
+            continue;
+        }
+        blobs.write_raw(file_id, &crypto::seal(path, &data)?)?;
+        encrypted += 1;
+    }
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(encrypted),
+        Err(e) => return Err(FileError::IOError(e)),
+    };
+    if !data.is_empty() && !crypto::is_sealed(&data) {
+        write_atomically(path, &crypto::seal(path, &data)?)?;
+        encrypted += 1;
+    }
+    for record_path in get_record_paths(path) {
+        if catalog::journal::seal_plain_records(path, &record_path)? {
+            encrypted += 1;
+        }
+    }
+    Ok(encrypted)
+}
+
+/// The append-only record files kept next to the catalog.
+fn get_record_paths(path: &Path) -> Vec<PathBuf> {
+    vec![
+        catalog::journal::get_journal_path(path), ledger::get_ledger_path(path),
+        raft::node::get_raft_path(path, ".raft"), raft::node::get_raft_path(path, ".raft-log"),
+        access_log(path).path().to_path_buf(), baseline_files(path).path().to_path_buf(), orphaned_operations(path).path().to_path_buf(),
+        pending_operations(path).path().to_path_buf(), validator_proposals(path).path().to_path_buf(),
+        deletion::deletion_policies(path).path().to_path_buf(), deletion::pending_deletions(path).path().to_path_buf(),
+        access::access_requests(path).path().to_path_buf(), transfer::transfer_proposals(path).path().to_path_buf(),
+        api::auth::api_tokens(path).path().to_path_buf(), api::auth::s3_credentials(path).path().to_path_buf(),
+        ledger::anchor::anchors(path).path().to_path_buf(),
+    ]
+}
+
+pub fn create_new_file(file_data: FileData, file_path: &PathBuf) -> Result<File, FileError> {
     let mut file = get_default_file(&file_data, file_path).map_err(|e| FileError::InputError(format!("Error creating file: {}", e)))?;
     file.name = file_data.name;
-    files.push(file);
-    save_files_to_file(&files, &path)?;
-    Ok(())
+    let content = if file_path.is_file() { Some(fs::read(file_path)?) } else { None };
+    store_new_file(file, content)
+}
+
+/// Stores a new catalog entry and its content as given, without prompting for anything.
+/// Returns the entry as stored.
+pub fn store_new_file(mut file: File, content: Option<Vec<u8>>) -> Result<File, FileError> {
+    let path = get_path();
+    file.content_hash = content.as_deref().map(content_hash);
+    let blob = match &content {
+        Some(content) => Some(seal_for_people(&path, content, &file.people_with_access)?),
+        None => None,
+    };
+    address_content(&path, &mut file, blob.as_deref())?;
+    file.onchain_txn_id = ledger::transaction_id(&file)?;
+    let blob_hash = blob.as_deref().map(content_hash);
+    submit_change(&path, JournalOp::Store { file: Box::new(file.clone()), content_hash: blob_hash }, blob)?;
+    Ok(file)
+}
+
+/// Returns the block that recorded the store with the given `onchain_txn_id`, or `None`
+/// while the store is still waiting to enter the chain.
+pub fn get_transaction_block(transaction_id: &str) -> Result<Option<Block>, FileError> {
+    let blocks = Ledger::read_blocks(&get_path())?;
+    Ok(ledger::find_transaction(&blocks, transaction_id).cloned())
+}
+
+/// Issues a signed receipt proving that the store with the given `onchain_txn_id` is in the
+/// chain, or `None` while it is still waiting to enter it.
+pub fn issue_receipt(transaction_id: &str) -> Result<Option<ledger::receipt::Receipt>, FileError> {
+    ledger::receipt::issue(&get_path(), transaction_id)
+}
+
+pub fn find_identity_by_email(email: &str) -> Result<Option<identity::PublicIdentity>, FileError> {
+    IdentityRegistry::open(&get_path())?.find_by_email(email)
+}
+
+/// Decrypts the stored content of a file with the data key wrapped for the local identity.
+pub fn read_file_content(file_id: i64) -> Result<Vec<u8>, FileError> {
+    let path = get_path();
+    find_in_catalog(&path, file_id)?.ok_or(FileError::FileNotFound)?;
+    let blob = BlobStore::open(&path)?.read(file_id)?.ok_or(FileError::FileNotFound)?;
+    if !is_envelope(&blob) {
+        return Ok(blob);
+    }
+    let local = load_or_create_local_identity(&path)?;
+    Envelope::from_bytes(&blob)?.open(local.id(), &local.encryption_secret())
+}
+
+/// Checks the stored content of every file against the content hash in its catalog entry,
+/// wherever blobs are kept. Returns the files whose content is missing (`FileNotFound`) or
+/// damaged. Content sealed only for other identities cannot be checked and is skipped.
+pub fn verify_file_contents() -> Result<Vec<(i64, FileError)>, FileError> {
+    let mut problems = Vec::new();
+    for file in get_all_files()? {
+        let Some(expected) = &file.content_hash else { continue };
+        match read_file_content(file.id) {
+            Ok(content) if content_hash(&content) == *expected => {},
+            Ok(_) => problems.push((file.id, FileError::IntegrityError("The stored content does not match its hash".to_string()))),
+            Err(FileError::PermissionDenied) => {},
+            Err(e @ (FileError::IOError(_) | FileError::PassphraseRequired)) => return Err(e),
+            Err(e) => problems.push((file.id, e)),
+        }
+    }
+    Ok(problems)
+}
+
+pub fn get_all_files() -> Result<Vec<File>, FileError> {
+    let path = get_path();
+    if get_backend() == Backend::Sqlite {
+        return open_sqlite_catalog(&path)?.list_files();
+    }
+    with_cached_catalog(&path, |catalog| Ok(catalog.files().cloned().collect()))
+}
+
+pub fn get_files_by_owner(owner_id: i64) -> Result<Vec<File>, FileError> {
+    let path = get_path();
+    if get_backend() == Backend::Sqlite {
+        return open_sqlite_catalog(&path)?.files_by_owner(owner_id);
+    }
+    with_cached_catalog(&path, |catalog| Ok(catalog.by_owner(owner_id).into_iter().cloned().collect()))
+}
+
+pub fn get_files_by_name(name: &str) -> Result<Vec<File>, FileError> {
+    let path = get_path();
+    if get_backend() == Backend::Sqlite {
+        return open_sqlite_catalog(&path)?.files_by_name(name);
+    }
+    with_cached_catalog(&path, |catalog| Ok(catalog.by_name(name).into_iter().cloned().collect()))
+}
+
+pub fn get_files_by_content_hash(content_hash: &str) -> Result<Vec<File>, FileError> {
+    let path = get_path();
+    if get_backend() == Backend::Sqlite {
+        return open_sqlite_catalog(&path)?.files_by_content_hash(content_hash);
+    }
+    with_cached_catalog(&path, |catalog| Ok(catalog.by_content_hash(content_hash).into_iter().cloned().collect()))
+}
+
+/// Returns a catalog entry without recording the access, for checking what may be done with it.
+pub fn find_file(file_id: i64) -> Result<File, FileError> {
+    find_in_catalog(&get_path(), file_id)?.ok_or(FileError::FileNotFound)
 }
 
 pub fn get_file(file_id: i64) -> Result<File, FileError> {
     let path = get_path();
-    let mut files = load_files_from_file(&path)?;
-    let file_index = files.iter().position(|file| file.id == file_id).ok_or(FileError::FileNotFound)?;
-    {
-        let file = &mut files[file_index];
-        *file = update_accessed_file_date(file.clone())?;
+    if get_backend() == Backend::Sqlite {
+        return open_sqlite_catalog(&path)?.touch_file(file_id);
     }
-    save_files_to_file(&files, &get_path())?;
-    Ok(files[file_index].clone())
+    let _lock = CatalogLock::acquire(&path)?;
+    with_cached_catalog(&path, |catalog| {
+        let file = update_accessed_file_date(catalog.get(file_id).ok_or(FileError::FileNotFound)?.clone())?;
+        catalog.upsert(file.clone());
+        record_access(&path, catalog, &file)?;
+        Ok(file)
+    })
 }
 
 pub fn modify_file(file_id: i64, updated_file: File) -> Result<(), FileError> {
+    find_in_catalog(&get_path(), file_id)?.ok_or(FileError::FileNotFound)?;
+    save_modified_file(file_id, process_modified_file(updated_file)?).map(|_| ())
+}
+
+/// Replaces the catalog entry of a file with `file` as given, without prompting for
+/// anything, and follows any change of its access list in the stored content. Returns
+/// the entry as stored.
+pub fn save_modified_file(file_id: i64, mut file: File) -> Result<File, FileError> {
     let path = get_path();
-    let mut files = load_files_from_file(&path)?;
-    let file_index = files.iter().position(|file| file.id == file_id).ok_or(FileError::FileNotFound)?;
-    files[file_index] = process_modified_file(updated_file)?;
-    save_files_to_file(&files, &path)?;
-    Ok(())
+    let current = find_in_catalog(&path, file_id)?.ok_or(FileError::FileNotFound)?;
+    file.id = file_id;
+    let blob = reshare_content(&path, &current, &file)?;
+    address_content(&path, &mut file, blob.as_deref())?;
+    file.onchain_txn_id = ledger::transaction_id(&file)?;
+    let blob_hash = blob.as_deref().map(content_hash);
+    submit_change(&path, JournalOp::Store { file: Box::new(file.clone()), content_hash: blob_hash }, blob)?;
+    Ok(file)
 }
 
-pub fn remove_file(file_id: i64) -> Result<(), FileError> {
+/// Replaces the stored content of a file, updating its size, content hash and modification
+/// date. Returns the entry as stored.
+pub fn save_file_content(file_id: i64, content: Vec<u8>) -> Result<File, FileError> {
     let path = get_path();
-    let mut files = load_files_from_file(&path)?;
-    let initial_length = files.len();
-    files.retain(|file| file.id != file_id);
-    if files.len() == initial_length {
-        return Err(FileError::FileNotFound);
+    let mut file = find_in_catalog(&path, file_id)?.ok_or(FileError::FileNotFound)?;
+    let now = chrono::Utc::now().naive_utc();
+    file.size = content.len() as u64;
+    file.modified = Some(now);
+    file.accessed = Some(now);
+    file.content_hash = Some(content_hash(&content));
+    let blob = seal_for_people(&path, &content, &file.people_with_access)?;
+    address_content(&path, &mut file, Some(&blob))?;
+    file.onchain_txn_id = ledger::transaction_id(&file)?;
+    let blob_hash = Some(content_hash(&blob));
+    submit_change(&path, JournalOp::Store { file: Box::new(file.clone()), content_hash: blob_hash }, Some(blob))?;
+    Ok(file)
+}
+
+/// Records in `ipfs_hash` the CID a new blob will be stored under when blobs are kept on IPFS.
+fn address_content(path: &Path, file: &mut File, blob: Option<&[u8]>) -> Result<(), FileError> {
+    let Some(blob) = blob else { return Ok(()) };
+    if let Some(cid) = BlobStore::open(path)?.content_id(blob)? {
+        file.ipfs_hash = cid;
     }
-    save_files_to_file(&files, &path)?;
     Ok(())
 }
 
+/// Encrypts content under a fresh data key wrapped for everyone on the access list whose
+/// public identity is registered, and for the local identity so that this repository can
+/// still serve and reshare the content.
+fn seal_for_people(path: &Path, content: &[u8], people: &[(i64, String, String)]) -> Result<Vec<u8>, FileError> {
+    let local = load_or_create_local_identity(path)?;
+    let registry = IdentityRegistry::open(path)?;
+    let mut recipients = vec![local.public.clone()];
+    for person in people.iter().filter(|person| person.0 != local.id()) {
+        match registry.resolve(person)? {
+            Some(identity) => recipients.push(identity),
+            None => warn!("{} <{}> has no registered public key and will not be able to decrypt the file.", person.1, person.2),
+        }
+    }
+    Envelope::seal(content, &recipients)?.to_bytes()
+}
+
+/// Follows a change of the access list in the stored content: new people get the data key
+/// wrapped for them, and removing anyone re-encrypts the content under a new data key.
+/// Returns the new blob, or `None` when the blob does not need to change.
+fn reshare_content(path: &Path, current: &File, updated: &File) -> Result<Option<Vec<u8>>, FileError> {
+    let blob = match BlobStore::open(path)?.read(current.id)? {
+        Some(blob) if is_envelope(&blob) => blob,
+        _ => return Ok(None),
+    };
+    let people = |file: &File| file.people_with_access.iter().map(|person| person.0).collect::<HashSet<i64>>();
+    let (before, after) = (people(current), people(updated));
+    if before == after {
+        return Ok(None);
+    }
+    let local = load_or_create_local_identity(path)?;
+    let mut envelope = Envelope::from_bytes(&blob)?;
+    let data_key = envelope.unwrap_key(local.id(), &local.encryption_secret())?;
+    if !before.is_subset(&after) {
+        let content = envelope.open_with_key(&data_key)?;
+        return seal_for_people(path, &content, &updated.people_with_access).map(Some);
+    }
+    let registry = IdentityRegistry::open(path)?;
+    for person in updated.people_with_access.iter().filter(|person| !before.contains(&person.0)) {
+        match registry.resolve(person)? {
+            Some(identity) => envelope.grant(&data_key, &identity)?,
+            None => warn!("{} <{}> has no registered public key and will not be able to decrypt the file.", person.1, person.2),
+        }
+    }
+    envelope.to_bytes().map(Some)
+}
+
+/// Removes a file, unless its deletion needs several approvals: then the deletion waits
+/// for them, and is carried out by the approval that completes them.
+pub fn remove_file(file_id: i64) -> Result<Removal, FileError> {
+    let path = get_path();
+    if let Some(file) = find_in_catalog(&path, file_id)? {
+        let threshold = deletion::threshold(&path, file_id)?;
+        if threshold > 1 {
+            return deletion::request(&path, &file, threshold).map(Removal::Pending);
+        }
+    }
+    submit_change(&path, JournalOp::Remove { file_id, deletion: None }, None)?;
+    deletion::forget(&path, file_id)?;
+    transfer::forget(&path, file_id)?;
+    access::forget(&path, file_id)?;
+    Ok(Removal::Removed)
+}
+
+/// Requires `threshold` of the owner and the people with access to approve the deletion of
+/// a file. A threshold of 1 or less lets any single removal through again.
+pub fn set_deletion_threshold(file_id: i64, threshold: usize) -> Result<(), FileError> {
+    let path = get_path();
+    let policy = deletion::policy_for(&find_in_catalog(&path, file_id)?.ok_or(FileError::FileNotFound)?, threshold)?;
+    submit_change(&path, JournalOp::Policy { policy }, None)
+}
+
+/// The deletions waiting for approvals, leaving out the ones that lapsed.
+pub fn list_pending_deletions() -> Result<Vec<PendingDeletion>, FileError> {
+    deletion::list(&get_path())
+}
+
+/// Adds the approval a registered identity signed over the pending deletion's
+/// `signing_message`, and removes the file once enough people have approved, handing
+/// the approvals on with the removal so that other nodes can check them.
+pub fn submit_deletion_approval(file_id: i64, identity_id: i64, signature: &[u8]) -> Result<Removal, FileError> {
+    let path = get_path();
+    let file = find_in_catalog(&path, file_id)?.ok_or(FileError::FileNotFound)?;
+    let approver = IdentityRegistry::open(&path)?.get(identity_id)?
+        .ok_or_else(|| FileError::Unauthenticated(format!("Identity {} is not registered", identity_id)))?;
+    let pending = deletion::approve(&path, &file, &approver, signature)?;
+    if !pending.is_approved() {
+        return Ok(Removal::Pending(pending));
+    }
+    submit_change(&path, JournalOp::Remove { file_id, deletion: Some(Box::new(pending)) }, None)?;
+    deletion::forget(&path, file_id)?;
+    transfer::forget(&path, file_id)?;
+    access::forget(&path, file_id)?;
+    Ok(Removal::Removed)
+}
+
+/// Approves the pending deletion of a file as the local identity.
+pub fn approve_deletion(file_id: i64) -> Result<Removal, FileError> {
+    let path = get_path();
+    let local = load_or_create_local_identity(&path)?;
+    IdentityRegistry::open(&path)?.register(&local.public)?;
+    let pending = deletion::list(&path)?.into_iter().find(|pending| pending.file_id == file_id)
+        .ok_or_else(|| FileError::InputError(format!("No deletion of file {} is waiting for approval", file_id)))?;
+    submit_deletion_approval(file_id, local.id(), &local.sign(&pending.signing_message()?))
+}
+
+/// Records the offer a registered owner signed over `transfer::signing_message` to hand a
+/// file over to another registered identity, who takes it with `submit_transfer_acceptance`.
+pub fn submit_transfer_proposal(file_id: i64, owner_id: i64, recipient_id: i64, signature: &[u8]) -> Result<TransferProposal, FileError> {
+    let path = get_path();
+    let file = find_in_catalog(&path, file_id)?.ok_or(FileError::FileNotFound)?;
+    let registry = IdentityRegistry::open(&path)?;
+    let owner = registry.get(owner_id)?
+        .ok_or_else(|| FileError::Unauthenticated(format!("Identity {} is not registered", owner_id)))?;
+    let recipient = registry.get(recipient_id)?
+        .ok_or_else(|| FileError::InputError(format!("Identity {} is not registered", recipient_id)))?;
+    transfer::propose(&path, &file, &owner, &recipient, signature)
+}
+
+/// Offers a file to another registered identity as the local identity.
+pub fn propose_transfer(file_id: i64, recipient_id: i64) -> Result<TransferProposal, FileError> {
+    let path = get_path();
+    let local = load_or_create_local_identity(&path)?;
+    IdentityRegistry::open(&path)?.register(&local.public)?;
+    let file = find_in_catalog(&path, file_id)?.ok_or(FileError::FileNotFound)?;
+    let signature = local.sign(&transfer::signing_message(file_id, &file.onchain_txn_id, local.id(), recipient_id)?);
+    submit_transfer_proposal(file_id, local.id(), recipient_id, &signature)
+}
+
+/// The transfers waiting for their recipients, leaving out the ones that lapsed.
+pub fn list_transfer_proposals() -> Result<Vec<TransferProposal>, FileError> {
+    transfer::list(&get_path())
+}
+
+/// Hands a file over to the recipient of its pending transfer, with the signature they gave
+/// over the proposal's `signing_message`. The recipient joins the access list, and the
+/// ledger records the transfer with both signatures. Returns the entry as stored.
+pub fn submit_transfer_acceptance(file_id: i64, recipient_id: i64, signature: &[u8]) -> Result<File, FileError> {
+    let path = get_path();
+    let current = find_in_catalog(&path, file_id)?.ok_or(FileError::FileNotFound)?;
+    let recipient = IdentityRegistry::open(&path)?.get(recipient_id)?
+        .ok_or_else(|| FileError::Unauthenticated(format!("Identity {} is not registered", recipient_id)))?;
+    let transfer = transfer::accept(&path, &current, &recipient, signature)?;
+    let mut file = transfer::handed_over(&current, &recipient);
+    let blob = reshare_content(&path, &current, &file)?;
+    address_content(&path, &mut file, blob.as_deref())?;
+    file.onchain_txn_id = ledger::transaction_id(&file)?;
+    let blob_hash = blob.as_deref().map(content_hash);
+    submit_change(&path, JournalOp::Transfer { file: Box::new(file.clone()), content_hash: blob_hash, transfer: Box::new(transfer) }, blob)?;
+    transfer::forget(&path, file_id)?;
+    Ok(file)
+}
+
+/// Accepts the transfer of a file to the local identity.
+pub fn accept_transfer(file_id: i64) -> Result<File, FileError> {
+    let path = get_path();
+    let local = load_or_create_local_identity(&path)?;
+    IdentityRegistry::open(&path)?.register(&local.public)?;
+    let proposal = transfer::list(&path)?.into_iter().find(|proposal| proposal.file_id == file_id)
+        .ok_or_else(|| FileError::InputError(format!("No transfer of file {} is waiting to be accepted", file_id)))?;
+    submit_transfer_acceptance(file_id, local.id(), &local.sign(&proposal.signing_message()?))
+}
+
+/// Withdraws the pending transfer of a file as its owner, or declines it as its recipient.
+pub fn withdraw_transfer(file_id: i64, person_id: i64) -> Result<(), FileError> {
+    transfer::withdraw(&get_path(), file_id, person_id)
+}
+
+/// Asks the owner of a file to add `requester` to its access list, for the given reason.
+pub fn request_access(file_id: i64, requester: (i64, String, String), reason: &str) -> Result<AccessRequest, FileError> {
+    let path = get_path();
+    access::submit(&path, &find_in_catalog(&path, file_id)?.ok_or(FileError::FileNotFound)?, &requester, reason)
+}
+
+/// Every access request, pending or answered.
+pub fn list_access_requests() -> Result<Vec<AccessRequest>, FileError> {
+    access::list(&get_path())
+}
+
+/// Answers a pending access request as the person with `owner_id`, who must own the file.
+/// Approving it adds the requester to the access list and shares the content with them.
+/// The request is settled first, so that two answers cannot both go through, and is put
+/// back when the access cannot be granted.
+pub fn decide_access_request(request_id: i64, owner_id: i64, approved: bool) -> Result<AccessRequest, FileError> {
+    let path = get_path();
+    let request = access::list(&path)?.into_iter().find(|request| request.id == request_id && request.status == AccessStatus::Pending)
+        .ok_or_else(|| FileError::InputError(format!("No access request {} is pending", request_id)))?;
+    let file = find_in_catalog(&path, request.file_id)?.ok_or(FileError::FileNotFound)?;
+    permissions::authorize(owner_id, &file, permissions::Action::Modify)?;
+    let settled = access::settle(&path, request_id, approved)?;
+    if approved && !file.people_with_access.iter().any(|person| person.0 == request.requester.0) {
+        let mut updated = file.clone();
+        updated.people_with_access.push(request.requester.clone());
+        if let Err(e) = save_modified_file(file.id, updated) {
+            access::reopen(&path, &request)?;
+            return Err(e);
+        }
+    }
+    Ok(settled)
+}
+
+/// The answers to the access requests of `requester_id` they have not been told about
+/// yet. Each answer is returned once.
+pub fn take_access_outcomes(requester_id: i64) -> Result<Vec<AccessRequest>, FileError> {
+    access::take_outcomes(&get_path(), requester_id)
+}
+
+/// Settles every operation a crashed process left in the journal: an operation whose
+/// content reached the blob store is completed, one whose content did not is rolled back
+/// and the blob it was replacing, if any, is put back.
+pub fn recover_from_journal() -> Result<Vec<RecoveryAction>, FileError> {
+    let path = get_path();
+    let _lock = CatalogLock::acquire(&path)?;
+    let mut journal = Journal::open(&path)?;
+    let mut ledger = Ledger::open(&path)?;
+    let blobs = BlobStore::open(&path)?;
+    let pending = pending_operations(&path).list()?;
+    let mut actions = Vec::new();
+    for (seq, op) in journal.pending() {
+        let action = match op.stored() {
+            Some((file, Some(hash))) if blobs.hash(file.id)?.as_ref() != Some(hash) => {
+                blobs.restore_previous(file.id)?;
+                RecoveryAction::RolledBack(op)
+            },
+            _ => {
+                match apply_operation(&path, &blobs, &op, None) {
+                    Ok(()) | Err(FileError::FileNotFound) => {},
+                    Err(e) => return Err(e),
+                }
+                blobs.discard_previous(op.file_id())?;
+                if !ledger.contains_operation(&op) && !pending.iter().any(|pending| pending.op == op) {
+                    let content = match op.stored() {
+                        Some((file, Some(hash))) if blobs.hash(file.id)?.as_ref() == Some(hash) => blobs.read(file.id)?,
+                        _ => None,
+                    };
+                    record_operation(&path, &mut ledger, op.clone(), content)?;
+                }
+                RecoveryAction::Completed(op)
+            },
+        };
+        journal.finish(seq)?;
+        actions.push(action);
+    }
+    Ok(actions)
+}
+
+/// Replicates a catalog change through the Raft cluster when this process is a member of
+/// one, or runs it locally otherwise.
+fn submit_change(path: &Path, op: JournalOp, content: Option<Vec<u8>>) -> Result<(), FileError> {
+    match raft::active_node() {
+        Some(node) => node.submit(raft::Proposal { op, content }).map(|_| ()),
+        None => run_journaled(path, op, content.as_deref()),
+    }
+}
+
+/// Runs one catalog change under the catalog lock, journaling it first so that a crash
+/// at any point leaves enough behind for `recover_from_journal` to settle it. A change
+/// that succeeds is recorded in a new ledger block.
+fn run_journaled(path: &Path, op: JournalOp, content: Option<&[u8]>) -> Result<(), FileError> {
+    let _lock = CatalogLock::acquire(path)?;
+    let mut journal = Journal::open(path)?;
+    let mut ledger = Ledger::open(path)?;
+    let blobs = BlobStore::open(path)?;
+    keep_baseline(path, &ledger, &blobs, &op)?;
+    let seq = journal.begin(&op)?;
+    let result = apply_operation(path, &blobs, &op, content);
+    if result.is_ok() {
+        record_operation(path, &mut ledger, op, content.map(<[u8]>::to_vec))?;
+    }
+    journal.finish(seq)?;
+    result
+}
+
+/// Applies a change committed by the Raft cluster under the catalog lock and the journal,
+/// leaving the ledger alone. A transfer is applied only when both sides signed it, and a
+/// removal only with the approvals the file's deletion policy asks for.
+pub(crate) fn apply_journaled(path: &Path, op: &JournalOp, content: Option<&[u8]>) -> Result<(), FileError> {
+    let _lock = CatalogLock::acquire(path)?;
+    let mut journal = Journal::open(path)?;
+    let blobs = BlobStore::open(path)?;
+    check_consent(path, op, find_in_catalog(path, op.file_id())?.as_ref())?;
+    let seq = journal.begin(op)?;
+    let result = apply_operation(path, &blobs, op, content);
+    journal.finish(seq)?;
+    result
+}
+
+/// Checks an operation that came from another node against `previous`, the file as it
+/// stands before it, so that a peer cannot hand a file to a new owner with a plain store
+/// or with a transfer the two sides did not sign, nor remove it without the approvals
+/// its deletion policy asks for.
+fn check_imported_operation(path: &Path, op: &JournalOp, previous: Option<&File>) -> Result<(), FileError> {
+    match (op, previous) {
+        (JournalOp::Store { file, .. }, Some(previous)) if file.owner.0 != previous.owner.0 => {
+            Err(FileError::IntegrityError(format!("The imported {} changes the owner of the file without a transfer", op)))
+        },
+        (JournalOp::Policy { policy }, Some(previous)) => deletion::policy_for(previous, policy.threshold).map(|_| ())
+            .map_err(|e| FileError::IntegrityError(format!("The imported {} is invalid: {}", op, e))),
+        _ => check_consent(path, op, previous),
+    }
+}
+
+/// Checks that a transfer or a removal carries the signatures of the people who had to
+/// agree to it.
+fn check_consent(path: &Path, op: &JournalOp, previous: Option<&File>) -> Result<(), FileError> {
+    match op {
+        JournalOp::Transfer { .. } => check_transfer(path, op, previous),
+        JournalOp::Remove { .. } => check_deletion(path, op, previous),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that a transfer hands `previous` over from its owner to the new owner of the
+/// file, signed by both as the identity registry knows them.
+fn check_transfer(path: &Path, op: &JournalOp, previous: Option<&File>) -> Result<(), FileError> {
+    let JournalOp::Transfer { file, transfer, .. } = op else { return Ok(()) };
+    let refused = |reason: String| FileError::IntegrityError(format!("The {} {}", op, reason));
+    let previous = previous.ok_or_else(|| refused("is for a file that does not exist".to_string()))?;
+    if transfer.file_id != file.id || transfer.from != previous.owner.0 || transfer.to != file.owner.0 || transfer.transaction_id != previous.onchain_txn_id {
+        return Err(refused("does not match the file it hands over".to_string()));
+    }
+    let registry = IdentityRegistry::open(path)?;
+    let identity = |id: i64| registry.get(id)?.ok_or_else(|| refused(format!("is signed by identity {}, which is not registered", id)));
+    transfer.verify(&identity(transfer.from)?, &identity(transfer.to)?)
+}
+
+/// Checks that the removal of `previous` carries the approvals its deletion policy asks
+/// for. A file without a policy needs none.
+fn check_deletion(path: &Path, op: &JournalOp, previous: Option<&File>) -> Result<(), FileError> {
+    let (JournalOp::Remove { deletion, .. }, Some(previous)) = (op, previous) else { return Ok(()) };
+    let threshold = deletion::threshold(path, previous.id)?;
+    if threshold <= 1 {
+        return Ok(());
+    }
+    let deletion = deletion.as_ref()
+        .ok_or_else(|| FileError::IntegrityError(format!("The {} has no approvals, and it needs {}", op, threshold)))?;
+    deletion.verify(previous, threshold, &IdentityRegistry::open(path)?)
+}
+
+/// Keeps the file `op` changes as it stands, with its content, when no block recorded a
+/// change to it yet, so that a fork that abandons `op` can put the file back. Call it only
+/// while holding the catalog lock.
+fn keep_baseline(path: &Path, ledger: &Ledger, blobs: &BlobStore, op: &JournalOp) -> Result<(), FileError> {
+    let file_id = op.file_id();
+    if ledger.blocks().iter().flat_map(|block| block.operations.iter()).any(|known| known.file_id() == file_id) {
+        return Ok(());
+    }
+    let baseline = baseline_files(path);
+    if baseline.list()?.iter().any(|kept| kept.op.file_id() == file_id) {
+        return Ok(());
+    }
+    let Some(file) = find_in_catalog(path, file_id)? else { return Ok(()) };
+    let content = blobs.read(file_id)?;
+    let op = JournalOp::Store { file: Box::new(file), content_hash: content.as_deref().map(content_hash) };
+    baseline.add(&[PendingOperation { op, content }])
+}
+
+/// Puts a change that was carried out locally on its way into the chain: straight into a
+/// new block on an open network without proof of work, or into the pending operations
+/// otherwise, for the validators or the block producer to pick up.
+fn record_operation(path: &Path, ledger: &mut Ledger, op: JournalOp, content: Option<Vec<u8>>) -> Result<(), FileError> {
+    if ledger.genesis().is_open() && ledger.genesis().proof_of_work.is_none() {
+        ledger.produce(vec![op])?;
+        return Ok(());
+    }
+    pending_operations(path).add(&[PendingOperation { op, content }])
+}
+
+/// Drops the pending operations that made it into the chain.
+fn prune_pending_operations(path: &Path, ledger: &Ledger) -> Result<(), FileError> {
+    pending_operations(path).retain(|pending| !ledger.contains_operation(&pending.op))
+}
+
+/// Takes in operations pending on a peer that are neither in the chain nor pending here
+/// yet, so that they travel on towards the validators. Returns how many were new.
+pub(crate) fn add_pending_operations(path: &Path, operations: Vec<PendingOperation>) -> Result<usize, FileError> {
+    let _lock = CatalogLock::acquire(path)?;
+    let ledger = Ledger::open(path)?;
+    let pool = pending_operations(path);
+    let mut known = pool.list()?;
+    let mut added = Vec::new();
+    for pending in operations {
+        if ledger.contains_operation(&pending.op) || known.iter().any(|known| known.op == pending.op) {
+            continue;
+        }
+        if let Some((_, Some(hash))) = pending.op.stored() {
+            if pending.content.as_deref().map(content_hash).as_ref() != Some(hash) {
+                warn!("Ignored a pending {} whose content does not match its hash.", pending.op);
+                continue;
+            }
+        }
+        if let Err(e) = check_imported_operation(path, &pending.op, find_in_catalog(path, pending.op.file_id())?.as_ref()) {
+            warn!("Ignored a pending {}: {}", pending.op, e);
+            continue;
+        }
+        known.push(pending.clone());
+        added.push(pending);
+    }
+    pool.add(&added)?;
+    Ok(added.len())
+}
+
+/// Adds a block with every pending operation and validator proposal when the local node
+/// may add one: on its turn under proof of authority, or at any time on an open network
+/// that asks for proof of work. The seal is searched for without holding the catalog
+/// lock; when the chain moved on in the meantime, the block is dropped and its operations
+/// stay pending for the next one. Returns the new block, if any.
+pub(crate) fn produce_block(path: &Path) -> Result<Option<Block>, FileError> {
+    let Some(BlockDraft { genesis, parents, mut block, signer }) = prepare_block(path)? else { return Ok(None) };
+    genesis.seal(&parents, &mut block, signer.as_ref())?;
+    let _lock = CatalogLock::acquire(path)?;
+    let mut ledger = Ledger::open(path)?;
+    if ledger.tip().map(|tip| &tip.hash) != parents.last().map(|parent| &parent.hash) {
+        return Ok(None);
+    }
+    ledger.append(block.clone())?;
+    validator_proposals(path).retain(|proposal| !block.validator_changes.contains(proposal))?;
+    prune_pending_operations(path, &ledger)?;
+    Ok(Some(block))
+}
+
+/// An unsealed block, with the chain it goes on top of and who signs it.
+struct BlockDraft {
+    genesis: Genesis,
+    parents: Vec<Block>,
+    block: Block,
+    signer: Option<LocalIdentity>,
+}
+
+/// Gathers what `produce_block` puts in its block, under the catalog lock, and carries out
+/// the operations that came from other nodes.
+fn prepare_block(path: &Path) -> Result<Option<BlockDraft>, FileError> {
+    let _lock = CatalogLock::acquire(path)?;
+    let ledger = Ledger::open(path)?;
+    let genesis = ledger.genesis().clone();
+    let signer = match genesis.is_open() {
+        true if genesis.proof_of_work.is_none() => return Ok(None),
+        true => None,
+        false => {
+            let local = load_or_create_local_identity(path)?;
+            if genesis.expected_signer(ledger.blocks()).map(|validator| validator.id) != Some(local.id()) {
+                return Ok(None);
+            }
+            Some(local)
+        },
+    };
+    let pending: Vec<PendingOperation> = pending_operations(path).list()?.into_iter().filter(|pending| !ledger.contains_operation(&pending.op)).collect();
+    let proposals = validator_proposals(path).list()?;
+    if pending.is_empty() && proposals.is_empty() {
+        return Ok(None);
+    }
+    if !genesis.is_open() {
+        let mut journal = Journal::open(path)?;
+        let blobs = BlobStore::open(path)?;
+        for PendingOperation { op, content } in &pending {
+            let content = match op.stored() {
+                Some((file, Some(hash))) if blobs.hash(file.id)?.as_ref() != Some(hash) => content.as_deref(),
+                _ => None,
+            };
+            keep_baseline(path, &ledger, &blobs, op)?;
+            let seq = journal.begin(op)?;
+            let result = apply_operation(path, &blobs, op, content);
+            journal.finish(seq)?;
+            match result {
+                Ok(()) | Err(FileError::FileNotFound) => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    let mut block = Block::new(ledger.tip(), pending.into_iter().map(|pending| pending.op).collect())?;
+    block.validator_changes = proposals;
+    Ok(Some(BlockDraft { genesis, parents: ledger.blocks().to_vec(), block, signer }))
+}
+
+/// Queues a change to the validator set for the next block the local identity signs.
+pub fn propose_validator_change(change: ValidatorChange) -> Result<(), FileError> {
+    let path = get_path();
+    let _lock = CatalogLock::acquire(&path)?;
+    validator_proposals(&path).add(&[change])
+}
+
+/// Takes the blocks a peer has from `fork_height` on. When they extend the local chain,
+/// they are appended and their operations carried out; when the local chain already
+/// goes on past `fork_height`, the two chains compete under the fork choice rule.
+/// `contents` holds the blobs the new blocks store, keyed by content hash; a blob may be
+/// left out when a later operation replaces or removes it.
+pub(crate) fn import_blocks(path: &Path, fork_height: u64, blocks: &[Block], contents: &HashMap<String, Vec<u8>>) -> Result<ChainUpdate, FileError> {
+    let _lock = CatalogLock::acquire(path)?;
+    let mut journal = Journal::open(path)?;
+    let mut ledger = Ledger::open(path)?;
+    let blobs = BlobStore::open(path)?;
+    if fork_height > ledger.height() {
+        return Err(FileError::IntegrityError(format!("The local chain has no block at height {}", fork_height - 1)));
+    }
+    if fork_height < ledger.height() {
+        return reorganise(path, &mut journal, &mut ledger, fork_height, blocks, contents);
+    }
+    let mut imported = 0;
+    for (position, block) in blocks.iter().enumerate() {
+        if ledger.get(block.height).is_some_and(|known| known.hash == block.hash) {
+            continue;
+        }
+        block.verify_child_of(ledger.tip())?;
+        for (index, op) in block.operations.iter().enumerate() {
+            let content = match op.stored() {
+                Some((file, Some(hash))) if blobs.hash(file.id)?.as_ref() != Some(hash) => {
+                    match contents.get(hash) {
+                        Some(content) => Some(content.as_slice()),
+                        None if is_content_replaced(blocks, position, index + 1, file.id) => None,
+                        None => return Err(FileError::IntegrityError(format!("Content of file {} in block {} is missing", file.id, block.hash))),
+                    }
+                },
+                _ => None,
+            };
+            check_imported_operation(path, op, find_in_catalog(path, op.file_id())?.as_ref())?;
+            keep_baseline(path, &ledger, &blobs, op)?;
+            let seq = journal.begin(op)?;
+            let result = apply_operation(path, &blobs, op, content);
+            journal.finish(seq)?;
+            match result {
+                Ok(()) | Err(FileError::FileNotFound) => {},
+                Err(e) => return Err(e),
+            }
+        }
+        ledger.append(block.clone())?;
+        imported += 1;
+    }
+    prune_pending_operations(path, &ledger)?;
+    Ok(ChainUpdate { imported, ..ChainUpdate::default() })
+}
+
+/// Switches to a chain that forks from the local one at `fork_height`, if the fork choice
+/// rule prefers it. The catalog is brought to the state the new chain describes for
+/// every file touched on either side of the fork, which for a file the ledger never
+/// recorded before is the one kept in its baseline. The local operations the new chain
+/// leaves out are kept aside with their content for `resubmit_orphaned_operations`.
+fn reorganise(path: &Path, journal: &mut Journal, ledger: &mut Ledger, fork_height: u64, blocks: &[Block], contents: &HashMap<String, Vec<u8>>) -> Result<ChainUpdate, FileError> {
+    if !is_preferred(blocks, &ledger.blocks()[fork_height as usize..]) {
+        return Ok(ChainUpdate::default());
+    }
+    ledger.verify_extension(fork_height, blocks)?;
+    let blobs = BlobStore::open(path)?;
+    let kept: Vec<&JournalOp> = blocks.iter().flat_map(|block| block.operations.iter()).collect();
+    let baseline = baseline_files(path).list()?;
+    let baseline_ops: Vec<JournalOp> = baseline.iter().map(|kept| kept.op.clone()).collect();
+    let mut known: HashMap<i64, Option<File>> = HashMap::new();
+    for op in baseline_ops.iter().chain(ledger.blocks()[..fork_height as usize].iter().flat_map(|block| block.operations.iter())) {
+        if op.is_file_change() {
+            known.insert(op.file_id(), op.stored().map(|(file, _)| file.clone()));
+        }
+    }
+    for op in &kept {
+        let previous = match known.get(&op.file_id()) {
+            Some(previous) => previous.clone(),
+            None => find_in_catalog(path, op.file_id())?,
+        };
+        check_imported_operation(path, op, previous.as_ref())?;
+        if op.is_file_change() {
+            known.insert(op.file_id(), op.stored().map(|(file, _)| file.clone()));
+        }
+    }
+    let mut orphans = Vec::new();
+    for op in ledger.blocks()[fork_height as usize..].iter().flat_map(|block| block.operations.iter()) {
+        if kept.contains(&op) {
+            continue;
+        }
+        let content = match op.stored() {
+            Some((file, Some(hash))) if blobs.hash(file.id)?.as_ref() == Some(hash) => blobs.read(file.id)?,
+            _ => None,
+        };
+        orphans.push(PendingOperation { op: op.clone(), content });
+    }
+    let mut plan = Vec::new();
+    for op in fork_targets(ledger.blocks(), fork_height, blocks, &baseline_ops) {
+        let content = match op.stored() {
+            Some((file, Some(hash))) if blobs.hash(file.id)?.as_ref() != Some(hash) => {
+                let kept = baseline.iter().find(|kept| kept.op == op).and_then(|kept| kept.content.as_ref());
+                Some(contents.get(hash).or(kept).ok_or_else(|| FileError::IntegrityError(format!("Content of file {} on the new chain is missing", file.id)))?)
+            },
+            _ => None,
+        };
+        keep_baseline(path, ledger, &blobs, &op)?;
+        plan.push((journal.begin(&op)?, op, content));
+    }
+    for op in kept.into_iter().filter(|op| !op.is_file_change()) {
+        plan.push((journal.begin(op)?, op.clone(), None));
+    }
+    orphaned_operations(path).add(&orphans)?;
+    ledger.replace_from(fork_height, blocks.to_vec())?;
+    for (seq, op, content) in plan {
+        let result = apply_operation(path, &blobs, &op, content.map(Vec::as_slice));
+        journal.finish(seq)?;
+        match result {
+            Ok(()) | Err(FileError::FileNotFound) => {},
+            Err(e) => return Err(e),
+        }
+    }
+    prune_pending_operations(path, ledger)?;
+    Ok(ChainUpdate { imported: blocks.len(), fork_height: Some(fork_height), orphaned: orphans.into_iter().map(|orphan| orphan.op).collect() })
+}
+
+/// Carries out again, each in a new block on top of the current chain, the local
+/// operations that were orphaned when another chain won a fork. An operation whose
+/// content was lost along the way is resubmitted without it. Returns the operations
+/// that were resubmitted.
+pub fn resubmit_orphaned_operations() -> Result<Vec<JournalOp>, FileError> {
+    let path = get_path();
+    let orphans = orphaned_operations(&path);
+    let mut resubmitted = Vec::new();
+    for PendingOperation { op, content } in orphans.list()? {
+        let op = match op {
+            JournalOp::Store { file, content_hash: Some(_) } if content.is_none() => JournalOp::Store { file, content_hash: None },
+            JournalOp::Transfer { file, content_hash: Some(_), transfer } if content.is_none() => JournalOp::Transfer { file, content_hash: None, transfer },
+            op => op,
+        };
+        match run_journaled(&path, op.clone(), content.as_deref()) {
+            Ok(()) => resubmitted.push(op),
+            Err(FileError::FileNotFound) => warn!("Skipped the orphaned {} since the file no longer exists.", op),
+            Err(e) => return Err(e),
+        }
+    }
+    orphans.clear()?;
+    Ok(resubmitted)
+}
+
+/// Tells whether an operation after `blocks[position].operations[index - 1]` gives file
+/// `file_id` new content or removes it.
+pub(crate) fn is_content_replaced(blocks: &[Block], position: usize, index: usize, file_id: i64) -> bool {
+    let later_in_block = blocks[position].operations.iter().skip(index);
+    let later_blocks = blocks[position + 1..].iter().flat_map(|block| block.operations.iter());
+    later_in_block.chain(later_blocks).any(|op| match op.stored() {
+        Some((file, content_hash)) => file.id == file_id && content_hash.is_some(),
+        None => op.is_file_change() && op.file_id() == file_id,
+    })
+}
+
+fn apply_operation(path: &Path, blobs: &BlobStore, op: &JournalOp, content: Option<&[u8]>) -> Result<(), FileError> {
+    match op {
+        JournalOp::Store { file, .. } | JournalOp::Transfer { file, .. } => {
+            let Some(content) = content else { return store_in_catalog(path, file.as_ref().clone()) };
+            blobs.keep_previous(file.id)?;
+            match blobs.write(file.id, content).and_then(|()| store_in_catalog(path, file.as_ref().clone())) {
+                Ok(()) => blobs.discard_previous(file.id),
+                Err(e) => {
+                    if !blobs.restore_previous(file.id)? {
+                        blobs.remove(file.id)?;
+                    }
+                    Err(e)
+                },
+            }
+        },
+        JournalOp::Remove { file_id, .. } => {
+            let removed = remove_from_catalog(path, *file_id);
+            blobs.remove(*file_id)?;
+            deletion::apply_policy(path, &DeletionPolicy { file_id: *file_id, threshold: 1 })?;
+            removed
+        },
+        JournalOp::Policy { policy } => deletion::apply_policy(path, policy),
+    }
+}
+
+fn find_in_catalog(path: &Path, file_id: i64) -> Result<Option<File>, FileError> {
+    if get_backend() == Backend::Sqlite {
+        return open_sqlite_catalog(path)?.get_file(file_id);
+    }
+    with_cached_catalog(path, |catalog| Ok(catalog.get(file_id).cloned()))
+}
+
+fn store_in_catalog(path: &Path, file: File) -> Result<(), FileError> {
+    if get_backend() == Backend::Sqlite {
+        let mut catalog = open_sqlite_catalog(path)?;
+        return match catalog.get_file(file.id)? {
+            Some(_) => catalog.update_file(&file),
+            None => catalog.insert_file(&file),
+        };
+    }
+    with_cached_catalog(path, |catalog| {
+        catalog.upsert(file);
+        save_files_to_file(catalog.files(), path)
+    })
+}
+
+fn remove_from_catalog(path: &Path, file_id: i64) -> Result<(), FileError> {
+    if get_backend() == Backend::Sqlite {
+        return open_sqlite_catalog(path)?.remove_file(file_id);
+    }
+    with_cached_catalog(path, |catalog| {
+        catalog.remove(file_id).ok_or(FileError::FileNotFound)?;
+        save_files_to_file(catalog.files(), path)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{env, fs::{self, File as FsFile},path::PathBuf};
+    use std::{fs::{self, File as FsFile},path::PathBuf};
     use std::io::Write;
 
     use chrono::Utc;
 
+    use testing::AssetsPath;
     use utils::generate_fake_hash;
 
     fn get_test_file() -> File {
@@ -95,22 +1078,25 @@ mod tests {
             id: 1, name: "test-file".to_string(), file_type: model::FileType::Pdf, size: 100, 
             created: Utc::now().naive_utc(), modified: None, accessed: None, owner, 
             people_with_access: vec![owner_access], ipfs_hash: generate_fake_hash(46), 
-            onchain_txn_id: generate_fake_hash(64), download_permission: false, description: None 
+            onchain_txn_id: generate_fake_hash(64), download_permission: false, description: None,
+            content_hash: None
         }
     }
 
+    /// The catalog the tests built on `setup_temp_file` share.
+    const TEST_CATALOG_PATH: &str = "./test_temp_dir/test_file.bin";
+
     fn setup_temp_file() -> (PathBuf, FsFile) {
-        let temp_dir = PathBuf::from("./test_temp_dir");
+        let test_file_path = PathBuf::from(TEST_CATALOG_PATH);
+        let temp_dir = test_file_path.parent().unwrap();
         if !temp_dir.exists() {
-            fs::create_dir_all(&temp_dir).expect("Failed to create temp directory");
+            fs::create_dir_all(temp_dir).expect("Failed to create temp directory");
         }
-        let test_file_path = temp_dir.join("test_file.bin");
         let test_file = FsFile::create(&test_file_path).expect("Failed to create test file");
-        env::set_var("ASSETS_PATH", test_file_path.to_str().unwrap());
         (test_file_path, test_file)
     }
 
-    fn _create_fake_pdf_in_other_dir(directory: &PathBuf) -> PathBuf {
+    fn _create_fake_pdf_in_other_dir(directory: &Path) -> PathBuf {
         let fake_pdf_path = directory.join("fake_test_file.pdf");
         let fake_pdf_content = b"%PDF-1.4\n%...\n%%EOF"; 
         let mut file = FsFile::create(&fake_pdf_path).expect("Failed to create fake PDF file");
@@ -120,12 +1106,12 @@ mod tests {
 
     #[test]
     fn test_load_files_from_empty_file() {
+        let _assets = AssetsPath::set(TEST_CATALOG_PATH);
         let (test_file_path, _temp_dir) = setup_temp_file();
         StdFile::create(&test_file_path).expect("Failed to create an empty test file");
         let result = load_files_from_file(&test_file_path);
         assert!(result.is_ok(), "Load failed: {:?}", result);
         assert_eq!(result.unwrap(), Vec::<File>::new(), "Expected an empty file");
-        env::remove_var("ASSETS_PATH");
     } 
 
     fn save_file() -> (PathBuf, FsFile, Vec<File>) {
@@ -140,31 +1126,335 @@ mod tests {
 
     #[test]
     fn test_save_and_load_files() {
+        let _assets = AssetsPath::set(TEST_CATALOG_PATH);
         let (test_file_path, _temp_dir, files) = save_file();
         let load_result = load_files_from_file(&test_file_path);
         assert!(load_result.is_ok(), "Load failed: {:?}", load_result);
         assert_eq!(load_result.unwrap(), files, "Loaded files do not match saved files");
-        env::remove_var("ASSETS_PATH");
     }
 
     #[test]
     fn test_remove_file() {
+        let _assets = AssetsPath::set(TEST_CATALOG_PATH);
         let (test_file_path, _temp_dir, _files) = save_file();
         let file_id = 1;
         let remove_result = remove_file(file_id);
         assert!(remove_result.is_ok(), "Failed to delete file: {:?}", remove_result);
         let files = load_files_from_file(&test_file_path).expect("Failed to load files");
         assert!(files.is_empty(), "File is not empty");
-        env::remove_var("ASSETS_PATH");
+    }
+
+    #[test]
+    fn test_remove_file_waits_for_approvals() {
+        let dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        save_files_to_file(&[], &path).expect("Save failed");
+        let local = load_or_create_local_identity(&path).unwrap();
+        let guest = identity::LocalIdentity::generate(7, "Guest", "guest@gmail.com");
+        let stranger = identity::LocalIdentity::generate(8, "Stranger", "stranger@gmail.com");
+        let registry = IdentityRegistry::open(&path).unwrap();
+        registry.register(&guest.public).unwrap();
+        registry.register(&stranger.public).unwrap();
+        let owner = local.public.as_person();
+        let file = store_new_file(File { id: 1, owner: owner.clone(), people_with_access: vec![owner, guest.public.as_person()], ..get_test_file() }, None).unwrap();
+        assert!(matches!(set_deletion_threshold(file.id, 3), Err(FileError::InputError(_))));
+        set_deletion_threshold(file.id, 2).unwrap();
+
+        let Removal::Pending(pending) = remove_file(file.id).unwrap() else { panic!("The file was removed without approvals") };
+        assert_eq!(remove_file(file.id).unwrap(), Removal::Pending(pending.clone()));
+        assert_eq!(list_pending_deletions().unwrap(), vec![pending.clone()]);
+        let message = pending.signing_message().unwrap();
+        assert_eq!(submit_deletion_approval(file.id, stranger.id(), &stranger.sign(&message)), Err(FileError::PermissionDenied));
+        assert!(matches!(submit_deletion_approval(file.id, guest.id(), &stranger.sign(&message)), Err(FileError::Unauthenticated(_))));
+        let Removal::Pending(approved) = approve_deletion(file.id).unwrap() else { panic!("One approval removed the file") };
+        assert_eq!(approved.approvals.len(), 1);
+        assert!(matches!(approve_deletion(file.id).unwrap(), Removal::Pending(again) if again.approvals.len() == 1));
+        assert_eq!(find_file(file.id).unwrap().id, file.id);
+
+        assert_eq!(submit_deletion_approval(file.id, guest.id(), &guest.sign(&message)).unwrap(), Removal::Removed);
+        assert_eq!(find_file(file.id), Err(FileError::FileNotFound));
+        assert!(list_pending_deletions().unwrap().is_empty());
+        assert_eq!(deletion::threshold(&path, file.id).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_reads_log_access_times_instead_of_rewriting_the_catalog() {
+        let dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        save_files_to_file(&[], &path).expect("Save failed");
+        let file = store_new_file(get_test_file(), None).unwrap();
+        let catalog = fs::read(&path).unwrap();
+
+        let read = get_file(file.id).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), catalog);
+        assert_eq!(find_file(file.id).unwrap().accessed, read.accessed);
+        for _ in 1..catalog::index::ACCESS_BATCH {
+            get_file(file.id).unwrap();
+        }
+        assert_ne!(fs::read(&path).unwrap(), catalog);
+        assert!(!catalog::index::access_log(&path).path().exists());
+    }
+
+    #[test]
+    fn test_pending_deletion_lapses_when_the_file_changes() {
+        let dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        save_files_to_file(&[], &path).expect("Save failed");
+        let local = load_or_create_local_identity(&path).unwrap();
+        let guest = identity::LocalIdentity::generate(7, "Guest", "guest@gmail.com");
+        IdentityRegistry::open(&path).unwrap().register(&guest.public).unwrap();
+        let owner = local.public.as_person();
+        let file = store_new_file(File { id: 1, owner: owner.clone(), people_with_access: vec![owner, guest.public.as_person()], ..get_test_file() }, None).unwrap();
+        set_deletion_threshold(file.id, 2).unwrap();
+        let Removal::Pending(pending) = remove_file(file.id).unwrap() else { panic!("The file was removed without approvals") };
+
+        save_modified_file(file.id, File { name: "renamed".to_string(), ..file.clone() }).unwrap();
+        let message = pending.signing_message().unwrap();
+        assert!(matches!(submit_deletion_approval(file.id, guest.id(), &guest.sign(&message)), Err(FileError::InputError(_))));
+        assert!(list_pending_deletions().unwrap().is_empty());
+        assert!(matches!(remove_file(file.id).unwrap(), Removal::Pending(renewed) if renewed.transaction_id != pending.transaction_id));
+    }
+
+    #[test]
+    fn test_transfer_needs_both_signatures() {
+        let dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        save_files_to_file(&[], &path).expect("Save failed");
+        let local = load_or_create_local_identity(&path).unwrap();
+        let recipient = identity::LocalIdentity::generate(7, "Recipient", "recipient@gmail.com");
+        let stranger = identity::LocalIdentity::generate(8, "Stranger", "stranger@gmail.com");
+        let registry = IdentityRegistry::open(&path).unwrap();
+        registry.register(&recipient.public).unwrap();
+        registry.register(&stranger.public).unwrap();
+        let owner = local.public.as_person();
+        let file = store_new_file(File { id: 1, owner: owner.clone(), people_with_access: vec![owner], ..get_test_file() }, Some(b"deed".to_vec())).unwrap();
+        let message = transfer::signing_message(file.id, &file.onchain_txn_id, stranger.id(), recipient.id()).unwrap();
+        assert_eq!(submit_transfer_proposal(file.id, stranger.id(), recipient.id(), &stranger.sign(&message)), Err(FileError::PermissionDenied));
+        assert!(matches!(submit_transfer_proposal(file.id, local.id(), recipient.id(), &stranger.sign(&message)), Err(FileError::Unauthenticated(_))));
+
+        let proposal = propose_transfer(file.id, recipient.id()).unwrap();
+        assert_eq!(list_transfer_proposals().unwrap(), vec![proposal.clone()]);
+        let message = proposal.signing_message().unwrap();
+        assert_eq!(submit_transfer_acceptance(file.id, stranger.id(), &stranger.sign(&message)), Err(FileError::PermissionDenied));
+        assert!(matches!(submit_transfer_acceptance(file.id, recipient.id(), &stranger.sign(&message)), Err(FileError::Unauthenticated(_))));
+        assert_eq!(withdraw_transfer(file.id, stranger.id()), Err(FileError::PermissionDenied));
+
+        let handed_over = submit_transfer_acceptance(file.id, recipient.id(), &recipient.sign(&message)).unwrap();
+        assert_eq!(handed_over.owner, recipient.public.as_person());
+        assert_eq!(handed_over.people_with_access, vec![local.public.as_person(), recipient.public.as_person()]);
+        assert_eq!(find_file(file.id).unwrap(), handed_over);
+        assert_eq!(read_file_content(file.id).unwrap(), b"deed");
+        assert!(list_transfer_proposals().unwrap().is_empty());
+        let blocks = Ledger::read_blocks(&path).unwrap();
+        let block = ledger::find_transaction(&blocks, &handed_over.onchain_txn_id).expect("The transfer is not in the ledger");
+        let Some(JournalOp::Transfer { transfer, .. }) = block.operations.iter().find(|op| op.file_id() == file.id) else { panic!("The block records no transfer") };
+        assert_eq!((transfer.from, transfer.to, transfer.transaction_id.as_str()), (local.id(), recipient.id(), file.onchain_txn_id.as_str()));
+        transfer.verify(&local.public, &recipient.public).unwrap();
+        assert!(transfer.verify(&recipient.public, &local.public).is_err());
+        assert_eq!(propose_transfer(file.id, stranger.id()), Err(FileError::PermissionDenied));
+    }
+
+    #[test]
+    fn test_access_requests_are_answered_by_the_owner() {
+        let dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        save_files_to_file(&[], &path).expect("Save failed");
+        let local = load_or_create_local_identity(&path).unwrap();
+        let requester = identity::LocalIdentity::generate(7, "Requester", "requester@gmail.com");
+        IdentityRegistry::open(&path).unwrap().register(&requester.public).unwrap();
+        let owner = local.public.as_person();
+        let file = store_new_file(File { id: 1, owner: owner.clone(), people_with_access: vec![owner.clone()], ..get_test_file() }, Some(b"minutes".to_vec())).unwrap();
+        let person = requester.public.as_person();
+        assert!(matches!(request_access(file.id, person.clone(), " "), Err(FileError::InputError(_))));
+        assert!(matches!(request_access(file.id, owner, "mine"), Err(FileError::InputError(_))));
+
+        let first = request_access(file.id, person.clone(), "I took the minutes").unwrap();
+        let request = request_access(file.id, person.clone(), "I chaired the meeting").unwrap();
+        assert_eq!((request.id, request.status), (first.id, AccessStatus::Pending));
+        assert_eq!(list_access_requests().unwrap(), vec![request.clone()]);
+        assert_eq!(decide_access_request(request.id, requester.id(), true), Err(FileError::PermissionDenied));
+        assert!(take_access_outcomes(requester.id()).unwrap().is_empty());
+
+        let approved = decide_access_request(request.id, local.id(), true).unwrap();
+        assert_eq!(approved.status, AccessStatus::Approved);
+        assert!(find_file(file.id).unwrap().people_with_access.contains(&person));
+        let envelope = Envelope::from_bytes(&BlobStore::open(&path).unwrap().read(file.id).unwrap().unwrap()).unwrap();
+        assert_eq!(envelope.open(requester.id(), &requester.encryption_secret()).unwrap(), b"minutes");
+        assert!(matches!(decide_access_request(request.id, local.id(), false), Err(FileError::InputError(_))));
+        assert_eq!(take_access_outcomes(requester.id()).unwrap(), vec![approved]);
+        assert!(take_access_outcomes(requester.id()).unwrap().is_empty());
+        assert!(matches!(request_access(file.id, person, "again"), Err(FileError::InputError(_))));
+
+        let guest = request_access(file.id, (8, "Guest".to_string(), "guest@gmail.com".to_string()), "I was there").unwrap();
+        fs::remove_file(identity::get_local_identity_path(&path)).unwrap();
+        assert!(decide_access_request(guest.id, local.id(), true).is_err());
+        assert_eq!(list_access_requests().unwrap().into_iter().find(|request| request.id == guest.id), Some(guest));
     }
 
     #[test]
     fn test_get_file() {
+        let _assets = AssetsPath::set(TEST_CATALOG_PATH);
         let (_test_file_path, _temp_dir, _files) = save_file();
         let file_id = 1;
         let get_result = get_file(file_id);
         assert!(get_result.is_ok(), "File was not found: {:?}", get_result);
         assert_eq!(get_result.unwrap().id, file_id, "File ID mismatch");
-        env::remove_var("ASSETS_PATH");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_remove_file_fails_fast_when_locked() {
+        let _assets = AssetsPath::set(TEST_CATALOG_PATH);
+        let (test_file_path, _temp_dir, files) = save_file();
+        let lock = CatalogLock::acquire(&test_file_path).expect("Failed to take the catalog lock");
+        let remove_result = remove_file(1);
+        assert!(matches!(remove_result, Err(FileError::RepositoryLocked(_))), "Expected a locked error: {:?}", remove_result);
+        drop(lock);
+        assert_eq!(load_files_from_file(&test_file_path).expect("Failed to load files"), files, "Catalog changed while locked");
+    }
+
+    #[test]
+    fn test_recover_completes_store_whose_content_was_written() {
+        let _assets = AssetsPath::set(TEST_CATALOG_PATH);
+        let (test_file_path, _temp_dir, mut files) = save_file();
+        let mut file = get_test_file();
+        file.id = 2;
+        let op = JournalOp::Store { file: Box::new(file.clone()), content_hash: Some(content_hash(b"content")) };
+        Journal::open(&test_file_path).unwrap().begin(&op).unwrap();
+        BlobStore::open(&test_file_path).unwrap().write(2, b"content").unwrap();
+        let actions = recover_from_journal().expect("Recovery failed");
+        assert_eq!(actions, vec![RecoveryAction::Completed(op)]);
+        files.push(file);
+        assert_eq!(load_files_from_file(&test_file_path).expect("Failed to load files"), files);
+        assert!(Journal::open(&test_file_path).unwrap().pending().is_empty(), "Journal still has pending operations");
+    }
+
+    #[test]
+    fn test_recover_rolls_back_store_whose_content_is_missing() {
+        let _assets = AssetsPath::set(TEST_CATALOG_PATH);
+        let (test_file_path, _temp_dir, files) = save_file();
+        let mut file = get_test_file();
+        file.id = 3;
+        let op = JournalOp::Store { file: Box::new(file), content_hash: Some(content_hash(b"content")) };
+        Journal::open(&test_file_path).unwrap().begin(&op).unwrap();
+        let actions = recover_from_journal().expect("Recovery failed");
+        assert_eq!(actions, vec![RecoveryAction::RolledBack(op)]);
+        assert_eq!(load_files_from_file(&test_file_path).expect("Failed to load files"), files);
+    }
+
+    #[test]
+    fn test_recover_restores_content_of_an_existing_file() {
+        let _assets = AssetsPath::set(TEST_CATALOG_PATH);
+        let (test_file_path, _temp_dir, files) = save_file();
+        let blobs = BlobStore::open(&test_file_path).unwrap();
+        blobs.write(1, b"old content").unwrap();
+        let op = JournalOp::Store { file: Box::new(File { size: 11, ..get_test_file() }), content_hash: Some(content_hash(b"new content")) };
+        Journal::open(&test_file_path).unwrap().begin(&op).unwrap();
+        let actions = recover_from_journal().expect("Recovery failed");
+        assert_eq!(actions, vec![RecoveryAction::RolledBack(op.clone())]);
+        assert_eq!(blobs.read(1).unwrap().as_deref(), Some(&b"old content"[..]));
+
+        Journal::open(&test_file_path).unwrap().begin(&op).unwrap();
+        blobs.keep_previous(1).unwrap();
+        blobs.write_raw(1, b"torn").unwrap();
+        let actions = recover_from_journal().expect("Recovery failed");
+        assert_eq!(actions, vec![RecoveryAction::RolledBack(op)]);
+        assert_eq!(blobs.read(1).unwrap().as_deref(), Some(&b"old content"[..]));
+        assert_eq!(load_files_from_file(&test_file_path).expect("Failed to load files"), files);
+    }
+
+    #[test]
+    fn test_recover_removes_blob_left_behind_by_a_removal() {
+        let _assets = AssetsPath::set(TEST_CATALOG_PATH);
+        let (test_file_path, _temp_dir, files) = save_file();
+        let op = JournalOp::Remove { file_id: 4, deletion: None };
+        BlobStore::open(&test_file_path).unwrap().write(4, b"content").unwrap();
+        Journal::open(&test_file_path).unwrap().begin(&op).unwrap();
+        let actions = recover_from_journal().expect("Recovery failed");
+        assert_eq!(actions, vec![RecoveryAction::Completed(op)]);
+        assert_eq!(BlobStore::open(&test_file_path).unwrap().read(4).unwrap(), None);
+        assert_eq!(load_files_from_file(&test_file_path).expect("Failed to load files"), files);
+    }
+
+    #[test]
+    fn test_migrate_catalog_format_backs_up_version_1() {
+        let _assets = AssetsPath::set(TEST_CATALOG_PATH);
+        let (test_file_path, _temp_dir) = setup_temp_file();
+        let files = vec![get_test_file()];
+        let legacy = catalog::format::encode_legacy_v1(&files);
+        fs::write(&test_file_path, &legacy).expect("Failed to write legacy catalog");
+        let backup_path = test_file_path.with_file_name("test_file.bin.v1.bak");
+        let _ = fs::remove_file(&backup_path);
+        assert_eq!(migrate_catalog_format().expect("Migration failed"), Some((1, backup_path.clone())));
+        assert_eq!(fs::read(&backup_path).expect("Backup missing"), legacy, "Backup differs from the original");
+        assert_eq!(catalog::format::get_format_version(&fs::read(&test_file_path).unwrap()), CURRENT_VERSION);
+        assert_eq!(load_files_from_file(&test_file_path).expect("Failed to load files"), files);
+        assert_eq!(migrate_catalog_format().expect("Second migration failed"), None);
+    }
+
+    #[test]
+    fn test_verify_file_contents_finds_missing_and_damaged_content() {
+        let dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        save_files_to_file(&[], &path).expect("Save failed");
+        let stored: Vec<File> = (1..=3).map(|id| store_new_file(File { id, ..get_test_file() }, Some(format!("content {}", id).into_bytes())).unwrap()).collect();
+        assert_eq!(verify_file_contents(), Ok(Vec::new()));
+        let blobs = BlobStore::open(&path).unwrap();
+        blobs.remove(stored[1].id).unwrap();
+        blobs.write(stored[2].id, &seal_for_people(&path, b"tampered", &stored[2].people_with_access).unwrap()).unwrap();
+        let problems = verify_file_contents().expect("Verification failed");
+        assert_eq!(problems.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(problems[0].1, FileError::FileNotFound);
+        assert!(matches!(problems[1].1, FileError::IntegrityError(_)));
+    }
+
+    #[test]
+    fn test_encrypt_repository_and_unlock() {
+        let dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        let files = vec![get_test_file()];
+        save_files_to_file(&files, &path).expect("Save failed");
+        BlobStore::open(&path).unwrap().write(1, b"content").unwrap();
+        assert_eq!(encrypt_repository("passphrase").expect("Encryption failed"), 2);
+        assert!(crypto::is_sealed(&fs::read(&path).unwrap()), "Catalog was left in plain text");
+        assert!(crypto::is_sealed(&BlobStore::open(&path).unwrap().read_raw(1).unwrap().unwrap()), "Blob was left in plain text");
+        assert_eq!(get_all_files().expect("Failed to list files"), files);
+        crypto::lock(&path);
+        assert_eq!(load_files_from_file(&path), Err(FileError::PassphraseRequired));
+        assert_eq!(unlock_repository("wrong"), Err(FileError::WrongPassphrase));
+        assert_eq!(unlock_repository("passphrase"), Ok(0));
+        assert_eq!(BlobStore::open(&path).unwrap().read(1).unwrap(), Some(b"content".to_vec()));
+        crypto::lock(&path);
+    }
+
+    #[test]
+    fn test_reshare_content_grants_and_rotates() {
+        let dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("assets");
+        let _assets = AssetsPath::set(&path);
+        let owner = load_or_create_local_identity(&path).expect("Failed to create the local identity");
+        let guest = identity::LocalIdentity::generate(7, "Guest", "guest@gmail.com");
+        IdentityRegistry::open(&path).unwrap().register(&guest.public).unwrap();
+        let mut file = get_test_file();
+        file.people_with_access = vec![owner.public.as_person()];
+        BlobStore::open(&path).unwrap().write(file.id, &seal_for_people(&path, b"content", &file.people_with_access).unwrap()).unwrap();
+
+        let mut shared = file.clone();
+        shared.people_with_access.push((7, "Guest".to_string(), "guest@gmail.com".to_string()));
+        let granted = Envelope::from_bytes(&reshare_content(&path, &file, &shared).unwrap().expect("Expected a new blob")).unwrap();
+        assert_eq!(granted.open(7, &guest.encryption_secret()).unwrap(), b"content");
+        BlobStore::open(&path).unwrap().write(file.id, &granted.to_bytes().unwrap()).unwrap();
+
+        let rotated = Envelope::from_bytes(&reshare_content(&path, &shared, &file).unwrap().expect("Expected a new blob")).unwrap();
+        assert_eq!(rotated.open(owner.id(), &owner.encryption_secret()).unwrap(), b"content");
+        assert_eq!(rotated.open(7, &guest.encryption_secret()), Err(FileError::PermissionDenied));
+        assert_ne!(rotated.unwrap_key(owner.id(), &owner.encryption_secret()), granted.unwrap_key(owner.id(), &owner.encryption_secret()));
+        assert_eq!(reshare_content(&path, &file, &file).unwrap(), None);
+    }
+}
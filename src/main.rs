@@ -1,13 +1,20 @@
-use std::process;
+use std::{env, process};
 use log::{info, error};
-use env_logger;
 
+use unichain::api::s3::{S3Config, S3Server};
+use unichain::api::{ApiConfig, ApiServer};
+use unichain::commands::verify_receipt;
+use unichain::ledger::anchor::AnchorConfig;
 use unichain::model::FileError;
+use unichain::node::NodeConfig;
+use unichain::raft::RaftConfig;
+use unichain::rpc::{RpcConfig, RpcServer};
+use unichain::utils::get_passphrase;
 
 mod cli;
 
 fn main() {
-    if let Err(_) = run_app() {
+    if run_app().is_err() {
         process::exit(1);
     }
 }
@@ -16,10 +23,95 @@ fn run_app() -> Result<(), FileError> {
     env_logger::Builder::from_default_env()
         .filter_level(log::LevelFilter::Info)
         .init();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("verify-receipt") {
+        return verify_receipt(&args[1..]).inspect_err(|e| error!("Receipt verification failed: {e}"));
+    }
     info!("Initializing the program.");
+    if let Err(e) = prepare_repository() {
+        error!("Startup failed: {e}");
+        return Err(e);
+    }
+    let _node = match NodeConfig::from_env().map(|config| unichain::start_node(&config)).transpose() {
+        Ok(node) => node,
+        Err(e) => {
+            error!("Failed to start the node: {e}");
+            return Err(e);
+        },
+    };
+    let _raft = match RaftConfig::from_env().and_then(|config| config.map(|config| unichain::start_raft(&config)).transpose()) {
+        Ok(raft) => raft,
+        Err(e) => {
+            error!("Failed to join the Raft cluster: {e}");
+            return Err(e);
+        },
+    };
+    let _producer = match unichain::start_block_producer() {
+        Ok(producer) => producer,
+        Err(e) => {
+            error!("Failed to start the block producer: {e}");
+            return Err(e);
+        },
+    };
+    let _anchorer = AnchorConfig::from_env().map(|config| unichain::start_anchoring(&config));
+    let rpc_config = RpcConfig::from_env();
+    let _rpc = match rpc_config.as_ref().map(RpcServer::start).transpose() {
+        Ok(rpc) => rpc,
+        Err(e) => {
+            error!("Failed to start the JSON-RPC endpoint: {e}");
+            return Err(e);
+        },
+    };
+    let api_config = ApiConfig::from_env();
+    let _api = match api_config.as_ref().map(ApiServer::start).transpose() {
+        Ok(api) => api,
+        Err(e) => {
+            error!("Failed to start the API: {e}");
+            return Err(e);
+        },
+    };
+    let s3_config = S3Config::from_env();
+    let _s3 = match s3_config.as_ref().map(S3Server::start).transpose() {
+        Ok(s3) => s3,
+        Err(e) => {
+            error!("Failed to start the S3 endpoint: {e}");
+            return Err(e);
+        },
+    };
+    if api_config.is_some() || rpc_config.is_some() || s3_config.is_some() {
+        info!("Serving the API until the process is stopped.");
+        loop {
+            std::thread::park();
+        }
+    }
     if let Err(e) = cli::run() {
         error!("Application error: {e}");
         return Err(e);
     };
     Ok(())
 }
+
+fn prepare_repository() -> Result<(), FileError> {
+    if unichain::is_repository_encrypted() {
+        let encrypted = unichain::unlock_repository(&get_passphrase("Enter the repository passphrase: ")?)?;
+        info!("Repository unlocked.");
+        if encrypted > 0 {
+            info!("Encrypted {encrypted} files left in plain text by an interrupted encryption.");
+        }
+    }
+    if let Some((version, backup_path)) = unichain::migrate_catalog_format()? {
+        info!("Migrated the catalog from format version {version}; the original was kept at {}.", backup_path.display());
+    }
+    for action in unichain::recover_from_journal()? {
+        info!("Journal recovery :: {action}");
+    }
+    for op in unichain::resubmit_orphaned_operations()? {
+        info!("Resubmitted the orphaned {op}.");
+    }
+    let encryption_requested = env::var("UNICHAIN_ENCRYPT").map(|value| value == "1").unwrap_or(false);
+    if encryption_requested && !unichain::is_repository_encrypted() {
+        let encrypted = unichain::encrypt_repository(&get_passphrase("Choose a passphrase for the repository: ")?)?;
+        info!("Encryption at rest enabled; encrypted {encrypted} files.");
+    }
+    Ok(())
+}
@@ -1,10 +1,11 @@
 use std::error::Error;
+use std::str::FromStr;
 use std::{io, fmt};
 
 use serde::{Serialize, Deserialize};
 use chrono::NaiveDateTime;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum FileType {
     Pdf,
     Docx,
@@ -17,6 +18,42 @@ pub enum FileType {
     Unknown
 }
 
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FileType::Pdf => "pdf",
+            FileType::Docx => "docx",
+            FileType::Xls => "xls",
+            FileType::Txt => "txt",
+            FileType::Csv => "csv",
+            FileType::Pptx => "pptx",
+            FileType::Jpg => "jpg",
+            FileType::Png => "png",
+            FileType::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for FileType {
+    type Err = FileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "pdf" => Ok(FileType::Pdf),
+            "docx" => Ok(FileType::Docx),
+            "xls" => Ok(FileType::Xls),
+            "txt" => Ok(FileType::Txt),
+            "csv" => Ok(FileType::Csv),
+            "pptx" => Ok(FileType::Pptx),
+            "jpg" => Ok(FileType::Jpg),
+            "png" => Ok(FileType::Png),
+            "unknown" => Ok(FileType::Unknown),
+            other => Err(FileError::InvalidFileType(format!("Unsupported file type: {}", other))),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct File {
     pub id: i64,
@@ -32,6 +69,7 @@ pub struct File {
     pub onchain_txn_id: String,
     pub download_permission: bool,
     pub description: Option<String>,
+    pub content_hash: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -52,6 +90,13 @@ pub enum FileError {
     IdGenerationError(String),
     InvalidFileType(String),
     InvalidFileSize,
+    DatabaseError(String),
+    RepositoryLocked(String),
+    PassphraseRequired,
+    WrongPassphrase,
+    IntegrityError(String),
+    PeerError(String),
+    Unauthenticated(String),
 }
 
 impl fmt::Display for FileError {
@@ -67,6 +112,13 @@ impl fmt::Display for FileError {
             FileError::IdGenerationError(msg) => write!(f, "ID generation error :: {}", msg),
             FileError::InvalidFileType(msg) => write!(f, "Invalid file type :: {}", msg),
             FileError::InvalidFileSize => write!(f, "Invalid file size."),
+            FileError::DatabaseError(msg) => write!(f, "Database error :: {}", msg),
+            FileError::RepositoryLocked(path) => write!(f, "The repository is locked by another UniChain process :: {}", path),
+            FileError::PassphraseRequired => write!(f, "The repository is encrypted; unlock it with its passphrase first."),
+            FileError::WrongPassphrase => write!(f, "Wrong passphrase."),
+            FileError::IntegrityError(msg) => write!(f, "Integrity error :: {}", msg),
+            FileError::PeerError(msg) => write!(f, "Peer error :: {}", msg),
+            FileError::Unauthenticated(msg) => write!(f, "Authentication failed :: {}", msg),
         }
     }
 }
@@ -84,6 +136,13 @@ impl PartialEq for FileError {
             (FileError::IdGenerationError(a), FileError::IdGenerationError(b)) => a == b,
             (FileError::InvalidFileType(a), FileError::InvalidFileType(b)) => a == b,
             (FileError::InvalidFileSize, FileError::InvalidFileSize) => true,
+            (FileError::DatabaseError(a), FileError::DatabaseError(b)) => a == b,
+            (FileError::RepositoryLocked(a), FileError::RepositoryLocked(b)) => a == b,
+            (FileError::PassphraseRequired, FileError::PassphraseRequired) => true,
+            (FileError::WrongPassphrase, FileError::WrongPassphrase) => true,
+            (FileError::IntegrityError(a), FileError::IntegrityError(b)) => a == b,
+            (FileError::PeerError(a), FileError::PeerError(b)) => a == b,
+            (FileError::Unauthenticated(a), FileError::Unauthenticated(b)) => a == b,
             _ => false,
         }
     }
@@ -104,3 +163,9 @@ impl From<io::Error> for FileError {
         FileError::IOError(error)
     }
 }
+
+impl From<rusqlite::Error> for FileError {
+    fn from(error: rusqlite::Error) -> Self {
+        FileError::DatabaseError(error.to_string())
+    }
+}
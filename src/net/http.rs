@@ -0,0 +1,18 @@
+use std::io;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use ureq::{Agent, AgentBuilder};
+
+use crate::model::FileError;
+
+/// The agent every outgoing HTTP request goes through, so that connections are reused.
+pub fn agent() -> &'static Agent {
+    static AGENT: OnceLock<Agent> = OnceLock::new();
+    AGENT.get_or_init(|| AgentBuilder::new().timeout_connect(Duration::from_secs(10)).timeout_read(Duration::from_secs(300)).build())
+}
+
+/// The error for a remote service that failed or sent something unexpected.
+pub fn remote_error(message: &str) -> FileError {
+    FileError::IOError(io::Error::other(message.to_string()))
+}
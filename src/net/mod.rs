@@ -0,0 +1,26 @@
+//! What the servers and the clients of other services share to speak HTTP: the SigV4
+//! signer, the few XML helpers S3 needs and the HTTP agent.
+
+pub mod http;
+pub mod sigv4;
+pub mod xml;
+
+/// Decodes `%XX` escapes, and `+` as a space when `plus_as_space` is set.
+pub fn percent_decode(value: &str, plus_as_space: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() => {
+                let hex = [bytes[i + 1], bytes[i + 2]];
+                decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).unwrap_or("00"), 16).unwrap_or(0));
+                i += 2;
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,86 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::percent_decode;
+use crate::catalog::content_hash;
+
+pub const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+pub const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+pub const STREAMING_PAYLOAD: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD";
+pub const STREAMING_UNSIGNED_PAYLOAD: &str = "STREAMING-UNSIGNED-PAYLOAD-TRAILER";
+
+pub(crate) const DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The headers that sign a request with an S3 access key, as an S3 client would send
+/// them. `headers` must include `host`.
+pub fn sign_request(access_key_id: &str, secret_access_key: &str, region: &str, method: &str, target: &str, headers: &[(String, String)], body: &[u8]) -> Vec<(String, String)> {
+    let amz_date = Utc::now().format(DATE_FORMAT).to_string();
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let mut signed: Vec<(String, String)> = headers.iter().map(|(name, value)| (name.to_lowercase(), value.clone())).collect();
+    signed.push(("x-amz-date".to_string(), amz_date.clone()));
+    signed.push(("x-amz-content-sha256".to_string(), content_hash(body)));
+    signed.sort();
+    let signed_headers: Vec<String> = signed.iter().map(|(name, _)| name.clone()).collect();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let canonical = canonical_request(method, path, query, &signed, &signed_headers, &content_hash(body));
+    let key = signing_key(secret_access_key, date, region);
+    let signature = hex::encode(hmac(&key, string_to_sign(&amz_date, &scope, &canonical).as_bytes()));
+    let authorization = format!("{} Credential={}/{}, SignedHeaders={}, Signature={}", ALGORITHM, access_key_id, scope, signed_headers.join(";"), signature);
+    signed.push(("authorization".to_string(), authorization));
+    signed
+}
+
+pub(crate) fn canonical_request(method: &str, path: &str, query: &str, headers: &[(String, String)], signed_headers: &[String], payload_hash: &str) -> String {
+    let uri = path.split('/').map(|segment| uri_encode(&percent_decode(segment, false))).collect::<Vec<_>>().join("/");
+    let mut query: Vec<(String, String)> = query.split('&').filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .map(|(key, value)| (uri_encode(&percent_decode(key, false)), uri_encode(&percent_decode(value, false))))
+        .collect();
+    query.sort();
+    let query = query.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join("&");
+    let canonical_headers: String = signed_headers.iter().map(|name| {
+        let values: Vec<String> = headers.iter().filter(|(header, _)| header == name)
+            .map(|(_, value)| value.split_whitespace().collect::<Vec<_>>().join(" ")).collect();
+        format!("{}:{}\n", name, values.join(","))
+    }).collect();
+    format!("{}\n{}\n{}\n{}\n{}\n{}", method.to_uppercase(), if uri.is_empty() { "/" } else { &uri }, query, canonical_headers, signed_headers.join(";"), payload_hash)
+}
+
+pub(crate) fn string_to_sign(amz_date: &str, scope: &str, canonical_request: &str) -> String {
+    format!("{}\n{}\n{}\n{}", ALGORITHM, amz_date, scope, content_hash(canonical_request.as_bytes()))
+}
+
+pub(crate) fn signing_key(secret: &str, date: &str, region: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, b"s3");
+    hmac(&key, b"aws4_request")
+}
+
+pub(crate) fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Checks a hex signature against the HMAC of `message` in constant time, so that timing
+/// does not tell how much of a forged signature is right.
+pub(crate) fn verify_hmac(key: &[u8], message: &[u8], signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else { return false };
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Percent-encodes everything but the unreserved characters, as SigV4 requires.
+pub fn uri_encode(value: &str) -> String {
+    value.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+        byte => format!("%{:02X}", byte),
+    }).collect()
+}
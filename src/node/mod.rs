@@ -0,0 +1,670 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::OpenOptions;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::catalog::{content_hash, BlobStore, JournalOp};
+use crate::identity::{load_or_create_local_identity, verify_with_key};
+use crate::ledger::{baseline_files, fork_targets, pending_operations, Block, ChainUpdate, Genesis, Ledger};
+use crate::model::FileError;
+
+pub mod protocol;
+
+use protocol::{handshake_message, read_message, read_message_within, write_message, Request, Response, MAX_BLOCKS_PER_MESSAGE, MAX_HANDSHAKE_LEN, MAX_HASHES_PER_MESSAGE};
+
+const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a peer has to accept a connection, so that one unreachable peer does not hold
+/// up syncing with the rest.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How a node is reached and which other nodes it talks to. `peer_keys` holds the Ed25519
+/// verifying keys of the local identities of the nodes it trusts: it only answers, and
+/// only syncs with, nodes that prove they hold one of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeConfig {
+    pub listen: String,
+    pub peers: Vec<String>,
+    pub peer_keys: Vec<[u8; 32]>,
+    pub sync_interval: Duration,
+}
+
+impl NodeConfig {
+    /// Reads the node settings from `UNICHAIN_LISTEN`, `UNICHAIN_PEERS` (comma-separated
+    /// addresses), `UNICHAIN_PEER_KEYS` (comma-separated hex verifying keys) and
+    /// `UNICHAIN_SYNC_INTERVAL` (seconds). Returns `None` when no listen address is set,
+    /// in which case UniChain runs on its own. Keys that are not 32 bytes of hex are
+    /// logged and skipped.
+    pub fn from_env() -> Option<Self> {
+        let listen = env::var("UNICHAIN_LISTEN").ok().filter(|listen| !listen.trim().is_empty())?;
+        let peers = env::var("UNICHAIN_PEERS").unwrap_or_default()
+            .split(',')
+            .map(|peer| peer.trim().to_string())
+            .filter(|peer| !peer.is_empty())
+            .collect();
+        let sync_interval = env::var("UNICHAIN_SYNC_INTERVAL").ok()
+            .and_then(|seconds| seconds.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SYNC_INTERVAL);
+        let peer_keys = env::var("UNICHAIN_PEER_KEYS").unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .filter_map(|key| match hex::decode(key).ok().and_then(|key| <[u8; 32]>::try_from(key).ok()) {
+                Some(key) => Some(key),
+                None => {
+                    warn!("Ignored the peer key {}, which is not 32 bytes of hex.", key);
+                    None
+                },
+            })
+            .collect();
+        Some(NodeConfig { listen: listen.trim().to_string(), peers, peer_keys, sync_interval })
+    }
+}
+
+/// Serves the local ledger and blobs to other nodes and pulls whatever they have that
+/// this node is missing. The server stops when the node is dropped.
+pub struct Node {
+    catalog_path: PathBuf,
+    peers: Vec<String>,
+    peer_keys: Arc<Vec<[u8; 32]>>,
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+}
+
+impl Node {
+    pub fn start(catalog_path: &Path, config: &NodeConfig) -> Result<Self, FileError> {
+        if !catalog_path.exists() {
+            OpenOptions::new().create(true).truncate(false).write(true).open(catalog_path)?;
+        }
+        let identity = load_or_create_local_identity(catalog_path)?;
+        let listener = TcpListener::bind(&config.listen)?;
+        let local_addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let peer_keys = Arc::new(config.peer_keys.clone());
+        let (server_path, server_keys, server_stop) = (catalog_path.to_path_buf(), peer_keys.clone(), stop.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server_stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                match stream {
+                    Ok(stream) => {
+                        let (path, keys) = (server_path.clone(), server_keys.clone());
+                        thread::spawn(move || serve_connection(&path, &keys, stream));
+                    },
+                    Err(e) => warn!("Failed to accept a peer connection: {}", e),
+                }
+            }
+        });
+        info!("Node listening on {} with key {}.", local_addr, hex::encode(identity.public.verifying_key));
+        Ok(Node { catalog_path: catalog_path.to_path_buf(), peers: config.peers.clone(), peer_keys, local_addr, stop })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Pulls from every configured peer in turn, then signs a block if it is the local
+    /// validator's turn. Returns how many blocks were imported. A peer that cannot be
+    /// reached is logged and skipped.
+    pub fn sync(&self) -> usize {
+        sync_with_peers(&self.catalog_path, &self.peers, &self.peer_keys)
+    }
+
+    pub fn sync_with(&self, peer: &str) -> Result<ChainUpdate, FileError> {
+        sync_with_peer(&self.catalog_path, peer, &self.peer_keys)
+    }
+
+    /// Syncs with the peers every `interval` until the node is dropped.
+    pub fn spawn_sync_loop(&self, interval: Duration) {
+        let (path, peers, peer_keys, stop) = (self.catalog_path.clone(), self.peers.clone(), self.peer_keys.clone(), self.stop.clone());
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                sync_with_peers(&path, &peers, &peer_keys);
+                thread::sleep(interval);
+            }
+        });
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect(self.local_addr);
+    }
+}
+
+fn sync_with_peers(catalog_path: &Path, peers: &[String], peer_keys: &[[u8; 32]]) -> usize {
+    let mut imported = 0;
+    for peer in peers {
+        match sync_with_peer(catalog_path, peer, peer_keys) {
+            Ok(update) => {
+                if let Some(fork_height) = update.fork_height {
+                    warn!("Switched to the better chain of {} forking at height {}.", peer, fork_height);
+                }
+                for op in &update.orphaned {
+                    warn!("The local {} was orphaned and can be resubmitted.", op);
+                }
+                if update.imported > 0 {
+                    info!("Imported {} blocks from {}.", update.imported, peer);
+                }
+                imported += update.imported;
+            },
+            Err(e) => warn!("Sync with {} failed: {}", peer, e),
+        }
+    }
+    if Genesis::load(catalog_path).is_ok_and(|genesis| genesis.proof_of_work.is_none()) {
+        match crate::produce_block(catalog_path) {
+            Ok(Some(block)) => info!("Signed block {} with {} operations.", block.height, block.operations.len()),
+            Ok(None) => {},
+            Err(e) => warn!("Failed to sign a block: {}", e),
+        }
+    }
+    imported
+}
+
+/// Fetches the blocks `peer` has past the point where its chain and the local one
+/// part, and the content they store that is not here yet, then hands them to the local
+/// catalog, which keeps whichever chain the fork choice rule prefers.
+fn sync_with_peer(catalog_path: &Path, peer: &str, peer_keys: &[[u8; 32]]) -> Result<ChainUpdate, FileError> {
+    let socket = peer.to_socket_addrs()?.next().ok_or_else(|| FileError::PeerError(format!("Cannot resolve {}", peer)))?;
+    let mut stream = TcpStream::connect_timeout(&socket, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(PEER_TIMEOUT))?;
+    stream.set_write_timeout(Some(PEER_TIMEOUT))?;
+    open_handshake(catalog_path, &mut stream, peer, peer_keys)?;
+    let genesis = Genesis::load(catalog_path)?;
+    let (peer_height, peer_tip) = match request(&mut stream, &Request::Status)? {
+        Response::Status { genesis: peer_genesis, .. } if peer_genesis != genesis.hash()? => {
+            return Err(FileError::PeerError(format!("{} belongs to another network", peer)));
+        },
+        Response::Status { height, tip, .. } => (height, tip),
+        other => return Err(unexpected_response(&other)),
+    };
+    if !genesis.is_open() {
+        match request(&mut stream, &Request::GetPending)? {
+            Response::Pending(operations) => {
+                crate::add_pending_operations(catalog_path, operations)?;
+            },
+            other => return Err(unexpected_response(&other)),
+        }
+    }
+    let local = Ledger::read_blocks(catalog_path)?;
+    if peer_tip.is_none() || peer_tip.as_ref() == local.last().map(|tip| &tip.hash) {
+        return Ok(ChainUpdate::default());
+    }
+    let fork_height = find_fork_height(&mut stream, &local)?;
+    let mut blocks = Vec::new();
+    while fork_height + (blocks.len() as u64) < peer_height {
+        match request(&mut stream, &Request::GetBlocks { from_height: fork_height + blocks.len() as u64 })? {
+            Response::Blocks(batch) if !batch.is_empty() => blocks.extend(batch),
+            Response::Blocks(_) => break,
+            other => return Err(unexpected_response(&other)),
+        }
+    }
+    if blocks.is_empty() {
+        return Ok(ChainUpdate::default());
+    }
+    let blobs = BlobStore::open(catalog_path)?;
+    let mut needed = Vec::new();
+    if fork_height == local.len() as u64 {
+        for (position, block) in blocks.iter().enumerate() {
+            for (index, op) in block.operations.iter().enumerate() {
+                if let Some((file, Some(hash))) = op.stored() {
+                    if !crate::is_content_replaced(&blocks, position, index + 1, file.id) {
+                        needed.push((file.id, hash.clone()));
+                    }
+                }
+            }
+        }
+    } else {
+        let baseline: Vec<JournalOp> = baseline_files(catalog_path).list()?.into_iter().filter(|kept| kept.content.is_some()).map(|kept| kept.op).collect();
+        for op in fork_targets(&local, fork_height, &blocks, &baseline) {
+            if let Some((file, Some(hash))) = op.stored() {
+                if !baseline.contains(&op) {
+                    needed.push((file.id, hash.clone()));
+                }
+            }
+        }
+    }
+    let mut contents = HashMap::new();
+    for (file_id, hash) in needed {
+        if contents.contains_key(&hash) || blobs.hash(file_id)?.as_ref() == Some(&hash) {
+            continue;
+        }
+        match request(&mut stream, &Request::GetBlob { file_id })? {
+            Response::Blob(Some(content)) if content_hash(&content) == hash => {
+                contents.insert(hash, content);
+            },
+            Response::Blob(_) => return Err(FileError::PeerError(format!("{} no longer has the content {} of file {}", peer, hash, file_id))),
+            other => return Err(unexpected_response(&other)),
+        }
+    }
+    crate::import_blocks(catalog_path, fork_height, &blocks, &contents)
+}
+
+/// Proves the local node key to `peer` and checks that the peer holds one of `peer_keys`.
+fn open_handshake(catalog_path: &Path, stream: &mut TcpStream, peer: &str, peer_keys: &[[u8; 32]]) -> Result<(), FileError> {
+    let identity = load_or_create_local_identity(catalog_path)?;
+    let challenge: [u8; 32] = rand::random();
+    let server_challenge = match request(stream, &Request::Hello { verifying_key: identity.public.verifying_key, challenge })? {
+        Response::Hello { verifying_key, challenge: server_challenge, signature } => {
+            if !peer_keys.contains(&verifying_key) {
+                return Err(FileError::PeerError(format!("{} does not hold the key of a configured peer", peer)));
+            }
+            verify_with_key(&verifying_key, &handshake_message("server", &challenge)?, &signature)
+                .map_err(|_| FileError::PeerError(format!("{} failed to prove its key", peer)))?;
+            server_challenge
+        },
+        other => return Err(unexpected_response(&other)),
+    };
+    match request(stream, &Request::Prove { signature: identity.sign(&handshake_message("client", &server_challenge)?) })? {
+        Response::Welcome => Ok(()),
+        other => Err(unexpected_response(&other)),
+    }
+}
+
+/// Answers the opening of a connection, and returns the key the peer proved it holds when
+/// that key is one of `peer_keys`.
+fn accept_handshake(catalog_path: &Path, stream: &mut TcpStream, peer_keys: &[[u8; 32]]) -> Result<[u8; 32], FileError> {
+    let (verifying_key, challenge) = match read_message_within(stream, MAX_HANDSHAKE_LEN)? {
+        Some(Request::Hello { verifying_key, challenge }) => (verifying_key, challenge),
+        _ => return Err(FileError::Unauthenticated("The peer did not open with its key".to_string())),
+    };
+    if !peer_keys.contains(&verifying_key) {
+        return Err(FileError::Unauthenticated(format!("Key {} is not the key of a configured peer", hex::encode(verifying_key))));
+    }
+    let identity = load_or_create_local_identity(catalog_path)?;
+    let own_challenge: [u8; 32] = rand::random();
+    let signature = identity.sign(&handshake_message("server", &challenge)?);
+    write_message(stream, &Response::Hello { verifying_key: identity.public.verifying_key, challenge: own_challenge, signature })?;
+    match read_message_within(stream, MAX_HANDSHAKE_LEN)? {
+        Some(Request::Prove { signature }) => verify_with_key(&verifying_key, &handshake_message("client", &own_challenge)?, &signature)
+            .map_err(|_| FileError::Unauthenticated(format!("The peer failed to prove it holds key {}", hex::encode(verifying_key))))?,
+        _ => return Err(FileError::Unauthenticated("The peer did not prove its key".to_string())),
+    }
+    write_message(stream, &Response::Welcome)?;
+    Ok(verifying_key)
+}
+
+/// Finds how many blocks at the start of the peer's chain are also in `local`, looking
+/// back from the local tip in steps that double in size.
+fn find_fork_height(stream: &mut TcpStream, local: &[Block]) -> Result<u64, FileError> {
+    let mut step = 1;
+    loop {
+        let from_height = local.len().saturating_sub(step);
+        let hashes = match request(stream, &Request::GetHashes { from_height: from_height as u64 })? {
+            Response::Hashes(hashes) => hashes,
+            other => return Err(unexpected_response(&other)),
+        };
+        let shared = hashes.iter().zip(&local[from_height..]).take_while(|(hash, block)| **hash == block.hash).count();
+        if shared > 0 || from_height == 0 {
+            return Ok((from_height + shared) as u64);
+        }
+        step *= 2;
+    }
+}
+
+fn request(stream: &mut TcpStream, request: &Request) -> Result<Response, FileError> {
+    write_message(stream, request)?;
+    match read_message(stream)? {
+        Some(Response::Error(message)) => Err(FileError::PeerError(message)),
+        Some(response) => Ok(response),
+        None => Err(FileError::PeerError("The peer closed the connection".to_string())),
+    }
+}
+
+fn unexpected_response(response: &Response) -> FileError {
+    FileError::PeerError(format!("Unexpected response: {:?}", response))
+}
+
+fn serve_connection(catalog_path: &Path, peer_keys: &[[u8; 32]], mut stream: TcpStream) {
+    let _ = stream.set_read_timeout(Some(PEER_TIMEOUT));
+    if let Err(e) = accept_handshake(catalog_path, &mut stream, peer_keys) {
+        warn!("Refused a peer connection: {}", e);
+        let _ = write_message(&mut stream, &Response::Error(e.to_string()));
+        return;
+    }
+    loop {
+        let request = match read_message::<Request>(&mut stream) {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Dropped a peer connection: {}", e);
+                return;
+            },
+        };
+        let response = answer(catalog_path, request).unwrap_or_else(|e| Response::Error(e.to_string()));
+        if let Err(e) = write_message(&mut stream, &response) {
+            warn!("Failed to answer a peer: {}", e);
+            return;
+        }
+    }
+}
+
+fn answer(catalog_path: &Path, request: Request) -> Result<Response, FileError> {
+    match request {
+        Request::Status => {
+            let blocks = Ledger::read_blocks(catalog_path)?;
+            Ok(Response::Status { genesis: Genesis::load(catalog_path)?.hash()?, height: blocks.len() as u64, tip: blocks.last().map(|tip| tip.hash.clone()) })
+        },
+        Request::GetBlocks { from_height } => {
+            let blocks = Ledger::read_blocks(catalog_path)?;
+            Ok(Response::Blocks(blocks.into_iter().skip(from_height as usize).take(MAX_BLOCKS_PER_MESSAGE).collect()))
+        },
+        Request::GetHashes { from_height } => {
+            let blocks = Ledger::read_blocks(catalog_path)?;
+            Ok(Response::Hashes(blocks.into_iter().skip(from_height as usize).take(MAX_HASHES_PER_MESSAGE).map(|block| block.hash).collect()))
+        },
+        Request::GetBlob { file_id } => Ok(Response::Blob(BlobStore::open(catalog_path)?.read(file_id)?)),
+        Request::GetPending => Ok(Response::Pending(pending_operations(catalog_path).list()?)),
+        Request::Hello { .. } | Request::Prove { .. } => Err(FileError::PeerError("The connection is already open".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::identity::{save_local_identity, IdentityRegistry, LocalIdentity};
+    use crate::ledger::{find_transaction, transaction_id, validator_proposals, Consensus, PendingOperation, ProofOfWork, ValidatorChange};
+    use crate::model::File;
+    use crate::permissions::deletion::{DeletionApproval, DeletionPolicy, PendingDeletion};
+    use crate::permissions::transfer::{self, handed_over, OwnershipTransfer};
+    use crate::testing::TestFile;
+
+    /// The node keys of the repositories at `paths`, for nodes that all trust each other.
+    fn node_keys(paths: &[&Path]) -> Vec<[u8; 32]> {
+        paths.iter().map(|path| load_or_create_local_identity(path).unwrap().public.verifying_key).collect()
+    }
+
+    fn start_node(path: &Path, peers: Vec<String>, peer_keys: &[[u8; 32]]) -> Node {
+        let config = NodeConfig { listen: "127.0.0.1:0".to_string(), peers, peer_keys: peer_keys.to_vec(), sync_interval: DEFAULT_SYNC_INTERVAL };
+        Node::start(path, &config).unwrap()
+    }
+
+    #[test]
+    fn test_three_nodes_converge_on_localhost() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = (0..3).map(|n| dir.path().join(format!("node{}", n)).join("assets")).collect();
+        paths.iter().for_each(|path| fs::create_dir_all(path.parent().unwrap()).unwrap());
+        let keys = node_keys(&paths.iter().map(PathBuf::as_path).collect::<Vec<_>>());
+        let first = start_node(&paths[0], Vec::new(), &keys);
+        let second = start_node(&paths[1], vec![first.local_addr().to_string()], &keys);
+        let third = start_node(&paths[2], vec![second.local_addr().to_string()], &keys);
+
+        for id in [1, 2] {
+            let content = format!("content {}", id).into_bytes();
+            let op = JournalOp::Store { file: Box::new(TestFile::new(id).build()), content_hash: Some(content_hash(&content)) };
+            crate::run_journaled(&paths[0], op, Some(&content)).unwrap();
+        }
+        crate::run_journaled(&paths[0], JournalOp::Remove { file_id: 1, deletion: None }, None).unwrap();
+        assert_eq!(second.sync(), 3);
+        assert_eq!(third.sync(), 3);
+        assert_eq!(third.sync(), 0);
+
+        let expected = crate::load_files_from_file(&paths[0]).unwrap();
+        assert_eq!(expected.len(), 1);
+        for path in &paths[1..] {
+            assert_eq!(crate::load_files_from_file(path).unwrap(), expected);
+            assert_eq!(BlobStore::open(path).unwrap().read(2).unwrap(), Some(b"content 2".to_vec()));
+            assert_eq!(BlobStore::open(path).unwrap().read(1).unwrap(), None);
+            assert_eq!(Ledger::read_blocks(path).unwrap(), Ledger::read_blocks(&paths[0]).unwrap());
+        }
+    }
+
+    fn store(path: &Path, file: File, content: &[u8]) -> JournalOp {
+        let op = JournalOp::Store { file: Box::new(file), content_hash: Some(content_hash(content)) };
+        crate::run_journaled(path, op.clone(), Some(content)).unwrap();
+        op
+    }
+
+    #[test]
+    fn test_longer_chain_wins_and_orphans_local_operations() {
+        let dir = tempdir().unwrap();
+        let (first_path, second_path) = (dir.path().join("first"), dir.path().join("second"));
+        let keys = node_keys(&[&first_path, &second_path]);
+        let first = start_node(&first_path, Vec::new(), &keys);
+        let second = start_node(&second_path, Vec::new(), &keys);
+        store(&first_path, TestFile::new(1).build(), b"one");
+        assert_eq!(second.sync_with(&first.local_addr().to_string()).unwrap().imported, 1);
+
+        let orphaned = store(&first_path, TestFile::new(2).build(), b"two");
+        store(&second_path, TestFile::new(3).build(), b"three");
+        let mut renamed = TestFile::new(1).build();
+        renamed.name = "renamed".to_string();
+        store(&second_path, renamed, b"uno");
+
+        let update = first.sync_with(&second.local_addr().to_string()).unwrap();
+        assert_eq!(update, ChainUpdate { imported: 2, fork_height: Some(1), orphaned: vec![orphaned.clone()] });
+        assert_eq!(crate::load_files_from_file(&first_path).unwrap(), crate::load_files_from_file(&second_path).unwrap());
+        assert_eq!(Ledger::read_blocks(&first_path).unwrap(), Ledger::read_blocks(&second_path).unwrap());
+        let blobs = BlobStore::open(&first_path).unwrap();
+        assert_eq!((blobs.read(1).unwrap(), blobs.read(2).unwrap()), (Some(b"uno".to_vec()), None));
+        let orphans = crate::ledger::orphaned_operations(&first_path).list().unwrap();
+        assert_eq!(orphans, vec![PendingOperation { op: orphaned, content: Some(b"two".to_vec()) }]);
+        assert_eq!(second.sync_with(&first.local_addr().to_string()).unwrap(), ChainUpdate::default());
+    }
+
+    #[test]
+    fn test_losing_chain_puts_back_files_older_than_the_ledger() {
+        let dir = tempdir().unwrap();
+        let (first_path, second_path) = (dir.path().join("first"), dir.path().join("second"));
+        let original = TestFile::new(9).build();
+        crate::save_files_to_file(std::slice::from_ref(&original), &first_path).unwrap();
+        BlobStore::open(&first_path).unwrap().write(9, b"original").unwrap();
+        let keys = node_keys(&[&first_path, &second_path]);
+        let first = start_node(&first_path, Vec::new(), &keys);
+        let second = start_node(&second_path, Vec::new(), &keys);
+
+        let mut renamed = TestFile::new(9).build();
+        renamed.name = "renamed".to_string();
+        let orphaned = store(&first_path, renamed, b"changed");
+        store(&second_path, TestFile::new(1).build(), b"one");
+        store(&second_path, TestFile::new(2).build(), b"two");
+
+        let update = first.sync_with(&second.local_addr().to_string()).unwrap();
+        assert_eq!(update, ChainUpdate { imported: 2, fork_height: Some(0), orphaned: vec![orphaned] });
+        let files = crate::load_files_from_file(&first_path).unwrap();
+        assert_eq!(files.iter().find(|file| file.id == 9), Some(&original));
+        assert_eq!(BlobStore::open(&first_path).unwrap().read(9).unwrap(), Some(b"original".to_vec()));
+    }
+
+    #[test]
+    fn test_nodes_only_answer_configured_peers() {
+        let dir = tempdir().unwrap();
+        let (first_path, second_path) = (dir.path().join("first"), dir.path().join("second"));
+        let first = start_node(&first_path, Vec::new(), &node_keys(&[&first_path]));
+        let second = start_node(&second_path, Vec::new(), &node_keys(&[&first_path, &second_path]));
+        store(&first_path, TestFile::new(1).build(), b"one");
+        assert!(matches!(second.sync_with(&first.local_addr().to_string()), Err(FileError::PeerError(_))));
+        assert!(matches!(first.sync_with(&second.local_addr().to_string()), Err(FileError::PeerError(_))));
+
+        let mut stream = TcpStream::connect(first.local_addr()).unwrap();
+        write_message(&mut stream, &Request::GetBlob { file_id: 1 }).unwrap();
+        assert!(matches!(read_message::<Response>(&mut stream).unwrap(), Some(Response::Error(_))));
+        assert!(Ledger::read_blocks(&second_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_imported_store_cannot_change_the_owner() {
+        let dir = tempdir().unwrap();
+        let (first_path, second_path) = (dir.path().join("first"), dir.path().join("second"));
+        let keys = node_keys(&[&first_path, &second_path]);
+        let first = start_node(&first_path, Vec::new(), &keys);
+        let second = start_node(&second_path, Vec::new(), &keys);
+        store(&second_path, TestFile::new(1).build(), b"one");
+        assert_eq!(first.sync_with(&second.local_addr().to_string()).unwrap().imported, 1);
+
+        let mut seized = TestFile::new(1).build();
+        seized.owner = (2, String::from("Intruder"), String::from("intruder@gmail.com"));
+        seized.people_with_access = vec![seized.owner.clone()];
+        store(&second_path, seized, b"mine");
+        assert!(matches!(first.sync_with(&second.local_addr().to_string()), Err(FileError::IntegrityError(_))));
+        assert_eq!(crate::load_files_from_file(&first_path).unwrap()[0].owner.0, 1);
+        assert_eq!(Ledger::read_blocks(&first_path).unwrap().len(), 1);
+    }
+
+    /// A transfer of `file` from `from` to `to`, with the owner's signature made by `signer`.
+    fn transfer_op(file: &File, from: &LocalIdentity, to: &LocalIdentity, signer: &LocalIdentity) -> JournalOp {
+        let message = transfer::signing_message(file.id, &file.onchain_txn_id, from.id(), to.id()).unwrap();
+        let transfer = OwnershipTransfer {
+            file_id: file.id, transaction_id: file.onchain_txn_id.clone(), from: from.id(), to: to.id(),
+            owner_signature: hex::encode(signer.sign(&message)), recipient_signature: hex::encode(to.sign(&message)),
+        };
+        JournalOp::Transfer { file: Box::new(handed_over(file, &to.public)), content_hash: None, transfer: Box::new(transfer) }
+    }
+
+    #[test]
+    fn test_imported_transfer_needs_both_signatures() {
+        let dir = tempdir().unwrap();
+        let (first_path, second_path) = (dir.path().join("first"), dir.path().join("second"));
+        let keys = node_keys(&[&first_path, &second_path]);
+        let first = start_node(&first_path, Vec::new(), &keys);
+        let second = start_node(&second_path, Vec::new(), &keys);
+        let owner = LocalIdentity::generate(1, "Username", "username@gmail.com");
+        let recipient = LocalIdentity::generate(2, "Recipient", "recipient@gmail.com");
+        let registry = IdentityRegistry::open(&first_path).unwrap();
+        registry.register(&owner.public).unwrap();
+        registry.register(&recipient.public).unwrap();
+        let file = TestFile::new(1).build();
+        store(&second_path, file.clone(), b"one");
+
+        crate::run_journaled(&second_path, transfer_op(&file, &owner, &recipient, &owner), None).unwrap();
+        assert_eq!(first.sync_with(&second.local_addr().to_string()).unwrap().imported, 2);
+        let handed = crate::load_files_from_file(&first_path).unwrap().remove(0);
+        assert_eq!(handed.owner.0, 2);
+
+        crate::run_journaled(&second_path, transfer_op(&handed, &recipient, &owner, &owner), None).unwrap();
+        assert!(matches!(first.sync_with(&second.local_addr().to_string()), Err(FileError::IntegrityError(_))));
+        assert_eq!(crate::load_files_from_file(&first_path).unwrap()[0].owner.0, 2);
+        assert_eq!(Ledger::read_blocks(&first_path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_imported_removal_needs_the_approvals_of_its_policy() {
+        let dir = tempdir().unwrap();
+        let (first_path, second_path) = (dir.path().join("first"), dir.path().join("second"));
+        let keys = node_keys(&[&first_path, &second_path]);
+        let first = start_node(&first_path, Vec::new(), &keys);
+        let second = start_node(&second_path, Vec::new(), &keys);
+        let owner = LocalIdentity::generate(1, "Username", "username@gmail.com");
+        let guest = LocalIdentity::generate(2, "Guest", "guest@gmail.com");
+        let registry = IdentityRegistry::open(&first_path).unwrap();
+        registry.register(&owner.public).unwrap();
+        registry.register(&guest.public).unwrap();
+        let mut file = TestFile::new(1).build();
+        file.people_with_access.push((2, "Guest".to_string(), "guest@gmail.com".to_string()));
+        store(&second_path, file.clone(), b"one");
+        crate::run_journaled(&second_path, JournalOp::Policy { policy: DeletionPolicy { file_id: 1, threshold: 2 } }, None).unwrap();
+        assert_eq!(first.sync_with(&second.local_addr().to_string()).unwrap().imported, 2);
+        assert_eq!(crate::permissions::deletion::threshold(&first_path, 1).unwrap(), 2);
+
+        let now = Utc::now().naive_utc();
+        let mut deletion = PendingDeletion {
+            file_id: 1, transaction_id: file.onchain_txn_id.clone(), threshold: 2, requested: now, expires: now, approvals: Vec::new(),
+        };
+        let message = deletion.signing_message().unwrap();
+        deletion.approvals = [&owner, &owner].iter()
+            .map(|approver| DeletionApproval { identity_id: approver.id(), signature: hex::encode(approver.sign(&message)), approved: now })
+            .collect();
+        crate::run_journaled(&second_path, JournalOp::Remove { file_id: 1, deletion: Some(Box::new(deletion)) }, None).unwrap();
+        assert!(matches!(first.sync_with(&second.local_addr().to_string()), Err(FileError::IntegrityError(_))));
+        assert_eq!(crate::load_files_from_file(&first_path).unwrap(), vec![file]);
+        assert_eq!(Ledger::read_blocks(&first_path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_chains_of_equal_length_settle_on_the_same_tip() {
+        let dir = tempdir().unwrap();
+        let (first_path, second_path) = (dir.path().join("first"), dir.path().join("second"));
+        let keys = node_keys(&[&first_path, &second_path]);
+        let first = start_node(&first_path, vec![], &keys);
+        let second = start_node(&second_path, vec![], &keys);
+        store(&first_path, TestFile::new(1).build(), b"one");
+        store(&second_path, TestFile::new(2).build(), b"two");
+        first.sync_with(&second.local_addr().to_string()).unwrap();
+        second.sync_with(&first.local_addr().to_string()).unwrap();
+        assert_eq!(Ledger::read_blocks(&first_path).unwrap(), Ledger::read_blocks(&second_path).unwrap());
+        assert_eq!(crate::load_files_from_file(&first_path).unwrap(), crate::load_files_from_file(&second_path).unwrap());
+    }
+
+    #[test]
+    fn test_validators_take_turns_signing_blocks() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["first", "second", "observer"].iter().map(|name| dir.path().join(name)).collect();
+        let identities: Vec<LocalIdentity> = (1..=3).map(|id| LocalIdentity::generate(id, &format!("Node {}", id), &format!("node{}@gmail.com", id))).collect();
+        let genesis = Genesis { network: "consortium".to_string(), consensus: Consensus::Authority { validators: vec![identities[0].public.clone(), identities[1].public.clone()] }, proof_of_work: None };
+        for (path, identity) in paths.iter().zip(&identities) {
+            genesis.save(path).unwrap();
+            save_local_identity(path, identity).unwrap();
+        }
+        let keys: Vec<[u8; 32]> = identities.iter().map(|identity| identity.public.verifying_key).collect();
+        let observer = start_node(&paths[2], Vec::new(), &keys);
+        let first = start_node(&paths[0], vec![observer.local_addr().to_string()], &keys);
+        let second = start_node(&paths[1], vec![first.local_addr().to_string()], &keys);
+
+        let op = store(&paths[2], TestFile::new(1).build(), b"one");
+        assert!(Ledger::read_blocks(&paths[2]).unwrap().is_empty());
+        assert_eq!(crate::produce_block(&paths[2]).unwrap(), None);
+        first.sync();
+        let blocks = Ledger::read_blocks(&paths[0]).unwrap();
+        assert_eq!((blocks.len(), blocks[0].signer, blocks[0].operations.clone()), (1, Some(1), vec![op]));
+        assert_eq!(BlobStore::open(&paths[0]).unwrap().read(1).unwrap(), Some(b"one".to_vec()));
+        assert_eq!(observer.sync_with(&first.local_addr().to_string()).unwrap().imported, 1);
+        assert!(pending_operations(&paths[2]).list().unwrap().is_empty());
+
+        store(&paths[0], TestFile::new(2).build(), b"two");
+        assert_eq!(crate::produce_block(&paths[0]).unwrap(), None, "The first validator signed out of turn");
+        let mut out_of_turn = crate::ledger::Block::new(blocks.last(), Vec::new()).unwrap();
+        out_of_turn.sign(&identities[0]).unwrap();
+        assert!(matches!(Ledger::open(&paths[1]).unwrap().append(out_of_turn), Err(FileError::IntegrityError(_))));
+
+        validator_proposals(&paths[1]).add(&[ValidatorChange::Add(identities[2].public.clone())]).unwrap();
+        second.sync();
+        let blocks = Ledger::read_blocks(&paths[1]).unwrap();
+        assert_eq!((blocks.len(), blocks[1].signer), (2, Some(2)));
+        assert_eq!(blocks[1].operations.len(), 1, "The second validator did not pick up the pending operation");
+        assert_eq!(genesis.expected_signer(&blocks).map(|validator| validator.id), Some(3));
+        assert_eq!(crate::load_files_from_file(&paths[1]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_proof_of_work_blocks_replicate() {
+        let dir = tempdir().unwrap();
+        let (first_path, second_path) = (dir.path().join("first"), dir.path().join("second"));
+        let proof_of_work = ProofOfWork { initial_difficulty: 8, target_block_seconds: 60, retarget_interval: 10 };
+        let genesis = Genesis { network: "public".to_string(), consensus: Consensus::Open, proof_of_work: Some(proof_of_work) };
+        genesis.save(&first_path).unwrap();
+        let keys = node_keys(&[&first_path, &second_path]);
+        let first = start_node(&first_path, Vec::new(), &keys);
+        let second = start_node(&second_path, Vec::new(), &keys);
+        let mut file = TestFile::new(1).build();
+        file.onchain_txn_id = transaction_id(&file).unwrap();
+        store(&first_path, file.clone(), b"one");
+        assert!(Ledger::read_blocks(&first_path).unwrap().is_empty(), "The block was sealed under the catalog lock");
+        let block = crate::produce_block(&first_path).unwrap().expect("No block was sealed");
+        let blocks = Ledger::read_blocks(&first_path).unwrap();
+        assert_eq!(blocks, vec![block]);
+        assert!(pending_operations(&first_path).list().unwrap().is_empty());
+        assert!(blocks[0].difficulty == 8 && blocks[0].meets_difficulty());
+        assert_eq!(find_transaction(&blocks, &file.onchain_txn_id), Some(&blocks[0]));
+        assert!(matches!(second.sync_with(&first.local_addr().to_string()), Err(FileError::PeerError(_))), "A node of another network was synced");
+        genesis.save(&second_path).unwrap();
+        assert_eq!(second.sync_with(&first.local_addr().to_string()).unwrap().imported, 1);
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{ErrorKind, Read, Write};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::ledger::{Block, PendingOperation};
+use crate::model::FileError;
+
+/// Largest message a node accepts, which bounds the size of a single blob it can replicate.
+pub const MAX_MESSAGE_LEN: usize = 256 * 1024 * 1024;
+
+/// Most blocks sent in reply to one `GetBlocks`.
+pub const MAX_BLOCKS_PER_MESSAGE: usize = 500;
+
+/// Most block hashes sent in reply to one `GetHashes`.
+pub const MAX_HASHES_PER_MESSAGE: usize = 10_000;
+
+/// Largest message accepted while the other side has not authenticated.
+pub const MAX_HANDSHAKE_LEN: usize = 1024;
+
+/// Every connection opens with `Hello` and `Prove`, through which each side shows it holds
+/// the node key it claims by signing a challenge from the other. Nothing else is answered
+/// before that.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Request {
+    Status,
+    GetBlocks { from_height: u64 },
+    GetHashes { from_height: u64 },
+    GetBlob { file_id: i64 },
+    GetPending,
+    Hello { verifying_key: [u8; 32], challenge: [u8; 32] },
+    Prove { signature: Vec<u8> },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Response {
+    Status { genesis: String, height: u64, tip: Option<String> },
+    Blocks(Vec<Block>),
+    Hashes(Vec<String>),
+    Blob(Option<Vec<u8>>),
+    Pending(Vec<PendingOperation>),
+    Error(String),
+    Hello { verifying_key: [u8; 32], challenge: [u8; 32], signature: Vec<u8> },
+    Welcome,
+}
+
+/// What a node signs to answer the challenge of the other side of a connection. `role`
+/// keeps a signature made as the server from being replayed as the client.
+pub fn handshake_message(role: &str, challenge: &[u8; 32]) -> Result<Vec<u8>, FileError> {
+    bincode::serialize(&("unichain-node", role, challenge)).map_err(|_| FileError::DeserializationError("Handshake serialization failed".to_string()))
+}
+
+/// Sends one message framed by its length.
+pub fn write_message<T: Serialize>(stream: &mut impl Write, message: &T) -> Result<(), FileError> {
+    let encoded = bincode::serialize(message).map_err(|_| FileError::DeserializationError("Message serialization failed".to_string()))?;
+    if encoded.len() > MAX_MESSAGE_LEN {
+        return Err(FileError::PeerError(format!("Message of {} bytes is too large to send", encoded.len())));
+    }
+    stream.write_all(&(encoded.len() as u32).to_le_bytes())?;
+    stream.write_all(&encoded)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Receives one message, or `None` when the other side closed the connection.
+pub fn read_message<T: DeserializeOwned>(stream: &mut impl Read) -> Result<Option<T>, FileError> {
+    read_message_within(stream, MAX_MESSAGE_LEN)
+}
+
+/// Receives one message of at most `max_len` bytes, which keeps a peer that has not
+/// authenticated yet from making the node allocate much.
+pub fn read_message_within<T: DeserializeOwned>(stream: &mut impl Read, max_len: usize) -> Result<Option<T>, FileError> {
+    let mut header = [0u8; 4];
+    match stream.read_exact(&mut header) {
+        Ok(()) => {},
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(FileError::IOError(e)),
+    }
+    let len = u32::from_le_bytes(header) as usize;
+    if len > max_len {
+        return Err(FileError::PeerError(format!("Message of {} bytes is too large to receive", len)));
+    }
+    let mut encoded = vec![0u8; len];
+    stream.read_exact(&mut encoded)?;
+    bincode::deserialize(&encoded).map(Some).map_err(|_| FileError::DeserializationError("Failed to deserialize a peer message".to_string()))
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::CatalogLock;
+use crate::ledger::RecordPool;
+use crate::model::{File, FileError};
+use crate::permissions::{is_allowed, Action};
+use crate::utils::generate_id;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AccessStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+/// Someone's request to join the access list of a file, and what the owner made of it.
+/// `notified` tells whether the requester has been shown the outcome.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AccessRequest {
+    pub id: i64,
+    pub file_id: i64,
+    pub requester: (i64, String, String),
+    pub reason: String,
+    pub requested: NaiveDateTime,
+    pub status: AccessStatus,
+    pub decided: Option<NaiveDateTime>,
+    pub notified: bool,
+}
+
+pub fn access_requests(catalog_path: &Path) -> RecordPool<AccessRequest> {
+    RecordPool::open(catalog_path, ".access-requests")
+}
+
+/// Records a request by `requester` to view `file`. Asking again while a request is
+/// pending replaces its reason.
+pub fn submit(catalog_path: &Path, file: &File, requester: &(i64, String, String), reason: &str) -> Result<AccessRequest, FileError> {
+    let reason = reason.trim();
+    if reason.is_empty() {
+        return Err(FileError::InputError("An access request needs a reason".to_string()));
+    }
+    if is_allowed(requester.0, file, Action::View) {
+        return Err(FileError::InputError(format!("Identity {} already has access to file {}", requester.0, file.id)));
+    }
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    let requests = access_requests(catalog_path);
+    let earlier = requests.list()?.into_iter()
+        .find(|request| request.file_id == file.id && request.requester.0 == requester.0 && request.status == AccessStatus::Pending);
+    let request = AccessRequest {
+        id: earlier.as_ref().map_or_else(generate_id, |earlier| Ok(earlier.id))?,
+        file_id: file.id, requester: requester.clone(), reason: reason.to_string(), requested: Utc::now().naive_utc(),
+        status: AccessStatus::Pending, decided: None, notified: false,
+    };
+    requests.retain(|other| other.id != request.id)?;
+    requests.add(std::slice::from_ref(&request))?;
+    Ok(request)
+}
+
+pub fn list(catalog_path: &Path) -> Result<Vec<AccessRequest>, FileError> {
+    access_requests(catalog_path).list()
+}
+
+/// Records the owner's answer to a pending request. Granting the access itself is up to
+/// the caller.
+pub fn settle(catalog_path: &Path, request_id: i64, approved: bool) -> Result<AccessRequest, FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    let requests = access_requests(catalog_path);
+    let mut request = requests.list()?.into_iter().find(|request| request.id == request_id && request.status == AccessStatus::Pending)
+        .ok_or_else(|| FileError::InputError(format!("No access request {} is pending", request_id)))?;
+    request.status = if approved { AccessStatus::Approved } else { AccessStatus::Denied };
+    request.decided = Some(Utc::now().naive_utc());
+    requests.retain(|other| other.id != request_id)?;
+    requests.add(std::slice::from_ref(&request))?;
+    Ok(request)
+}
+
+/// Puts a settled request back as it was while pending, when granting the access it asked
+/// for failed.
+pub fn reopen(catalog_path: &Path, pending: &AccessRequest) -> Result<(), FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    let requests = access_requests(catalog_path);
+    requests.retain(|other| other.id != pending.id)?;
+    requests.add(std::slice::from_ref(pending))
+}
+
+/// The answers to the requests of `requester_id` that they have not been shown yet, which
+/// are marked as shown.
+pub fn take_outcomes(catalog_path: &Path, requester_id: i64) -> Result<Vec<AccessRequest>, FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    let requests = access_requests(catalog_path);
+    let is_new_outcome = |request: &AccessRequest| request.requester.0 == requester_id && request.status != AccessStatus::Pending && !request.notified;
+    let outcomes: Vec<AccessRequest> = requests.list()?.into_iter().filter(is_new_outcome).collect();
+    if outcomes.is_empty() {
+        return Ok(outcomes);
+    }
+    requests.retain(|request| !is_new_outcome(request))?;
+    let shown: Vec<AccessRequest> = outcomes.iter().cloned().map(|request| AccessRequest { notified: true, ..request }).collect();
+    requests.add(&shown)?;
+    Ok(outcomes)
+}
+
+/// Drops the requests for a file that was removed.
+pub fn forget(catalog_path: &Path, file_id: i64) -> Result<(), FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    access_requests(catalog_path).retain(|request| request.file_id != file_id)
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::CatalogLock;
+use crate::identity::{IdentityRegistry, PublicIdentity};
+use crate::ledger::RecordPool;
+use crate::model::{File, FileError};
+
+/// How long a deletion waits for its approvals before it lapses.
+pub const APPROVAL_WINDOW_DAYS: i64 = 7;
+
+/// How many of the people who rely on a file must approve its deletion.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeletionPolicy {
+    pub file_id: i64,
+    pub threshold: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeletionApproval {
+    pub identity_id: i64,
+    pub signature: String,
+    pub approved: NaiveDateTime,
+}
+
+/// A deletion waiting for `threshold` approvals. It applies to the version of the file
+/// with `transaction_id` only, and lapses at `expires`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PendingDeletion {
+    pub file_id: i64,
+    pub transaction_id: String,
+    pub threshold: usize,
+    pub requested: NaiveDateTime,
+    pub expires: NaiveDateTime,
+    pub approvals: Vec<DeletionApproval>,
+}
+
+impl PendingDeletion {
+    /// What an approver signs: the file, the version of it being deleted and when the
+    /// deletion was requested.
+    pub fn signing_message(&self) -> Result<Vec<u8>, FileError> {
+        bincode::serialize(&("unichain-deletion", self.file_id, &self.transaction_id, self.requested))
+            .map_err(|_| FileError::DeserializationError("Deletion serialization failed".to_string()))
+    }
+
+    pub fn is_approved(&self) -> bool {
+        self.approvals.len() >= self.threshold
+    }
+
+    /// Checks that the approvals are for `file` as it stands, and that at least
+    /// `threshold` of its approvers signed them, as the identity registry knows them.
+    pub fn verify(&self, file: &File, threshold: usize, registry: &IdentityRegistry) -> Result<(), FileError> {
+        let refused = |reason: String| FileError::IntegrityError(format!("The approvals to delete file {} {}", file.id, reason));
+        if self.file_id != file.id || self.transaction_id != file.onchain_txn_id {
+            return Err(refused("are for another version of it".to_string()));
+        }
+        let message = self.signing_message()?;
+        let allowed = approvers(file);
+        let mut approved = BTreeSet::new();
+        for approval in &self.approvals {
+            if !allowed.contains(&approval.identity_id) {
+                return Err(refused(format!("include identity {}, who may not approve it", approval.identity_id)));
+            }
+            let approver = registry.get(approval.identity_id)?
+                .ok_or_else(|| refused(format!("include identity {}, which is not registered", approval.identity_id)))?;
+            let signature = hex::decode(&approval.signature).map_err(|_| refused("hold a signature that is not hex".to_string()))?;
+            approver.verify(&message, &signature).map_err(|_| refused(format!("hold a signature that is not by identity {}", approval.identity_id)))?;
+            approved.insert(approval.identity_id);
+        }
+        if approved.len() < threshold {
+            return Err(refused(format!("number {}, and it needs {}", approved.len(), threshold)));
+        }
+        Ok(())
+    }
+}
+
+/// What became of a request to remove a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Removal {
+    Removed,
+    Pending(PendingDeletion),
+}
+
+pub fn deletion_policies(catalog_path: &Path) -> RecordPool<DeletionPolicy> {
+    RecordPool::open(catalog_path, ".deletion-policies")
+}
+
+pub fn pending_deletions(catalog_path: &Path) -> RecordPool<PendingDeletion> {
+    RecordPool::open(catalog_path, ".deletions")
+}
+
+/// The owner and everyone on the access list, who may each approve the file's deletion.
+pub fn approvers(file: &File) -> BTreeSet<i64> {
+    file.people_with_access.iter().map(|person| person.0).chain([file.owner.0]).collect()
+}
+
+/// How many approvals deleting the file takes; 1 when it has no policy.
+pub fn threshold(catalog_path: &Path, file_id: i64) -> Result<usize, FileError> {
+    Ok(deletion_policies(catalog_path).list()?.into_iter().rev()
+        .find(|policy| policy.file_id == file_id)
+        .map_or(1, |policy| policy.threshold))
+}
+
+/// The policy requiring `threshold` approvals from the file's approvers to delete it.
+pub fn policy_for(file: &File, threshold: usize) -> Result<DeletionPolicy, FileError> {
+    let count = approvers(file).len();
+    if threshold > count {
+        return Err(FileError::InputError(format!("File {} has only {} people who can approve its deletion", file.id, count)));
+    }
+    Ok(DeletionPolicy { file_id: file.id, threshold })
+}
+
+/// Puts `policy` in place of the file's current one. A threshold of 1 or less drops the
+/// requirement. Call it only while holding the catalog lock.
+pub fn apply_policy(catalog_path: &Path, policy: &DeletionPolicy) -> Result<(), FileError> {
+    let policies = deletion_policies(catalog_path);
+    policies.retain(|other| other.file_id != policy.file_id)?;
+    if policy.threshold > 1 {
+        policies.add(std::slice::from_ref(policy))?;
+    }
+    Ok(())
+}
+
+/// The deletions still waiting for approvals, after dropping the ones that lapsed.
+pub fn list(catalog_path: &Path) -> Result<Vec<PendingDeletion>, FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    prune(catalog_path)
+}
+
+/// Starts a deletion of the current version of `file` that waits for `threshold`
+/// approvals, or returns the one already waiting.
+pub fn request(catalog_path: &Path, file: &File, threshold: usize) -> Result<PendingDeletion, FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    if let Some(deletion) = prune(catalog_path)?.into_iter().find(|deletion| deletion.file_id == file.id && deletion.transaction_id == file.onchain_txn_id) {
+        return Ok(deletion);
+    }
+    let requested = Utc::now().naive_utc();
+    let deletion = PendingDeletion {
+        file_id: file.id, transaction_id: file.onchain_txn_id.clone(), threshold, requested,
+        expires: requested + Duration::days(APPROVAL_WINDOW_DAYS), approvals: Vec::new(),
+    };
+    let pending = pending_deletions(catalog_path);
+    pending.retain(|other| other.file_id != file.id)?;
+    pending.add(std::slice::from_ref(&deletion))?;
+    Ok(deletion)
+}
+
+/// Records the approval `approver` signed for the pending deletion of `file`. Approving
+/// twice changes nothing. Returns the deletion with its approvals so far.
+pub fn approve(catalog_path: &Path, file: &File, approver: &PublicIdentity, signature: &[u8]) -> Result<PendingDeletion, FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    let pending = pending_deletions(catalog_path);
+    let mut deletion = prune(catalog_path)?.into_iter().find(|deletion| deletion.file_id == file.id)
+        .ok_or_else(|| FileError::InputError(format!("No deletion of file {} is waiting for approval", file.id)))?;
+    if deletion.transaction_id != file.onchain_txn_id {
+        pending.retain(|other| other.file_id != file.id)?;
+        return Err(FileError::InputError(format!("File {} changed since its deletion was requested", file.id)));
+    }
+    if !approvers(file).contains(&approver.id) {
+        return Err(FileError::PermissionDenied);
+    }
+    approver.verify(&deletion.signing_message()?, signature)
+        .map_err(|_| FileError::Unauthenticated(format!("The approval is not signed by identity {}", approver.id)))?;
+    if deletion.approvals.iter().any(|approval| approval.identity_id == approver.id) {
+        return Ok(deletion);
+    }
+    deletion.approvals.push(DeletionApproval { identity_id: approver.id, signature: hex::encode(signature), approved: Utc::now().naive_utc() });
+    pending.retain(|other| other.file_id != file.id)?;
+    pending.add(std::slice::from_ref(&deletion))?;
+    Ok(deletion)
+}
+
+/// Drops the pending deletion and the policy of a file that was removed.
+pub fn forget(catalog_path: &Path, file_id: i64) -> Result<(), FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    pending_deletions(catalog_path).retain(|deletion| deletion.file_id != file_id)?;
+    deletion_policies(catalog_path).retain(|policy| policy.file_id != file_id)
+}
+
+/// Drops the deletions that lapsed and returns the rest. Call it only while holding the
+/// catalog lock.
+fn prune(catalog_path: &Path) -> Result<Vec<PendingDeletion>, FileError> {
+    let now = Utc::now().naive_utc();
+    let pending = pending_deletions(catalog_path);
+    pending.retain(|deletion| deletion.expires > now)?;
+    pending.list()
+}
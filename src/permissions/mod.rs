@@ -0,0 +1,59 @@
+use crate::model::{File, FileError};
+
+pub mod access;
+pub mod deletion;
+pub mod transfer;
+
+/// Something a person asks to do with a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    View,
+    Download,
+    Modify,
+    Remove,
+}
+
+/// Whether the person with `person_id` may carry out `action` on `file`. The owner may do
+/// anything. People on the access list may view the file, and download its content when
+/// the file allows downloads.
+pub fn is_allowed(person_id: i64, file: &File, action: Action) -> bool {
+    if file.owner.0 == person_id {
+        return true;
+    }
+    let has_access = file.people_with_access.iter().any(|person| person.0 == person_id);
+    match action {
+        Action::View => has_access,
+        Action::Download => has_access && file.download_permission,
+        Action::Modify | Action::Remove => false,
+    }
+}
+
+pub fn authorize(person_id: i64, file: &File, action: Action) -> Result<(), FileError> {
+    match is_allowed(person_id, file, action) {
+        true => Ok(()),
+        false => Err(FileError::PermissionDenied),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::TestFile;
+
+    #[test]
+    fn test_owner_and_people_with_access() {
+        let owner = (1, String::from("Owner"), String::from("owner@gmail.com"));
+        let guest = (2, String::from("Guest"), String::from("guest@gmail.com"));
+        let mut file = TestFile::new(10).owned_by(owner).shared_with(guest).build();
+        for action in [Action::View, Action::Download, Action::Modify, Action::Remove] {
+            assert!(is_allowed(1, &file, action));
+            assert!(!is_allowed(3, &file, action));
+        }
+        assert!(is_allowed(2, &file, Action::View));
+        assert_eq!(authorize(2, &file, Action::Download), Err(FileError::PermissionDenied));
+        file.download_permission = true;
+        assert!(is_allowed(2, &file, Action::Download));
+        assert!(!is_allowed(2, &file, Action::Modify) && !is_allowed(2, &file, Action::Remove));
+    }
+}
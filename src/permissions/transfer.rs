@@ -0,0 +1,158 @@
+use std::path::Path;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::CatalogLock;
+use crate::identity::PublicIdentity;
+use crate::ledger::RecordPool;
+use crate::model::{File, FileError};
+
+/// How long a proposed transfer waits for the recipient before it lapses.
+pub const TRANSFER_WINDOW_DAYS: i64 = 7;
+
+/// What both the owner and the recipient sign: the file, the version of it being handed
+/// over, and who hands it to whom.
+pub fn signing_message(file_id: i64, transaction_id: &str, from: i64, to: i64) -> Result<Vec<u8>, FileError> {
+    bincode::serialize(&("unichain-transfer", file_id, transaction_id, from, to))
+        .map_err(|_| FileError::DeserializationError("Transfer serialization failed".to_string()))
+}
+
+/// An owner's signed offer to hand the version of a file with `transaction_id` over to
+/// `to`, waiting for them to accept until `expires`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TransferProposal {
+    pub file_id: i64,
+    pub transaction_id: String,
+    pub from: i64,
+    pub to: i64,
+    pub owner_signature: String,
+    pub proposed: NaiveDateTime,
+    pub expires: NaiveDateTime,
+}
+
+impl TransferProposal {
+    pub fn signing_message(&self) -> Result<Vec<u8>, FileError> {
+        signing_message(self.file_id, &self.transaction_id, self.from, self.to)
+    }
+}
+
+/// A change of owner as recorded in the ledger, with the signatures of the previous owner
+/// and the new one over the same `signing_message`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OwnershipTransfer {
+    pub file_id: i64,
+    pub transaction_id: String,
+    pub from: i64,
+    pub to: i64,
+    pub owner_signature: String,
+    pub recipient_signature: String,
+}
+
+impl OwnershipTransfer {
+    pub fn signing_message(&self) -> Result<Vec<u8>, FileError> {
+        signing_message(self.file_id, &self.transaction_id, self.from, self.to)
+    }
+
+    /// Checks both signatures against the public identities of the two sides.
+    pub fn verify(&self, owner: &PublicIdentity, recipient: &PublicIdentity) -> Result<(), FileError> {
+        let message = self.signing_message()?;
+        for (identity, signature) in [(owner, &self.owner_signature), (recipient, &self.recipient_signature)] {
+            let signature = hex::decode(signature).map_err(|_| FileError::IntegrityError("A transfer signature is not hex".to_string()))?;
+            identity.verify(&message, &signature)
+                .map_err(|_| FileError::IntegrityError(format!("The transfer of file {} is not signed by identity {}", self.file_id, identity.id)))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn transfer_proposals(catalog_path: &Path) -> RecordPool<TransferProposal> {
+    RecordPool::open(catalog_path, ".transfers")
+}
+
+/// The file as it stands once `recipient` owns it. The recipient joins the access list,
+/// and the previous owner stays on it until the new owner decides otherwise.
+pub fn handed_over(file: &File, recipient: &PublicIdentity) -> File {
+    let mut file = file.clone();
+    file.owner = recipient.as_person();
+    if !file.people_with_access.iter().any(|person| person.0 == recipient.id) {
+        file.people_with_access.push(recipient.as_person());
+    }
+    file
+}
+
+/// Records the offer `owner` signed to hand the current version of `file` over to
+/// `recipient`, replacing any earlier offer for the file.
+pub fn propose(catalog_path: &Path, file: &File, owner: &PublicIdentity, recipient: &PublicIdentity, signature: &[u8]) -> Result<TransferProposal, FileError> {
+    if file.owner.0 != owner.id {
+        return Err(FileError::PermissionDenied);
+    }
+    if recipient.id == owner.id {
+        return Err(FileError::InputError(format!("File {} already belongs to identity {}", file.id, owner.id)));
+    }
+    let proposed = Utc::now().naive_utc();
+    let proposal = TransferProposal {
+        file_id: file.id, transaction_id: file.onchain_txn_id.clone(), from: owner.id, to: recipient.id,
+        owner_signature: hex::encode(signature), proposed, expires: proposed + Duration::days(TRANSFER_WINDOW_DAYS),
+    };
+    owner.verify(&proposal.signing_message()?, signature)
+        .map_err(|_| FileError::Unauthenticated(format!("The transfer is not signed by identity {}", owner.id)))?;
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    let proposals = transfer_proposals(catalog_path);
+    proposals.retain(|other| other.file_id != file.id)?;
+    proposals.add(std::slice::from_ref(&proposal))?;
+    Ok(proposal)
+}
+
+/// The offers still waiting for their recipients, after dropping the ones that lapsed.
+pub fn list(catalog_path: &Path) -> Result<Vec<TransferProposal>, FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    prune(catalog_path)
+}
+
+/// Completes the offer for `file` with the signature `recipient` gave to accept it. The
+/// offer stays in place until `forget` drops it once the transfer is carried out.
+pub fn accept(catalog_path: &Path, file: &File, recipient: &PublicIdentity, signature: &[u8]) -> Result<OwnershipTransfer, FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    let proposal = prune(catalog_path)?.into_iter().find(|proposal| proposal.file_id == file.id)
+        .ok_or_else(|| FileError::InputError(format!("No transfer of file {} is waiting to be accepted", file.id)))?;
+    if proposal.transaction_id != file.onchain_txn_id || proposal.from != file.owner.0 {
+        transfer_proposals(catalog_path).retain(|other| other.file_id != file.id)?;
+        return Err(FileError::InputError(format!("File {} changed since its transfer was proposed", file.id)));
+    }
+    if proposal.to != recipient.id {
+        return Err(FileError::PermissionDenied);
+    }
+    recipient.verify(&proposal.signing_message()?, signature)
+        .map_err(|_| FileError::Unauthenticated(format!("The acceptance is not signed by identity {}", recipient.id)))?;
+    Ok(OwnershipTransfer {
+        file_id: file.id, transaction_id: proposal.transaction_id, from: proposal.from, to: proposal.to,
+        owner_signature: proposal.owner_signature, recipient_signature: hex::encode(signature),
+    })
+}
+
+/// Drops the offer for a file, which only its owner or its recipient may do.
+pub fn withdraw(catalog_path: &Path, file_id: i64, person_id: i64) -> Result<(), FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    let proposal = prune(catalog_path)?.into_iter().find(|proposal| proposal.file_id == file_id)
+        .ok_or_else(|| FileError::InputError(format!("No transfer of file {} is waiting to be accepted", file_id)))?;
+    if person_id != proposal.from && person_id != proposal.to {
+        return Err(FileError::PermissionDenied);
+    }
+    transfer_proposals(catalog_path).retain(|other| other.file_id != file_id)
+}
+
+/// Drops the offer for a file that was handed over or removed.
+pub fn forget(catalog_path: &Path, file_id: i64) -> Result<(), FileError> {
+    let _lock = CatalogLock::acquire(catalog_path)?;
+    transfer_proposals(catalog_path).retain(|proposal| proposal.file_id != file_id)
+}
+
+/// Drops the offers that lapsed and returns the rest. Call it only while holding the
+/// catalog lock.
+fn prune(catalog_path: &Path) -> Result<Vec<TransferProposal>, FileError> {
+    let now = Utc::now().naive_utc();
+    let proposals = transfer_proposals(catalog_path);
+    proposals.retain(|proposal| proposal.expires > now)?;
+    proposals.list()
+}
@@ -0,0 +1,423 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::JournalOp;
+
+pub mod node;
+
+pub use node::{LocalTransport, RaftConfig, RaftNode, RaftStatus, TcpTransport, Transport};
+pub(crate) use node::active_node;
+
+pub type NodeId = u64;
+
+/// Ticks between two heartbeats of the leader.
+pub const HEARTBEAT_TICKS: u32 = 2;
+
+/// Fewest ticks a follower waits without hearing from a leader before it stands for
+/// election; each wait is picked at random between this and twice as many.
+pub const ELECTION_TICKS: u32 = 10;
+
+/// Most log entries sent in one `AppendEntries`.
+const MAX_ENTRIES_PER_MESSAGE: usize = 64;
+
+/// A catalog change submitted to the cluster, with the content it stores.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Proposal {
+    pub op: JournalOp,
+    pub content: Option<Vec<u8>>,
+}
+
+/// A log entry. A leader starts its term with an entry without a proposal, so that the
+/// entries left by earlier terms get committed without waiting for the next write.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LogEntry {
+    pub term: u64,
+    pub proposal: Option<Proposal>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Message {
+    RequestVote { term: u64, last_log_index: u64, last_log_term: u64 },
+    Vote { term: u64, granted: bool },
+    AppendEntries { term: u64, prev_log_index: u64, prev_log_term: u64, entries: Vec<LogEntry>, leader_commit: u64 },
+    AppendReply { term: u64, success: bool, match_index: u64 },
+}
+
+impl Message {
+    fn term(&self) -> u64 {
+        match self {
+            Message::RequestVote { term, .. } | Message::Vote { term, .. } | Message::AppendEntries { term, .. } | Message::AppendReply { term, .. } => *term,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// What a node must keep across restarts. Log indexes start at 1.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct HardState {
+    pub term: u64,
+    pub voted_for: Option<NodeId>,
+    pub last_applied: u64,
+}
+
+/// The Raft consensus algorithm for one node, free of I/O and clocks: the caller feeds it
+/// ticks and messages, sends what it queues and applies the entries it commits.
+pub struct RaftCore {
+    id: NodeId,
+    peers: Vec<NodeId>,
+    hard_state: HardState,
+    log: Vec<LogEntry>,
+    stable_len: usize,
+    commit_index: u64,
+    role: Role,
+    leader: Option<NodeId>,
+    votes: HashSet<NodeId>,
+    next_index: HashMap<NodeId, u64>,
+    match_index: HashMap<NodeId, u64>,
+    elapsed: u32,
+    election_timeout: u32,
+    outbox: Vec<(NodeId, Message)>,
+}
+
+impl RaftCore {
+    /// Restarts a node from what it persisted. `peers` are the other members of the cluster.
+    pub fn new(id: NodeId, peers: Vec<NodeId>, hard_state: HardState, log: Vec<LogEntry>) -> Self {
+        let commit_index = hard_state.last_applied;
+        let stable_len = log.len();
+        RaftCore {
+            id, peers: peers.into_iter().filter(|peer| *peer != id).collect(), hard_state, log, stable_len, commit_index,
+            role: Role::Follower, leader: None, votes: HashSet::new(), next_index: HashMap::new(), match_index: HashMap::new(),
+            elapsed: 0, election_timeout: random_election_timeout(), outbox: Vec::new(),
+        }
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn leader(&self) -> Option<NodeId> {
+        self.leader
+    }
+
+    pub fn term(&self) -> u64 {
+        self.hard_state.term
+    }
+
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    pub fn hard_state(&self) -> &HardState {
+        &self.hard_state
+    }
+
+    pub fn log(&self) -> &[LogEntry] {
+        &self.log
+    }
+
+    /// How many entries at the start of the log are unchanged since `mark_stable` was last
+    /// called; anything after them must be written again.
+    pub fn stable_len(&self) -> usize {
+        self.stable_len
+    }
+
+    pub fn mark_stable(&mut self) {
+        self.stable_len = self.log.len();
+    }
+
+    pub fn take_messages(&mut self) -> Vec<(NodeId, Message)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Entries that are committed but not applied yet, with their indexes.
+    pub fn committed_entries(&self) -> Vec<(u64, LogEntry)> {
+        (self.hard_state.last_applied + 1..=self.commit_index).map(|index| (index, self.log[index as usize - 1].clone())).collect()
+    }
+
+    pub fn mark_applied(&mut self, index: u64) {
+        self.hard_state.last_applied = self.hard_state.last_applied.max(index.min(self.commit_index));
+    }
+
+    pub fn entry_term(&self, index: u64) -> Option<u64> {
+        index.checked_sub(1).and_then(|position| self.log.get(position as usize)).map(|entry| entry.term)
+    }
+
+    pub fn tick(&mut self) {
+        self.elapsed += 1;
+        match self.role {
+            Role::Leader if self.elapsed >= HEARTBEAT_TICKS => {
+                self.elapsed = 0;
+                self.broadcast_append();
+            },
+            Role::Follower | Role::Candidate if self.elapsed >= self.election_timeout => self.start_election(),
+            _ => {},
+        }
+    }
+
+    /// Appends a proposal to the log of the leader. Returns its index and term, or the
+    /// leader this node knows of when it is not the leader itself.
+    pub fn propose(&mut self, proposal: Proposal) -> Result<(u64, u64), Option<NodeId>> {
+        if self.role != Role::Leader {
+            return Err(self.leader);
+        }
+        self.log.push(LogEntry { term: self.hard_state.term, proposal: Some(proposal) });
+        self.advance_commit();
+        self.broadcast_append();
+        Ok((self.last_log_index(), self.hard_state.term))
+    }
+
+    pub fn step(&mut self, from: NodeId, message: Message) {
+        if message.term() > self.hard_state.term {
+            self.become_follower(message.term(), None);
+        }
+        let term = self.hard_state.term;
+        match message {
+            Message::RequestVote { term: candidate_term, last_log_index, last_log_term } => {
+                let up_to_date = (last_log_term, last_log_index) >= (self.last_log_term(), self.last_log_index());
+                let granted = candidate_term == term && up_to_date && self.hard_state.voted_for.is_none_or(|voted_for| voted_for == from);
+                if granted {
+                    self.hard_state.voted_for = Some(from);
+                    self.elapsed = 0;
+                }
+                self.outbox.push((from, Message::Vote { term, granted }));
+            },
+            Message::Vote { term: vote_term, granted } => {
+                if self.role == Role::Candidate && vote_term == term && granted {
+                    self.votes.insert(from);
+                    if self.has_quorum(self.votes.len()) {
+                        self.become_leader();
+                    }
+                }
+            },
+            Message::AppendEntries { term: leader_term, prev_log_index, prev_log_term, entries, leader_commit } => {
+                if leader_term < term {
+                    self.outbox.push((from, Message::AppendReply { term, success: false, match_index: 0 }));
+                    return;
+                }
+                self.role = Role::Follower;
+                self.leader = Some(from);
+                self.elapsed = 0;
+                if prev_log_index > self.last_log_index() || (prev_log_index > 0 && self.entry_term(prev_log_index) != Some(prev_log_term)) {
+                    let hint = self.last_log_index().min(prev_log_index.saturating_sub(1));
+                    self.outbox.push((from, Message::AppendReply { term, success: false, match_index: hint }));
+                    return;
+                }
+                for (offset, entry) in entries.iter().enumerate() {
+                    let index = prev_log_index + 1 + offset as u64;
+                    match self.entry_term(index) {
+                        Some(existing) if existing == entry.term => continue,
+                        Some(_) => {
+                            self.log.truncate(index as usize - 1);
+                            self.stable_len = self.stable_len.min(self.log.len());
+                        },
+                        None => {},
+                    }
+                    self.log.push(entry.clone());
+                }
+                let match_index = prev_log_index + entries.len() as u64;
+                if leader_commit > self.commit_index {
+                    self.commit_index = leader_commit.min(match_index).max(self.commit_index);
+                }
+                self.outbox.push((from, Message::AppendReply { term, success: true, match_index }));
+            },
+            Message::AppendReply { term: reply_term, success, match_index } => {
+                if self.role != Role::Leader || reply_term != term {
+                    return;
+                }
+                if success {
+                    let matched = self.match_index.entry(from).or_insert(0);
+                    *matched = (*matched).max(match_index);
+                    self.next_index.insert(from, *matched + 1);
+                    self.advance_commit();
+                } else {
+                    let next = self.next_index.get(&from).copied().unwrap_or(1);
+                    self.next_index.insert(from, (match_index + 1).min(next.saturating_sub(1)).max(1));
+                    self.send_append(from);
+                }
+            },
+        }
+    }
+
+    fn start_election(&mut self) {
+        self.hard_state.term += 1;
+        self.hard_state.voted_for = Some(self.id);
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.votes = HashSet::from([self.id]);
+        self.elapsed = 0;
+        self.election_timeout = random_election_timeout();
+        if self.has_quorum(self.votes.len()) {
+            self.become_leader();
+            return;
+        }
+        let (term, last_log_index, last_log_term) = (self.hard_state.term, self.last_log_index(), self.last_log_term());
+        for peer in &self.peers {
+            self.outbox.push((*peer, Message::RequestVote { term, last_log_index, last_log_term }));
+        }
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<NodeId>) {
+        self.hard_state.term = term;
+        self.hard_state.voted_for = None;
+        self.role = Role::Follower;
+        self.leader = leader;
+        self.elapsed = 0;
+    }
+
+    fn become_leader(&mut self) {
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        self.elapsed = 0;
+        self.log.push(LogEntry { term: self.hard_state.term, proposal: None });
+        let next = self.last_log_index();
+        self.next_index = self.peers.iter().map(|peer| (*peer, next)).collect();
+        self.match_index = self.peers.iter().map(|peer| (*peer, 0)).collect();
+        self.advance_commit();
+        self.broadcast_append();
+    }
+
+    fn broadcast_append(&mut self) {
+        for peer in self.peers.clone() {
+            self.send_append(peer);
+        }
+    }
+
+    fn send_append(&mut self, peer: NodeId) {
+        let next = self.next_index.get(&peer).copied().unwrap_or(1).max(1);
+        let prev_log_index = next - 1;
+        let prev_log_term = self.entry_term(prev_log_index).unwrap_or(0);
+        let entries = self.log[prev_log_index as usize..].iter().take(MAX_ENTRIES_PER_MESSAGE).cloned().collect();
+        self.outbox.push((peer, Message::AppendEntries { term: self.hard_state.term, prev_log_index, prev_log_term, entries, leader_commit: self.commit_index }));
+    }
+
+    /// Commits the latest entry of the current term that a majority has stored.
+    fn advance_commit(&mut self) {
+        for index in (self.commit_index + 1..=self.last_log_index()).rev() {
+            if self.entry_term(index) != Some(self.hard_state.term) {
+                break;
+            }
+            let stored = 1 + self.match_index.values().filter(|matched| **matched >= index).count();
+            if self.has_quorum(stored) {
+                self.commit_index = index;
+                break;
+            }
+        }
+    }
+
+    fn has_quorum(&self, count: usize) -> bool {
+        count * 2 > self.peers.len() + 1
+    }
+
+    fn last_log_index(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    fn last_log_term(&self) -> u64 {
+        self.log.last().map_or(0, |entry| entry.term)
+    }
+}
+
+fn random_election_timeout() -> u32 {
+    rand::thread_rng().gen_range(ELECTION_TICKS..ELECTION_TICKS * 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster(size: u64) -> Vec<RaftCore> {
+        let ids: Vec<NodeId> = (1..=size).collect();
+        ids.iter().map(|id| RaftCore::new(*id, ids.clone(), HardState::default(), Vec::new())).collect()
+    }
+
+    /// Delivers queued messages until none are left, dropping those to or from `down`.
+    fn deliver(nodes: &mut [RaftCore], down: &[NodeId]) {
+        loop {
+            let mut messages = Vec::new();
+            for node in nodes.iter_mut() {
+                messages.extend(node.take_messages().into_iter().map(|(to, message)| (node.id(), to, message)));
+            }
+            if messages.is_empty() {
+                return;
+            }
+            for (from, to, message) in messages {
+                if !down.contains(&from) && !down.contains(&to) {
+                    nodes[to as usize - 1].step(from, message);
+                }
+            }
+        }
+    }
+
+    fn run_until_leader(nodes: &mut [RaftCore], down: &[NodeId]) -> NodeId {
+        for _ in 0..200 {
+            for node in nodes.iter_mut().filter(|node| !down.contains(&node.id())) {
+                node.tick();
+            }
+            deliver(nodes, down);
+            let leaders: Vec<NodeId> = nodes.iter().filter(|node| node.role() == Role::Leader && !down.contains(&node.id())).map(RaftCore::id).collect();
+            if leaders.len() == 1 {
+                return leaders[0];
+            }
+        }
+        panic!("No leader was elected");
+    }
+
+    fn proposal(file_id: i64) -> Proposal {
+        Proposal { op: JournalOp::Remove { file_id, deletion: None }, content: None }
+    }
+
+    #[test]
+    fn test_leader_replicates_and_commits_on_majority() {
+        let mut nodes = cluster(3);
+        let leader = run_until_leader(&mut nodes, &[]);
+        let follower = nodes.iter().map(RaftCore::id).find(|id| *id != leader).unwrap();
+        assert_eq!(nodes[follower as usize - 1].propose(proposal(1)), Err(Some(leader)));
+        let (index, _) = nodes[leader as usize - 1].propose(proposal(1)).unwrap();
+        deliver(&mut nodes, &[]);
+        nodes[leader as usize - 1].tick();
+        nodes[leader as usize - 1].tick();
+        deliver(&mut nodes, &[]);
+        for node in &nodes {
+            assert_eq!(node.commit_index(), index);
+            assert_eq!(node.committed_entries().last().and_then(|(_, entry)| entry.proposal.clone()), Some(proposal(1)));
+        }
+    }
+
+    #[test]
+    fn test_new_leader_after_failure_overwrites_uncommitted_entries() {
+        let mut nodes = cluster(5);
+        let first = run_until_leader(&mut nodes, &[]);
+        nodes[first as usize - 1].propose(proposal(1)).unwrap();
+        nodes[first as usize - 1].take_messages();
+        let second = run_until_leader(&mut nodes, &[first]);
+        assert_ne!(second, first);
+        let (index, term) = nodes[second as usize - 1].propose(proposal(2)).unwrap();
+        deliver(&mut nodes, &[first]);
+        for _ in 0..HEARTBEAT_TICKS {
+            nodes[second as usize - 1].tick();
+        }
+        deliver(&mut nodes, &[]);
+        for _ in 0..HEARTBEAT_TICKS {
+            nodes[second as usize - 1].tick();
+        }
+        deliver(&mut nodes, &[]);
+        assert_eq!(nodes[first as usize - 1].role(), Role::Follower);
+        for node in &nodes {
+            assert_eq!(node.entry_term(index), Some(term));
+            assert!(node.commit_index() >= index, "Node {} did not commit", node.id());
+            assert!(!node.log().iter().any(|entry| entry.proposal == Some(proposal(1))), "Node {} kept the uncommitted entry", node.id());
+        }
+    }
+}
@@ -0,0 +1,881 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::catalog::journal::{decode_records, encode_record};
+use crate::catalog::write_atomically;
+use crate::model::FileError;
+use crate::node::protocol::{read_message, read_message_within, write_message, MAX_HANDSHAKE_LEN};
+use crate::raft::{HardState, LogEntry, Message, NodeId, Proposal, RaftCore, Role};
+
+const DEFAULT_TICK: Duration = Duration::from_millis(50);
+
+/// How long a write waits for the cluster to apply it.
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Shortest cluster key accepted from `UNICHAIN_RAFT_KEY`.
+const MIN_CLUSTER_KEY_LEN: usize = 16;
+
+type HmacSha256 = Hmac<Sha256>;
+
+static ACTIVE_NODE: Mutex<Option<Weak<Shared>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaftConfig {
+    pub id: NodeId,
+    pub members: Vec<(NodeId, String)>,
+    pub tick: Duration,
+    /// Secret shared by every member, with which members prove to each other that they
+    /// belong to the cluster.
+    pub key: Vec<u8>,
+}
+
+impl RaftConfig {
+    /// Reads the cluster settings from `UNICHAIN_RAFT_ID`, `UNICHAIN_RAFT_MEMBERS`
+    /// (comma-separated `id=address` pairs, this node included), `UNICHAIN_RAFT_KEY` and
+    /// `UNICHAIN_RAFT_TICK_MS`. Returns `None` when no node id is set.
+    pub fn from_env() -> Result<Option<Self>, FileError> {
+        let id = match env::var("UNICHAIN_RAFT_ID").ok().filter(|id| !id.trim().is_empty()) {
+            Some(id) => id.trim().parse().map_err(|_| FileError::InputError(format!("Invalid Raft node id '{}'", id)))?,
+            None => return Ok(None),
+        };
+        let mut members = Vec::new();
+        for member in env::var("UNICHAIN_RAFT_MEMBERS").unwrap_or_default().split(',').map(str::trim).filter(|member| !member.is_empty()) {
+            let (member_id, address) = member.split_once('=')
+                .and_then(|(member_id, address)| Some((member_id.trim().parse().ok()?, address.trim().to_string())))
+                .ok_or_else(|| FileError::InputError(format!("Invalid Raft member '{}', expected id=address", member)))?;
+            members.push((member_id, address));
+        }
+        if !members.iter().any(|(member_id, _)| *member_id == id) {
+            return Err(FileError::InputError(format!("Raft node {} is not among UNICHAIN_RAFT_MEMBERS", id)));
+        }
+        let tick = env::var("UNICHAIN_RAFT_TICK_MS").ok()
+            .and_then(|millis| millis.trim().parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_TICK);
+        let key = env::var("UNICHAIN_RAFT_KEY").unwrap_or_default().trim().as_bytes().to_vec();
+        if key.len() < MIN_CLUSTER_KEY_LEN {
+            return Err(FileError::InputError(format!("UNICHAIN_RAFT_KEY must hold a key of at least {} characters shared by every member", MIN_CLUSTER_KEY_LEN)));
+        }
+        Ok(Some(RaftConfig { id, members, tick, key }))
+    }
+
+    pub fn listen_address(&self) -> Option<&str> {
+        self.members.iter().find(|(id, _)| *id == self.id).map(|(_, address)| address.as_str())
+    }
+}
+
+/// Carries Raft messages between the members of a cluster.
+pub trait Transport: Send + Sync {
+    /// Delivers a message, or drops it when the peer cannot be reached; Raft sends again.
+    fn send(&self, from: NodeId, to: NodeId, message: Message);
+
+    /// Hands a write to the leader and waits until the leader applied it. Returns its log index.
+    fn forward(&self, to: NodeId, proposal: Proposal) -> Result<u64, FileError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaftStatus {
+    pub role: Role,
+    pub leader: Option<NodeId>,
+    pub term: u64,
+    pub applied: u64,
+}
+
+enum Input {
+    Message(NodeId, Message),
+    Propose(Proposal, Sender<Result<u64, FileError>>),
+}
+
+struct Shared {
+    id: NodeId,
+    inbox: Sender<Input>,
+    transport: Arc<dyn Transport>,
+    status: Mutex<RaftStatus>,
+    changed: Condvar,
+    tick: Duration,
+}
+
+/// One member of a Raft cluster that replicates catalog changes. Every member applies the
+/// committed changes to its own catalog, so reads are served locally on any member, while
+/// writes go through the leader. The member stops once every handle to it is dropped.
+#[derive(Clone)]
+pub struct RaftNode {
+    shared: Arc<Shared>,
+}
+
+impl RaftNode {
+    /// Starts the member `id` of the cluster made of `members` on the catalog at `catalog_path`,
+    /// picking up the log it persisted next to the catalog.
+    pub fn start(catalog_path: &Path, id: NodeId, members: &[NodeId], transport: Arc<dyn Transport>, tick: Duration) -> Result<Self, FileError> {
+        if !catalog_path.exists() {
+            OpenOptions::new().create(true).truncate(false).write(true).open(catalog_path)?;
+        }
+        let (storage, hard_state, log) = RaftStorage::load(catalog_path)?;
+        let core = RaftCore::new(id, members.to_vec(), hard_state, log);
+        let (inbox, receiver) = mpsc::channel();
+        let status = RaftStatus { role: core.role(), leader: core.leader(), term: core.term(), applied: core.hard_state().last_applied };
+        let shared = Arc::new(Shared { id, inbox, transport, status: Mutex::new(status), changed: Condvar::new(), tick });
+        let (weak, path) = (Arc::downgrade(&shared), catalog_path.to_path_buf());
+        thread::spawn(move || run(weak, receiver, core, storage, path));
+        Ok(RaftNode { shared })
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.shared.id
+    }
+
+    pub fn status(&self) -> RaftStatus {
+        *self.shared.status.lock().unwrap()
+    }
+
+    /// Replicates a catalog change and waits until this member applied it, forwarding it
+    /// to the leader when this member is a follower.
+    pub fn submit(&self, proposal: Proposal) -> Result<u64, FileError> {
+        self.shared.submit(proposal)
+    }
+
+    /// Makes the library functions that change the catalog go through this member.
+    pub fn activate(&self) {
+        *ACTIVE_NODE.lock().unwrap() = Some(Arc::downgrade(&self.shared));
+    }
+}
+
+/// The member the library writes through, if one is activated and still running.
+pub(crate) fn active_node() -> Option<RaftNode> {
+    ACTIVE_NODE.lock().unwrap().as_ref().and_then(Weak::upgrade).map(|shared| RaftNode { shared })
+}
+
+impl Shared {
+    fn deliver(&self, from: NodeId, message: Message) {
+        let _ = self.inbox.send(Input::Message(from, message));
+    }
+
+    fn submit(&self, proposal: Proposal) -> Result<u64, FileError> {
+        let deadline = Instant::now() + SUBMIT_TIMEOUT;
+        loop {
+            let leader = self.status.lock().unwrap().leader;
+            match leader {
+                Some(leader) if leader == self.id => {
+                    let (reply, result) = mpsc::channel();
+                    self.inbox.send(Input::Propose(proposal, reply)).map_err(|_| stopped())?;
+                    return result.recv_timeout(deadline.saturating_duration_since(Instant::now())).unwrap_or_else(|_| Err(timed_out()));
+                },
+                Some(leader) => {
+                    let index = self.transport.forward(leader, proposal)?;
+                    self.wait_applied(index, deadline)?;
+                    return Ok(index);
+                },
+                None if Instant::now() >= deadline => return Err(FileError::PeerError("No Raft leader was elected".to_string())),
+                None => thread::sleep(self.tick),
+            }
+        }
+    }
+
+    fn wait_applied(&self, index: u64, deadline: Instant) -> Result<(), FileError> {
+        let mut status = self.status.lock().unwrap();
+        while status.applied < index {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(timed_out());
+            }
+            status = self.changed.wait_timeout(status, remaining).unwrap().0;
+        }
+        Ok(())
+    }
+}
+
+fn stopped() -> FileError {
+    FileError::PeerError("The Raft node stopped".to_string())
+}
+
+fn timed_out() -> FileError {
+    FileError::PeerError("Timed out waiting for the Raft cluster to apply the change".to_string())
+}
+
+/// Drives the member: feeds ticks and messages to Raft, persists its state before sending
+/// anything, and applies committed entries to the catalog.
+fn run(weak: Weak<Shared>, receiver: Receiver<Input>, mut core: RaftCore, mut storage: RaftStorage, catalog_path: PathBuf) {
+    let mut waiting: HashMap<u64, (u64, Sender<Result<u64, FileError>>)> = HashMap::new();
+    let mut next_tick = Instant::now();
+    loop {
+        let Some(shared) = weak.upgrade() else { return };
+        match receiver.recv_timeout(next_tick.saturating_duration_since(Instant::now())) {
+            Ok(Input::Message(from, message)) => core.step(from, message),
+            Ok(Input::Propose(proposal, reply)) => match core.propose(proposal) {
+                Ok((index, term)) => {
+                    waiting.insert(index, (term, reply));
+                },
+                Err(_) => {
+                    let _ = reply.send(Err(FileError::PeerError("This Raft node is no longer the leader".to_string())));
+                },
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                core.tick();
+                next_tick = Instant::now() + shared.tick;
+            },
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        if let Err(e) = storage.persist(&mut core) {
+            warn!("Raft node {} stopped, its state could not be saved: {}", shared.id, e);
+            return;
+        }
+        for (to, message) in core.take_messages() {
+            shared.transport.send(shared.id, to, message);
+        }
+        let mut replies = Vec::new();
+        for (index, entry) in core.committed_entries() {
+            let result = match &entry.proposal {
+                Some(proposal) => crate::apply_journaled(&catalog_path, &proposal.op, proposal.content.as_deref()),
+                None => Ok(()),
+            };
+            if let Err(e @ (FileError::RepositoryLocked(_) | FileError::IOError(_))) = &result {
+                warn!("Raft node {} will retry applying entry {}: {}", shared.id, index, e);
+                break;
+            }
+            core.mark_applied(index);
+            if let Some((term, reply)) = waiting.remove(&index) {
+                let outcome = match term == entry.term {
+                    true => result.map(|_| index),
+                    false => Err(FileError::PeerError("The change was dropped by a new Raft leader".to_string())),
+                };
+                replies.push((reply, outcome));
+            }
+        }
+        if let Err(e) = storage.persist(&mut core) {
+            warn!("Raft node {} stopped, its state could not be saved: {}", shared.id, e);
+            return;
+        }
+        for (reply, outcome) in replies {
+            let _ = reply.send(outcome);
+        }
+        let status = RaftStatus { role: core.role(), leader: core.leader(), term: core.term(), applied: core.hard_state().last_applied };
+        let mut current = shared.status.lock().unwrap();
+        if *current != status {
+            if current.leader != status.leader && status.leader == Some(shared.id) {
+                info!("Raft node {} became the leader for term {}.", shared.id, status.term);
+            }
+            *current = status;
+            shared.changed.notify_all();
+        }
+    }
+}
+
+/// The Raft state of a member, kept next to the catalog: the term and vote in `.raft`,
+/// the log in `.raft-log`, both encrypted whenever the catalog is.
+struct RaftStorage {
+    catalog_path: PathBuf,
+    state_path: PathBuf,
+    log_path: PathBuf,
+    saved_state: HardState,
+    saved_len: usize,
+}
+
+impl RaftStorage {
+    fn load(catalog_path: &Path) -> Result<(Self, HardState, Vec<LogEntry>), FileError> {
+        let (state_path, log_path) = (get_raft_path(catalog_path, ".raft"), get_raft_path(catalog_path, ".raft-log"));
+        let hard_state = read_records::<HardState>(catalog_path, &state_path)?.pop().unwrap_or_default();
+        let log = read_records::<LogEntry>(catalog_path, &log_path)?;
+        let storage = RaftStorage { catalog_path: catalog_path.to_path_buf(), state_path, log_path, saved_state: hard_state.clone(), saved_len: log.len() };
+        Ok((storage, hard_state, log))
+    }
+
+    fn persist(&mut self, core: &mut RaftCore) -> Result<(), FileError> {
+        let log = core.log();
+        if core.stable_len() < self.saved_len {
+            let mut encoded = Vec::new();
+            for entry in log {
+                encoded.extend(encode_record(&self.catalog_path, entry)?);
+            }
+            write_atomically(&self.log_path, &encoded)?;
+        } else if log.len() > self.saved_len {
+            let mut file = OpenOptions::new().create(true).append(true).open(&self.log_path)?;
+            for entry in &log[self.saved_len..] {
+                file.write_all(&encode_record(&self.catalog_path, entry)?)?;
+            }
+            file.sync_all()?;
+        }
+        self.saved_len = log.len();
+        core.mark_stable();
+        if *core.hard_state() != self.saved_state {
+            write_atomically(&self.state_path, &encode_record(&self.catalog_path, core.hard_state())?)?;
+            self.saved_state = core.hard_state().clone();
+        }
+        Ok(())
+    }
+}
+
+/// Reads the records of a file, cutting off a record torn by a crash.
+fn read_records<T: serde::de::DeserializeOwned>(catalog_path: &Path, path: &Path) -> Result<Vec<T>, FileError> {
+    let encoded = match fs::read(path) {
+        Ok(encoded) => encoded,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(FileError::IOError(e)),
+    };
+    let (records, valid_len) = decode_records(catalog_path, &encoded)?;
+    if valid_len < encoded.len() {
+        OpenOptions::new().write(true).open(path)?.set_len(valid_len as u64)?;
+    }
+    Ok(records)
+}
+
+pub fn get_raft_path(catalog_path: &Path, suffix: &str) -> PathBuf {
+    let mut name = catalog_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(suffix);
+    catalog_path.with_file_name(name)
+}
+
+/// Connects the members of a cluster that run in the same process, which can be cut off
+/// from each other to simulate failures.
+#[derive(Default)]
+pub struct LocalTransport {
+    nodes: Mutex<HashMap<NodeId, Weak<Shared>>>,
+    disconnected: Mutex<HashSet<NodeId>>,
+}
+
+impl LocalTransport {
+    pub fn new() -> Arc<Self> {
+        Arc::new(LocalTransport::default())
+    }
+
+    pub fn register(&self, node: &RaftNode) {
+        self.nodes.lock().unwrap().insert(node.id(), Arc::downgrade(&node.shared));
+    }
+
+    pub fn disconnect(&self, id: NodeId) {
+        self.disconnected.lock().unwrap().insert(id);
+    }
+
+    pub fn reconnect(&self, id: NodeId) {
+        self.disconnected.lock().unwrap().remove(&id);
+    }
+
+    fn reachable(&self, from: NodeId, to: NodeId) -> Option<Arc<Shared>> {
+        let disconnected = self.disconnected.lock().unwrap();
+        if disconnected.contains(&from) || disconnected.contains(&to) {
+            return None;
+        }
+        self.nodes.lock().unwrap().get(&to).and_then(Weak::upgrade)
+    }
+}
+
+impl Transport for LocalTransport {
+    fn send(&self, from: NodeId, to: NodeId, message: Message) {
+        if let Some(node) = self.reachable(from, to) {
+            node.deliver(from, message);
+        }
+    }
+
+    fn forward(&self, to: NodeId, proposal: Proposal) -> Result<u64, FileError> {
+        let node = self.nodes.lock().unwrap().get(&to).and_then(Weak::upgrade);
+        match node {
+            Some(node) if !self.disconnected.lock().unwrap().contains(&to) => node.submit(proposal),
+            _ => Err(FileError::PeerError(format!("Raft node {} is unreachable", to))),
+        }
+    }
+}
+
+/// Every connection opens with `Hello` and `Prove`, through which each side shows it holds
+/// the cluster key by answering a challenge from the other. The sender of the messages on a
+/// connection is the member that proved itself when it opened, and every message after the
+/// handshake travels in a [`SealedFrame`].
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+enum RaftRequest {
+    Message(Message),
+    Forward(Proposal),
+    Hello { from: NodeId, challenge: [u8; 32] },
+    Prove { proof: Vec<u8> },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+enum RaftResponse {
+    Applied(u64),
+    Error(String),
+    Hello { challenge: [u8; 32], proof: Vec<u8> },
+    Welcome,
+}
+
+/// A message sent after the handshake, with the MAC the session key gives it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct SealedFrame {
+    message: Vec<u8>,
+    mac: Vec<u8>,
+}
+
+/// An authenticated connection. Both sides derive its key from the cluster key and both
+/// challenges of the handshake, and MAC every frame over its direction and its place in
+/// the stream, so that a frame cannot be altered, replayed, reordered or sent back.
+struct Channel {
+    stream: TcpStream,
+    key: Vec<u8>,
+    outgoing: &'static str,
+    incoming: &'static str,
+    sent: u64,
+    received: u64,
+}
+
+impl Channel {
+    fn open(stream: TcpStream, cluster_key: &[u8], client_challenge: &[u8; 32], server_challenge: &[u8; 32], client: bool) -> Self {
+        let mut mac = HmacSha256::new_from_slice(cluster_key).expect("HMAC takes keys of any length");
+        mac.update(b"unichain-raft-session");
+        mac.update(client_challenge);
+        mac.update(server_challenge);
+        let (outgoing, incoming) = if client { ("client", "server") } else { ("server", "client") };
+        Channel { stream, key: mac.finalize().into_bytes().to_vec(), outgoing, incoming, sent: 0, received: 0 }
+    }
+
+    fn write<T: Serialize>(&mut self, message: &T) -> Result<(), FileError> {
+        let message = bincode::serialize(message).map_err(|_| FileError::DeserializationError("Message serialization failed".to_string()))?;
+        let mac = self.frame_mac(self.outgoing, self.sent, &message).finalize().into_bytes().to_vec();
+        write_message(&mut self.stream, &SealedFrame { message, mac })?;
+        self.sent += 1;
+        Ok(())
+    }
+
+    /// Receives one message, or `None` when the other side closed the connection.
+    fn read<T: DeserializeOwned>(&mut self) -> Result<Option<T>, FileError> {
+        let Some(frame) = read_message::<SealedFrame>(&mut self.stream)? else { return Ok(None) };
+        if self.frame_mac(self.incoming, self.received, &frame.message).verify_slice(&frame.mac).is_err() {
+            return Err(FileError::Unauthenticated("A Raft frame does not carry the MAC of its session".to_string()));
+        }
+        self.received += 1;
+        bincode::deserialize(&frame.message).map(Some).map_err(|_| FileError::DeserializationError("Failed to deserialize a peer message".to_string()))
+    }
+
+    fn frame_mac(&self, direction: &str, sequence: u64, message: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        mac.update(direction.as_bytes());
+        mac.update(&sequence.to_le_bytes());
+        mac.update(message);
+        mac
+    }
+}
+
+/// Connects the members of a cluster over TCP, framing messages like the ledger nodes do.
+pub struct TcpTransport {
+    id: NodeId,
+    key: Vec<u8>,
+    addresses: HashMap<NodeId, String>,
+    connections: HashMap<NodeId, Mutex<Option<Channel>>>,
+}
+
+impl TcpTransport {
+    pub fn new(config: &RaftConfig) -> Arc<Self> {
+        Arc::new(TcpTransport {
+            id: config.id,
+            key: config.key.clone(),
+            addresses: config.members.iter().cloned().collect(),
+            connections: config.members.iter().map(|(id, _)| (*id, Mutex::new(None))).collect(),
+        })
+    }
+
+    /// Accepts messages and forwarded writes for `node` from the other members until it stops.
+    pub fn serve(self: &Arc<Self>, listener: TcpListener, node: &RaftNode) {
+        let (weak, transport) = (Arc::downgrade(&node.shared), Arc::clone(self));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if weak.strong_count() == 0 {
+                    break;
+                }
+                match stream {
+                    Ok(stream) => {
+                        let (weak, transport) = (weak.clone(), transport.clone());
+                        thread::spawn(move || serve_connection(weak, &transport, stream));
+                    },
+                    Err(e) => warn!("Failed to accept a Raft connection: {}", e),
+                }
+            }
+        });
+    }
+
+    fn connect(&self, to: NodeId) -> Result<Channel, FileError> {
+        let address = self.addresses.get(&to).ok_or_else(|| FileError::PeerError(format!("Unknown Raft node {}", to)))?;
+        let socket = address.to_socket_addrs()?.next().ok_or_else(|| FileError::PeerError(format!("Cannot resolve {}", address)))?;
+        let stream = TcpStream::connect_timeout(&socket, CONNECT_TIMEOUT)?;
+        self.open_handshake(stream, to)
+    }
+
+    fn open_handshake(&self, mut stream: TcpStream, to: NodeId) -> Result<Channel, FileError> {
+        let challenge: [u8; 32] = rand::random();
+        write_message(&mut stream, &RaftRequest::Hello { from: self.id, challenge })?;
+        let server_challenge = match read_message_within(&mut stream, MAX_HANDSHAKE_LEN)? {
+            Some(RaftResponse::Hello { challenge: server_challenge, proof }) if handshake_mac(&self.key, "server", to, &challenge).verify_slice(&proof).is_ok() => server_challenge,
+            Some(RaftResponse::Error(message)) => return Err(FileError::PeerError(message)),
+            _ => return Err(FileError::PeerError(format!("Raft node {} failed to prove it holds the cluster key", to))),
+        };
+        let proof = handshake_mac(&self.key, "client", self.id, &server_challenge).finalize().into_bytes().to_vec();
+        write_message(&mut stream, &RaftRequest::Prove { proof })?;
+        match read_message_within(&mut stream, MAX_HANDSHAKE_LEN)? {
+            Some(RaftResponse::Welcome) => Ok(Channel::open(stream, &self.key, &challenge, &server_challenge, true)),
+            Some(RaftResponse::Error(message)) => Err(FileError::PeerError(message)),
+            _ => Err(FileError::PeerError(format!("Raft node {} did not accept the connection", to))),
+        }
+    }
+
+    /// Answers the opening of a connection, and returns the member that proved it holds
+    /// the cluster key with the channel to it.
+    fn accept_handshake(&self, mut stream: TcpStream) -> Result<(NodeId, Channel), FileError> {
+        let (from, challenge) = match read_message_within(&mut stream, MAX_HANDSHAKE_LEN)? {
+            Some(RaftRequest::Hello { from, challenge }) => (from, challenge),
+            _ => return Err(FileError::Unauthenticated("The peer did not open with its member id".to_string())),
+        };
+        if !self.addresses.contains_key(&from) {
+            return Err(FileError::Unauthenticated(format!("Raft node {} is not a member of the cluster", from)));
+        }
+        let own_challenge: [u8; 32] = rand::random();
+        let proof = handshake_mac(&self.key, "server", self.id, &challenge).finalize().into_bytes().to_vec();
+        write_message(&mut stream, &RaftResponse::Hello { challenge: own_challenge, proof })?;
+        match read_message_within(&mut stream, MAX_HANDSHAKE_LEN)? {
+            Some(RaftRequest::Prove { proof }) if handshake_mac(&self.key, "client", from, &own_challenge).verify_slice(&proof).is_ok() => {},
+            _ => return Err(FileError::Unauthenticated(format!("Raft node {} failed to prove it holds the cluster key", from))),
+        }
+        write_message(&mut stream, &RaftResponse::Welcome)?;
+        Ok((from, Channel::open(stream, &self.key, &challenge, &own_challenge, false)))
+    }
+}
+
+/// The MAC a member answers a challenge with. It covers the id of the member and the side
+/// of the connection it is on, so that no other member or side can replay it.
+fn handshake_mac(key: &[u8], role: &str, id: NodeId, challenge: &[u8; 32]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(b"unichain-raft");
+    mac.update(role.as_bytes());
+    mac.update(&id.to_le_bytes());
+    mac.update(challenge);
+    mac
+}
+
+impl Transport for TcpTransport {
+    fn send(&self, _from: NodeId, to: NodeId, message: Message) {
+        let Some(connection) = self.connections.get(&to) else { return };
+        let mut connection = connection.lock().unwrap();
+        if connection.is_none() {
+            *connection = self.connect(to).ok();
+        }
+        if let Some(channel) = connection.as_mut() {
+            if channel.write(&RaftRequest::Message(message)).is_err() {
+                *connection = None;
+            }
+        }
+    }
+
+    fn forward(&self, to: NodeId, proposal: Proposal) -> Result<u64, FileError> {
+        let mut channel = self.connect(to)?;
+        channel.write(&RaftRequest::Forward(proposal))?;
+        match channel.read()? {
+            Some(RaftResponse::Applied(index)) => Ok(index),
+            Some(RaftResponse::Error(message)) => Err(FileError::PeerError(message)),
+            Some(RaftResponse::Hello { .. } | RaftResponse::Welcome) => Err(FileError::PeerError("The Raft leader answered out of turn".to_string())),
+            None => Err(FileError::PeerError("The Raft leader closed the connection".to_string())),
+        }
+    }
+}
+
+fn serve_connection(weak: Weak<Shared>, transport: &TcpTransport, stream: TcpStream) {
+    let Ok(mut refusal) = stream.try_clone() else { return };
+    let (from, mut channel) = match transport.accept_handshake(stream) {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            warn!("Refused a Raft connection: {}", e);
+            let _ = write_message(&mut refusal, &RaftResponse::Error(e.to_string()));
+            return;
+        },
+    };
+    loop {
+        let request = match channel.read::<RaftRequest>() {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Dropped a Raft connection: {}", e);
+                return;
+            },
+        };
+        let Some(shared) = weak.upgrade() else { return };
+        match request {
+            RaftRequest::Message(message) => shared.deliver(from, message),
+            RaftRequest::Forward(proposal) => {
+                let response = shared.submit(proposal).map_or_else(|e| RaftResponse::Error(e.to_string()), RaftResponse::Applied);
+                if let Err(e) = channel.write(&response) {
+                    warn!("Failed to answer a forwarded Raft write: {}", e);
+                    return;
+                }
+            },
+            RaftRequest::Hello { .. } | RaftRequest::Prove { .. } => {
+                warn!("Raft node {} opened a handshake on an open connection", from);
+                return;
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+
+    use tempfile::{tempdir, TempDir};
+
+    use crate::catalog::{content_hash, BlobStore, JournalOp};
+    use crate::identity::{IdentityRegistry, LocalIdentity};
+    use crate::permissions::deletion::{DeletionApproval, DeletionPolicy, PendingDeletion};
+    use crate::permissions::transfer::{self, handed_over, OwnershipTransfer};
+    use crate::testing::TestFile;
+
+    const TEST_TICK: Duration = Duration::from_millis(5);
+
+    const TEST_KEY: &[u8] = b"raft test cluster key";
+
+    fn store(id: i64, content: &[u8]) -> Proposal {
+        Proposal { op: JournalOp::Store { file: Box::new(TestFile::new(id).build()), content_hash: Some(content_hash(content)) }, content: Some(content.to_vec()) }
+    }
+
+    fn catalog_paths(dir: &TempDir, size: u64) -> Vec<PathBuf> {
+        (1..=size).map(|id| {
+            let path = dir.path().join(format!("node{}", id)).join("assets");
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            path
+        }).collect()
+    }
+
+    fn start_local_cluster(paths: &[PathBuf], transport: &Arc<LocalTransport>) -> Vec<RaftNode> {
+        let ids: Vec<NodeId> = (1..=paths.len() as u64).collect();
+        paths.iter().zip(&ids).map(|(path, id)| {
+            let node = RaftNode::start(path, *id, &ids, transport.clone(), TEST_TICK).unwrap();
+            transport.register(&node);
+            node
+        }).collect()
+    }
+
+    fn wait_until(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !condition() {
+            assert!(Instant::now() < deadline, "Timed out waiting for the cluster");
+            thread::sleep(TEST_TICK);
+        }
+    }
+
+    fn wait_for_leader(nodes: &[RaftNode], down: &[NodeId]) -> NodeId {
+        let mut leader = None;
+        wait_until(|| {
+            let leaders: Vec<NodeId> = nodes.iter().filter(|node| !down.contains(&node.id()) && node.status().role == Role::Leader).map(RaftNode::id).collect();
+            leader = leaders.first().copied().filter(|_| leaders.len() == 1);
+            leader.is_some()
+        });
+        leader.unwrap()
+    }
+
+    #[test]
+    fn test_writes_through_a_follower_are_readable_on_every_member() {
+        let dir = tempdir().unwrap();
+        let paths = catalog_paths(&dir, 3);
+        let transport = LocalTransport::new();
+        let nodes = start_local_cluster(&paths, &transport);
+        let leader = wait_for_leader(&nodes, &[]);
+        let follower = nodes.iter().find(|node| node.id() != leader).unwrap();
+
+        follower.submit(store(1, b"content 1")).unwrap();
+        follower.submit(store(2, b"content 2")).unwrap();
+        let index = follower.submit(Proposal { op: JournalOp::Remove { file_id: 1, deletion: None }, content: None }).unwrap();
+        assert!(matches!(follower.submit(Proposal { op: JournalOp::Remove { file_id: 1, deletion: None }, content: None }), Err(FileError::FileNotFound)));
+
+        let files = crate::load_files_from_file(&paths[follower.id() as usize - 1]).unwrap();
+        assert_eq!(files.iter().map(|file| file.id).collect::<Vec<_>>(), vec![2]);
+        wait_until(|| nodes.iter().all(|node| node.status().applied >= index));
+        for path in &paths {
+            assert_eq!(crate::load_files_from_file(path).unwrap(), files);
+            assert_eq!(BlobStore::open(path).unwrap().read(2).unwrap(), Some(b"content 2".to_vec()));
+            assert_eq!(BlobStore::open(path).unwrap().read(1).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_cluster_survives_the_loss_of_its_leader() {
+        let dir = tempdir().unwrap();
+        let paths = catalog_paths(&dir, 5);
+        let transport = LocalTransport::new();
+        let nodes = start_local_cluster(&paths, &transport);
+        let first = wait_for_leader(&nodes, &[]);
+        nodes[first as usize - 1].submit(store(1, b"content 1")).unwrap();
+
+        transport.disconnect(first);
+        let second = wait_for_leader(&nodes, &[first]);
+        assert_ne!(second, first);
+        let follower = nodes.iter().find(|node| node.id() != first && node.id() != second).unwrap();
+        let index = follower.submit(store(2, b"content 2")).unwrap();
+
+        transport.reconnect(first);
+        wait_until(|| nodes.iter().all(|node| node.status().applied >= index));
+        assert_eq!(nodes[first as usize - 1].status().leader, Some(second));
+        for path in &paths {
+            assert_eq!(crate::load_files_from_file(path).unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_members_apply_only_signed_transfers() {
+        let dir = tempdir().unwrap();
+        let paths = catalog_paths(&dir, 1);
+        let node = RaftNode::start(&paths[0], 1, &[1], LocalTransport::new(), TEST_TICK).unwrap();
+        wait_for_leader(std::slice::from_ref(&node), &[]);
+        let owner = LocalIdentity::generate(1, "Username", "username@gmail.com");
+        let recipient = LocalIdentity::generate(2, "Recipient", "recipient@gmail.com");
+        let registry = IdentityRegistry::open(&paths[0]).unwrap();
+        registry.register(&owner.public).unwrap();
+        registry.register(&recipient.public).unwrap();
+        node.submit(store(1, b"content 1")).unwrap();
+
+        let file = TestFile::new(1).build();
+        let message = transfer::signing_message(1, &file.onchain_txn_id, 1, 2).unwrap();
+        let forged = OwnershipTransfer {
+            file_id: 1, transaction_id: file.onchain_txn_id.clone(), from: 1, to: 2,
+            owner_signature: hex::encode(recipient.sign(&message)), recipient_signature: hex::encode(recipient.sign(&message)),
+        };
+        let op = JournalOp::Transfer { file: Box::new(handed_over(&file, &recipient.public)), content_hash: None, transfer: Box::new(forged) };
+        assert!(matches!(node.submit(Proposal { op, content: None }), Err(FileError::IntegrityError(_))));
+        assert_eq!(crate::load_files_from_file(&paths[0]).unwrap()[0].owner.0, 1);
+    }
+
+    #[test]
+    fn test_members_apply_only_approved_removals() {
+        let dir = tempdir().unwrap();
+        let paths = catalog_paths(&dir, 1);
+        let node = RaftNode::start(&paths[0], 1, &[1], LocalTransport::new(), TEST_TICK).unwrap();
+        wait_for_leader(std::slice::from_ref(&node), &[]);
+        let owner = LocalIdentity::generate(1, "Username", "username@gmail.com");
+        let guest = LocalIdentity::generate(2, "Guest", "guest@gmail.com");
+        let registry = IdentityRegistry::open(&paths[0]).unwrap();
+        registry.register(&owner.public).unwrap();
+        registry.register(&guest.public).unwrap();
+        let mut file = TestFile::new(1).build();
+        file.people_with_access.push((2, "Guest".to_string(), "guest@gmail.com".to_string()));
+        node.submit(Proposal { op: JournalOp::Store { file: Box::new(file.clone()), content_hash: None }, content: None }).unwrap();
+        node.submit(Proposal { op: JournalOp::Policy { policy: DeletionPolicy { file_id: 1, threshold: 2 } }, content: None }).unwrap();
+
+        let removal = |approvers: &[&LocalIdentity]| {
+            let now = Utc::now().naive_utc();
+            let mut deletion = PendingDeletion {
+                file_id: 1, transaction_id: file.onchain_txn_id.clone(), threshold: 2, requested: now, expires: now, approvals: Vec::new(),
+            };
+            let message = deletion.signing_message().unwrap();
+            deletion.approvals = approvers.iter()
+                .map(|approver| DeletionApproval { identity_id: approver.id(), signature: hex::encode(approver.sign(&message)), approved: now })
+                .collect();
+            Proposal { op: JournalOp::Remove { file_id: 1, deletion: Some(Box::new(deletion)) }, content: None }
+        };
+        let unapproved = Proposal { op: JournalOp::Remove { file_id: 1, deletion: None }, content: None };
+        assert!(matches!(node.submit(unapproved), Err(FileError::IntegrityError(_))));
+        assert!(matches!(node.submit(removal(&[&owner, &owner])), Err(FileError::IntegrityError(_))));
+        assert_eq!(crate::load_files_from_file(&paths[0]).unwrap(), vec![file.clone()]);
+        node.submit(removal(&[&owner, &guest])).unwrap();
+        assert!(crate::load_files_from_file(&paths[0]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_restarted_member_keeps_its_log() {
+        let dir = tempdir().unwrap();
+        let paths = catalog_paths(&dir, 1);
+        let node = RaftNode::start(&paths[0], 1, &[1], LocalTransport::new(), TEST_TICK).unwrap();
+        wait_for_leader(std::slice::from_ref(&node), &[]);
+        let proposal = store(1, b"content 1");
+        let index = node.submit(proposal.clone()).unwrap();
+        drop(node);
+
+        let (_, hard_state, log) = RaftStorage::load(&paths[0]).unwrap();
+        assert_eq!(hard_state.last_applied, index);
+        assert_eq!(log.len() as u64, index);
+        assert_eq!(log.last().unwrap().proposal, Some(proposal));
+    }
+
+    fn start_tcp_cluster(paths: &[PathBuf]) -> (Vec<RaftNode>, Vec<(NodeId, String)>) {
+        let listeners: Vec<TcpListener> = paths.iter().map(|_| TcpListener::bind("127.0.0.1:0").unwrap()).collect();
+        let members: Vec<(NodeId, String)> = listeners.iter().enumerate().map(|(n, listener)| (n as u64 + 1, listener.local_addr().unwrap().to_string())).collect();
+        let ids: Vec<NodeId> = members.iter().map(|(id, _)| *id).collect();
+        let nodes = listeners.into_iter().zip(paths).zip(&ids).map(|((listener, path), id)| {
+            let transport = TcpTransport::new(&tcp_config(*id, &members, TEST_KEY));
+            let node = RaftNode::start(path, *id, &ids, transport.clone(), TEST_TICK).unwrap();
+            transport.serve(listener, &node);
+            node
+        }).collect();
+        (nodes, members)
+    }
+
+    fn tcp_config(id: NodeId, members: &[(NodeId, String)], key: &[u8]) -> RaftConfig {
+        RaftConfig { id, members: members.to_vec(), tick: TEST_TICK, key: key.to_vec() }
+    }
+
+    #[test]
+    fn test_members_replicate_over_tcp() {
+        let dir = tempdir().unwrap();
+        let paths = catalog_paths(&dir, 3);
+        let (nodes, _) = start_tcp_cluster(&paths);
+        let leader = wait_for_leader(&nodes, &[]);
+        let follower = nodes.iter().find(|node| node.id() != leader).unwrap();
+
+        let index = follower.submit(store(1, b"content 1")).unwrap();
+        wait_until(|| nodes.iter().all(|node| node.status().applied >= index));
+        for path in &paths {
+            assert_eq!(BlobStore::open(path).unwrap().read(1).unwrap(), Some(b"content 1".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_members_refuse_peers_without_the_cluster_key() {
+        let dir = tempdir().unwrap();
+        let paths = catalog_paths(&dir, 1);
+        let (nodes, members) = start_tcp_cluster(&paths);
+        wait_for_leader(&nodes, &[]);
+
+        let outsider = TcpTransport::new(&tcp_config(1, &members, b"another cluster key"));
+        assert!(matches!(outsider.forward(1, store(1, b"content 1")), Err(FileError::PeerError(_))));
+        let stranger = TcpTransport::new(&tcp_config(9, &members, TEST_KEY));
+        assert!(matches!(stranger.forward(1, store(2, b"content 2")), Err(FileError::PeerError(_))));
+
+        let mut stream = TcpStream::connect(&members[0].1).unwrap();
+        write_message(&mut stream, &RaftRequest::Forward(store(3, b"content 3"))).unwrap();
+        assert!(matches!(read_message::<RaftResponse>(&mut stream).unwrap(), Some(RaftResponse::Error(_))));
+        assert!(crate::load_files_from_file(&paths[0]).unwrap().is_empty());
+
+        let member = TcpTransport::new(&tcp_config(1, &members, TEST_KEY));
+        member.forward(1, store(4, b"content 4")).unwrap();
+        assert_eq!(crate::load_files_from_file(&paths[0]).unwrap().iter().map(|file| file.id).collect::<Vec<_>>(), vec![4]);
+    }
+
+    #[test]
+    fn test_members_drop_frames_without_the_session_mac() {
+        let dir = tempdir().unwrap();
+        let paths = catalog_paths(&dir, 1);
+        let (nodes, members) = start_tcp_cluster(&paths);
+        wait_for_leader(&nodes, &[]);
+        let member = TcpTransport::new(&tcp_config(1, &members, TEST_KEY));
+
+        let mut forged = member.connect(1).unwrap();
+        let message = bincode::serialize(&RaftRequest::Forward(store(1, b"content 1"))).unwrap();
+        write_message(&mut forged.stream, &SealedFrame { message, mac: vec![0; 32] }).unwrap();
+        assert!(!matches!(forged.read::<RaftResponse>(), Ok(Some(_))));
+
+        let mut replayed = member.connect(1).unwrap();
+        let message = bincode::serialize(&RaftRequest::Forward(store(2, b"content 2"))).unwrap();
+        let frame = SealedFrame { mac: replayed.frame_mac("client", 0, &message).finalize().into_bytes().to_vec(), message };
+        write_message(&mut replayed.stream, &frame).unwrap();
+        assert!(matches!(replayed.read::<RaftResponse>().unwrap(), Some(RaftResponse::Applied(_))));
+        write_message(&mut replayed.stream, &frame).unwrap();
+        assert!(!matches!(replayed.read::<RaftResponse>(), Ok(Some(_))));
+        assert_eq!(crate::load_files_from_file(&paths[0]).unwrap().iter().map(|file| file.id).collect::<Vec<_>>(), vec![2]);
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use crate::catalog::JournalOp;
+use crate::identity::{IdentityRegistry, LocalIdentity};
+use crate::ledger::{find_transaction, Block, Ledger};
+use crate::model::FileError;
+use crate::permissions::deletion::Removal;
+use crate::permissions::{authorize, Action};
+
+/// Largest JSON-RPC payload accepted, which bounds the content a transaction can store.
+pub const MAX_PAYLOAD_LEN: usize = 256 * 1024 * 1024;
+
+/// Most blocks returned by one `chain_getBlocks`.
+pub const MAX_BLOCKS_PER_CALL: usize = 500;
+
+/// How far the timestamp of a transaction may be from the node clock, in seconds.
+pub const TRANSACTION_WINDOW_SECONDS: i64 = 300;
+
+const DEFAULT_WORKERS: usize = 4;
+
+const SUBSCRIPTION_POLL: Duration = Duration::from_millis(200);
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+const NOT_ALLOWED: i64 = -32001;
+const NOT_FOUND: i64 = -32004;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcConfig {
+    pub listen: Option<String>,
+    pub socket: Option<PathBuf>,
+    pub workers: usize,
+}
+
+impl RpcConfig {
+    /// Reads the JSON-RPC settings from `UNICHAIN_RPC_LISTEN` (an HTTP address) and
+    /// `UNICHAIN_RPC_SOCKET` (a Unix socket path), with `UNICHAIN_RPC_WORKERS` HTTP calls
+    /// answered at once. Returns `None` when neither endpoint is set.
+    pub fn from_env() -> Option<Self> {
+        let listen = env::var("UNICHAIN_RPC_LISTEN").ok().map(|listen| listen.trim().to_string()).filter(|listen| !listen.is_empty());
+        let socket = env::var("UNICHAIN_RPC_SOCKET").ok().map(|socket| socket.trim().to_string()).filter(|socket| !socket.is_empty()).map(PathBuf::from);
+        let workers = env::var("UNICHAIN_RPC_WORKERS").ok()
+            .and_then(|workers| workers.trim().parse().ok())
+            .filter(|workers| *workers > 0)
+            .unwrap_or(DEFAULT_WORKERS);
+        (listen.is_some() || socket.is_some()).then_some(RpcConfig { listen, socket, workers })
+    }
+}
+
+/// A catalog change as submitted by a client, with the plain content of a new file in hex.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Transaction {
+    pub op: JournalOp,
+    pub content: Option<String>,
+    pub identity_id: i64,
+    pub timestamp: i64,
+}
+
+impl Transaction {
+    pub fn signing_message(&self) -> Result<Vec<u8>, FileError> {
+        bincode::serialize(self).map_err(|_| FileError::DeserializationError("Transaction serialization failed".to_string()))
+    }
+
+    pub fn sign(self, identity: &LocalIdentity) -> Result<SignedTransaction, FileError> {
+        let signature = hex::encode(identity.sign(&self.signing_message()?));
+        Ok(SignedTransaction { transaction: self, signature })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SignedTransaction {
+    pub transaction: Transaction,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+}
+
+impl From<FileError> for RpcError {
+    fn from(error: FileError) -> Self {
+        let code = match error {
+            FileError::FileNotFound => NOT_FOUND,
+            FileError::PermissionDenied | FileError::Unauthenticated(_) => NOT_ALLOWED,
+            _ => SERVER_ERROR,
+        };
+        RpcError::new(code, error.to_string())
+    }
+}
+
+/// Answers JSON-RPC 2.0 calls about the ledger and the catalog over HTTP, a Unix socket,
+/// or both. Queries are open to anyone who can reach the endpoints, which expose every
+/// file record in the ledger, so bind them to trusted interfaces. The endpoints stop
+/// when the server is dropped.
+pub struct RpcServer {
+    http: Option<(Arc<Server>, SocketAddr)>,
+    socket: Option<PathBuf>,
+    stop: Arc<AtomicBool>,
+}
+
+impl RpcServer {
+    pub fn start(config: &RpcConfig) -> Result<Self, FileError> {
+        let catalog_path = crate::get_path();
+        if !catalog_path.exists() {
+            OpenOptions::new().create(true).truncate(false).write(true).open(&catalog_path)?;
+        }
+        let rpc = Arc::new(Rpc { catalog_path, seen_transactions: Mutex::new(HashMap::new()), next_subscription: AtomicU64::new(1) });
+        let stop = Arc::new(AtomicBool::new(false));
+        let http = match &config.listen {
+            Some(listen) => Some(serve_http(listen, config.workers, rpc.clone())?),
+            None => None,
+        };
+        if let Some(socket) = &config.socket {
+            serve_socket(socket, rpc, stop.clone())?;
+        }
+        Ok(RpcServer { http, socket: config.socket.clone(), stop })
+    }
+
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.http.as_ref().map(|(_, addr)| *addr)
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some((server, _)) = &self.http {
+            server.unblock();
+        }
+        if let Some(socket) = &self.socket {
+            wake_socket(socket);
+            let _ = fs::remove_file(socket);
+        }
+    }
+}
+
+struct Rpc {
+    catalog_path: PathBuf,
+    seen_transactions: Mutex<HashMap<Vec<u8>, i64>>,
+    next_subscription: AtomicU64,
+}
+
+/// A connection that can receive notifications.
+struct Session {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    subscriptions: Mutex<HashMap<u64, Arc<AtomicBool>>>,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        for active in self.subscriptions.lock().unwrap().values() {
+            active.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Rpc {
+    /// Answers a request or a batch, or returns `None` when it held only notifications.
+    fn handle_payload(&self, payload: &[u8], session: Option<&Session>) -> Option<Value> {
+        let request: Value = match serde_json::from_slice(payload) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, format!("Parse error: {}", e)))),
+        };
+        match request {
+            Value::Array(batch) if batch.is_empty() => Some(error_response(Value::Null, RpcError::new(INVALID_REQUEST, "Empty batch"))),
+            Value::Array(batch) => {
+                let responses: Vec<Value> = batch.into_iter().filter_map(|request| self.handle_request(request, session)).collect();
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            },
+            request => self.handle_request(request, session),
+        }
+    }
+
+    fn handle_request(&self, request: Value, session: Option<&Session>) -> Option<Value> {
+        let id = request.get("id").cloned();
+        let method = request.get("method").and_then(Value::as_str);
+        let (Some(method), Some("2.0")) = (method, request.get("jsonrpc").and_then(Value::as_str)) else {
+            return Some(error_response(id.unwrap_or(Value::Null), RpcError::new(INVALID_REQUEST, "Invalid request")));
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        let result = self.call(method, params, session);
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => error_response(id, error),
+        })
+    }
+
+    fn call(&self, method: &str, params: Value, session: Option<&Session>) -> Result<Value, RpcError> {
+        match method {
+            "chain_getHeight" => {
+                let blocks = Ledger::read_blocks(&self.catalog_path)?;
+                Ok(json!({ "height": blocks.len(), "tip": blocks.last().map(|tip| tip.hash.clone()) }))
+            },
+            "chain_getBlock" => {
+                #[derive(Deserialize)]
+                struct Params { height: Option<u64>, hash: Option<String> }
+                let params: Params = parse_params(params)?;
+                let blocks = Ledger::read_blocks(&self.catalog_path)?;
+                let block = match (params.height, params.hash) {
+                    (Some(height), _) => blocks.iter().find(|block| block.height == height),
+                    (None, Some(hash)) => blocks.iter().find(|block| block.hash == hash),
+                    (None, None) => return Err(RpcError::new(INVALID_PARAMS, "Give a height or a hash")),
+                };
+                Ok(serde_json::to_value(block.ok_or(FileError::FileNotFound)?).map_err(internal)?)
+            },
+            "chain_getBlocks" => {
+                #[derive(Deserialize)]
+                struct Params { from_height: u64, limit: Option<usize> }
+                let params: Params = parse_params(params)?;
+                let limit = params.limit.unwrap_or(MAX_BLOCKS_PER_CALL).min(MAX_BLOCKS_PER_CALL);
+                let blocks: Vec<Block> = Ledger::read_blocks(&self.catalog_path)?.into_iter().skip(params.from_height as usize).take(limit).collect();
+                Ok(serde_json::to_value(blocks).map_err(internal)?)
+            },
+            "chain_getTransaction" => {
+                let txn_id = parse_txn_id(params)?;
+                let blocks = Ledger::read_blocks(&self.catalog_path)?;
+                let block = find_transaction(&blocks, &txn_id).ok_or(FileError::FileNotFound)?;
+                let op = block.operations.iter().find(|op| op.stored().is_some_and(|(file, _)| file.onchain_txn_id == txn_id));
+                Ok(json!({ "block_height": block.height, "block_hash": block.hash, "operation": op }))
+            },
+            "catalog_getFileByTransaction" => {
+                let txn_id = parse_txn_id(params)?;
+                let blocks = Ledger::read_blocks(&self.catalog_path)?;
+                let recorded = find_transaction(&blocks, &txn_id).and_then(|block| block.operations.iter().find_map(|op| match op.stored() {
+                    Some((file, _)) if file.onchain_txn_id == txn_id => Some(file.clone()),
+                    _ => None,
+                })).ok_or(FileError::FileNotFound)?;
+                Ok(json!(recorded))
+            },
+            "chain_submitTransaction" => self.submit_transaction(parse_params(params)?),
+            "chain_subscribe" => {
+                let session = session.ok_or_else(|| RpcError::new(SERVER_ERROR, "Subscriptions are only available over the Unix socket"))?;
+                let known = Ledger::read_blocks(&self.catalog_path)?.into_iter().map(|block| block.hash).collect();
+                let id = self.next_subscription.fetch_add(1, Ordering::SeqCst);
+                let active = Arc::new(AtomicBool::new(true));
+                session.subscriptions.lock().unwrap().insert(id, active.clone());
+                let (path, writer) = (self.catalog_path.clone(), session.writer.clone());
+                thread::spawn(move || notify_new_blocks(&path, id, known, &writer, &active));
+                Ok(json!(id))
+            },
+            "chain_unsubscribe" => {
+                let session = session.ok_or_else(|| RpcError::new(SERVER_ERROR, "Subscriptions are only available over the Unix socket"))?;
+                #[derive(Deserialize)]
+                struct Params { subscription: u64 }
+                let params: Params = parse_params(params)?;
+                let active = session.subscriptions.lock().unwrap().remove(&params.subscription);
+                active.iter().for_each(|active| active.store(false, Ordering::SeqCst));
+                Ok(json!(active.is_some()))
+            },
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+        }
+    }
+
+    /// Carries out a transaction signed by a registered identity, with the permission
+    /// checks of the menu: anyone may store a new file they own, and only the owner may
+    /// change or remove one.
+    fn submit_transaction(&self, signed: SignedTransaction) -> Result<Value, RpcError> {
+        let transaction = &signed.transaction;
+        let now = Utc::now().timestamp();
+        if (now - transaction.timestamp).abs() > TRANSACTION_WINDOW_SECONDS {
+            return Err(FileError::Unauthenticated("The transaction timestamp is too far from the node clock".to_string()).into());
+        }
+        let identity = IdentityRegistry::open(&self.catalog_path)?.get(transaction.identity_id)?
+            .ok_or_else(|| FileError::Unauthenticated(format!("Identity {} is not registered", transaction.identity_id)))?;
+        let signature = hex::decode(&signed.signature).map_err(|_| RpcError::new(INVALID_PARAMS, "The signature is not hex"))?;
+        identity.verify(&transaction.signing_message()?, &signature)
+            .map_err(|_| FileError::Unauthenticated("The signature does not match the transaction".to_string()))?;
+        let content = transaction.content.as_deref().map(hex::decode).transpose().map_err(|_| RpcError::new(INVALID_PARAMS, "The content is not hex"))?;
+        {
+            let mut seen = self.seen_transactions.lock().unwrap();
+            seen.retain(|_, seen_at| now - *seen_at <= TRANSACTION_WINDOW_SECONDS * 2);
+            if seen.insert(signature, transaction.timestamp).is_some() {
+                return Err(FileError::Unauthenticated("The transaction was already submitted".to_string()).into());
+            }
+        }
+        match &transaction.op {
+            JournalOp::Store { file, .. } => match crate::find_file(file.id) {
+                Err(FileError::FileNotFound) => {
+                    if file.owner.0 != identity.id {
+                        return Err(FileError::PermissionDenied.into());
+                    }
+                    let stored = crate::store_new_file(file.as_ref().clone(), content)?;
+                    Ok(json!({ "file_id": stored.id, "onchain_txn_id": stored.onchain_txn_id }))
+                },
+                Ok(current) => {
+                    authorize(identity.id, &current, Action::Modify)?;
+                    if content.is_some() || file.owner != current.owner {
+                        return Err(RpcError::new(INVALID_PARAMS, "Changing a file cannot replace its content or its owner"));
+                    }
+                    let stored = crate::save_modified_file(file.id, file.as_ref().clone())?;
+                    Ok(json!({ "file_id": stored.id, "onchain_txn_id": stored.onchain_txn_id }))
+                },
+                Err(e) => Err(e.into()),
+            },
+            JournalOp::Remove { file_id, .. } => {
+                authorize(identity.id, &crate::find_file(*file_id)?, Action::Remove)?;
+                match crate::remove_file(*file_id)? {
+                    Removal::Removed => Ok(json!({ "file_id": file_id })),
+                    Removal::Pending(pending) => Ok(json!({ "file_id": file_id, "pending_deletion": pending })),
+                }
+            },
+            JournalOp::Transfer { .. } => Err(RpcError::new(INVALID_PARAMS, "An ownership transfer is proposed by the owner and accepted by the recipient")),
+            JournalOp::Policy { policy } => {
+                authorize(identity.id, &crate::find_file(policy.file_id)?, Action::Remove)?;
+                crate::set_deletion_threshold(policy.file_id, policy.threshold)?;
+                Ok(json!({ "file_id": policy.file_id, "threshold": policy.threshold }))
+            },
+        }
+    }
+}
+
+/// Sends a `chain_newBlock` notification for every block that joins the chain, including
+/// the blocks of a chain that replaces part of the known one.
+fn notify_new_blocks(catalog_path: &Path, subscription: u64, mut known: Vec<String>, writer: &Mutex<Box<dyn Write + Send>>, active: &AtomicBool) {
+    while active.load(Ordering::SeqCst) {
+        thread::sleep(SUBSCRIPTION_POLL);
+        let blocks = match Ledger::read_blocks(catalog_path) {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                warn!("Subscription {} could not read the ledger: {}", subscription, e);
+                continue;
+            },
+        };
+        let fork = known.iter().zip(&blocks).take_while(|(hash, block)| **hash == block.hash).count();
+        for block in &blocks[fork..] {
+            let notification = json!({ "jsonrpc": "2.0", "method": "chain_newBlock", "params": { "subscription": subscription, "result": block } });
+            let mut writer = writer.lock().unwrap();
+            if writeln!(writer, "{}", notification).and_then(|_| writer.flush()).is_err() {
+                return;
+            }
+        }
+        known = blocks.into_iter().map(|block| block.hash).collect();
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+fn parse_txn_id(params: Value) -> Result<String, RpcError> {
+    #[derive(Deserialize)]
+    struct Params { txn_id: String }
+    parse_params::<Params>(params).map(|params| params.txn_id)
+}
+
+fn internal(error: serde_json::Error) -> RpcError {
+    RpcError::new(SERVER_ERROR, error.to_string())
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": error.code, "message": error.message } })
+}
+
+fn serve_http(listen: &str, workers: usize, rpc: Arc<Rpc>) -> Result<(Arc<Server>, SocketAddr), FileError> {
+    let server = Server::http(listen).map_err(|e| FileError::InputError(format!("Cannot serve JSON-RPC on {}: {}", listen, e)))?;
+    let addr = server.server_addr().to_ip().ok_or_else(|| FileError::InputError(format!("{} is not an IP address", listen)))?;
+    let server = Arc::new(server);
+    for _ in 0..workers.max(1) {
+        let (server, rpc) = (server.clone(), rpc.clone());
+        thread::spawn(move || {
+            while let Ok(mut request) = server.recv() {
+                let response = answer_http(&mut request, &rpc);
+                if let Err(e) = request.respond(response) {
+                    warn!("Failed to answer a JSON-RPC call: {}", e);
+                }
+            }
+        });
+    }
+    info!("JSON-RPC listening on http://{}.", addr);
+    Ok((server, addr))
+}
+
+fn answer_http(request: &mut Request, rpc: &Rpc) -> Response<Cursor<Vec<u8>>> {
+    if request.method() != &Method::Post {
+        return Response::from_data(b"JSON-RPC calls are POSTed".to_vec()).with_status_code(StatusCode(405));
+    }
+    let mut payload = Vec::new();
+    match request.as_reader().take(MAX_PAYLOAD_LEN as u64).read_to_end(&mut payload) {
+        Ok(_) => match rpc.handle_payload(&payload, None) {
+            Some(answer) => Response::from_data(answer.to_string().into_bytes())
+                .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("Header is ASCII")),
+            None => Response::from_data(Vec::new()).with_status_code(StatusCode(204)),
+        },
+        Err(e) => Response::from_data(e.to_string().into_bytes()).with_status_code(StatusCode(400)),
+    }
+}
+
+#[cfg(unix)]
+fn serve_socket(socket: &Path, rpc: Arc<Rpc>, stop: Arc<AtomicBool>) -> Result<(), FileError> {
+    use std::os::unix::net::UnixListener;
+
+    let _ = fs::remove_file(socket);
+    let listener = UnixListener::bind(socket)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            match stream {
+                Ok(stream) => {
+                    let rpc = rpc.clone();
+                    thread::spawn(move || {
+                        let Ok(writer) = stream.try_clone() else { return };
+                        let session = Session { writer: Arc::new(Mutex::new(Box::new(writer))), subscriptions: Mutex::new(HashMap::new()) };
+                        for line in BufReader::new(stream).lines() {
+                            let Ok(line) = line else { return };
+                            if line.trim().is_empty() {
+                                continue;
+                            }
+                            if let Some(answer) = rpc.handle_payload(line.as_bytes(), Some(&session)) {
+                                let mut writer = session.writer.lock().unwrap();
+                                if writeln!(writer, "{}", answer).and_then(|_| writer.flush()).is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    });
+                },
+                Err(e) => warn!("Failed to accept a JSON-RPC connection: {}", e),
+            }
+        }
+    });
+    info!("JSON-RPC listening on {}.", socket.display());
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve_socket(_socket: &Path, _rpc: Arc<Rpc>, _stop: Arc<AtomicBool>) -> Result<(), FileError> {
+    Err(FileError::InputError("Unix sockets are not available on this platform".to_string()))
+}
+
+#[cfg(unix)]
+fn wake_socket(socket: &Path) {
+    let _ = std::os::unix::net::UnixStream::connect(socket);
+}
+
+#[cfg(not(unix))]
+fn wake_socket(_socket: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpStream;
+    use std::os::unix::net::UnixStream;
+
+    use tempfile::tempdir;
+
+    use crate::model::File;
+    use crate::testing::{AssetsPath, TestFile};
+
+    fn post(addr: SocketAddr, payload: &Value) -> Value {
+        let body = payload.to_string();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap()
+    }
+
+    fn call(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params })
+    }
+
+    fn store(identity: &LocalIdentity, file: File, content: Option<&[u8]>) -> Value {
+        let transaction = Transaction { op: JournalOp::Store { file: Box::new(file), content_hash: None }, content: content.map(hex::encode), identity_id: identity.id(), timestamp: Utc::now().timestamp() };
+        serde_json::to_value(transaction.sign(identity).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_submit_and_query_over_http() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let rpc = RpcServer::start(&RpcConfig { listen: Some("127.0.0.1:0".to_string()), socket: None, workers: 2 }).unwrap();
+        let addr = rpc.http_addr().unwrap();
+        let owner = LocalIdentity::generate(5, "Owner", "owner@gmail.com");
+        let stranger = LocalIdentity::generate(6, "Stranger", "stranger@gmail.com");
+        let registry = IdentityRegistry::open(&crate::get_path()).unwrap();
+        registry.register(&owner.public).unwrap();
+        registry.register(&stranger.public).unwrap();
+
+        let signed = store(&owner, TestFile::new(1).owned_by(owner.public.as_person()).build(), Some(b"content"));
+        let submitted = post(addr, &call("chain_submitTransaction", signed.clone()));
+        let txn_id = submitted["result"]["onchain_txn_id"].as_str().unwrap().to_string();
+        assert_eq!(post(addr, &call("chain_submitTransaction", signed))["error"]["code"], NOT_ALLOWED);
+        assert_eq!(post(addr, &call("chain_submitTransaction", store(&stranger, TestFile::new(2).owned_by(owner.public.as_person()).build(), None)))["error"]["code"], NOT_ALLOWED);
+
+        assert_eq!(post(addr, &call("chain_getHeight", Value::Null))["result"]["height"], 1);
+        let transaction = post(addr, &call("chain_getTransaction", json!({ "txn_id": txn_id })));
+        assert_eq!(transaction["result"]["block_height"], 0);
+        let block = post(addr, &call("chain_getBlock", json!({ "height": 0 })));
+        assert_eq!(block["result"]["hash"], transaction["result"]["block_hash"]);
+        let file = post(addr, &call("catalog_getFileByTransaction", json!({ "txn_id": txn_id })));
+        assert_eq!((file["result"]["id"].clone(), file["result"]["onchain_txn_id"].clone()), (json!(1), json!(txn_id)));
+
+        let batch = post(addr, &json!([call("chain_getBlocks", json!({ "from_height": 0 })), call("nope", Value::Null), { "jsonrpc": "2.0", "method": "chain_getHeight" }]));
+        assert_eq!(batch.as_array().unwrap().len(), 2);
+        assert_eq!(batch[0]["result"].as_array().unwrap().len(), 1);
+        assert_eq!(batch[1]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(post(addr, &call("chain_getBlock", json!({})))["error"]["code"], INVALID_PARAMS);
+        assert_eq!(post(addr, &call("chain_subscribe", Value::Null))["error"]["code"], SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_subscribers_get_new_blocks_over_the_socket() {
+        let dir = tempdir().unwrap();
+        let _assets = AssetsPath::set(dir.path().join("assets"));
+        let socket = dir.path().join("rpc.sock");
+        let _rpc = RpcServer::start(&RpcConfig { listen: None, socket: Some(socket.clone()), workers: 2 }).unwrap();
+        let stream = UnixStream::connect(&socket).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut next_message = || {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            serde_json::from_str::<Value>(&line).unwrap()
+        };
+
+        writeln!(writer, "{}", call("chain_subscribe", Value::Null)).unwrap();
+        let subscription = next_message()["result"].clone();
+        let owner = LocalIdentity::generate(5, "Owner", "owner@gmail.com");
+        IdentityRegistry::open(&crate::get_path()).unwrap().register(&owner.public).unwrap();
+        let stored = crate::store_new_file(TestFile::new(1).owned_by(owner.public.as_person()).build(), None).unwrap();
+
+        let notification = next_message();
+        assert_eq!(notification["method"], "chain_newBlock");
+        assert_eq!(notification["params"]["subscription"], subscription);
+        assert_eq!(notification["params"]["result"]["operations"][0]["Store"]["file"]["onchain_txn_id"], json!(stored.onchain_txn_id));
+        writeln!(writer, "{}", call("chain_unsubscribe", json!({ "subscription": subscription }))).unwrap();
+        assert_eq!(next_message()["result"], true);
+    }
+}
@@ -0,0 +1,73 @@
+//! Fixtures shared by the unit tests of every module.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::Utc;
+
+use crate::model::{File, FileType};
+
+/// Points `ASSETS_PATH` at a test repository for as long as it lives. Tests that hold one
+/// run one at a time, and the variable is put back as it was when the guard drops, even
+/// when the test panics.
+pub(crate) struct AssetsPath {
+    previous: Option<OsString>,
+    _serialized: MutexGuard<'static, ()>,
+}
+
+impl AssetsPath {
+    pub(crate) fn set(path: impl AsRef<Path>) -> Self {
+        static SERIALIZED: Mutex<()> = Mutex::new(());
+        let serialized = SERIALIZED.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = env::var_os("ASSETS_PATH");
+        env::set_var("ASSETS_PATH", path.as_ref());
+        AssetsPath { previous, _serialized: serialized }
+    }
+}
+
+impl Drop for AssetsPath {
+    fn drop(&mut self) {
+        match self.previous.take() {
+            Some(previous) => env::set_var("ASSETS_PATH", previous),
+            None => env::remove_var("ASSETS_PATH"),
+        }
+    }
+}
+
+/// Builds the record of a file for a test. It starts as `file-{id}`, a text file of 7
+/// bytes that only identity 1 owns and can open.
+pub(crate) struct TestFile {
+    file: File,
+}
+
+impl TestFile {
+    pub(crate) fn new(id: i64) -> Self {
+        let owner = (1, String::from("Username"), String::from("username@gmail.com"));
+        TestFile {
+            file: File {
+                id, name: format!("file-{}", id), file_type: FileType::Txt, size: 7, created: Utc::now().naive_utc(),
+                modified: None, accessed: None, owner: owner.clone(), people_with_access: vec![owner],
+                ipfs_hash: String::new(), onchain_txn_id: String::new(), download_permission: false, description: None,
+                content_hash: None,
+            },
+        }
+    }
+
+    /// Hands the file to `owner`, who becomes the only person with access.
+    pub(crate) fn owned_by(mut self, owner: (i64, String, String)) -> Self {
+        self.file.people_with_access = vec![owner.clone()];
+        self.file.owner = owner;
+        self
+    }
+
+    pub(crate) fn shared_with(mut self, person: (i64, String, String)) -> Self {
+        self.file.people_with_access.push(person);
+        self
+    }
+
+    pub(crate) fn build(self) -> File {
+        self.file
+    }
+}
@@ -20,9 +20,9 @@ pub fn generate_fake_hash(length: usize) -> String {
 
 pub fn get_file_type_from_input() -> Result<FileType, FileError> {
     print!("Enter the file type (pdf, docx, xls, txt, csv, pptx, jpg, png): ");
-    io::stdout().flush().map_err(|_| FileError::IOError(std::io::Error::new(io::ErrorKind::Other, "Failed to flush output")))?;
+    io::stdout().flush().map_err(|_| FileError::IOError(io::Error::other("Failed to flush output")))?;
     let mut input = String::new();
-    io::stdin().read_line(&mut input).map_err(|_| FileError::IOError(std::io::Error::new(io::ErrorKind::Other, "Failed to read input")))?;
+    io::stdin().read_line(&mut input).map_err(|_| FileError::IOError(io::Error::other("Failed to read input")))?;
     let file_type = input.trim().to_lowercase();
     match file_type.as_str() {
         "pdf" => Ok(FileType::Pdf),
@@ -72,13 +72,13 @@ pub fn parse_date_input() -> Result<NaiveDateTime, String> {
 
 pub fn get_default_file(file_data: &FileData, _path: &PathBuf) -> Result<File, FileError> {
     let owner_access = file_data.owner.clone();
-    let file_size = get_file_size().map_err(|e| e)?;
+    let file_size = get_file_size()?;
     let created_date = parse_date_input().unwrap();
     let file_type = get_file_type_from_input()?;
     Ok(File {
         id: generate_id()?,
         name: String::new(),
-        file_type: file_type,
+        file_type,
         size: file_size,
         created: created_date,
         modified: None,
@@ -89,11 +89,12 @@ pub fn get_default_file(file_data: &FileData, _path: &PathBuf) -> Result<File, F
         onchain_txn_id: generate_fake_hash(64),
         download_permission: false,
         description: None,
+        content_hash: None,
     })
 }
 
 pub fn process_modified_file(mut file: File) -> Result<File, FileError> {
-    file.size = get_file_size().map_err(|e| e)?;
+    file.size = get_file_size()?;
     file.file_type = get_file_type_from_input()?;
     file.modified = Some(parse_date_input().unwrap());
     file.accessed = Some(Utc::now().naive_utc());
@@ -120,6 +121,20 @@ pub fn process_input(prompt: &str, allow_empty: bool) -> Result<Option<String>,
     }
 }
 
+/// Reads a passphrase from the terminal without echoing it.
+pub fn get_passphrase(prompt: &str) -> Result<String, FileError> {
+    if let Ok(passphrase) = std::env::var("UNICHAIN_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    loop {
+        let passphrase = rpassword::prompt_password(prompt).map_err(FileError::IOError)?;
+        if !passphrase.is_empty() {
+            return Ok(passphrase);
+        }
+        warn!("Input cannot be empty for prompt '{}'. Please try again.", prompt);
+    }
+}
+
 pub fn get_system_owner() -> (i64, String, String) {
     (2454826096558341, String::from("Juan Carvalho Silva de Lima"), String::from("juanc.s.delima@gmail.com"))
 }
@@ -127,13 +142,13 @@ pub fn get_system_owner() -> (i64, String, String) {
 pub fn prompt_for_file_id() -> Result<i64, FileError> {
     loop {
         print!("\nInsert file ID: ");
-        io::stdout().flush().map_err(|e| FileError::IOError(e))?;
+        io::stdout().flush().map_err(FileError::IOError)?;
         let mut file_id_input = String::new();
-        io::stdin().read_line(&mut file_id_input).map_err(|e| FileError::IOError(e))?;
+        io::stdin().read_line(&mut file_id_input).map_err(FileError::IOError)?;
         match file_id_input.trim().parse::<i64>() {
             Ok(file_id) => return Ok(file_id),
             Err(_) => {
-                print!("\n");
+                println!();
                 warn!("Invalid ID number. Please enter a valid number.");
                 continue;
             }
@@ -142,9 +157,9 @@ pub fn prompt_for_file_id() -> Result<i64, FileError> {
 }
 
 pub fn handle_input() -> Result<String, FileError> {
-    io::stdout().flush().map_err(|e| FileError::IOError(e))?;
+    io::stdout().flush().map_err(FileError::IOError)?;
     let mut response = String::new();
-    io::stdin().read_line(&mut response).map_err(|e| FileError::IOError(e))?;
+    io::stdin().read_line(&mut response).map_err(FileError::IOError)?;
     Ok(response.trim().to_string())
 }
 